@@ -0,0 +1,14 @@
+//! Lets the unit tests find the libpython they link.
+//!
+//! Wheels are built by maturin with `pyo3/extension-module` (see
+//! `pyproject.toml`), leaving libpython to the interpreter that loads them.
+//! `cargo test` builds without it and links libpython, which need not be on the
+//! loader's path, so the test binaries get an rpath to its directory. It is
+//! unused by the extension module, which does not link libpython.
+
+fn main() {
+    let config = pyo3_build_config::get();
+    if let Some(lib_dir) = &config.lib_dir {
+        println!("cargo:rustc-link-arg=-Wl,-rpath,{}", lib_dir);
+    }
+}
@@ -0,0 +1,123 @@
+//! Awaitable variants of the conversion functions and client methods, for asyncio.
+//!
+//! Built with the `asyncio` feature. Each returns an asyncio future of a task
+//! that pyo3-async-runtimes runs on its tokio runtime, which the connections
+//! run on too: requests wait for the server there without taking up a thread,
+//! and conversions run on the runtime's blocking threads with the GIL released,
+//! so the event loop goes on serving other tasks in the meantime.
+
+use std::future::Future;
+
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyTuple};
+#[cfg(feature = "client")]
+use tokio::runtime::Runtime;
+
+/// The tokio runtime the awaitables and the connections run on.
+#[cfg(feature = "client")]
+pub(crate) fn runtime() -> &'static Runtime {
+    pyo3_async_runtimes::tokio::get_runtime()
+}
+
+/// Run `future` to completion on the runtime, with the GIL released.
+#[cfg(feature = "client")]
+pub(crate) fn block_on<F>(py: Python, future: F) -> F::Output
+where
+    F: Future + Send,
+    F::Output: Send,
+{
+    py.allow_threads(|| runtime().block_on(future))
+}
+
+/// An asyncio future of `future`, run on the runtime. Raises `RuntimeError`
+/// outside of a coroutine.
+pub(crate) fn future_into_py<F, T>(py: Python, future: F) -> PyResult<Bound<PyAny>>
+where
+    F: Future<Output = PyResult<T>> + Send + 'static,
+    T: for<'py> IntoPyObject<'py>,
+{
+    pyo3_async_runtimes::tokio::future_into_py(py, future)
+}
+
+/// An asyncio future of `f`, run on a blocking thread of the runtime.
+pub(crate) fn blocking_into_py<F, T>(py: Python, f: F) -> PyResult<Bound<PyAny>>
+where
+    F: FnOnce() -> PyResult<T> + Send + 'static,
+    T: for<'py> IntoPyObject<'py> + Send + 'static,
+{
+    future_into_py(py, async move {
+        tokio::task::spawn_blocking(f).await.map_err(|e| PyRuntimeError::new_err(format!("the task failed: {}", e)))?
+    })
+}
+
+/// `cbor_to_arrow`, awaitable: `table = await cbor_to_arrow_async(data)`.
+///
+/// Takes the same arguments as `cbor_to_arrow` and converts on a thread of the
+/// runtime, while the event loop goes on running.
+#[pyfunction]
+#[pyo3(signature = (*args, **options))]
+pub(crate) fn cbor_to_arrow_async<'py>(
+    py: Python<'py>,
+    args: &Bound<'py, PyTuple>,
+    options: Option<&Bound<'py, PyDict>>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let convert = wrap_pyfunction!(crate::cbor_to_arrow, py)?.into_any().unbind();
+    let (args, options) = (args.clone().unbind(), options.map(|options| options.clone().unbind()));
+    blocking_into_py(py, move || {
+        Python::with_gil(|py| {
+            convert.bind(py).call(args.bind(py), options.as_ref().map(|options| options.bind(py))).map(Bound::unbind)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::AsArray;
+    use arrow::datatypes::Int64Type;
+    use cbor4ii::core::Value;
+    use pyo3::ffi::c_str;
+    use pyo3::types::PyBytes;
+    use pyo3_arrow::PyRecordBatchReader;
+
+    use super::*;
+    use crate::encode::encode;
+
+    fn response() -> Vec<u8> {
+        let text = |s: &str| Value::Text(s.to_string());
+        let records = (0..3).map(|n| Value::Map(vec![(text("n"), Value::Integer(n))])).collect();
+        let statement = Value::Map(vec![
+            (text("status"), text("OK")),
+            (text("time"), text("1ms")),
+            (text("result"), Value::Array(records)),
+        ]);
+        encode(&Value::Map(vec![(text("id"), Value::Integer(1)), (text("result"), Value::Array(vec![statement]))]))
+    }
+
+    #[test]
+    fn conversions_are_awaited() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let code = c_str!(
+                "import asyncio\n\
+                 async def convert(convert_async, data):\n    \
+                     both = [convert_async(data, output='stream') for _ in range(2)]\n    \
+                     return await asyncio.gather(*both)\n"
+            );
+            let module = PyModule::from_code(py, code, c_str!("aio_test.py"), c_str!("aio_test")).unwrap();
+            let convert = wrap_pyfunction!(cbor_to_arrow_async, py).unwrap();
+            let coroutine = module.getattr("convert").unwrap().call1((convert, PyBytes::new(py, &response()))).unwrap();
+            let results = py.import("asyncio").unwrap().call_method1("run", (coroutine,)).unwrap();
+            for result in results.try_iter().unwrap() {
+                let reader = result.unwrap().extract::<PyRecordBatchReader>().unwrap().into_reader().unwrap();
+                let batches: Vec<_> = reader.map(Result::unwrap).collect();
+                let n: Vec<_> =
+                    batches.iter().flat_map(|b| b.column(0).as_primitive::<Int64Type>().values().to_vec()).collect();
+                assert_eq!(n, [0, 1, 2]);
+            }
+            // Outside of a coroutine there is no event loop to wait on.
+            let err = cbor_to_arrow_async(py, &PyTuple::empty(py), None).unwrap_err();
+            assert!(err.is_instance_of::<PyRuntimeError>(py));
+        });
+    }
+}
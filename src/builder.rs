@@ -0,0 +1,623 @@
+//! Direct CBOR to Arrow conversion for a known schema.
+//!
+//! Once a `BatchPlan` has its schema, records can be appended row by row to typed
+//! Arrow builders instead of being serialized through serde_arrow. The output is the
+//! same as `SurrealRecord` produces. Only the types schema inference emits are
+//! handled here. Anything else is an error, and the caller falls back to
+//! serde_arrow, which also reports conversion errors.
+//!
+//! For a declared schema the builder also coerces values that do not match (see
+//! `coerce`) and builds other Arrow types by casting a column of a close type.
+
+use std::sync::Arc;
+
+use arrow::array::builder::{
+    BinaryBuilder, BooleanBuilder, Decimal128Builder, Decimal256Builder, DurationNanosecondBuilder, FixedSizeBinaryBuilder, Float64Builder, Int64Builder,
+    LargeBinaryBuilder, LargeStringBuilder, NullBufferBuilder, StringBuilder, StringDictionaryBuilder,
+    TimestampNanosecondBuilder, UInt64Builder,
+};
+use arrow::array::{ArrayRef, GenericListArray, NullArray, OffsetSizeTrait, StructArray, UnionArray};
+use arrow::buffer::{OffsetBuffer, ScalarBuffer};
+use arrow::compute::kernels::cast_utils::parse_decimal;
+use arrow::compute::{cast_with_options, CastOptions};
+use arrow::datatypes::{
+    i256, DataType, Decimal256Type, DecimalType, Field, FieldRef, Fields, Int32Type, TimeUnit, UnionFields, UnionMode,
+};
+use arrow::error::ArrowError;
+use cbor4ii::core::Value;
+
+use crate::pull::{self, Spans, Step};
+use crate::{coerce, schema};
+use crate::tags::{self, SurrealTag};
+use crate::{is_big_int, is_null, key_string, object_entries, BigInt, Entries, ConvertOptions, Loss, MapKeys, ObjectsAs, RecordIdMode, UnknownTagPolicy, UuidMode};
+
+/// Why `build_columns` failed.
+#[derive(Debug)]
+pub(crate) enum BuildError {
+    /// A record that does not fit the fields, and where it is.
+    Record(String),
+    /// Anything else, such as a field type there is no column for.
+    Other(String),
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::Record(message) | BuildError::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+/// Build one array per field of `fields` from the top-level records, applying the
+/// same record id splitting and scalar wrapping as `SurrealRecord`. With `coerce`
+/// values are converted to the field types where possible. Errors say where a
+/// record is in the input if its `spans` are given.
+pub(crate) fn build_columns(
+    fields: &[FieldRef],
+    records: &[Value],
+    spans: Option<Spans>,
+    split_ids: &[String],
+    value_column: Option<&str>,
+    coerce: bool,
+    opts: &ConvertOptions,
+) -> Result<Vec<ArrayRef>, BuildError> {
+    let suffixes = &opts.record_id_suffixes;
+    let mut columns = fields
+        .iter()
+        .map(|field| {
+            let split = split_ids.iter().find_map(|id| {
+                let part = field.name().strip_prefix(id.as_str())?;
+                (part == suffixes.table || part == suffixes.key).then_some((id.as_str(), part == suffixes.table))
+            });
+            let source = match split {
+                Some((id, table)) => Source::Split { id, table },
+                None => Source::Member(field.name()),
+            };
+            let column = Column::new(field, records.len(), coerce, opts).ok_or_else(|| {
+                BuildError::Other(format!("unsupported type {} for column {}", field.data_type(), field.name()))
+            })?;
+            Ok((source, column))
+        })
+        .collect::<Result<Vec<_>, BuildError>>()?;
+
+    let mut hint = 0;
+    for (row, record) in records.iter().enumerate() {
+        let entries = match (record, value_column) {
+            (_, Some(_)) => Entries::default(),
+            (Value::Map(map), None) => object_entries(map, opts)
+                .map_err(|e| BuildError::Record(format!("{}: {}", location(row, &[], spans, opts), e)))?,
+            _ => return Err(BuildError::Record(format!("{} is not an object", location(row, &[], spans, opts)))),
+        };
+        let mut member = |name: &str| match value_column {
+            Some(column) => (column == name).then_some(record),
+            None => lookup(&entries, name, &mut hint, opts.map_keys),
+        };
+        for ((source, column), field) in columns.iter_mut().zip(fields) {
+            let appended = match source {
+                Source::Member(name) => column.append(member(name), coerce, opts),
+                Source::Split { id, table } => {
+                    let parts = match member(id) {
+                        Some(Value::Tag(_, payload)) => tags::record_id_parts(payload),
+                        _ => None,
+                    };
+                    column.append_str(parts.map(|(tb, key)| if *table { tb } else { key }))
+                }
+            };
+            if appended.is_none() {
+                let mut steps = Vec::new();
+                let data_type = match source {
+                    Source::Member(name) => {
+                        if value_column.is_none() {
+                            steps.push(Step::Member(name.to_string()));
+                        }
+                        misfit(field, member(name), coerce, opts, &mut steps)
+                    }
+                    Source::Split { id, .. } => {
+                        steps.push(Step::Member(id.to_string()));
+                        field.data_type().clone()
+                    }
+                };
+                let at = location(row, &steps, spans, opts);
+                return Err(BuildError::Record(format!("cannot convert {} to {} (column {})", at, data_type, field.name())));
+            }
+        }
+    }
+    columns.into_iter().map(|(_, column)| column.finish().map_err(|e| BuildError::Other(e.to_string()))).collect()
+}
+
+/// Where the value at `steps` in record `row` is: its path and byte offset in
+/// the input with `spans`, otherwise its path in the record.
+fn location(row: usize, steps: &[Step], spans: Option<Spans>, opts: &ConvertOptions) -> String {
+    if let Some(spans) = spans {
+        return spans.locate(row, steps, opts.map_keys);
+    }
+    let mut path = String::new();
+    for step in steps {
+        pull::push_step(&mut path, step);
+    }
+    match path.is_empty() {
+        true => format!("row {}", row),
+        false => format!("{} of row {}", path, row),
+    }
+}
+
+/// The type of the innermost part of `value`, which does not fit `field`, that
+/// does not fit, adding the steps to it to `steps`. Found by appending members
+/// and elements one at a time to fresh columns.
+fn misfit(field: &FieldRef, value: Option<&Value>, coerce: bool, opts: &ConvertOptions, steps: &mut Vec<Step>) -> DataType {
+    let fits = |field: &FieldRef, value: Option<&Value>| {
+        Column::new(field, 1, coerce, opts).is_some_and(|mut column| column.append(value, coerce, opts).is_some())
+    };
+    match (field.data_type(), resolve(value, opts)) {
+        (DataType::Struct(fields), Some(Value::Map(map))) if !schema::is_json(field) => {
+            let entries = object_entries(map, opts).unwrap_or_default();
+            let member = fields
+                .iter()
+                .map(|field| (field, lookup(&entries, field.name(), &mut 0, opts.map_keys)))
+                .find(|(field, value)| !fits(field, *value));
+            if let Some((field, value)) = member {
+                steps.push(Step::Member(field.name().clone()));
+                return misfit(field, value, coerce, opts, steps);
+            }
+        }
+        (DataType::List(element) | DataType::LargeList(element), Some(Value::Array(items))) => {
+            if let Some((i, item)) = items.iter().enumerate().find(|(_, item)| !fits(element, Some(item))) {
+                steps.push(Step::Index(i));
+                return misfit(element, Some(item), coerce, opts, steps);
+            }
+        }
+        _ => {}
+    }
+    field.data_type().clone()
+}
+
+/// Where a top-level column takes its values from.
+enum Source<'a> {
+    Member(&'a str),
+    /// The table or key half of a split record id column.
+    Split { id: &'a str, table: bool },
+}
+
+/// The value stored under `name`, with non-text keys matched in their stringified form.
+///
+/// Records usually share their key order, so the search starts after the previous
+/// match and wraps around.
+fn lookup<'a>(map: &Entries<'a>, name: &str, hint: &mut usize, keys: MapKeys) -> Option<&'a Value> {
+    let start = (*hint).min(map.len());
+    let i = (start..map.len()).chain(0..start).find(|&i| key_string(map.get(i).0, keys) == name)?;
+    *hint = (i + 1) % map.len();
+    Some(map.get(i).1)
+}
+
+/// The unscaled value of an integer, bignum or decimal fraction in a decimal
+/// column of `precision` and `scale`, if it fits exactly. With `coerce` other
+/// values are parsed from their text.
+pub(crate) fn decimal_value(value: &Value, precision: u8, scale: i8, coerce: bool) -> Option<i256> {
+    let unscaled = match value {
+        Value::Integer(i) => tags::rescale(i256::from_i128(*i), 0, scale)?,
+        Value::Tag(tag, payload) if SurrealTag::of(*tag) == SurrealTag::BigNum => {
+            tags::rescale(tags::bignum_i256(*tag, payload)?, 0, scale)?
+        }
+        Value::Tag(tag, payload) if SurrealTag::of(*tag) == SurrealTag::DecimalFraction => {
+            let (exponent, mantissa) = tags::decimal_fraction(payload)?;
+            tags::rescale(mantissa, exponent, scale)?
+        }
+        v if coerce => parse_decimal::<Decimal256Type>(coerce::to_text(v)?.trim(), precision, scale).ok()?,
+        _ => return None,
+    };
+    Decimal256Type::is_valid_decimal_precision(unscaled, precision).then_some(unscaled)
+}
+
+/// Strip the tags `SurrealValueRef` serializes as their bare payload. `None` is a null.
+pub(crate) fn resolve<'a>(value: Option<&'a Value>, opts: &ConvertOptions) -> Option<&'a Value> {
+    resolve_noting(value, opts, |_| {})
+}
+
+/// `resolve`, calling `note` with what is lost on the way.
+fn resolve_noting<'a>(value: Option<&'a Value>, opts: &ConvertOptions, mut note: impl FnMut(Loss)) -> Option<&'a Value> {
+    let mut value = value?;
+    loop {
+        if is_null(value) {
+            return None;
+        }
+        if opts.big_int == BigInt::Null && is_big_int(value) {
+            note(Loss::BigInt);
+            return None;
+        }
+        match value {
+            Value::Tag(tag, payload) => match SurrealTag::of(*tag) {
+                SurrealTag::Unknown if opts.on_unknown_tag == UnknownTagPolicy::Ignore => value = payload,
+                SurrealTag::Future | SurrealTag::Bound if !opts.strict_tags => value = payload,
+                _ => return Some(value),
+            },
+            _ => return Some(value),
+        }
+        note(Loss::Tag);
+    }
+}
+
+/// Convert a non-null value with `f`. The outer `None` means it is unsupported.
+fn leaf<'a, T>(value: Option<&'a Value>, f: impl FnOnce(&'a Value) -> Option<T>) -> Option<Option<T>> {
+    match value {
+        None => Some(None),
+        Some(v) => f(v).map(Some),
+    }
+}
+
+/// An Arrow builder for one field, filled one value at a time.
+enum Column {
+    Null(usize),
+    Boolean(BooleanBuilder),
+    Int64(Int64Builder),
+    UInt64(UInt64Builder),
+    Float64(Float64Builder),
+    Timestamp(TimestampNanosecondBuilder),
+    Duration(DurationNanosecondBuilder),
+    Utf8(StringBuilder),
+    LargeUtf8(LargeStringBuilder),
+    /// `Dictionary<Int32, Utf8>`.
+    Dictionary(StringDictionaryBuilder<Int32Type>),
+    Binary(BinaryBuilder),
+    LargeBinary(LargeBinaryBuilder),
+    Uuid(FixedSizeBinaryBuilder),
+    /// Decimal fractions, and big integers for `big_int="decimal"`.
+    Decimal128 { builder: Decimal128Builder, precision: u8, scale: i8 },
+    Decimal256 { builder: Decimal256Builder, precision: u8, scale: i8 },
+    Struct { fields: Fields, children: Vec<Column>, validity: NullBufferBuilder, hint: usize },
+    List { element: FieldRef, large: bool, offsets: Vec<usize>, child: Box<Column>, validity: NullBufferBuilder },
+    /// A string column of the JSON text of each value.
+    Json(Box<Column>),
+    /// Each value goes to the first variant it conforms to and nulls to the first
+    /// variant. Sparse unions (no `offsets`) append a null to the other variants.
+    Union { fields: UnionFields, children: Vec<Column>, type_ids: Vec<i8>, offsets: Option<Vec<i32>>, lens: Vec<i32> },
+    /// A column built as another type and cast to `to` when finished.
+    Cast { to: DataType, inner: Box<Column> },
+}
+
+impl Column {
+    fn new(field: &FieldRef, capacity: usize, coerce: bool, opts: &ConvertOptions) -> Option<Self> {
+        Some(match field.data_type() {
+            DataType::Null => Column::Null(0),
+            DataType::Boolean => Column::Boolean(BooleanBuilder::with_capacity(capacity)),
+            DataType::Int64 => Column::Int64(Int64Builder::with_capacity(capacity)),
+            DataType::UInt64 => Column::UInt64(UInt64Builder::with_capacity(capacity)),
+            DataType::Float64 => Column::Float64(Float64Builder::with_capacity(capacity)),
+            DataType::Timestamp(TimeUnit::Nanosecond, tz) => {
+                Column::Timestamp(TimestampNanosecondBuilder::with_capacity(capacity).with_timezone_opt(tz.clone()))
+            }
+            DataType::Duration(TimeUnit::Nanosecond) => Column::Duration(DurationNanosecondBuilder::with_capacity(capacity)),
+            DataType::Utf8 | DataType::LargeUtf8 if schema::is_json(field) => {
+                let text = Arc::new(Field::new(field.name(), field.data_type().clone(), true));
+                Column::Json(Box::new(Column::new(&text, capacity, coerce, opts)?))
+            }
+            DataType::Utf8 => Column::Utf8(StringBuilder::new()),
+            DataType::LargeUtf8 => Column::LargeUtf8(LargeStringBuilder::new()),
+            DataType::Dictionary(key, values) if **key == DataType::Int32 && **values == DataType::Utf8 => {
+                Column::Dictionary(StringDictionaryBuilder::new())
+            }
+            DataType::Binary => Column::Binary(BinaryBuilder::new()),
+            DataType::LargeBinary => Column::LargeBinary(LargeBinaryBuilder::new()),
+            DataType::FixedSizeBinary(16) if coerce || opts.uuid_mode == UuidMode::Binary => {
+                Column::Uuid(FixedSizeBinaryBuilder::with_capacity(capacity, 16))
+            }
+            DataType::Decimal128(precision, scale) if *scale >= 0 => Column::Decimal128 {
+                builder: Decimal128Builder::with_capacity(capacity).with_precision_and_scale(*precision, *scale).ok()?,
+                precision: *precision,
+                scale: *scale,
+            },
+            DataType::Decimal256(precision, scale) if *scale >= 0 => Column::Decimal256 {
+                builder: Decimal256Builder::with_capacity(capacity).with_precision_and_scale(*precision, *scale).ok()?,
+                precision: *precision,
+                scale: *scale,
+            },
+            DataType::Struct(fields) if !fields.is_empty() => Column::Struct {
+                children: fields.iter().map(|f| Column::new(f, capacity, coerce, opts)).collect::<Option<_>>()?,
+                fields: fields.clone(),
+                validity: NullBufferBuilder::new(capacity),
+                hint: 0,
+            },
+            DataType::List(element) | DataType::LargeList(element) => Column::List {
+                large: matches!(field.data_type(), DataType::LargeList(_)),
+                offsets: vec![0],
+                child: Box::new(Column::new(element, capacity, coerce, opts)?),
+                element: element.clone(),
+                validity: NullBufferBuilder::new(capacity),
+            },
+            DataType::Union(fields, mode) if !fields.is_empty() => Column::Union {
+                children: fields.iter().map(|(_, f)| Column::new(f, capacity, coerce, opts)).collect::<Option<_>>()?,
+                fields: fields.clone(),
+                type_ids: Vec::with_capacity(capacity),
+                offsets: (*mode == UnionMode::Dense).then(|| Vec::with_capacity(capacity)),
+                lens: vec![0; fields.len()],
+            },
+            to if coerce => {
+                let field = Arc::new(Field::new(field.name(), coerce::build_type(to)?, true));
+                Column::Cast { to: to.clone(), inner: Box::new(Column::new(&field, capacity, coerce, opts)?) }
+            }
+            _ => return None,
+        })
+    }
+
+    /// Append one value, or return `None` if it does not fit this column.
+    fn append(&mut self, value: Option<&Value>, coerce: bool, opts: &ConvertOptions) -> Option<()> {
+        let value = resolve_noting(value, opts, |loss| opts.lose(loss, 1));
+        // A bignum that fits converts as the integer.
+        if let Some(Value::Tag(tag, payload)) = value {
+            if let Some(i) = tags::bignum_i128(*tag, payload).filter(|_| SurrealTag::of(*tag) == SurrealTag::BigNum) {
+                return self.append(Some(&Value::Integer(i)), coerce, opts);
+            }
+        }
+        match self {
+            Column::Null(len) => {
+                if value.is_some() {
+                    return None;
+                }
+                *len += 1;
+            }
+            Column::Boolean(b) => b.append_option(leaf(value, |v| match v {
+                Value::Bool(b) => Some(*b),
+                v if coerce => coerce::to_bool(v),
+                _ => None,
+            })?),
+            Column::Int64(b) => b.append_option(leaf(value, |v| match v {
+                Value::Integer(i) => i64::try_from(*i).ok(),
+                Value::Tag(tag, payload) => match SurrealTag::of(*tag) {
+                    SurrealTag::Datetime => tags::datetime_to_nanos(*tag, payload),
+                    SurrealTag::Duration => tags::duration_to_nanos(*tag, payload),
+                    _ if coerce => coerce::to_i64(v),
+                    _ => None,
+                },
+                v if coerce => coerce::to_i64(v),
+                _ => None,
+            })?),
+            Column::UInt64(b) => b.append_option(leaf(value, |v| match v {
+                Value::Integer(i) => u64::try_from(*i).ok(),
+                v if coerce => coerce::to_u64(v),
+                _ => None,
+            })?),
+            Column::Float64(b) => b.append_option(leaf(value, |v| match v {
+                Value::Float(f) => Some(*f),
+                Value::Integer(i) if opts.tracing.coerce_numbers => Some(*i as f64),
+                v if coerce => coerce::to_f64(v),
+                _ => None,
+            })?),
+            Column::Timestamp(b) => b.append_option(leaf(value, |v| match v {
+                Value::Tag(tag, payload) if SurrealTag::of(*tag) == SurrealTag::Datetime => {
+                    tags::datetime_to_nanos(*tag, payload)
+                }
+                v if coerce => coerce::to_datetime(v),
+                _ => None,
+            })?),
+            Column::Duration(b) => b.append_option(leaf(value, |v| match v {
+                Value::Tag(tag, payload) if SurrealTag::of(*tag) == SurrealTag::Duration => {
+                    tags::duration_to_nanos(*tag, payload)
+                }
+                v if coerce => coerce::to_duration(v),
+                _ => None,
+            })?),
+            Column::Decimal128 { builder, precision, scale } => builder.append_option(leaf(value, |v| {
+                decimal_value(v, *precision, *scale, coerce)?.to_i128()
+            })?),
+            Column::Decimal256 { builder, precision, scale } => {
+                builder.append_option(leaf(value, |v| decimal_value(v, *precision, *scale, coerce))?)
+            }
+            Column::Utf8(_) | Column::LargeUtf8(_) | Column::Dictionary(_) => match value {
+                None => self.append_str(None::<&str>)?,
+                Some(Value::Text(s)) => self.append_str(Some(s))?,
+                Some(Value::Integer(i)) if opts.big_int == BigInt::String => self.append_str(Some(i.to_string()))?,
+                Some(v @ Value::Tag(tag, payload)) => match (SurrealTag::of(*tag), payload.as_ref()) {
+                    (SurrealTag::Table | SurrealTag::Decimal, Value::Text(s)) => self.append_str(Some(s))?,
+                    (SurrealTag::DecimalFraction, payload) => {
+                        self.append_str(Some(tags::decimal_fraction_string(payload)?))?
+                    }
+                    (SurrealTag::BigNum, payload) if opts.big_int == BigInt::String => {
+                        self.append_str(Some(tags::bignum_string(*tag, payload)?))?
+                    }
+                    (SurrealTag::RecordId, payload) if opts.record_id_mode != RecordIdMode::Struct => {
+                        self.append_str(Some(tags::record_id_string(payload)?))?
+                    }
+                    (SurrealTag::Uuid, payload) if opts.uuid_mode == UuidMode::String => {
+                        self.append_str(Some(tags::format_uuid(&tags::uuid_bytes(*tag, payload)?)))?
+                    }
+                    _ if coerce => self.append_str(Some(coerce::to_text(v)?))?,
+                    _ => return None,
+                },
+                Some(v @ Value::Map(_)) if opts.objects_as == ObjectsAs::Json => {
+                    self.append_str(Some(coerce::to_json(v, opts)?))?
+                }
+                Some(v @ (Value::Map(_) | Value::Array(_))) if coerce => {
+                    self.append_str(Some(coerce::to_json(v, opts)?))?
+                }
+                Some(v) if coerce => self.append_str(Some(coerce::to_text(v)?))?,
+                Some(_) => return None,
+            },
+            Column::Binary(_) | Column::LargeBinary(_) => match value {
+                None => self.append_bytes(None::<&[u8]>),
+                Some(Value::Bytes(b)) => self.append_bytes(Some(b)),
+                Some(v @ Value::Tag(tag, payload)) => match SurrealTag::of(*tag) {
+                    SurrealTag::Uuid if opts.uuid_mode == UuidMode::Binary => {
+                        self.append_bytes(Some(tags::uuid_bytes(*tag, payload)?))
+                    }
+                    SurrealTag::Geometry => self.append_bytes(Some(tags::geometry_to_wkb(*tag, payload)?)),
+                    _ if coerce => self.append_bytes(Some(coerce::to_bytes(v)?)),
+                    _ => return None,
+                },
+                Some(v) if coerce => self.append_bytes(Some(coerce::to_bytes(v)?)),
+                Some(_) => return None,
+            },
+            Column::Uuid(b) => match value {
+                None => b.append_null(),
+                Some(Value::Tag(tag, payload)) if SurrealTag::of(*tag) == SurrealTag::Uuid => {
+                    b.append_value(tags::uuid_bytes(*tag, payload)?).ok()?
+                }
+                Some(v) if coerce => b.append_value(coerce::to_uuid(v)?).ok()?,
+                Some(_) => return None,
+            },
+            Column::Struct { fields, children, validity, hint } => {
+                let entries = match value {
+                    None => None,
+                    Some(Value::Map(map)) => Some(object_entries(map, opts).ok()?),
+                    Some(_) => return None,
+                };
+                let map = entries.as_ref();
+                validity.append(map.is_some());
+                for (field, child) in fields.iter().zip(children.iter_mut()) {
+                    child.append(map.and_then(|m| lookup(m, field.name(), hint, opts.map_keys)), coerce, opts)?;
+                }
+            }
+            Column::List { offsets, child, validity, .. } => {
+                let items = match value {
+                    None => &[][..],
+                    Some(Value::Array(items)) => items.as_slice(),
+                    Some(_) => return None,
+                };
+                validity.append(value.is_some());
+                for item in items {
+                    child.append(Some(item), coerce, opts)?;
+                }
+                offsets.push(offsets.last().copied().unwrap_or(0) + items.len());
+            }
+            Column::Json(inner) => match value {
+                None => inner.append_str(None::<&str>)?,
+                Some(v) => inner.append_str(Some(coerce::to_json(v, opts)?))?,
+            },
+            Column::Union { fields, children, type_ids, offsets, lens } => {
+                let variant = match value {
+                    None => 0,
+                    Some(v) => fields.iter().position(|(_, f)| schema::value_conforms(f, Some(v), opts))?,
+                };
+                type_ids.push(fields.iter().nth(variant)?.0);
+                match offsets {
+                    Some(offsets) => {
+                        offsets.push(lens[variant]);
+                        lens[variant] += 1;
+                        children[variant].append(value, coerce, opts)?;
+                    }
+                    None => {
+                        for (i, child) in children.iter_mut().enumerate() {
+                            child.append(if i == variant { value } else { None }, coerce, opts)?;
+                        }
+                    }
+                }
+            }
+            Column::Cast { inner, .. } => inner.append(value, coerce, opts)?,
+        }
+        Some(())
+    }
+
+    /// Append to a string column; `None` for any other column.
+    fn append_str(&mut self, value: Option<impl AsRef<str>>) -> Option<()> {
+        match self {
+            Column::Utf8(b) => b.append_option(value),
+            Column::LargeUtf8(b) => b.append_option(value),
+            // Fails once the keys overflow.
+            Column::Dictionary(b) => match value {
+                Some(value) => {
+                    b.append(value).ok()?;
+                }
+                None => b.append_null(),
+            },
+            Column::Cast { inner, .. } => return inner.append_str(value),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn append_bytes(&mut self, value: Option<impl AsRef<[u8]>>) {
+        match self {
+            Column::Binary(b) => b.append_option(value),
+            Column::LargeBinary(b) => b.append_option(value),
+            _ => unreachable!("append_bytes on a non-binary column"),
+        }
+    }
+
+    fn finish(self) -> Result<ArrayRef, ArrowError> {
+        Ok(match self {
+            Column::Null(len) => Arc::new(NullArray::new(len)),
+            Column::Boolean(mut b) => Arc::new(b.finish()),
+            Column::Int64(mut b) => Arc::new(b.finish()),
+            Column::UInt64(mut b) => Arc::new(b.finish()),
+            Column::Float64(mut b) => Arc::new(b.finish()),
+            Column::Timestamp(mut b) => Arc::new(b.finish()),
+            Column::Duration(mut b) => Arc::new(b.finish()),
+            Column::Utf8(mut b) => Arc::new(b.finish()),
+            Column::LargeUtf8(mut b) => Arc::new(b.finish()),
+            Column::Dictionary(mut b) => Arc::new(b.finish()),
+            Column::Binary(mut b) => Arc::new(b.finish()),
+            Column::LargeBinary(mut b) => Arc::new(b.finish()),
+            Column::Uuid(mut b) => Arc::new(b.finish()),
+            Column::Decimal128 { mut builder, .. } => Arc::new(builder.finish()),
+            Column::Decimal256 { mut builder, .. } => Arc::new(builder.finish()),
+            Column::Struct { fields, children, mut validity, .. } => {
+                let arrays = children.into_iter().map(Column::finish).collect::<Result<Vec<_>, _>>()?;
+                Arc::new(StructArray::try_new(fields, arrays, validity.finish())?)
+            }
+            Column::List { element, large, offsets, child, mut validity } => {
+                let (child, nulls) = (child.finish()?, validity.finish());
+                if large {
+                    Arc::new(GenericListArray::try_new(element, list_offsets::<i64>(offsets)?, child, nulls)?)
+                } else {
+                    Arc::new(GenericListArray::try_new(element, list_offsets::<i32>(offsets)?, child, nulls)?)
+                }
+            }
+            Column::Json(inner) => inner.finish()?,
+            Column::Union { fields, children, type_ids, offsets, .. } => {
+                let arrays = children.into_iter().map(Column::finish).collect::<Result<Vec<_>, _>>()?;
+                Arc::new(UnionArray::try_new(fields, type_ids.into(), offsets.map(ScalarBuffer::from), arrays)?)
+            }
+            Column::Cast { to, inner } => {
+                // Unsafe casts fail instead of turning values that do not fit into nulls.
+                let options = CastOptions { safe: false, ..Default::default() };
+                cast_with_options(&inner.finish()?, &to, &options)?
+            }
+        })
+    }
+}
+
+/// List offsets in the width of the list type, or an error if they overflow it.
+fn list_offsets<O: OffsetSizeTrait>(offsets: Vec<usize>) -> Result<OffsetBuffer<O>, ArrowError> {
+    let offsets = offsets
+        .into_iter()
+        .map(O::from_usize)
+        .collect::<Option<Vec<O>>>()
+        .ok_or_else(|| ArrowError::InvalidArgumentError("list offsets overflow".to_string()))?;
+    Ok(OffsetBuffer::new(ScalarBuffer::from(offsets)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tags::{TAG_DATETIME, TAG_RECORDID, TAG_UUID};
+    use crate::{BatchPlan, SurrealRecord};
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    #[test]
+    fn builders_match_serde_arrow() {
+        let tagged = |tag, value| Value::Tag(tag, Box::new(value));
+        let record = |n: i128, name: Option<&str>| {
+            Value::Map(vec![
+                (text("id"), tagged(TAG_RECORDID, Value::Array(vec![text("person"), Value::Integer(n)]))),
+                (text("n"), Value::Integer(n)),
+                (text("score"), Value::Float(n as f64 / 2.0)),
+                (text("name"), name.map_or(Value::Null, text)),
+                (text("tags"), Value::Array(vec![text("a"), text("b")])),
+                (text("at"), tagged(TAG_DATETIME, text("2024-01-01T00:00:00Z"))),
+                (text("uuid"), tagged(TAG_UUID, Value::Bytes(vec![n as u8; 16]))),
+                (text("nested"), Value::Map(vec![(text("ok"), Value::Bool(n % 2 == 0))])),
+            ])
+        };
+        let records = vec![record(1, Some("a")), record(2, None), record(3, Some("c"))];
+        let opts = ConvertOptions::default();
+        let plan = BatchPlan::infer(&records, &opts).unwrap();
+        let direct = build_columns(&plan.fields, &records, None, &[], None, false, &opts).unwrap();
+        let wrapped: Vec<SurrealRecord> = records
+            .iter()
+            .map(|value| SurrealRecord { value, opts: &opts, split_ids: &[], value_column: None })
+            .collect();
+        let traced = serde_arrow::to_arrow(&plan.fields, &wrapped).unwrap();
+        assert_eq!(direct, traced);
+    }
+}
@@ -0,0 +1,40 @@
+//! Inferred schemas remembered across calls under a caller's `schema_cache_key`.
+//!
+//! A query re-run on a timer returns records of the same shape each time, so the
+//! plan inferred for the first result is kept and checked against later results
+//! instead of tracing them again. Plans are kept per set of the options that
+//! shape them (`ConvertOptions::plan_fingerprint`), so a key reused with, say,
+//! another `type_conflicts` or `flatten` gets a plan of its own.
+
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex, PoisonError};
+
+use pyo3::prelude::*;
+
+use crate::BatchPlan;
+
+/// Plans by key and options fingerprint.
+static PLANS: LazyLock<Mutex<HashMap<(String, u64), BatchPlan>>> = LazyLock::new(Default::default);
+
+fn plans() -> std::sync::MutexGuard<'static, HashMap<(String, u64), BatchPlan>> {
+    // A plan is only ever replaced whole, so a panic elsewhere leaves the map intact.
+    PLANS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub(crate) fn get(key: &str, options: u64) -> Option<BatchPlan> {
+    plans().get(&(key.to_string(), options)).cloned()
+}
+
+pub(crate) fn insert(key: &str, options: u64, plan: BatchPlan) {
+    plans().insert((key.to_string(), options), plan);
+}
+
+/// Forget the schema cached under `key`, or every cached schema without one.
+#[pyfunction]
+#[pyo3(signature = (key=None))]
+pub(crate) fn clear_schema_cache(key: Option<&str>) {
+    match key {
+        Some(key) => plans().retain(|(cached, _), _| cached != key),
+        None => plans().clear(),
+    }
+}
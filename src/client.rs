@@ -0,0 +1,1658 @@
+//! `Connection`, a SurrealDB client built into the accelerator.
+//!
+//! Requests go over a WebSocket speaking CBOR, or are posted to the HTTP RPC
+//! endpoint (see `http`), and query responses are converted as `cbor_to_arrow`
+//! converts them, so the bytes of a result go from the socket to Arrow without
+//! surfacing in Python. Connections run on a tokio runtime shared by all of
+//! them, with tungstenite and reqwest: a task reads each WebSocket and hands
+//! each response to the request waiting for its id, so one connection serves
+//! requests from several threads at once. Methods block on the runtime with the
+//! GIL released, and the `*_async` methods await it from asyncio (see `aio`).
+//! Live query notifications are passed on to a thread, which calls the
+//! subscribers back.
+//!
+//! Requests can be tried again when the connection is lost, after a delay
+//! doubling each time: those that were not sent always, those that were only
+//! when running them twice does no harm. A lost WebSocket is opened again, and
+//! signed in and switched to the namespace and database again, first.
+//!
+//! `wss://` and `https://` connections are encrypted with rustls (see `tls`).
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use cbor4ii::core::dec::{self, Decode, IgnoredAny};
+use cbor4ii::core::types;
+use cbor4ii::core::utils::SliceReader;
+use cbor4ii::core::Value;
+use pyo3::exceptions::{PyConnectionError, PyTimeoutError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyTuple};
+use futures::stream::{SplitSink, SplitStream};
+use futures::{SinkExt, StreamExt};
+use regex::Regex;
+use rustls::ClientConfig;
+use tokio::sync::oneshot;
+use tokio::task::AbortHandle;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::aio::{block_on, future_into_py, runtime};
+use crate::errors::CborDecodeError;
+use crate::encode::tagged;
+use crate::http::{self, HttpSession};
+use crate::live::{notification_batch, parse_notification, uuid_string};
+use crate::pull::Payload;
+use crate::pyvalue::surreal_value;
+use crate::rpc::{credentials, next_id, request_with_id};
+use crate::tags::{TAG_UUID, TAG_UUID_STRING};
+use crate::tls;
+use crate::websocket::{self, Endpoint, Scheme, WebSocket};
+use crate::{
+    convert_statement, seconds, decode_root, map_get, response_error, root_responses, statement_records, ConvertOptions,
+};
+
+/// The most notifications kept for live queries not subscribed to yet, which
+/// can arrive before the response starting them.
+const MAX_UNCLAIMED_NOTIFICATIONS: usize = 1024;
+
+/// The longest delay between two tries of a request.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How long closing a WebSocket waits to send the close frame.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// SurrealQL statements that change data or the session, which make a query
+/// unsafe to run twice. Matches inside strings only cost a retry.
+static WRITES: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"(?i)\b(CREATE|UPDATE|UPSERT|DELETE|INSERT|RELATE|DEFINE|REMOVE|ALTER|REBUILD",
+        r"|LET|BEGIN|COMMIT|LIVE|KILL|USE)\b"
+    ))
+    .unwrap()
+});
+
+/// A connection to SurrealDB over a WebSocket, returning query results as Arrow.
+///
+/// ```python
+/// conn = Connection.connect("ws://localhost:8000")
+/// conn.signin(username="root", password="root")
+/// conn.use_ns_db("test", "test")
+/// table = conn.query("SELECT * FROM person WHERE age > $age", {"age": 30})
+/// ```
+///
+/// `http://` URLs post each request to the HTTP RPC endpoint instead, for where
+/// WebSockets are blocked; the namespace, database and token of the session
+/// are then sent along with every request. The path of the URL defaults to
+/// `/rpc`. Methods release the GIL while they wait, and a connection may be
+/// shared between threads. The `_async` variants of the methods are awaitable,
+/// and wait for the server without holding up the running asyncio loop.
+///
+/// `connect_timeout` and `timeout` bound in seconds the time connecting, and
+/// waiting for each response, may take: `TimeoutError` is raised past them.
+/// With `retries`, a request failing as the connection was lost is tried again
+/// up to that many times, `backoff` seconds later the first time and twice as
+/// long each time after. Only requests that were not sent, or that are safe to
+/// run twice, are: queries that do not change data (see `Connection.query`),
+/// sign in, `authenticate`, `use_ns_db` and `ping`. Live queries end with the
+/// connection.
+///
+/// `wss://` and `https://` URLs connect with TLS, trusting the CAs in the PEM
+/// file `ca_file` (or the system's) and presenting the client certificate in
+/// `cert_file`, with its key in `key_file` or in `cert_file`. A session signed in with
+/// `signin`, `signup` or `authenticate` is signed in again on a new WebSocket;
+/// `authenticate` also swaps in a refreshed token.
+#[pyclass(frozen)]
+pub(crate) struct Connection {
+    url: String,
+    policy: Policy,
+    transport: Transport,
+}
+
+/// How long a connection waits, and how often it tries again.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Policy {
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) retries: u32,
+    pub(crate) backoff: Duration,
+}
+
+/// A query to send, and how its response is converted.
+pub(crate) struct Query {
+    params: Vec<Value>,
+    idempotent: bool,
+    statement: isize,
+    opts: ConvertOptions,
+}
+
+enum Transport {
+    WebSocket(WebSocketLink),
+    /// `None` once closed.
+    Http(Mutex<Option<HttpSession>>),
+}
+
+/// A WebSocket connection, opened again when it was lost.
+struct WebSocketLink {
+    endpoint: Endpoint,
+    tls: Option<Arc<ClientConfig>>,
+    /// The socket in use, replaced when it was lost.
+    socket: Mutex<Arc<Shared>>,
+    /// Held while the socket is opened again, which one request does at a time.
+    reopening: tokio::sync::Mutex<()>,
+    /// The requests making up the session, sent again on a new socket.
+    session: Mutex<Session>,
+    /// Where live query notifications go, once a live query was started.
+    live: Arc<Mutex<Option<Sender<Dispatch>>>>,
+    /// Set by `close`, after which the connection is not opened again.
+    closed: AtomicBool,
+}
+
+#[derive(Default)]
+struct Session {
+    /// The method and parameters of the last sign in.
+    auth: Option<(&'static str, Vec<Value>)>,
+    /// The token of the session, if it was issued one.
+    token: Option<String>,
+    /// The parameters of the last `use`.
+    used: Option<Vec<Value>>,
+}
+
+/// What a socket and its reading task share.
+struct Shared {
+    writer: tokio::sync::Mutex<SplitSink<WebSocket, Message>>,
+    state: Mutex<State>,
+    live: Arc<Mutex<Option<Sender<Dispatch>>>>,
+}
+
+/// Why a request failed.
+struct Failure {
+    kind: FailureKind,
+    message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FailureKind {
+    /// The connection was closed with `close`.
+    Closed,
+    /// The request was not sent, as the connection was lost or could not be opened.
+    NotSent,
+    /// The connection was lost after the request was sent, which may have run.
+    Lost,
+    /// No response came in time.
+    TimedOut,
+    /// The request failed otherwise, and would fail again.
+    Failed,
+}
+
+struct State {
+    /// The requests waiting for their response, by id.
+    pending: HashMap<String, oneshot::Sender<Result<Vec<u8>, String>>>,
+    /// Why the connection can no longer be used, once it cannot.
+    closed: Option<String>,
+    /// The task reading the socket.
+    reading: Option<AbortHandle>,
+}
+
+#[pymethods]
+impl Connection {
+    /// Connect to the SurrealDB at `url`, a `ws://`, `wss://`, `http://` or `https://` URL.
+    #[staticmethod]
+    #[pyo3(signature = (
+        url, connect_timeout=None, timeout=None, retries=0, backoff=0.1, ca_file=None, cert_file=None,
+        key_file=None
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn connect(
+        py: Python,
+        url: &str,
+        connect_timeout: Option<f64>,
+        timeout: Option<f64>,
+        retries: u32,
+        backoff: f64,
+        ca_file: Option<&str>,
+        cert_file: Option<&str>,
+        key_file: Option<&str>,
+    ) -> PyResult<Connection> {
+        let policy = Policy::new(connect_timeout, timeout, retries, backoff)?;
+        Connection::open(py, url, policy, tls::options(ca_file, cert_file, key_file)?)
+    }
+
+    /// Sign in as a root, namespace or database user with `username` and
+    /// `password`, or through the record `access` method of `namespace` and
+    /// `database` with the variables `vars` (`scope` for the scopes of
+    /// SurrealDB 1). Returns the session token, if the server issued one.
+    #[pyo3(signature = (username=None, password=None, namespace=None, database=None, access=None, scope=None, **vars))]
+    #[allow(clippy::too_many_arguments)]
+    fn signin(
+        &self,
+        py: Python,
+        username: Option<&str>,
+        password: Option<&str>,
+        namespace: Option<&str>,
+        database: Option<&str>,
+        access: Option<&str>,
+        scope: Option<&str>,
+        vars: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<Option<String>> {
+        let params = signin_params([username, password, namespace, database, access, scope], vars)?;
+        block_on(py, self.sign_in(params))
+    }
+
+    /// Sign up a record user through the record `access` method (`scope` for
+    /// SurrealDB 1) of `namespace` and `database`, with the variables `vars`,
+    /// and sign in as it. Returns the session token.
+    #[pyo3(signature = (namespace=None, database=None, access=None, scope=None, **vars))]
+    fn signup(
+        &self,
+        py: Python,
+        namespace: Option<&str>,
+        database: Option<&str>,
+        access: Option<&str>,
+        scope: Option<&str>,
+        vars: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<Option<String>> {
+        let params = credentials([("NS", namespace), ("DB", database), ("AC", access), ("SC", scope)], vars)?;
+        block_on(py, self.sign_up(params))
+    }
+
+    /// Sign in with the token `token`, such as a refreshed one, in place of the
+    /// session's authentication.
+    fn authenticate(&self, py: Python, token: &str) -> PyResult<()> {
+        check_token(token)?;
+        block_on(py, self.authenticate_with(token.to_string()))
+    }
+
+    /// End the session's authentication.
+    fn invalidate(&self, py: Python) -> PyResult<()> {
+        if let Transport::WebSocket(_) = &self.transport {
+            rpc_result(&self.call(py, "invalidate", Vec::new(), true)?)?;
+        }
+        self.set_token(None, None);
+        Ok(())
+    }
+
+    /// The token of the session, once signed in with one.
+    #[getter]
+    fn token(&self, py: Python) -> Option<String> {
+        py.allow_threads(|| match &self.transport {
+            Transport::WebSocket(link) => link.session.lock().unwrap().token.clone(),
+            Transport::Http(session) => session.lock().unwrap().as_ref().and_then(|session| session.token.clone()),
+        })
+    }
+
+    /// Switch to the namespace `namespace` and database `database`.
+    fn use_ns_db(&self, py: Python, namespace: &str, database: &str) -> PyResult<()> {
+        block_on(py, self.use_database(namespace.to_string(), database.to_string()))
+    }
+
+    /// Run the SurrealQL `sql` with the variables `params`, encoded as
+    /// `encode_params` encodes them, and convert the result of statement
+    /// `statement` as `cbor_to_arrow` does, with the same keyword options.
+    ///
+    /// `idempotent` tells whether the query may run twice when the connection
+    /// is lost before its response came; by default, when `sql` names no
+    /// statement changing data or the session.
+    #[pyo3(signature = (sql, params=None, statement=0, idempotent=None, **options))]
+    fn query(
+        &self,
+        py: Python,
+        sql: &str,
+        params: Option<&Bound<'_, PyDict>>,
+        statement: isize,
+        idempotent: Option<bool>,
+        options: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<PyObject> {
+        let query = Query::new(sql, params, statement, idempotent, options)?;
+        block_on(py, query.run(self))
+    }
+
+    /// Start the live query `sql`, a `SELECT` statement that `LIVE` may be left
+    /// out of, and call `on_batch(action, batch)` with each of its
+    /// notifications, converted as `notification_to_arrow` converts them with
+    /// the keyword options. Returns the UUID of the live query.
+    ///
+    /// Callbacks run one at a time, in the order of the notifications, on a
+    /// thread of the connection; exceptions they raise are reported as
+    /// unraisable. Live queries need a WebSocket connection.
+    #[pyo3(signature = (sql, on_batch, params=None, **options))]
+    fn live(
+        &self,
+        py: Python,
+        sql: &str,
+        on_batch: PyObject,
+        params: Option<&Bound<'_, PyDict>>,
+        options: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<String> {
+        let (params, subscription) = self.live_query(py, sql, on_batch, params, options)?;
+        block_on(py, self.start_live(params, subscription))
+    }
+
+    /// Stop the live query `live_id`, and its callbacks.
+    fn kill(&self, py: Python, live_id: &str) -> PyResult<()> {
+        block_on(py, self.kill_query(live_uuid(live_id)?))
+    }
+
+    /// `Connection.connect`, awaitable.
+    #[staticmethod]
+    #[pyo3(signature = (
+        url, connect_timeout=None, timeout=None, retries=0, backoff=0.1, ca_file=None, cert_file=None,
+        key_file=None
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn connect_async<'py>(
+        py: Python<'py>,
+        url: String,
+        connect_timeout: Option<f64>,
+        timeout: Option<f64>,
+        retries: u32,
+        backoff: f64,
+        ca_file: Option<&str>,
+        cert_file: Option<&str>,
+        key_file: Option<&str>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let policy = Policy::new(connect_timeout, timeout, retries, backoff)?;
+        let tls = tls::options(ca_file, cert_file, key_file)?;
+        future_into_py(py, async move { Connection::connect_to(&url, policy, tls).await })
+    }
+
+    /// `signin`, awaitable.
+    #[pyo3(signature = (username=None, password=None, namespace=None, database=None, access=None, scope=None, **vars))]
+    #[allow(clippy::too_many_arguments)]
+    fn signin_async<'py>(
+        slf: &Bound<'py, Self>,
+        username: Option<&str>,
+        password: Option<&str>,
+        namespace: Option<&str>,
+        database: Option<&str>,
+        access: Option<&str>,
+        scope: Option<&str>,
+        vars: Option<&Bound<'py, PyDict>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let params = signin_params([username, password, namespace, database, access, scope], vars)?;
+        let conn = slf.clone().unbind();
+        future_into_py(slf.py(), async move { conn.get().sign_in(params).await })
+    }
+
+    /// `signup`, awaitable.
+    #[pyo3(signature = (namespace=None, database=None, access=None, scope=None, **vars))]
+    fn signup_async<'py>(
+        slf: &Bound<'py, Self>,
+        namespace: Option<&str>,
+        database: Option<&str>,
+        access: Option<&str>,
+        scope: Option<&str>,
+        vars: Option<&Bound<'py, PyDict>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let params = credentials([("NS", namespace), ("DB", database), ("AC", access), ("SC", scope)], vars)?;
+        let conn = slf.clone().unbind();
+        future_into_py(slf.py(), async move { conn.get().sign_up(params).await })
+    }
+
+    /// `authenticate`, awaitable.
+    fn authenticate_async<'py>(slf: &Bound<'py, Self>, token: String) -> PyResult<Bound<'py, PyAny>> {
+        check_token(&token)?;
+        let conn = slf.clone().unbind();
+        future_into_py(slf.py(), async move { conn.get().authenticate_with(token).await })
+    }
+
+    /// `use_ns_db`, awaitable.
+    fn use_ns_db_async<'py>(slf: &Bound<'py, Self>, namespace: String, database: String) -> PyResult<Bound<'py, PyAny>> {
+        let conn = slf.clone().unbind();
+        future_into_py(slf.py(), async move { conn.get().use_database(namespace, database).await })
+    }
+
+    /// `query`, awaitable: `table = await conn.query_async(sql, params)`.
+    #[pyo3(signature = (sql, params=None, statement=0, idempotent=None, **options))]
+    fn query_async<'py>(
+        slf: &Bound<'py, Self>,
+        sql: &str,
+        params: Option<&Bound<'py, PyDict>>,
+        statement: isize,
+        idempotent: Option<bool>,
+        options: Option<&Bound<'py, PyDict>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let query = Query::new(sql, params, statement, idempotent, options)?;
+        let conn = slf.clone().unbind();
+        future_into_py(slf.py(), async move { query.run(conn.get()).await })
+    }
+
+    /// `live`, awaitable. The callbacks are still called on a thread of the connection.
+    #[pyo3(signature = (sql, on_batch, params=None, **options))]
+    fn live_async<'py>(
+        slf: &Bound<'py, Self>,
+        sql: &str,
+        on_batch: PyObject,
+        params: Option<&Bound<'py, PyDict>>,
+        options: Option<&Bound<'py, PyDict>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let (params, subscription) = slf.get().live_query(slf.py(), sql, on_batch, params, options)?;
+        let conn = slf.clone().unbind();
+        future_into_py(slf.py(), async move { conn.get().start_live(params, subscription).await })
+    }
+
+    /// `kill`, awaitable.
+    fn kill_async<'py>(slf: &Bound<'py, Self>, live_id: &str) -> PyResult<Bound<'py, PyAny>> {
+        let uuid = live_uuid(live_id)?;
+        let conn = slf.clone().unbind();
+        future_into_py(slf.py(), async move { conn.get().kill_query(uuid).await })
+    }
+
+    /// Check that the server answers, raising `ConnectionError` if it does not.
+    fn ping(&self, py: Python) -> PyResult<()> {
+        block_on(py, self.ping_server())
+    }
+
+    /// Close the connection. Requests still waiting fail.
+    fn close(&self, py: Python) {
+        py.allow_threads(|| self.transport.close());
+    }
+
+    /// Whether the connection is closed, or was lost and is not to be opened again.
+    #[getter]
+    fn closed(&self, py: Python) -> bool {
+        py.allow_threads(|| self.is_closed())
+    }
+
+    #[getter]
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    #[pyo3(signature = (*_exc))]
+    fn __exit__(&self, py: Python, _exc: &Bound<'_, PyTuple>) {
+        self.close(py);
+    }
+
+    fn __repr__(&self, py: Python) -> String {
+        format!("Connection({:?}{})", self.url, if self.closed(py) { ", closed" } else { "" })
+    }
+}
+
+impl Connection {
+    /// Connect to `url` with `policy`, and with TLS set up by `tls` (by default
+    /// trusting the system's CAs) for secure URLs.
+    pub(crate) fn open(py: Python, url: &str, policy: Policy, tls: Option<Arc<ClientConfig>>) -> PyResult<Connection> {
+        block_on(py, Connection::connect_to(url, policy, tls))
+    }
+
+    /// `open`, awaitable.
+    pub(crate) async fn connect_to(url: &str, policy: Policy, tls: Option<Arc<ClientConfig>>) -> PyResult<Connection> {
+        let endpoint = Endpoint::parse(url).map_err(PyConnectionError::new_err)?;
+        let tls = match (endpoint.scheme.is_secure(), tls) {
+            (true, Some(tls)) => Some(tls),
+            (true, None) => Some(tls::config(None, None, None)?),
+            (false, None) => None,
+            (false, Some(_)) => return Err(PyValueError::new_err("TLS options need a wss:// or https:// URL")),
+        };
+        let cannot_connect = |e: std::io::Error| match e.kind() {
+            std::io::ErrorKind::TimedOut => PyTimeoutError::new_err(format!("cannot connect to {}: {}", url, e)),
+            _ => PyConnectionError::new_err(format!("cannot connect to {}: {}", url, e)),
+        };
+        let transport = if matches!(endpoint.scheme, Scheme::Http | Scheme::Https) {
+            let session = HttpSession::connect(&endpoint, policy.connect_timeout, policy.timeout, tls)
+                .await
+                .map_err(cannot_connect)?;
+            Transport::Http(Mutex::new(Some(session)))
+        } else {
+            let live = Arc::new(Mutex::new(None));
+            let socket = Shared::open(&endpoint, &policy, tls.as_ref(), &live).await.map_err(cannot_connect)?;
+            Transport::WebSocket(WebSocketLink {
+                endpoint,
+                tls,
+                socket: Mutex::new(socket),
+                reopening: tokio::sync::Mutex::new(()),
+                session: Mutex::new(Session::default()),
+                live,
+                closed: AtomicBool::new(false),
+            })
+        };
+        Ok(Connection { url: url.to_string(), policy, transport })
+    }
+
+    /// Send a request and wait for its response, trying again as the policy
+    /// allows when the connection was lost; only before the request was sent
+    /// unless it is `idempotent`.
+    fn call(&self, py: Python, method: &str, params: Vec<Value>, idempotent: bool) -> PyResult<Vec<u8>> {
+        block_on(py, self.call_async(method, params, idempotent))
+    }
+
+    /// `call`, awaitable.
+    async fn call_async(&self, method: &str, params: Vec<Value>, idempotent: bool) -> PyResult<Vec<u8>> {
+        let id = next_id();
+        let frame = request_with_id(Value::Text(id.clone()), method, params);
+        self.request(&id, &frame, idempotent).await.map_err(PyErr::from)
+    }
+
+    /// `call`, with the request encoded as `frame` with id `id`.
+    async fn request(&self, id: &str, frame: &[u8], idempotent: bool) -> Result<Vec<u8>, Failure> {
+        let mut attempt = 0;
+        loop {
+            let failure = match self.send(id, frame).await {
+                Ok(response) => return Ok(response),
+                Err(failure) => failure,
+            };
+            let retry = match failure.kind {
+                FailureKind::NotSent => true,
+                FailureKind::Lost => idempotent,
+                FailureKind::Closed | FailureKind::TimedOut | FailureKind::Failed => false,
+            };
+            if !retry || attempt >= self.policy.retries {
+                return Err(failure);
+            }
+            tokio::time::sleep(self.policy.delay(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    /// Send a request once.
+    async fn send(&self, id: &str, frame: &[u8]) -> Result<Vec<u8>, Failure> {
+        let session = match &self.transport {
+            Transport::WebSocket(link) => {
+                return link.socket(&self.policy).await?.request(id, frame, self.policy.timeout).await;
+            }
+            Transport::Http(session) => session,
+        };
+        // The request is built with the session locked, and sent once it is not.
+        let request = match session.lock().unwrap().as_ref() {
+            Some(session) => session.request(frame.to_vec()),
+            None => return Err(Failure::closed()),
+        };
+        let response = match request {
+            Ok(request) => http::send(request).await,
+            Err(e) => Err(e),
+        };
+        response.map_err(|e| {
+            let kind = match e.kind() {
+                std::io::ErrorKind::TimedOut => FailureKind::TimedOut,
+                std::io::ErrorKind::ConnectionRefused => FailureKind::NotSent,
+                std::io::ErrorKind::InvalidInput | std::io::ErrorKind::Other => FailureKind::Failed,
+                _ => FailureKind::Lost,
+            };
+            Failure { kind, message: format!("the request failed: {}", e) }
+        })
+    }
+
+    /// Update the session sent again on a new WebSocket.
+    fn remember(&self, update: impl FnOnce(&mut Session)) {
+        if let Transport::WebSocket(link) = &self.transport {
+            update(&mut link.session.lock().unwrap());
+        }
+    }
+
+    /// Keep the token the session is signed in with, and the request signing
+    /// in again on a new WebSocket.
+    fn set_token(&self, token: Option<String>, auth: Option<(&'static str, Vec<Value>)>) {
+        match &self.transport {
+            Transport::WebSocket(link) => {
+                let mut session = link.session.lock().unwrap();
+                session.token = token;
+                session.auth = auth;
+            }
+            Transport::Http(session) => {
+                if let Some(session) = session.lock().unwrap().as_mut() {
+                    session.token = token;
+                }
+            }
+        }
+    }
+
+    /// `signin` with the parameters `params`.
+    pub(crate) async fn sign_in(&self, params: Value) -> PyResult<Option<String>> {
+        let token = session_token(rpc_result(&self.call_async("signin", vec![params.clone()], true).await?)?);
+        self.set_token(token.clone(), Some(("signin", vec![params])));
+        Ok(token)
+    }
+
+    /// `signup` with the parameters `params`.
+    async fn sign_up(&self, params: Value) -> PyResult<Option<String>> {
+        // Signing up twice would fail, so the session is set up again with the token.
+        let token = session_token(rpc_result(&self.call_async("signup", vec![params], false).await?)?);
+        let again = token.clone().map(|token| ("authenticate", vec![Value::Text(token)]));
+        self.set_token(token.clone(), again);
+        Ok(token)
+    }
+
+    async fn authenticate_with(&self, token: String) -> PyResult<()> {
+        let params = vec![Value::Text(token.clone())];
+        rpc_result(&self.call_async("authenticate", params.clone(), true).await?)?;
+        self.set_token(Some(token), Some(("authenticate", params)));
+        Ok(())
+    }
+
+    pub(crate) async fn use_database(&self, namespace: String, database: String) -> PyResult<()> {
+        if let Transport::Http(session) = &self.transport {
+            if [&namespace, &database].iter().any(|name| name.contains(['\r', '\n'])) {
+                return Err(PyValueError::new_err("namespace and database names cannot hold line breaks"));
+            }
+            let mut session = session.lock().unwrap();
+            let session = session.as_mut().ok_or_else(|| PyConnectionError::new_err("the connection is closed"))?;
+            session.namespace = Some(namespace);
+            session.database = Some(database);
+            return Ok(());
+        }
+        let params = vec![Value::Text(namespace), Value::Text(database)];
+        rpc_result(&self.call_async("use", params.clone(), true).await?)?;
+        self.remember(|session| session.used = Some(params));
+        Ok(())
+    }
+
+    /// The parameters of the live query `live` starts, and its subscription.
+    fn live_query(
+        &self,
+        py: Python,
+        sql: &str,
+        on_batch: PyObject,
+        params: Option<&Bound<'_, PyDict>>,
+        options: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<(Vec<Value>, Subscription)> {
+        let Transport::WebSocket(_) = &self.transport else {
+            return Err(PyConnectionError::new_err("live queries need a WebSocket connection"));
+        };
+        if !on_batch.bind(py).is_callable() {
+            return Err(PyTypeError::new_err("on_batch must be callable"));
+        }
+        let opts = ConvertOptions::from_kwargs(options)?;
+        let sql = match sql.split_whitespace().next() {
+            Some(word) if word.eq_ignore_ascii_case("LIVE") => sql.to_string(),
+            _ => format!("LIVE {}", sql),
+        };
+        Ok((vec![Value::Text(sql), query_vars(params)?], Subscription { callback: on_batch, opts }))
+    }
+
+    /// Start the live query of `params`, calling back `subscription` with its notifications.
+    async fn start_live(&self, params: Vec<Value>, subscription: Subscription) -> PyResult<String> {
+        let Transport::WebSocket(link) = &self.transport else {
+            return Err(PyConnectionError::new_err("live queries need a WebSocket connection"));
+        };
+        let dispatch = link.live_dispatch()?;
+        let response = self.call_async("query", params, false).await?;
+        let root = decode_root(&response)?;
+        let started = root_responses(&root)?
+            .first()
+            .ok_or_else(|| CborDecodeError::new_err("the live query returned no statement"))?;
+        statement_records(started, 0)?;
+        let id = match started {
+            Value::Map(map) => map_get(map, "result").and_then(uuid_string),
+            _ => None,
+        };
+        let id = id.ok_or_else(|| CborDecodeError::new_err("the live query returned no UUID"))?;
+        // Sent after the notifications received so far, which are kept for it.
+        let _ = dispatch.send(Dispatch::Subscribe(id.clone(), Box::new(subscription)));
+        Ok(id)
+    }
+
+    async fn kill_query(&self, uuid: [u8; 16]) -> PyResult<()> {
+        let params = vec![tagged(TAG_UUID, Value::Bytes(uuid.to_vec()))];
+        rpc_result(&self.call_async("kill", params, false).await?)?;
+        if let Transport::WebSocket(link) = &self.transport {
+            if let Some(dispatch) = &*link.live.lock().unwrap() {
+                let _ = dispatch.send(Dispatch::Unsubscribe(crate::tags::format_uuid(&uuid)));
+            }
+        }
+        Ok(())
+    }
+
+    /// The undecoded response of the SurrealQL `sql`, which may run twice when
+    /// it changes no data.
+    #[cfg(feature = "flight")]
+    pub(crate) async fn query_response(&self, sql: &str) -> PyResult<Vec<u8>> {
+        let params = vec![Value::Text(sql.to_string()), Value::Map(Vec::new())];
+        self.call_async("query", params, !WRITES.is_match(sql)).await
+    }
+
+    /// `ping`, awaitable.
+    pub(crate) async fn ping_server(&self) -> PyResult<()> {
+        rpc_result(&self.call_async("ping", Vec::new(), true).await?).map(drop)
+    }
+
+    /// `closed`, without the GIL.
+    pub(crate) fn is_closed(&self) -> bool {
+        match &self.transport {
+            Transport::WebSocket(link) => {
+                link.closed.load(Ordering::Acquire) || (self.policy.retries == 0 && !link.socket.lock().unwrap().is_open())
+            }
+            Transport::Http(session) => session.lock().unwrap().is_none(),
+        }
+    }
+
+    /// `close`, without the GIL.
+    pub(crate) fn shut(&self) {
+        self.transport.close();
+    }
+}
+
+impl Policy {
+    pub(crate) fn new(connect_timeout: Option<f64>, timeout: Option<f64>, retries: u32, backoff: f64) -> PyResult<Policy> {
+        Ok(Policy {
+            connect_timeout: connect_timeout.map(|t| seconds("connect_timeout", t)).transpose()?,
+            timeout: timeout.map(|t| seconds("timeout", t)).transpose()?,
+            retries,
+            backoff: seconds("backoff", backoff)?,
+        })
+    }
+
+    /// How long to wait before try `attempt + 1` of a request.
+    fn delay(&self, attempt: u32) -> Duration {
+        self.backoff.saturating_mul(1 << attempt.min(16)).min(MAX_BACKOFF)
+    }
+}
+
+impl Query {
+    /// The query `sql` with the arguments of `Connection.query`.
+    pub(crate) fn new(
+        sql: &str,
+        params: Option<&Bound<'_, PyDict>>,
+        statement: isize,
+        idempotent: Option<bool>,
+        options: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<Query> {
+        Ok(Query {
+            params: vec![Value::Text(sql.to_string()), query_vars(params)?],
+            idempotent: idempotent.unwrap_or_else(|| !WRITES.is_match(sql)),
+            statement,
+            opts: ConvertOptions::from_kwargs(options)?,
+        })
+    }
+
+    /// Send the query on `conn` and convert its response, taking the GIL.
+    pub(crate) async fn run(self, conn: &Connection) -> PyResult<PyObject> {
+        let Query { params, idempotent, statement, opts } = self;
+        let response = conn.call_async("query", params, idempotent).await?;
+        Python::with_gil(|py| {
+            opts.check_len(response.len())?;
+            let payload = Arc::new(Payload::load_owned(py, response, true, &opts)?);
+            convert_statement(py, &payload, statement, &opts)
+        })
+    }
+}
+
+impl Failure {
+    fn closed() -> Failure {
+        Failure { kind: FailureKind::Closed, message: "the connection is closed".to_string() }
+    }
+}
+
+impl From<Failure> for PyErr {
+    fn from(failure: Failure) -> PyErr {
+        match failure.kind {
+            FailureKind::TimedOut => PyTimeoutError::new_err(failure.message),
+            _ => PyConnectionError::new_err(failure.message),
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        Python::with_gil(|py| py.allow_threads(|| self.transport.close()));
+    }
+}
+
+impl Transport {
+    fn close(&self) {
+        match self {
+            Transport::WebSocket(link) => {
+                link.closed.store(true, Ordering::Release);
+                Shared::close(&link.socket.lock().unwrap());
+                // Ends the dispatching thread once it called back the notifications left.
+                link.live.lock().unwrap().take();
+            }
+            Transport::Http(session) => drop(session.lock().unwrap().take()),
+        }
+    }
+}
+
+impl WebSocketLink {
+    /// The socket to send requests on. One that was lost is opened again, and
+    /// the session set up again on it, when requests may be tried again.
+    async fn socket(&self, policy: &Policy) -> Result<Arc<Shared>, Failure> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(Failure::closed());
+        }
+        let current = || self.socket.lock().unwrap().clone();
+        let socket = current();
+        if policy.retries == 0 || socket.is_open() {
+            return Ok(socket);
+        }
+        let _reopening = self.reopening.lock().await;
+        // Another request may have opened it again meanwhile.
+        let socket = current();
+        if socket.is_open() {
+            return Ok(socket);
+        }
+        let not_sent = |message| Failure { kind: FailureKind::NotSent, message };
+        let reopened = Shared::open(&self.endpoint, policy, self.tls.as_ref(), &self.live)
+            .await
+            .map_err(|e| not_sent(format!("cannot connect again: {}", e)))?;
+        let setup: Vec<(&str, Vec<Value>)> = {
+            let session = self.session.lock().unwrap();
+            session.auth.iter().cloned().chain(session.used.iter().map(|params| ("use", params.clone()))).collect()
+        };
+        for (method, params) in setup {
+            let id = next_id();
+            let frame = request_with_id(Value::Text(id.clone()), method, params);
+            let refused = match reopened.request(&id, &frame, policy.timeout).await {
+                Ok(response) => match decode_root(&response) {
+                    Ok(Value::Map(map)) if map_get(&map, "error").is_none() => {
+                        // Signing in again issues a new token.
+                        if let (true, Some(Value::Text(token))) = (method == "signin", map_get(&map, "result")) {
+                            self.session.lock().unwrap().token = Some(token.clone());
+                        }
+                        false
+                    }
+                    _ => true,
+                },
+                Err(failure) => return Err(not_sent(failure.message)),
+            };
+            if refused {
+                Shared::close(&reopened);
+                let message = format!("{} failed on the new connection", method);
+                return Err(Failure { kind: FailureKind::Failed, message });
+            }
+        }
+        if self.closed.load(Ordering::Acquire) {
+            Shared::close(&reopened);
+            return Err(Failure::closed());
+        }
+        *self.socket.lock().unwrap() = reopened.clone();
+        Ok(reopened)
+    }
+
+    /// The channel of the thread calling back live query subscribers, started
+    /// on the first use.
+    fn live_dispatch(&self) -> PyResult<Sender<Dispatch>> {
+        let mut live = self.live.lock().unwrap();
+        if let Some(dispatch) = &*live {
+            return Ok(dispatch.clone());
+        }
+        if self.closed.load(Ordering::Acquire) {
+            return Err(PyConnectionError::new_err("the connection is closed"));
+        }
+        let (tx, rx) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("surreal-live".to_string())
+            .spawn(move || dispatch_live(rx))
+            .map_err(|e| PyConnectionError::new_err(format!("cannot start the live query thread: {}", e)))?;
+        *live = Some(tx.clone());
+        Ok(tx)
+    }
+}
+
+impl Shared {
+    /// Open a WebSocket to `endpoint` and start the task reading it.
+    async fn open(
+        endpoint: &Endpoint,
+        policy: &Policy,
+        tls: Option<&Arc<ClientConfig>>,
+        live: &Arc<Mutex<Option<Sender<Dispatch>>>>,
+    ) -> std::io::Result<Arc<Shared>> {
+        let opening = websocket::open(endpoint, "cbor", tls);
+        let socket = match policy.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, opening)
+                .await
+                .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "connection timed out"))??,
+            None => opening.await?,
+        };
+        let (writer, reader) = socket.split();
+        let shared = Arc::new(Shared {
+            writer: tokio::sync::Mutex::new(writer),
+            state: Mutex::new(State { pending: HashMap::new(), closed: None, reading: None }),
+            live: live.clone(),
+        });
+        let reading = tokio::spawn(shared.clone().read_loop(reader));
+        shared.state.lock().unwrap().reading = Some(reading.abort_handle());
+        Ok(shared)
+    }
+
+    fn is_open(&self) -> bool {
+        self.state.lock().unwrap().closed.is_none()
+    }
+
+    /// Send the request `frame` with id `id` and wait up to `timeout` for its response.
+    async fn request(&self, id: &str, frame: &[u8], timeout: Option<Duration>) -> Result<Vec<u8>, Failure> {
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(reason) = &state.closed {
+                return Err(Failure { kind: FailureKind::NotSent, message: reason.clone() });
+            }
+            state.pending.insert(id.to_string(), tx);
+        }
+        let exchange = async {
+            let sent = self.writer.lock().await.send(Message::binary(frame.to_vec())).await;
+            if let Err(e) = sent {
+                let message = format!("cannot send the request: {}", websocket::websocket_error(e));
+                return Err(Failure { kind: FailureKind::NotSent, message });
+            }
+            match rx.await {
+                Ok(response) => response.map_err(|message| Failure { kind: FailureKind::Lost, message }),
+                Err(_) => Err(Failure::closed()),
+            }
+        };
+        let received = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, exchange).await.unwrap_or_else(|_| {
+                Err(Failure {
+                    kind: FailureKind::TimedOut,
+                    message: format!("no response within {} s", timeout.as_secs_f64()),
+                })
+            }),
+            None => exchange.await,
+        };
+        if received.is_err() {
+            self.state.lock().unwrap().pending.remove(id);
+        }
+        received
+    }
+
+    /// Read responses until the connection closes, handing each to the request
+    /// waiting for it. Responses without a waiting request are dropped.
+    async fn read_loop(self: Arc<Self>, mut reader: SplitStream<WebSocket>) {
+        let reason = loop {
+            let frame = match reader.next().await {
+                Some(Ok(Message::Binary(frame))) => Vec::from(frame),
+                Some(Ok(Message::Text(text))) => text.as_bytes().to_vec(),
+                Some(Ok(Message::Close(_))) | None => break "the server closed the connection".to_string(),
+                // tungstenite answers pings itself.
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => break format!("the connection was lost: {}", websocket::websocket_error(e)),
+            };
+            let Some(id) = response_id(&frame) else {
+                if let Some(dispatch) = &*self.live.lock().unwrap() {
+                    let _ = dispatch.send(Dispatch::Notification(frame));
+                }
+                continue;
+            };
+            if let Some(tx) = self.state.lock().unwrap().pending.remove(&id) {
+                let _ = tx.send(Ok(frame));
+            }
+        };
+        self.fail(reason);
+    }
+
+    /// Fail the requests waiting for a response, as the connection can no
+    /// longer be used for `reason`.
+    fn fail(&self, reason: String) {
+        let mut state = self.state.lock().unwrap();
+        let reason = state.closed.get_or_insert(reason).clone();
+        for (_, tx) in state.pending.drain() {
+            let _ = tx.send(Err(reason.clone()));
+        }
+    }
+
+    /// Close the socket, sending the close frame on the runtime.
+    fn close(shared: &Arc<Shared>) {
+        let reading = {
+            let mut state = shared.state.lock().unwrap();
+            if state.closed.is_some() {
+                return;
+            }
+            state.closed = Some("the connection is closed".to_string());
+            state.reading.take()
+        };
+        shared.fail("the connection is closed".to_string());
+        if let Some(reading) = reading {
+            reading.abort();
+        }
+        let closing = shared.clone();
+        runtime().spawn(async move {
+            let _ = tokio::time::timeout(CLOSE_TIMEOUT, async { closing.writer.lock().await.close().await }).await;
+        });
+    }
+}
+
+/// What the thread calling back live query subscribers is sent.
+enum Dispatch {
+    Subscribe(String, Box<Subscription>),
+    Unsubscribe(String),
+    Notification(Vec<u8>),
+}
+
+struct Subscription {
+    callback: PyObject,
+    opts: ConvertOptions,
+}
+
+/// Call back the subscribers of each notification received, keeping those of
+/// live queries not subscribed to yet for when they are.
+fn dispatch_live(rx: Receiver<Dispatch>) {
+    let mut subscriptions: HashMap<String, Subscription> = HashMap::new();
+    let mut unclaimed: VecDeque<(String, Value)> = VecDeque::new();
+    for message in rx {
+        match message {
+            Dispatch::Subscribe(id, subscription) => {
+                let (claimed, others) = unclaimed.drain(..).partition(|(of, _)| *of == id);
+                unclaimed = others;
+                subscriptions.insert(id.clone(), *subscription);
+                for (_, root) in Vec::from(claimed) {
+                    notify(&mut subscriptions, &id, &root);
+                }
+            }
+            Dispatch::Unsubscribe(id) => drop(subscriptions.remove(&id)),
+            Dispatch::Notification(frame) => {
+                let Ok(root) = decode_root(&frame) else { continue };
+                let Some(id) = parse_notification(&root).ok().map(|notification| notification.id) else { continue };
+                if subscriptions.contains_key(&id) {
+                    notify(&mut subscriptions, &id, &root);
+                } else {
+                    if unclaimed.len() == MAX_UNCLAIMED_NOTIFICATIONS {
+                        unclaimed.pop_front();
+                    }
+                    unclaimed.push_back((id, root));
+                }
+            }
+        }
+    }
+}
+
+/// Call back the subscriber of live query `id` with the notification `root`,
+/// dropping the subscription once the query ended.
+fn notify(subscriptions: &mut HashMap<String, Subscription>, id: &str, root: &Value) {
+    let (Some(subscription), Ok(notification)) = (subscriptions.get(id), parse_notification(root)) else { return };
+    let ended = Python::with_gil(|py| {
+        let called = notification_batch(py, &notification, &subscription.opts)
+            .and_then(|batch| subscription.callback.call1(py, (notification.action.as_str(), batch)));
+        if let Err(e) = called {
+            e.write_unraisable(py, Some(subscription.callback.bind(py)));
+        }
+        matches!(notification.action.as_str(), "KILLED" | "CLOSE")
+    });
+    if ended {
+        subscriptions.remove(id);
+    }
+}
+
+/// The token a sign in returned, if any.
+fn session_token(result: Value) -> Option<String> {
+    match result {
+        Value::Text(token) => Some(token),
+        _ => None,
+    }
+}
+
+/// The variables of a query, as `encode_params` encodes them.
+fn query_vars(params: Option<&Bound<'_, PyDict>>) -> PyResult<Value> {
+    match params {
+        Some(params) => surreal_value(params, true),
+        None => Ok(Value::Map(Vec::new())),
+    }
+}
+
+/// The parameters of `signin` with `username`, `password`, `namespace`,
+/// `database`, `access` and `scope`, and the variables `vars`.
+fn signin_params(given: [Option<&str>; 6], vars: Option<&Bound<'_, PyDict>>) -> PyResult<Value> {
+    let [user, pass, ns, db, ac, sc] = given;
+    credentials([("user", user), ("pass", pass), ("NS", ns), ("DB", db), ("AC", ac), ("SC", sc)], vars)
+}
+
+/// The parameters of `signin` called with the keyword arguments `kwargs`.
+pub(crate) fn signin_kwargs(kwargs: &Bound<'_, PyDict>) -> PyResult<Value> {
+    let vars = kwargs.copy()?;
+    let mut given = [None, None, None, None, None, None];
+    for (name, value) in ["username", "password", "namespace", "database", "access", "scope"].iter().zip(&mut given) {
+        if let Some(item) = vars.get_item(name)? {
+            *value = item.extract::<Option<String>>()?;
+            vars.del_item(name)?;
+        }
+    }
+    signin_params(given.each_ref().map(Option::as_deref), Some(&vars))
+}
+
+fn check_token(token: &str) -> PyResult<()> {
+    match token.contains(['\r', '\n']) {
+        true => Err(PyValueError::new_err("tokens cannot hold line breaks")),
+        false => Ok(()),
+    }
+}
+
+/// The UUID of the live query `live_id`.
+fn live_uuid(live_id: &str) -> PyResult<[u8; 16]> {
+    crate::tags::uuid_bytes(TAG_UUID_STRING, &Value::Text(live_id.to_string()))
+        .ok_or_else(|| PyValueError::new_err(format!("{:?} is not a UUID", live_id)))
+}
+
+/// The id of a response, read without decoding the rest of it.
+fn response_id(frame: &[u8]) -> Option<String> {
+    let mut reader = SliceReader::new(frame);
+    let len = types::Map::len(&mut reader).ok()?;
+    let mut entry = 0;
+    while len.map_or_else(|| !dec::is_break(&mut reader).unwrap_or(true), |len| entry < len) {
+        entry += 1;
+        let is_id = matches!(Value::decode(&mut reader).ok()?, Value::Text(key) if key == "id");
+        if !is_id {
+            IgnoredAny::decode(&mut reader).ok()?;
+            continue;
+        }
+        return match Value::decode(&mut reader).ok()? {
+            Value::Text(id) => Some(id),
+            Value::Integer(id) => Some(id.to_string()),
+            _ => None,
+        };
+    }
+    None
+}
+
+/// The `result` of a response, raising the error of a failed one.
+fn rpc_result(frame: &[u8]) -> PyResult<Value> {
+    let root = decode_root(frame)?;
+    if let Some(err) = response_error(&root) {
+        return Err(err);
+    }
+    match &root {
+        Value::Map(entries) => Ok(map_get(entries, "result").cloned().unwrap_or(Value::Null)),
+        _ => Err(CborDecodeError::new_err("CBOR Root is not a Map")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    use arrow::array::AsArray;
+    use arrow::datatypes::Int64Type;
+    use pyo3::exceptions::{PyConnectionError, PyIndexError};
+    use pyo3::ffi::c_str;
+    use pyo3::types::{IntoPyDict, PyList};
+    use pyo3_arrow::PyRecordBatch;
+
+    use super::*;
+    use crate::encode::encode;
+    use crate::pool::ConnectionPool;
+
+    /// The methods of the requests each WebSocket served got, in order.
+    type Log = Arc<Mutex<Vec<Vec<String>>>>;
+
+    fn text(value: &str) -> Value {
+        Value::Text(value.to_string())
+    }
+
+    /// The result of a query: one statement selecting `n` from 0 to 2.
+    fn statements() -> Value {
+        let records = (0..3).map(|n| Value::Map(vec![(text("n"), Value::Integer(n))])).collect();
+        let result = Value::Array(records);
+        let statement = vec![(text("status"), text("OK")), (text("time"), text("1ms")), (text("result"), result)];
+        Value::Array(vec![Value::Map(statement)])
+    }
+
+    /// The live query the test server starts.
+    const LIVE_ID: [u8; 16] = [7; 16];
+
+    fn uuid(bytes: [u8; 16]) -> Value {
+        tagged(TAG_UUID, Value::Bytes(bytes.to_vec()))
+    }
+
+    /// A notification of the live query `live`, of a record with `n` if any.
+    fn notification(live: [u8; 16], action: &str, n: Option<i128>) -> Message {
+        let result = n.map_or(Value::Null, |n| Value::Map(vec![(text("n"), Value::Integer(n))]));
+        let body = vec![(text("id"), uuid(live)), (text("action"), text(action)), (text("result"), result)];
+        Message::binary(encode(&Value::Map(vec![(text("result"), Value::Map(body))])))
+    }
+
+    /// The `n` column of a batch converted with `output='batch'`.
+    fn numbers(batch: &Bound<PyAny>) -> Vec<i64> {
+        let batch = batch.extract::<PyRecordBatch>().unwrap().into_inner();
+        batch.column_by_name("n").unwrap().as_primitive::<Int64Type>().values().to_vec()
+    }
+
+    /// The response to the request `id`, with `value` under `key`.
+    fn reply(id: Value, key: &str, value: Value) -> Message {
+        Message::binary(encode(&Value::Map(vec![(text("id"), id), (text(key), value)])))
+    }
+
+    /// A SurrealDB server on the WebSocket of a port. `sleep` answers after its
+    /// parameter's milliseconds, `hang` never, `drop` closes the socket,
+    /// `signin` gives out a token naming the socket, and `query` returns
+    /// `statements`, or starts the live query `LIVE_ID` and notifies it; the
+    /// first socket is closed by queries naming `lost`, and those naming `hang`
+    /// are not answered.
+    fn serve() -> (String, Log) {
+        let listener = runtime().block_on(tokio::net::TcpListener::bind("127.0.0.1:0")).unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let log = Log::default();
+        let sockets = log.clone();
+        runtime().spawn(async move {
+            while let Ok((tcp, _)) = listener.accept().await {
+                tokio::spawn(answer(tcp, sockets.clone()));
+            }
+        });
+        (url, log)
+    }
+
+    async fn answer(tcp: tokio::net::TcpStream, log: Log) {
+        let socket = tokio_tungstenite::accept_hdr_async(tcp, websocket::accept_cbor).await.unwrap();
+        let (writer, mut reader) = socket.split();
+        let writer = Arc::new(tokio::sync::Mutex::new(writer));
+        let socket = {
+            let mut log = log.lock().unwrap();
+            log.push(Vec::new());
+            log.len() - 1
+        };
+        while let Some(Ok(Message::Binary(frame))) = reader.next().await {
+            let Ok(Value::Map(request)) = decode_root(&frame) else { return };
+            let id = map_get(&request, "id").cloned().unwrap_or(Value::Null);
+            let Some(Value::Text(method)) = map_get(&request, "method") else { return };
+            log.lock().unwrap()[socket].push(method.clone());
+            let response = match method.as_str() {
+                "drop" => return,
+                "hang" => continue,
+                "sleep" => {
+                    let Some(Value::Array(params)) = map_get(&request, "params") else { return };
+                    let Some(Value::Integer(ms)) = params.first() else { return };
+                    let (ms, writer) = (*ms as u64, writer.clone());
+                    tokio::spawn(async move {
+                        tokio::time::sleep(Duration::from_millis(ms)).await;
+                        let _ = writer.lock().await.send(reply(id, "result", Value::Integer(ms as _))).await;
+                    });
+                    continue;
+                }
+                "signin" => reply(id, "result", text(&format!("token-{}", socket))),
+                "query" => {
+                    let Some(Value::Array(params)) = map_get(&request, "params") else { return };
+                    let Some(Value::Text(sql)) = params.first() else { return };
+                    if sql.contains("lost") && socket == 0 {
+                        return;
+                    }
+                    if sql.contains("hang") {
+                        continue;
+                    }
+                    if !sql.starts_with("LIVE ") {
+                        reply(id, "result", statements())
+                    } else {
+                        // A notification before the response starting the live query, and one of another.
+                        let started = vec![(text("status"), text("OK")), (text("result"), uuid(LIVE_ID))];
+                        let mut writer = writer.lock().await;
+                        let _ = writer.send(notification(LIVE_ID, "CREATE", Some(1))).await;
+                        let _ = writer.send(reply(id, "result", Value::Array(vec![Value::Map(started)]))).await;
+                        let _ = writer.send(notification([8; 16], "CREATE", Some(3))).await;
+                        let _ = writer.send(notification(LIVE_ID, "UPDATE", Some(2))).await;
+                        let _ = writer.send(notification(LIVE_ID, "KILLED", None)).await;
+                        continue;
+                    }
+                }
+                "fail" => {
+                    let error = vec![(text("code"), Value::Integer(-32000)), (text("message"), text("refused"))];
+                    reply(id, "error", Value::Map(error))
+                }
+                _ => reply(id, "result", Value::Null),
+            };
+            let _ = writer.lock().await.send(response).await;
+        }
+    }
+
+    fn connect(py: Python, url: &str, timeout: Option<f64>, retries: u32) -> PyResult<Connection> {
+        let policy = Policy {
+            connect_timeout: Some(Duration::from_secs(5)),
+            timeout: timeout.map(Duration::from_secs_f64),
+            retries,
+            backoff: Duration::from_millis(10),
+        };
+        Connection::open(py, url, policy, None)
+    }
+
+    #[test]
+    fn responses_go_to_the_request_of_their_id() {
+        pyo3::prepare_freethreaded_python();
+        let (url, _) = serve();
+        let conn = Python::with_gil(|py| connect(py, &url, Some(5.0), 0)).unwrap();
+        // Answered in the reverse order of the requests.
+        let slept: Vec<_> = std::thread::scope(|scope| {
+            let requests: Vec<_> = [300, 200, 100]
+                .map(|ms| {
+                    let conn = &conn;
+                    scope.spawn(move || {
+                        let params = vec![Value::Integer(ms)];
+                        Python::with_gil(|py| rpc_result(&conn.call(py, "sleep", params, true).unwrap()))
+                    })
+                })
+                .into();
+            requests.into_iter().map(|request| request.join().unwrap().unwrap()).collect()
+        });
+        assert_eq!(slept, [Value::Integer(300), Value::Integer(200), Value::Integer(100)]);
+        Python::with_gil(|py| {
+            let err = rpc_result(&conn.call(py, "fail", Vec::new(), true).unwrap()).unwrap_err();
+            assert!(err.to_string().contains("SurrealDB Error (Integer(-32000)): refused"), "{}", err);
+        });
+    }
+
+    #[test]
+    fn lost_sockets_are_opened_again_with_the_session() {
+        pyo3::prepare_freethreaded_python();
+        let (url, log) = serve();
+        Python::with_gil(|py| {
+            let conn = connect(py, &url, Some(5.0), 2).unwrap();
+            let token = conn.signin(py, Some("root"), Some("root"), None, None, None, None, None).unwrap();
+            assert_eq!(token.as_deref(), Some("token-0"));
+            conn.use_ns_db(py, "test", "test").unwrap();
+            // Sent before the socket was lost, so it may have run: not tried again.
+            let err = conn.call(py, "drop", Vec::new(), false).unwrap_err();
+            assert!(err.is_instance_of::<PyConnectionError>(py));
+            conn.ping(py).unwrap();
+            assert_eq!(conn.token(py).as_deref(), Some("token-1"));
+            assert!(!conn.closed(py));
+        });
+        let log = log.lock().unwrap();
+        assert_eq!(log[0], ["signin", "use", "drop"]);
+        assert_eq!(log[1], ["signin", "use", "ping"]);
+    }
+
+    #[test]
+    fn requests_time_out_and_closed_connections_refuse_them() {
+        pyo3::prepare_freethreaded_python();
+        let (url, _) = serve();
+        Python::with_gil(|py| {
+            let conn = connect(py, &url, Some(0.2), 0).unwrap();
+            let err = conn.call(py, "hang", Vec::new(), true).unwrap_err();
+            assert!(err.is_instance_of::<PyTimeoutError>(py));
+            assert_eq!(err.to_string(), "TimeoutError: no response within 0.2 s");
+            conn.ping(py).unwrap();
+            conn.close(py);
+            assert!(conn.closed(py));
+            let err = conn.ping(py).unwrap_err();
+            assert_eq!(err.to_string(), "ConnectionError: the connection is closed");
+        });
+    }
+
+    #[test]
+    fn queries_are_tried_again_when_safe() {
+        pyo3::prepare_freethreaded_python();
+        // Each on a server whose first socket is lost with the query.
+        let query = |sql: &str, idempotent: Option<bool>, retries: u32| {
+            let (url, log) = serve();
+            let result = Python::with_gil(|py| {
+                let conn = connect(py, &url, Some(5.0), retries)?;
+                let options = [("output", "batch")].into_py_dict(py).unwrap();
+                Ok::<_, PyErr>(numbers(conn.query(py, sql, None, 0, idempotent, Some(&options))?.bind(py)))
+            });
+            let sockets = log.lock().unwrap().len();
+            (result.map_err(|e| Python::with_gil(|py| e.is_instance_of::<PyConnectionError>(py))), sockets)
+        };
+        assert_eq!(query("SELECT * FROM lost", None, 1), (Ok(vec![0, 1, 2]), 2));
+        assert_eq!(query("SELECT * FROM lost", None, 0), (Err(true), 1));
+        // Running a write twice could do it twice.
+        assert_eq!(query("CREATE lost", None, 1), (Err(true), 1));
+        assert_eq!(query("CREATE lost", Some(true), 1), (Ok(vec![0, 1, 2]), 2));
+        assert_eq!(query("SELECT * FROM lost", Some(false), 1), (Err(true), 1));
+    }
+
+    #[test]
+    fn backoff_doubles_and_connecting_times_out() {
+        pyo3::prepare_freethreaded_python();
+        let policy = Policy::new(Some(0.1), None, 3, 0.5).unwrap();
+        let delays = [0, 1, 2].map(|attempt| policy.delay(attempt).as_millis());
+        assert_eq!(delays, [500, 1000, 2000]);
+        assert_eq!(policy.delay(20), MAX_BACKOFF);
+        let err = Policy::new(None, None, 0, -1.0).unwrap_err();
+        assert_eq!(err.to_string(), "ValueError: backoff must be a non-negative number of seconds");
+
+        // Listening, but never answering the WebSocket handshake.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        Python::with_gil(|py| {
+            let err = Connection::open(py, &url, policy, None).err().unwrap();
+            assert_eq!(err.to_string(), format!("TimeoutError: cannot connect to {}: connection timed out", url));
+        });
+    }
+
+    #[test]
+    fn methods_and_pools_are_awaited() {
+        pyo3::prepare_freethreaded_python();
+        let (url, log) = serve();
+        Python::with_gil(|py| {
+            let code = c_str!(
+                "import asyncio\n\
+                 async def session(connect_async, url):\n    \
+                     conn = await connect_async(url, timeout=5)\n    \
+                     token = await conn.signin_async(username='root', password='root')\n    \
+                     await conn.use_ns_db_async('test', 'test')\n    \
+                     return token\n\
+                 async def checkouts(pool):\n    \
+                     first = await pool.checkout_async()\n    \
+                     waiting = asyncio.ensure_future(pool.checkout_async(timeout=5))\n    \
+                     await asyncio.sleep(0.05)\n    \
+                     held = waiting.done()\n    \
+                     pool.checkin(first)\n    \
+                     second = await waiting\n    \
+                     try:\n        \
+                         await pool.checkout_async(timeout=0.05)\n    \
+                     except TimeoutError as e:\n        \
+                         timed_out = str(e)\n    \
+                     pool.checkin(second)\n    \
+                     return held, second is first, timed_out\n"
+            );
+            let module = PyModule::from_code(py, code, c_str!("client_test.py"), c_str!("client_test")).unwrap();
+            let asyncio = py.import("asyncio").unwrap();
+            let run = |coroutine| asyncio.call_method1("run", (coroutine,)).unwrap();
+            let connect = py.get_type::<Connection>().getattr("connect_async").unwrap();
+            let token = run(module.getattr("session").unwrap().call1((connect, &url)).unwrap());
+            assert_eq!(token.extract::<String>().unwrap(), "token-0");
+
+            let credentials = [("username", "root"), ("password", "root")].into_py_dict(py).unwrap();
+            let kwargs = PyDict::new(py);
+            kwargs.set_item("max_size", 1).unwrap();
+            kwargs.set_item("credentials", credentials).unwrap();
+            let pool = py.get_type::<ConnectionPool>().call((&url,), Some(&kwargs)).unwrap();
+            let checkouts = run(module.getattr("checkouts").unwrap().call1((pool,)).unwrap());
+            let (held, reused, timed_out) = checkouts.extract::<(bool, bool, String)>().unwrap();
+            assert!(!held && reused);
+            assert_eq!(timed_out, "no connection became available within 0.05 s");
+        });
+        assert_eq!(log.lock().unwrap()[..2], [vec!["signin", "use"], vec!["signin"]]);
+    }
+
+    #[test]
+    fn pools_hand_out_healthy_connections() {
+        pyo3::prepare_freethreaded_python();
+        let (url, log) = serve();
+        Python::with_gil(|py| {
+            let credentials = [("username", "root"), ("password", "root")].into_py_dict(py).unwrap();
+            let kwargs = PyDict::new(py);
+            kwargs.set_item("max_size", 2).unwrap();
+            kwargs.set_item("credentials", credentials).unwrap();
+            kwargs.set_item("namespace", "test").unwrap();
+            kwargs.set_item("database", "test").unwrap();
+            kwargs.set_item("health_check_interval", 0.0).unwrap();
+            let pool = py.get_type::<ConnectionPool>().call((&url,), Some(&kwargs)).unwrap();
+            let count = |name: &str| pool.getattr(name).unwrap().extract::<usize>().unwrap();
+            assert_eq!((count("size"), count("idle")), (1, 1));
+            let first = pool.call_method0("checkout").unwrap();
+            let second = pool.call_method0("checkout").unwrap();
+            assert_eq!((count("size"), count("idle")), (2, 0));
+            let err = pool.call_method1("checkout", (0.05,)).unwrap_err();
+            assert_eq!(err.to_string(), "TimeoutError: no connection became available within 0.05 s");
+
+            // A connection that was lost is dropped from the pool as it is checked in.
+            let err = first.downcast::<Connection>().unwrap().get().call(py, "drop", Vec::new(), false).unwrap_err();
+            assert!(err.is_instance_of::<PyConnectionError>(py));
+            pool.call_method1("checkin", (&first,)).unwrap();
+            pool.call_method1("checkin", (&second,)).unwrap();
+            assert_eq!((count("size"), count("idle")), (1, 1));
+            let err = pool.call_method1("checkin", (&second,)).unwrap_err();
+            assert_eq!(err.to_string(), "ValueError: the connection was not checked out of this pool");
+
+            let options = [("output", "batch")].into_py_dict(py).unwrap();
+            let batch = pool.call_method("query", ("SELECT * FROM person",), Some(&options)).unwrap();
+            assert_eq!(numbers(&batch), [0, 1, 2]);
+            let third = pool.call_method0("checkout").unwrap();
+            assert!(third.is(&second));
+            pool.call_method0("close").unwrap();
+            pool.call_method1("checkin", (&third,)).unwrap();
+            assert_eq!((count("size"), count("idle")), (0, 0));
+            assert!(third.downcast::<Connection>().unwrap().get().is_closed());
+            let err = pool.call_method0("checkout").unwrap_err();
+            assert_eq!(err.to_string(), "ConnectionError: the pool is closed");
+        });
+        let log = log.lock().unwrap();
+        // Idle for longer than the health check interval, connections are pinged as they are checked out.
+        assert_eq!(log[0], ["signin", "use", "ping", "drop"]);
+        assert_eq!(log[1], ["signin", "use", "ping", "query", "ping"]);
+    }
+
+    #[test]
+    fn live_queries_call_back_with_batches() {
+        pyo3::prepare_freethreaded_python();
+        let (url, log) = serve();
+        let (conn, calls) = Python::with_gil(|py| {
+            let conn = connect(py, &url, Some(5.0), 0).unwrap();
+            let calls = PyList::empty(py);
+            let append = c_str!("lambda calls: lambda action, batch: calls.append((action, batch))");
+            let on_batch = py.eval(append, None, None).unwrap().call1((&calls,)).unwrap().unbind();
+            let options = [("output", "batch")].into_py_dict(py).unwrap();
+            let live_id = conn.live(py, "SELECT * FROM person", on_batch, None, Some(&options)).unwrap();
+            assert_eq!(live_id, crate::tags::format_uuid(&LIVE_ID));
+            let err = conn.live(py, "SELECT * FROM person", py.None(), None, None).unwrap_err();
+            assert_eq!(err.to_string(), "TypeError: on_batch must be callable");
+            (conn, calls.unbind())
+        });
+        // Called back on the connection's thread, which needs the GIL.
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while Python::with_gil(|py| calls.bind(py).len()) < 3 && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        Python::with_gil(|py| {
+            let calls = calls.bind(py);
+            let actions: Vec<String> = calls.iter().map(|call| call.get_item(0).unwrap().extract().unwrap()).collect();
+            assert_eq!(actions, ["CREATE", "UPDATE", "KILLED"]);
+            assert_eq!(numbers(&calls.get_item(0).unwrap().get_item(1).unwrap()), [1]);
+            assert_eq!(numbers(&calls.get_item(1).unwrap().get_item(1).unwrap()), [2]);
+            assert!(calls.get_item(2).unwrap().get_item(1).unwrap().is_none());
+
+            conn.kill(py, &crate::tags::format_uuid(&LIVE_ID)).unwrap();
+            let err = conn.kill(py, "person").unwrap_err();
+            assert_eq!(err.to_string(), "ValueError: \"person\" is not a UUID");
+            let (http, _) = serve_http();
+            let on_batch = calls.getattr("append").unwrap().unbind();
+            let err = connect(py, &http, Some(5.0), 0).unwrap().live(py, "SELECT * FROM person", on_batch, None, None);
+            assert_eq!(err.unwrap_err().to_string(), "ConnectionError: live queries need a WebSocket connection");
+        });
+        assert_eq!(log.lock().unwrap()[0], ["query", "kill"]);
+    }
+
+    #[test]
+    fn cancelled_pool_queries_give_their_connection_back() {
+        pyo3::prepare_freethreaded_python();
+        let (url, _) = serve();
+        Python::with_gil(|py| {
+            let code = c_str!(
+                "import asyncio\n\
+                 async def cancel(pool, max_size):\n    \
+                     for _ in range(max_size):\n        \
+                         try:\n            \
+                             await asyncio.wait_for(pool.query_async('SELECT * FROM hang'), 0.05)\n        \
+                         except asyncio.TimeoutError:\n            \
+                             pass\n    \
+                     conns = [await pool.checkout_async(timeout=5) for _ in range(max_size)]\n    \
+                     for conn in conns:\n        \
+                         pool.checkin(conn)\n    \
+                     return len(conns)\n"
+            );
+            let module = PyModule::from_code(py, code, c_str!("pool_test.py"), c_str!("pool_test")).unwrap();
+            let kwargs = PyDict::new(py);
+            kwargs.set_item("max_size", 2).unwrap();
+            let pool = py.get_type::<ConnectionPool>().call((&url,), Some(&kwargs)).unwrap();
+            let coroutine = module.getattr("cancel").unwrap().call1((&pool, 2)).unwrap();
+            let checked_out = py.import("asyncio").unwrap().call_method1("run", (coroutine,)).unwrap();
+            assert_eq!(checked_out.extract::<usize>().unwrap(), 2);
+            assert_eq!(pool.getattr("idle").unwrap().extract::<usize>().unwrap(), 2);
+        });
+    }
+
+    /// An HTTP server of SurrealDB's RPC endpoint on a port, answering queries
+    /// with `statements` and other requests with a `null` result, and logging
+    /// the headers of each.
+    fn serve_http() -> (String, Arc<Mutex<Vec<Vec<String>>>>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let requests = log.clone();
+        std::thread::spawn(move || {
+            for tcp in listener.incoming() {
+                let requests = requests.clone();
+                std::thread::spawn(move || {
+                    let mut tcp = BufReader::new(tcp.unwrap());
+                    loop {
+                        let mut lines = Vec::new();
+                        let mut line = String::new();
+                        while tcp.read_line(&mut line).unwrap_or(0) > 2 {
+                            lines.push(line.trim_end().to_string());
+                            line.clear();
+                        }
+                        let Some(first) = lines.first().cloned() else { return };
+                        let length = lines
+                            .iter()
+                            .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length: ")?.parse().ok());
+                        let mut body = vec![0; length.unwrap_or(0)];
+                        tcp.read_exact(&mut body).unwrap();
+                        requests.lock().unwrap().push(lines);
+                        let response = match first.starts_with("POST /rpc ") {
+                            true => {
+                                let Ok(Value::Map(request)) = decode_root(&body) else { return };
+                                let id = map_get(&request, "id").cloned().unwrap_or(Value::Null);
+                                let result = match map_get(&request, "method") {
+                                    Some(Value::Text(method)) if method == "query" => statements(),
+                                    _ => Value::Null,
+                                };
+                                let frame = encode(&Value::Map(vec![(text("id"), id), (text("result"), result)]));
+                                let head = format!(
+                                    "HTTP/1.1 200 OK\r\nContent-Type: application/cbor\r\nContent-Length: {}\r\n\r\n",
+                                    frame.len()
+                                );
+                                [head.into_bytes(), frame].concat()
+                            }
+                            false => b"HTTP/1.1 404 Not Found\r\nContent-Length: 10\r\n\r\nnot found\n".to_vec(),
+                        };
+                        tcp.get_mut().write_all(&response).unwrap();
+                    }
+                });
+            }
+        });
+        (url, log)
+    }
+
+    #[test]
+    fn http_requests_carry_the_session() {
+        pyo3::prepare_freethreaded_python();
+        let (url, log) = serve_http();
+        Python::with_gil(|py| {
+            let conn = connect(py, &url, Some(5.0), 0).unwrap();
+            conn.use_ns_db(py, "test", "db").unwrap();
+            conn.authenticate(py, "secret").unwrap();
+            conn.ping(py).unwrap();
+            let err = connect(py, &format!("{}/missing", url), Some(5.0), 0).err().unwrap();
+            assert!(err.is_instance_of::<PyConnectionError>(py));
+            assert!(err.to_string().ends_with("HTTP status 404: not found"), "{}", err);
+        });
+        let log = log.lock().unwrap();
+        let headers = |request: &Vec<String>| -> Vec<String> {
+            request.iter().filter(|l| l.starts_with("surreal-") || l.starts_with("authorization")).cloned().collect()
+        };
+        // The ping of connecting, `authenticate`, then the ping with the session.
+        assert_eq!(headers(&log[0]), Vec::<String>::new());
+        assert_eq!(headers(&log[1]), ["surreal-ns: test", "surreal-db: db"]);
+        assert_eq!(headers(&log[2]), ["surreal-ns: test", "surreal-db: db", "authorization: Bearer secret"]);
+    }
+
+    #[test]
+    fn http_queries_convert_to_arrow() {
+        pyo3::prepare_freethreaded_python();
+        let (url, log) = serve_http();
+        Python::with_gil(|py| {
+            let conn = connect(py, &url, Some(5.0), 0).unwrap();
+            let options = [("output", "batch")].into_py_dict(py).unwrap();
+            let batch = conn.query(py, "SELECT * FROM person", None, 0, None, Some(&options)).unwrap();
+            assert_eq!(numbers(batch.bind(py)), [0, 1, 2]);
+            let err = conn.query(py, "SELECT * FROM person", None, 1, None, Some(&options)).unwrap_err();
+            assert!(err.is_instance_of::<PyIndexError>(py));
+        });
+        let log = log.lock().unwrap();
+        // After the ping of connecting, the query.
+        assert!(log[1][0].starts_with("POST /rpc "), "{:?}", log[1]);
+        for header in ["content-type: application/cbor", "accept: application/cbor"] {
+            assert!(log[1].iter().any(|l| l == header), "{:?}", log[1]);
+        }
+    }
+}
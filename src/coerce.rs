@@ -0,0 +1,158 @@
+//! Best-effort conversion of CBOR values to a declared Arrow type.
+//!
+//! With an explicit `schema` the records are not required to match the declared
+//! types exactly. The builder first tries the conversion inference would use and
+//! falls back to these, so `"42"` fills an integer column and `42` a string one.
+//! Arrow types the builder has no column for are built as a close type and cast.
+
+use std::borrow::Cow;
+
+use arrow::datatypes::{DataType, TimeUnit};
+use cbor4ii::core::Value;
+
+use crate::tags::{self, SurrealTag};
+use crate::{ConvertOptions, SurrealValueRef};
+
+/// The type to build a column of the declared type `data_type` as before casting,
+/// or `None` if it cannot be built.
+pub(crate) fn build_type(data_type: &DataType) -> Option<DataType> {
+    Some(match data_type {
+        DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::UInt8 | DataType::UInt16 | DataType::UInt32 => {
+            DataType::Int64
+        }
+        DataType::Float16 | DataType::Float32 => DataType::Float64,
+        DataType::Decimal128(..) | DataType::Decimal256(..) | DataType::Utf8View => DataType::Utf8,
+        DataType::BinaryView | DataType::FixedSizeBinary(_) => DataType::Binary,
+        DataType::Date32 | DataType::Date64 | DataType::Time32(_) | DataType::Time64(_) => {
+            DataType::Timestamp(TimeUnit::Nanosecond, None)
+        }
+        DataType::Timestamp(_, tz) => DataType::Timestamp(TimeUnit::Nanosecond, tz.clone()),
+        DataType::Duration(_) => DataType::Duration(TimeUnit::Nanosecond),
+        DataType::FixedSizeList(element, _) => DataType::List(element.clone()),
+        DataType::Dictionary(_, values) => values.as_ref().clone(),
+        _ => return None,
+    })
+}
+
+/// The text of a string, or of a decimal or table tag.
+fn text(value: &Value) -> Option<&str> {
+    match value {
+        Value::Text(s) => Some(s),
+        Value::Tag(tag, payload) if matches!(SurrealTag::of(*tag), SurrealTag::Decimal | SurrealTag::Table) => {
+            match payload.as_ref() {
+                Value::Text(s) => Some(s),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn to_i64(value: &Value) -> Option<i64> {
+    match value {
+        Value::Integer(i) => i64::try_from(*i).ok(),
+        Value::Float(f) if f.fract() == 0.0 && *f >= i64::MIN as f64 && *f < i64::MAX as f64 => Some(*f as i64),
+        Value::Bool(b) => Some(i64::from(*b)),
+        _ => text(value)?.trim().parse().ok(),
+    }
+}
+
+pub(crate) fn to_u64(value: &Value) -> Option<u64> {
+    match value {
+        Value::Integer(i) => u64::try_from(*i).ok(),
+        Value::Float(f) if f.fract() == 0.0 && *f >= 0.0 && *f < u64::MAX as f64 => Some(*f as u64),
+        Value::Bool(b) => Some(u64::from(*b)),
+        _ => text(value)?.trim().parse().ok(),
+    }
+}
+
+pub(crate) fn to_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(i) => Some(*i as f64),
+        Value::Float(f) => Some(*f),
+        Value::Tag(tag, payload) if SurrealTag::of(*tag) == SurrealTag::DecimalFraction => {
+            tags::decimal_fraction_string(payload)?.parse().ok()
+        }
+        _ => text(value)?.trim().parse().ok(),
+    }
+}
+
+pub(crate) fn to_bool(value: &Value) -> Option<bool> {
+    match value {
+        Value::Integer(0) => Some(false),
+        Value::Integer(1) => Some(true),
+        _ => match text(value)?.trim() {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        },
+    }
+}
+
+/// Nanoseconds since the epoch of an RFC 3339 string or an integer (taken as nanoseconds).
+pub(crate) fn to_datetime(value: &Value) -> Option<i64> {
+    match value {
+        Value::Integer(i) => i64::try_from(*i).ok(),
+        Value::Text(_) => tags::datetime_to_nanos(tags::TAG_DATETIME, value),
+        _ => None,
+    }
+}
+
+/// Nanoseconds of a SurrealQL duration string or an integer (taken as nanoseconds).
+pub(crate) fn to_duration(value: &Value) -> Option<i64> {
+    match value {
+        Value::Integer(i) => i64::try_from(*i).ok(),
+        Value::Text(_) => tags::duration_to_nanos(tags::TAG_DURATION, value),
+        _ => None,
+    }
+}
+
+/// The 16 bytes of a UUID tag in either form, a UUID string, or 16 raw bytes.
+pub(crate) fn to_uuid(value: &Value) -> Option<[u8; 16]> {
+    match value {
+        Value::Tag(tag, payload) => tags::uuid_bytes(*tag, payload),
+        Value::Text(_) => tags::uuid_bytes(tags::TAG_UUID_STRING, value),
+        Value::Bytes(_) => tags::uuid_bytes(tags::TAG_UUID, value),
+        _ => None,
+    }
+}
+
+/// The bytes of a string, or of a UUID in binary form.
+pub(crate) fn to_bytes(value: &Value) -> Option<Cow<'_, [u8]>> {
+    match value {
+        Value::Text(s) => Some(Cow::Borrowed(s.as_bytes())),
+        Value::Tag(tag, payload) if SurrealTag::of(*tag) == SurrealTag::Uuid => {
+            Some(Cow::Owned(tags::uuid_bytes(*tag, payload)?.to_vec()))
+        }
+        _ => None,
+    }
+}
+
+/// The string form of a scalar: numbers and booleans as written, datetimes in
+/// RFC 3339, durations as SurrealQL literals, UUIDs hyphenated and record ids as
+/// `table:key`. Arrays, maps and bytes have none.
+pub(crate) fn to_text(value: &Value) -> Option<Cow<'_, str>> {
+    if let Some(s) = text(value) {
+        return Some(Cow::Borrowed(s));
+    }
+    Some(Cow::Owned(match value {
+        Value::Integer(i) => i.to_string(),
+        Value::Float(f) => f.to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Tag(tag, payload) => match SurrealTag::of(*tag) {
+            SurrealTag::Datetime => tags::format_datetime(tags::datetime_to_nanos(*tag, payload)?),
+            SurrealTag::Duration => tags::format_duration(tags::duration_to_nanos(*tag, payload)?),
+            SurrealTag::Uuid => tags::format_uuid(&tags::uuid_bytes(*tag, payload)?),
+            SurrealTag::RecordId => tags::record_id_string(payload)?,
+            SurrealTag::BigNum => tags::bignum_string(*tag, payload)?,
+            SurrealTag::DecimalFraction => tags::decimal_fraction_string(payload)?,
+            _ => return None,
+        },
+        _ => return None,
+    }))
+}
+
+/// A value as JSON text, for arrays and objects kept in a string column.
+pub(crate) fn to_json(value: &Value, opts: &ConvertOptions) -> Option<String> {
+    serde_json::to_string(&SurrealValueRef(value, opts)).ok()
+}
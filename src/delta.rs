@@ -0,0 +1,157 @@
+//! Writing of converted results to Delta Lake tables, with the `deltalake` crate.
+//!
+//! Built with the `deltalake` feature. The batches of a statement are scanned by
+//! the Delta write as a table of one partition, so they are converted as the
+//! write takes them and never held together.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use arrow::array::RecordBatchReader;
+use datafusion::catalog::streaming::StreamingTable;
+use datafusion::datasource::provider_as_source;
+use datafusion::logical_expr::LogicalPlanBuilder;
+use deltalake::operations::write::SchemaMode;
+use deltalake::protocol::SaveMode;
+use deltalake::DeltaOps;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::export::{statement_reader, Responses};
+use crate::frames::frame_options;
+use crate::query::ReaderPartition;
+use crate::OutputMode;
+
+/// Convert a statement of a CBOR response, or of several, and write it to a
+/// Delta Lake table.
+///
+/// `table_uri` is the path or `file://` URI of the table, created if it does
+/// not exist, and batches are written as they are converted. `storage_options`
+/// configure the object store. `mode` is `"append"` (default), `"overwrite"`,
+/// `"error"` or `"ignore"`. `schema_mode` handles results whose schema differs
+/// from the table's: `"merge"` (default) adds new columns to the table,
+/// `"overwrite"` replaces its schema (with `mode="overwrite"`), and `None`
+/// fails. A list of responses is written as `cbor_to_parquet` writes one. A
+/// statement without records cannot be written unless `empty_schema` names its
+/// columns. Accepts the keyword options of `cbor_to_arrow` other than `output`.
+#[pyfunction]
+#[pyo3(signature = (data, table_uri, statement=0, mode="append", schema_mode=Some("merge"), storage_options=None, **options))]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn cbor_to_delta(
+    py: Python,
+    data: Responses,
+    table_uri: String,
+    statement: isize,
+    mode: &str,
+    schema_mode: Option<&str>,
+    storage_options: Option<HashMap<String, String>>,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<()> {
+    let save_mode = match mode {
+        "append" => SaveMode::Append,
+        "overwrite" => SaveMode::Overwrite,
+        "error" => SaveMode::ErrorIfExists,
+        "ignore" => SaveMode::Ignore,
+        _ => {
+            return Err(PyValueError::new_err(format!(
+                "unknown mode {:?}; expected \"append\", \"overwrite\", \"error\" or \"ignore\"",
+                mode
+            )))
+        }
+    };
+    let schema_mode = match schema_mode {
+        None => None,
+        Some("merge") => Some(SchemaMode::Merge),
+        Some("overwrite") => Some(SchemaMode::Overwrite),
+        Some(other) => {
+            return Err(PyValueError::new_err(format!(
+                "unknown schema_mode {:?}; expected \"merge\", \"overwrite\" or None",
+                other
+            )))
+        }
+    };
+    let opts = frame_options("cbor_to_delta", options, OutputMode::Reader)?;
+    let payloads = data.load(py, &opts)?;
+    let reader = statement_reader(py, &payloads, statement, &opts, false)?;
+    let storage_options = storage_options.unwrap_or_default();
+    py.allow_threads(|| write_delta(reader, &table_uri, storage_options, save_mode, schema_mode))
+}
+
+/// Write the batches of `reader` to the Delta table at `table_uri`.
+fn write_delta(
+    reader: Box<dyn RecordBatchReader + Send>,
+    table_uri: &str,
+    storage_options: HashMap<String, String>,
+    save_mode: SaveMode,
+    schema_mode: Option<SchemaMode>,
+) -> PyResult<()> {
+    let source = Arc::new(ReaderPartition::new(reader));
+    let table = StreamingTable::try_new(source.schema.clone(), vec![source.clone()]).map_err(delta_error)?;
+    let plan = LogicalPlanBuilder::scan("source", provider_as_source(Arc::new(table)), None)
+        .and_then(LogicalPlanBuilder::build)
+        .map_err(delta_error)?;
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let written = runtime.block_on(async {
+        let ops = DeltaOps::try_from_uri_with_storage_options(table_uri, storage_options).await?;
+        let mut write = ops.write(Vec::new()).with_input_execution_plan(Arc::new(plan)).with_save_mode(save_mode);
+        if let Some(schema_mode) = schema_mode {
+            write = write.with_schema_mode(schema_mode);
+        }
+        write.await
+    });
+    // A batch that failed to convert fails the write with the error it raised.
+    source.take_error()?;
+    written.map(|_| ()).map_err(delta_error)
+}
+
+fn delta_error(error: impl fmt::Display) -> PyErr {
+    PyValueError::new_err(format!("Delta Lake write error: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{Int64Array, RecordBatch, RecordBatchIterator, StringArray};
+    use arrow::error::ArrowError;
+
+    use super::*;
+
+    fn write(uri: &str, batch: RecordBatch, save_mode: SaveMode) -> PyResult<()> {
+        let reader = RecordBatchIterator::new([Ok(batch.clone())], batch.schema());
+        write_delta(Box::new(reader), uri, HashMap::new(), save_mode, Some(SchemaMode::Merge))
+    }
+
+    #[test]
+    fn delta_tables_are_created_and_merged() {
+        pyo3::prepare_freethreaded_python();
+        let dir = std::env::temp_dir().join(format!("surrealengine-{}-delta", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let uri = dir.to_str().unwrap();
+        let numbers = || Arc::new(Int64Array::from_iter_values(0..3)) as _;
+        let names = || Arc::new(StringArray::from(vec!["x", "y", "z"])) as _;
+        let narrow = || RecordBatch::try_from_iter_with_nullable([("a", numbers(), true)]).unwrap();
+        let wide = || RecordBatch::try_from_iter_with_nullable([("a", numbers(), true), ("b", names(), true)]).unwrap();
+        write(uri, narrow(), SaveMode::Append).unwrap();
+        write(uri, wide(), SaveMode::Append).unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let table = runtime.block_on(deltalake::open_table(uri)).unwrap();
+        assert_eq!(table.version(), 1);
+        let columns: Vec<_> = table.get_schema().unwrap().fields().map(|f| f.name().clone()).collect();
+        assert_eq!(columns, ["a", "b"]);
+
+        Python::with_gil(|py| {
+            let err = write(uri, wide(), SaveMode::ErrorIfExists).unwrap_err();
+            assert!(err.is_instance_of::<PyValueError>(py));
+            let failing = ArrowError::ExternalError(Box::new(pyo3::exceptions::PyKeyError::new_err("row 2")));
+            let reader = RecordBatchIterator::new([Ok(wide()), Err(failing)], wide().schema());
+            let err = write_delta(Box::new(reader), uri, HashMap::new(), SaveMode::Append, None).unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyKeyError>(py));
+        });
+        // Neither failed write added a version.
+        let table = runtime.block_on(deltalake::open_table(uri)).unwrap();
+        assert_eq!(table.version(), 1);
+    }
+}
@@ -0,0 +1,333 @@
+//! Conversion of Arrow data to SurrealDB CBOR, the write path.
+//!
+//! Each row of a batch becomes an object of SurrealDB values: timestamps and
+//! dates become datetimes, durations durations, decimals decimals and 16-byte
+//! binary values UUIDs, each with its SurrealDB tag, and `{tb, id}` structs and
+//! the `table:key` strings of record id columns become record ids. This is the
+//! reverse of what `cbor_to_arrow` produces, so converted results can be written
+//! back. Null fields are left out of records and objects, which SurrealDB reads
+//! as NONE; null list items stay null.
+
+use arrow::array::{Array, ArrayRef, AsArray, RecordBatch};
+use arrow::compute::cast;
+use arrow::datatypes::{ArrowPrimitiveType, DataType, Field, Float64Type, Int64Type, TimeUnit, UInt64Type};
+use cbor4ii::core::enc::Encode;
+use cbor4ii::core::utils::BufWriter;
+use cbor4ii::core::Value;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use pyo3_arrow::{PyRecordBatch, PyTable};
+
+use crate::schema::is_json;
+use crate::surrealql;
+use crate::tags::{TAG_DATETIME_COMPACT, TAG_DECIMAL, TAG_DURATION_COMPACT, TAG_RECORDID, TAG_UUID};
+
+/// Arrow data to encode: a record batch or a table, from any library with the
+/// Arrow PyCapsule interface.
+#[derive(FromPyObject)]
+pub(crate) enum ArrowInput {
+    Batch(PyRecordBatch),
+    Table(PyTable),
+}
+
+impl ArrowInput {
+    pub(crate) fn into_batches(self) -> Vec<RecordBatch> {
+        match self {
+            ArrowInput::Batch(batch) => vec![batch.into_inner()],
+            ArrowInput::Table(table) => table.into_inner().0,
+        }
+    }
+}
+
+/// Encode a pyarrow RecordBatch or Table as SurrealDB CBOR: an array with an
+/// object for each row.
+///
+/// Strings in the `record_ids` columns that parse as record ids (`person:tobie`)
+/// are encoded as record ids, others as strings. Columns of `arrow.json` text
+/// are decoded back into values. Time of day, interval and union columns have no
+/// SurrealDB type and raise `ValueError`.
+#[pyfunction]
+#[pyo3(signature = (data, record_ids=vec!["id".to_string()]))]
+pub(crate) fn arrow_to_cbor(py: Python, data: ArrowInput, record_ids: Vec<String>) -> PyResult<PyObject> {
+    let batches = data.into_batches();
+    let bytes = py.allow_threads(|| -> PyResult<Vec<u8>> {
+        let mut records = Vec::new();
+        for batch in &batches {
+            records.extend(batch_records(batch, &record_ids, false)?);
+        }
+        Ok(encode(&Value::Array(records)))
+    })?;
+    Ok(PyBytes::new(py, &bytes).into_any().unbind())
+}
+
+/// The CBOR encoding of `value`.
+pub(crate) fn encode(value: &Value) -> Vec<u8> {
+    let mut out = BufWriter::new(Vec::new());
+    value.encode(&mut out).expect("writing to memory cannot fail");
+    out.into_inner()
+}
+
+/// The rows of `batch` as SurrealDB objects, leaving out null fields, or with
+/// them as `null` if `keep_nulls` is set.
+pub(crate) fn batch_records(batch: &RecordBatch, record_ids: &[String], keep_nulls: bool) -> PyResult<Vec<Value>> {
+    let schema = batch.schema();
+    let mut records: Vec<Vec<(Value, Value)>> = (0..batch.num_rows()).map(|_| Vec::new()).collect();
+    for (field, column) in schema.fields().iter().zip(batch.columns()) {
+        let record_id = record_ids.iter().any(|name| name == field.name());
+        let values = column_values(field, column, record_id)?;
+        let nulls = column.logical_nulls();
+        for (row, value) in values.into_iter().enumerate() {
+            if keep_nulls || nulls.as_ref().is_none_or(|nulls| nulls.is_valid(row)) {
+                records[row].push((Value::Text(field.name().clone()), value));
+            }
+        }
+    }
+    Ok(records.into_iter().map(Value::Map).collect())
+}
+
+/// The value of each row of `array`, `Value::Null` where it is null.
+fn column_values(field: &Field, array: &ArrayRef, record_id: bool) -> PyResult<Vec<Value>> {
+    let nulls = array.logical_nulls();
+    let valid = |row: usize| nulls.as_ref().is_none_or(|nulls| nulls.is_valid(row));
+    let cast_to = |to: &DataType| cast(array, to).map_err(|_| unsupported(field));
+    let values = match array.data_type() {
+        DataType::Null => vec![Value::Null; array.len()],
+        DataType::Boolean => array.as_boolean().iter().map(|b| b.map_or(Value::Null, Value::Bool)).collect(),
+        DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::Int64 => {
+            primitives::<Int64Type>(&cast_to(&DataType::Int64)?, |i| Value::Integer(i.into()))
+        }
+        DataType::UInt8 | DataType::UInt16 | DataType::UInt32 | DataType::UInt64 => {
+            primitives::<UInt64Type>(&cast_to(&DataType::UInt64)?, |i| Value::Integer(i.into()))
+        }
+        DataType::Float16 | DataType::Float32 | DataType::Float64 => {
+            primitives::<Float64Type>(&cast_to(&DataType::Float64)?, Value::Float)
+        }
+        DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View => {
+            let strings = cast_to(&DataType::LargeUtf8)?;
+            let json = is_json(field);
+            strings
+                .as_string::<i64>()
+                .iter()
+                .map(|s| match s {
+                    None => Ok(Value::Null),
+                    Some(s) if json => serde_json::from_str(s).map(json_value).map_err(|e| {
+                        PyValueError::new_err(format!("column {} holds invalid JSON: {}", field.name(), e))
+                    }),
+                    Some(s) if record_id => Ok(match surrealql::parse_record_id(s) {
+                        Ok((table, key)) => tagged(TAG_RECORDID, Value::Array(vec![Value::Text(table), key])),
+                        Err(_) => Value::Text(s.to_string()),
+                    }),
+                    Some(s) => Ok(Value::Text(s.to_string())),
+                })
+                .collect::<PyResult<_>>()?
+        }
+        DataType::FixedSizeBinary(16) => array
+            .as_fixed_size_binary()
+            .iter()
+            .map(|b| b.map_or(Value::Null, |b| tagged(TAG_UUID, Value::Bytes(b.to_vec()))))
+            .collect(),
+        DataType::Binary | DataType::LargeBinary | DataType::BinaryView | DataType::FixedSizeBinary(_) => {
+            let bytes = cast_to(&DataType::LargeBinary)?;
+            bytes.as_binary::<i64>().iter().map(|b| b.map_or(Value::Null, |b| Value::Bytes(b.to_vec()))).collect()
+        }
+        DataType::Timestamp(unit, _) => {
+            let per_second = units_per_second(unit);
+            primitives::<Int64Type>(&cast_to(&DataType::Int64)?, |v| compact(TAG_DATETIME_COMPACT, v, per_second))
+        }
+        DataType::Date32 => primitives::<Int64Type>(&cast_to(&DataType::Int64)?, |days| {
+            compact(TAG_DATETIME_COMPACT, days * 86_400, 1)
+        }),
+        DataType::Date64 => {
+            primitives::<Int64Type>(&cast_to(&DataType::Int64)?, |ms| compact(TAG_DATETIME_COMPACT, ms, 1_000))
+        }
+        DataType::Duration(unit) => {
+            let per_second = units_per_second(unit);
+            primitives::<Int64Type>(&cast_to(&DataType::Int64)?, |v| compact(TAG_DURATION_COMPACT, v, per_second))
+        }
+        DataType::Decimal128(..) | DataType::Decimal256(..) => {
+            let strings = cast_to(&DataType::LargeUtf8)?;
+            strings
+                .as_string::<i64>()
+                .iter()
+                .map(|s| s.map_or(Value::Null, |s| tagged(TAG_DECIMAL, Value::Text(s.to_string()))))
+                .collect()
+        }
+        DataType::List(item) | DataType::LargeList(item) | DataType::FixedSizeList(item, _) => {
+            let lists = cast_to(&DataType::LargeList(item.clone()))?;
+            let lists = lists.as_list::<i64>();
+            let items = column_values(item, lists.values(), false)?;
+            let offsets = lists.value_offsets();
+            (0..lists.len())
+                .map(|row| match valid(row) {
+                    true => Value::Array(items[offsets[row] as usize..offsets[row + 1] as usize].to_vec()),
+                    false => Value::Null,
+                })
+                .collect()
+        }
+        DataType::Struct(children) => {
+            let structs = array.as_struct();
+            let columns = children
+                .iter()
+                .zip(structs.columns())
+                .map(|(child, column)| Ok((child, column_values(child, column, false)?, column.logical_nulls())))
+                .collect::<PyResult<Vec<_>>>()?;
+            let is_record_id = children.len() == 2 && children.find("tb").is_some() && children.find("id").is_some();
+            (0..structs.len())
+                .map(|row| {
+                    if !valid(row) {
+                        return Value::Null;
+                    }
+                    if is_record_id {
+                        let part = |name: &str| {
+                            let (_, values, _) = columns.iter().find(|(child, _, _)| child.name() == name).unwrap();
+                            values[row].clone()
+                        };
+                        return tagged(TAG_RECORDID, Value::Array(vec![part("tb"), part("id")]));
+                    }
+                    Value::Map(
+                        columns
+                            .iter()
+                            .filter(|(_, _, nulls)| nulls.as_ref().is_none_or(|nulls| nulls.is_valid(row)))
+                            .map(|(child, values, _)| (Value::Text(child.name().clone()), values[row].clone()))
+                            .collect(),
+                    )
+                })
+                .collect()
+        }
+        DataType::Map(..) => {
+            let maps = array.as_map();
+            let entries = maps.entries();
+            let keys = column_values(entries.fields()[0].as_ref(), maps.keys(), false)?;
+            let values = column_values(entries.fields()[1].as_ref(), maps.values(), false)?;
+            let offsets = maps.value_offsets();
+            (0..maps.len())
+                .map(|row| match valid(row) {
+                    true => {
+                        let range = offsets[row] as usize..offsets[row + 1] as usize;
+                        Value::Map(keys[range.clone()].iter().cloned().zip(values[range].iter().cloned()).collect())
+                    }
+                    false => Value::Null,
+                })
+                .collect()
+        }
+        DataType::Dictionary(_, value_type) => {
+            let decoded = cast_to(value_type)?;
+            column_values(&field.clone().with_data_type(value_type.as_ref().clone()), &decoded, record_id)?
+        }
+        _ => return Err(unsupported(field)),
+    };
+    Ok(values)
+}
+
+fn primitives<T: ArrowPrimitiveType>(array: &ArrayRef, value: impl Fn(T::Native) -> Value) -> Vec<Value> {
+    array.as_primitive::<T>().iter().map(|v| v.map_or(Value::Null, &value)).collect()
+}
+
+pub(crate) fn tagged(tag: u64, value: Value) -> Value {
+    Value::Tag(tag, Box::new(value))
+}
+
+/// A compact datetime or duration tag, `[seconds, nanoseconds]`, of `value`
+/// counted in `per_second` units a second.
+pub(crate) fn compact(tag: u64, value: i64, per_second: i64) -> Value {
+    let secs = value.div_euclid(per_second);
+    let nanos = value.rem_euclid(per_second) * (1_000_000_000 / per_second);
+    tagged(tag, Value::Array(vec![Value::Integer(secs.into()), Value::Integer(nanos.into())]))
+}
+
+fn units_per_second(unit: &TimeUnit) -> i64 {
+    match unit {
+        TimeUnit::Second => 1,
+        TimeUnit::Millisecond => 1_000,
+        TimeUnit::Microsecond => 1_000_000,
+        TimeUnit::Nanosecond => 1_000_000_000,
+    }
+}
+
+fn json_value(value: serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Bool(b),
+        serde_json::Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => Value::Integer(i.into()),
+            (None, Some(u)) => Value::Integer(u.into()),
+            _ => Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(s) => Value::Text(s),
+        serde_json::Value::Array(items) => Value::Array(items.into_iter().map(json_value).collect()),
+        serde_json::Value::Object(entries) => {
+            Value::Map(entries.into_iter().map(|(k, v)| (Value::Text(k), json_value(v))).collect())
+        }
+    }
+}
+
+fn unsupported(field: &Field) -> PyErr {
+    PyValueError::new_err(format!(
+        "column {} of type {} has no SurrealDB value type",
+        field.name(),
+        field.data_type()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{Decimal128Array, DurationSecondArray, FixedSizeBinaryArray, StringArray};
+    use arrow::array::{Time64NanosecondArray, TimestampMicrosecondArray};
+
+    use super::*;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    fn records(py: Python, data: ArrowInput) -> PyResult<Value> {
+        let bytes = arrow_to_cbor(py, data, vec!["id".to_string()])?;
+        crate::pull::decode(bytes.bind(py).downcast::<PyBytes>()?.as_bytes())
+    }
+
+    #[test]
+    fn rows_encode_with_tags() {
+        pyo3::prepare_freethreaded_python();
+        let at = TimestampMicrosecondArray::from(vec![Some(1_500_000), None]).with_timezone("UTC");
+        let prices = Decimal128Array::from(vec![12345, -5]).with_precision_and_scale(10, 2).unwrap();
+        let uuids = FixedSizeBinaryArray::try_from_iter([[0xab; 16], [0xcd; 16]].into_iter()).unwrap();
+        let batch = RecordBatch::try_from_iter([
+            ("id", Arc::new(StringArray::from(vec!["person:1", "plain"])) as ArrayRef),
+            ("at", Arc::new(at) as _),
+            ("price", Arc::new(prices) as _),
+            ("uuid", Arc::new(uuids) as _),
+            ("took", Arc::new(DurationSecondArray::from(vec![90, 0])) as _),
+        ])
+        .unwrap();
+        let row = |id, at: Option<Value>, price: &str, uuid: u8, took| {
+            let took = Value::Array(vec![Value::Integer(took), Value::Integer(0)]);
+            let mut fields = vec![(text("id"), id)];
+            fields.extend(at.map(|at| (text("at"), at)));
+            fields.extend([
+                (text("price"), tagged(TAG_DECIMAL, text(price))),
+                (text("uuid"), tagged(TAG_UUID, Value::Bytes(vec![uuid; 16]))),
+                (text("took"), tagged(TAG_DURATION_COMPACT, took)),
+            ]);
+            Value::Map(fields)
+        };
+        let person = tagged(TAG_RECORDID, Value::Array(vec![text("person"), Value::Integer(1)]));
+        let at = tagged(TAG_DATETIME_COMPACT, Value::Array(vec![Value::Integer(1), Value::Integer(500_000_000)]));
+        let expected = Value::Array(vec![
+            row(person, Some(at), "123.45", 0xab, 90),
+            // A null field is left out, and a string that is no record id stays a string.
+            row(text("plain"), None, "-0.05", 0xcd, 0),
+        ]);
+        Python::with_gil(|py| {
+            assert_eq!(records(py, ArrowInput::Batch(PyRecordBatch::new(batch.clone()))).unwrap(), expected);
+            let table = PyTable::try_new(vec![batch.slice(0, 1), batch.slice(1, 1)], batch.schema()).unwrap();
+            assert_eq!(records(py, ArrowInput::Table(table)).unwrap(), expected);
+
+            let times = RecordBatch::try_from_iter([("t", Arc::new(Time64NanosecondArray::from(vec![1])) as ArrayRef)]);
+            let err = records(py, ArrowInput::Batch(PyRecordBatch::new(times.unwrap()))).unwrap_err();
+            assert!(err.is_instance_of::<PyValueError>(py), "{}", err);
+        });
+    }
+}
@@ -0,0 +1,63 @@
+//! The exceptions raised for input that cannot be converted, and the warning
+//! issued for input converted with a loss of information.
+//!
+//! The exceptions all derive from `SurrealEngineError`, itself a `ValueError`, so
+//! code that caught the `ValueError` raised before keeps working. Invalid
+//! arguments still raise plain `ValueError` and `TypeError`.
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyUserWarning, PyValueError};
+use pyo3::prelude::*;
+
+create_exception!(
+    surrealengine_accelerator,
+    SurrealEngineError,
+    PyValueError,
+    "Base class of the errors raised for input that cannot be converted."
+);
+create_exception!(
+    surrealengine_accelerator,
+    CborDecodeError,
+    SurrealEngineError,
+    "The input is not valid CBOR, or not shaped like a SurrealDB response."
+);
+create_exception!(
+    surrealengine_accelerator,
+    QueryStatusError,
+    SurrealEngineError,
+    "The response reports that the query or one of its statements failed. `code` and \
+     `message` are SurrealDB's error code (`None` for a failed statement) and message, \
+     `statement` the index of the failed statement (`None` for an error of the whole \
+     request), and `status` and `time` those of the statement."
+);
+create_exception!(
+    surrealengine_accelerator,
+    SchemaInferenceError,
+    SurrealEngineError,
+    "No Arrow schema could be inferred for the records."
+);
+create_exception!(
+    surrealengine_accelerator,
+    ArrowBuildError,
+    SurrealEngineError,
+    "The records could not be built into Arrow arrays of the schema."
+);
+create_exception!(
+    surrealengine_accelerator,
+    LossyConversionWarning,
+    PyUserWarning,
+    "Some values were converted with a loss of information, such as ignored tags or \
+     stringified object keys. The message counts them by kind."
+);
+
+/// Add the exception classes to the module.
+pub(crate) fn register(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+    m.add("SurrealEngineError", py.get_type::<SurrealEngineError>())?;
+    m.add("CborDecodeError", py.get_type::<CborDecodeError>())?;
+    m.add("QueryStatusError", py.get_type::<QueryStatusError>())?;
+    m.add("SchemaInferenceError", py.get_type::<SchemaInferenceError>())?;
+    m.add("ArrowBuildError", py.get_type::<ArrowBuildError>())?;
+    m.add("LossyConversionWarning", py.get_type::<LossyConversionWarning>())?;
+    Ok(())
+}
@@ -0,0 +1,106 @@
+//! Unnesting of list columns into rows for `explode`.
+//!
+//! Each element of an exploded list column becomes its own row and the other
+//! columns are repeated for it. Several columns are exploded together and must
+//! have lists of the same length in each row. An empty or null list gives one
+//! row with a null, as pandas' `explode` does.
+
+use std::sync::Arc;
+
+use arrow::array::{Array, ArrayRef, AsArray, GenericListArray, OffsetSizeTrait, UInt64Array};
+use arrow::compute::take;
+use arrow::datatypes::{DataType, FieldRef};
+use arrow::error::ArrowError;
+
+use crate::ConvertOptions;
+
+/// `fields` with each exploded list field replaced by one of its element type.
+pub(crate) fn explode_fields(fields: &[FieldRef], opts: &ConvertOptions) -> Vec<FieldRef> {
+    fields
+        .iter()
+        .map(|field| match field.data_type() {
+            DataType::List(element) | DataType::LargeList(element) if opts.explodes(field.name()) => {
+                Arc::new(field.as_ref().clone().with_data_type(element.data_type().clone()).with_nullable(true))
+            }
+            _ => field.clone(),
+        })
+        .collect()
+}
+
+/// The columns of `explode_fields(fields)` from the columns of `fields`.
+pub(crate) fn explode_arrays(
+    fields: &[FieldRef],
+    arrays: Vec<ArrayRef>,
+    opts: &ConvertOptions,
+) -> Result<Vec<ArrayRef>, ArrowError> {
+    let exploded: Vec<Exploded> = fields
+        .iter()
+        .zip(&arrays)
+        .enumerate()
+        .filter(|(_, (field, _))| opts.explodes(field.name()))
+        .filter_map(|(column, (_, array))| spans(array).map(|(spans, values)| Exploded { column, spans, values }))
+        .collect();
+    let Some(first) = exploded.first() else {
+        return Ok(arrays);
+    };
+    let mut rows = Vec::new();
+    for (row, &(_, len)) in first.spans.iter().enumerate() {
+        if let Some(other) = exploded.iter().find(|other| other.spans[row].1 != len) {
+            return Err(ArrowError::InvalidArgumentError(format!(
+                "cannot explode row {}: column {} has {} elements, column {} has {}",
+                row,
+                fields[first.column].name(),
+                len,
+                fields[other.column].name(),
+                other.spans[row].1
+            )));
+        }
+        rows.extend(std::iter::repeat_n(row as u64, len.max(1)));
+    }
+    let rows = UInt64Array::from(rows);
+    let mut out = Vec::with_capacity(arrays.len());
+    for (i, array) in arrays.iter().enumerate() {
+        out.push(match exploded.iter().find(|e| e.column == i) {
+            Some(Exploded { spans, values, .. }) => {
+                let elements: UInt64Array = spans
+                    .iter()
+                    .flat_map(|&(start, len)| match len {
+                        0 => vec![None],
+                        _ => (start..start + len).map(|e| Some(e as u64)).collect(),
+                    })
+                    .collect();
+                take(values, &elements, None)?
+            }
+            None => take(array, &rows, None)?,
+        });
+    }
+    Ok(out)
+}
+
+struct Exploded {
+    column: usize,
+    /// `(start, length)` of each row's list in `values`.
+    spans: Vec<(usize, usize)>,
+    values: ArrayRef,
+}
+
+/// The `(start, length)` of each row's list in the values of a list array, and
+/// the values; `None` for other arrays. Null lists are empty.
+fn spans(array: &ArrayRef) -> Option<(Vec<(usize, usize)>, ArrayRef)> {
+    match array.data_type() {
+        DataType::List(_) => Some(list_spans(array.as_list::<i32>())),
+        DataType::LargeList(_) => Some(list_spans(array.as_list::<i64>())),
+        _ => None,
+    }
+}
+
+fn list_spans<O: OffsetSizeTrait>(list: &GenericListArray<O>) -> (Vec<(usize, usize)>, ArrayRef) {
+    let offsets = list.value_offsets();
+    let spans = (0..list.len())
+        .map(|i| match list.is_null(i) {
+            true => (0, 0),
+            false => (offsets[i].as_usize(), (offsets[i + 1] - offsets[i]).as_usize()),
+        })
+        .collect();
+    (spans, list.values().clone())
+}
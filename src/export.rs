@@ -0,0 +1,800 @@
+//! Writing of converted results straight to files and bytes.
+//!
+//! A statement is converted lazily, as for `output="reader"`, and each batch is
+//! written out as soon as it is built, so a large result is never held as a whole
+//! in Arrow form next to the decoded response. Formats without nested types
+//! (CSV) get the values of nested fields as JSON text, as `type_conflicts="json"`
+//! stores them.
+
+use std::borrow::Cow;
+use std::collections::hash_map::{Entry, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use arrow::array::{Array, RecordBatchIterator, RecordBatchReader, UInt32Array};
+use arrow::compute::take_record_batch;
+use arrow::csv::WriterBuilder;
+use arrow::datatypes::{DataType, FieldRef, Schema};
+use arrow::error::ArrowError;
+use arrow::util::display::{ArrayFormatter, FormatOptions};
+use arrow::ipc::writer::{FileWriter, IpcWriteOptions, StreamWriter};
+use arrow::ipc::CompressionType;
+use arrow::pyarrow::FromPyArrow;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyTuple};
+
+use crate::frames::frame_options;
+use crate::pull::{Payload, Rows};
+use crate::reader::LazyBatches;
+use crate::{
+    result_plan, result_rows, root_responses, statement_index, BatchPlan, CborInput, ConvertOptions, OutputMode,
+    RecordsAt,
+};
+
+/// Convert a statement of a CBOR response, or of several, and write it to Parquet.
+///
+/// `path` is a path or a writable file object. Batches are written as they are
+/// converted, in row groups of at most `row_group_size` rows (1,048,576 if
+/// `None`). `compression` is a Parquet codec: `"zstd"`, `"snappy"`, `"gzip"`,
+/// `"brotli"`, `"lz4"` or `"none"`. With a list of responses, such as the pages
+/// of a paginated export, the statement of each is written in order under one
+/// schema inferred across them all, as `merge_cbor_to_arrow` infers it.
+///
+/// `partition_by` names columns to partition a dataset by: `path` is then the
+/// root directory of a Hive-style layout (`tenant=acme/date=2024-01-02/...`),
+/// with the rows of each partition in a file of its directory, without the
+/// partition columns. Null values name the `__HIVE_DEFAULT_PARTITION__`
+/// directory. Each call writes files of a new name, so several exports can
+/// land in the same dataset. A statement
+/// without records cannot be written unless `empty_schema` names its columns.
+/// Accepts the keyword options of `cbor_to_arrow` other than `output`.
+#[pyfunction]
+#[pyo3(signature = (data, path, statement=0, compression="zstd", row_group_size=None, partition_by=None, **options))]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn cbor_to_parquet(
+    py: Python,
+    data: Responses,
+    path: ParquetTarget,
+    statement: isize,
+    compression: &str,
+    row_group_size: Option<usize>,
+    partition_by: Option<Vec<String>>,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<()> {
+    let properties = parquet_properties(compression, row_group_size)?;
+    let opts = frame_options("cbor_to_parquet", options, OutputMode::Reader)?;
+    let payloads = data.load(py, &opts)?;
+    let reader = statement_reader(py, &payloads, statement, &opts, false)?;
+    if let Some(columns) = partition_by {
+        let ParquetTarget::Path(base_dir) = path else {
+            return Err(PyValueError::new_err("partition_by needs the path of a directory to write to"));
+        };
+        return py.allow_threads(|| write_dataset(reader, &base_dir, properties, columns));
+    }
+    let out = path.open()?;
+    py.allow_threads(|| write_parquet(reader, out, properties))
+}
+
+/// Where Parquet output goes: a file at a path, or a writable file object.
+#[derive(FromPyObject)]
+pub(crate) enum ParquetTarget {
+    Path(PathBuf),
+    File(PyObject),
+}
+
+impl ParquetTarget {
+    /// A writer of the output, creating (or truncating) the file at a path.
+    fn open(self) -> PyResult<Box<dyn Write + Send>> {
+        Ok(match self {
+            ParquetTarget::Path(path) => Box::new(BufWriter::new(File::create(path)?)),
+            ParquetTarget::File(file) => Box::new(PyFile(file)),
+        })
+    }
+}
+
+/// A Python file object, written through its `write` method with the GIL held.
+struct PyFile(PyObject);
+
+impl Write for PyFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Python::with_gil(|py| self.0.call_method1(py, "write", (PyBytes::new(py, buf),))).map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Python::with_gil(|py| self.0.call_method0(py, "flush")).map_err(io::Error::other)?;
+        Ok(())
+    }
+}
+
+/// The writer properties of a Parquet file compressed with the codec named
+/// `compression`, in row groups of at most `row_group_size` rows.
+fn parquet_properties(compression: &str, row_group_size: Option<usize>) -> PyResult<WriterProperties> {
+    let codec = match compression.to_ascii_lowercase().as_str() {
+        "none" | "uncompressed" => Compression::UNCOMPRESSED,
+        "snappy" => Compression::SNAPPY,
+        "gzip" => Compression::GZIP(Default::default()),
+        "brotli" => Compression::BROTLI(Default::default()),
+        "lz4" => Compression::LZ4_RAW,
+        "zstd" => Compression::ZSTD(Default::default()),
+        _ => {
+            return Err(PyValueError::new_err(format!(
+                "unknown compression {:?}; expected \"zstd\", \"snappy\", \"gzip\", \"brotli\", \"lz4\" or \"none\"",
+                compression
+            )))
+        }
+    };
+    let mut properties = WriterProperties::builder().set_compression(codec);
+    match row_group_size {
+        Some(0) => return Err(PyValueError::new_err("row_group_size must be positive")),
+        Some(rows) => properties = properties.set_max_row_group_size(rows),
+        None => {}
+    }
+    Ok(properties.build())
+}
+
+/// Write the batches of `reader` to `out` as one Parquet file.
+fn write_parquet(
+    mut reader: Box<dyn RecordBatchReader + Send>,
+    out: Box<dyn Write + Send>,
+    properties: WriterProperties,
+) -> PyResult<()> {
+    let mut writer = ArrowWriter::try_new(out, reader.schema(), Some(properties)).map_err(parquet_error)?;
+    reader.try_for_each(|batch| writer.write(&batch.map_err(batch_error)?).map_err(parquet_error))?;
+    writer.into_inner().map_err(parquet_error)?.flush()?;
+    Ok(())
+}
+
+/// The directory name of a partition whose column value is null.
+const HIVE_NULL: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// Write the batches of `reader` as a Parquet dataset under `base_dir`,
+/// partitioned by `columns` in Hive style.
+fn write_dataset(
+    reader: Box<dyn RecordBatchReader + Send>,
+    base_dir: &Path,
+    properties: WriterProperties,
+    columns: Vec<String>,
+) -> PyResult<()> {
+    let schema = reader.schema();
+    let partition = columns
+        .iter()
+        .map(|c| schema.index_of(c).map_err(|_| PyValueError::new_err(format!("partition column {} is not in the result", c))))
+        .collect::<PyResult<Vec<_>>>()?;
+    let kept: Vec<usize> = (0..schema.fields().len()).filter(|i| !partition.contains(i)).collect();
+    let file_schema = Arc::new(schema.project(&kept).map_err(write_error)?);
+    let name = format!("part-{}.parquet", unique_token());
+    let mut writers: HashMap<Vec<String>, ArrowWriter<BufWriter<File>>> = HashMap::new();
+    for batch in reader {
+        let batch = batch.map_err(batch_error)?;
+        let options = FormatOptions::default();
+        let formatters = partition
+            .iter()
+            .map(|&i| ArrayFormatter::try_new(batch.column(i).as_ref(), &options))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(write_error)?;
+        let mut partitions: HashMap<Vec<String>, Vec<u32>> = HashMap::new();
+        for row in 0..batch.num_rows() {
+            let key = partition
+                .iter()
+                .zip(&formatters)
+                .map(|(&i, formatter)| match batch.column(i).is_valid(row) {
+                    true => formatter.value(row).to_string(),
+                    false => HIVE_NULL.to_string(),
+                })
+                .collect();
+            partitions.entry(key).or_default().push(row as u32);
+        }
+        for (key, rows) in partitions {
+            let rows = take_record_batch(&batch, &UInt32Array::from(rows))
+                .and_then(|rows| rows.project(&kept))
+                .map_err(write_error)?;
+            let writer = match writers.entry(key) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let dir = entry
+                        .key()
+                        .iter()
+                        .zip(&columns)
+                        .fold(base_dir.to_path_buf(), |dir, (value, column)| {
+                            dir.join(format!("{}={}", escape_segment(column), escape_segment(value)))
+                        });
+                    std::fs::create_dir_all(&dir)?;
+                    let file = BufWriter::new(File::create(dir.join(&name))?);
+                    let writer = ArrowWriter::try_new(file, file_schema.clone(), Some(properties.clone()));
+                    entry.insert(writer.map_err(parquet_error)?)
+                }
+            };
+            writer.write(&rows).map_err(parquet_error)?;
+        }
+    }
+    for writer in writers.into_values() {
+        writer.into_inner().map_err(parquet_error)?.flush()?;
+    }
+    Ok(())
+}
+
+/// A token for the files of one write, unlike those of any other.
+fn unique_token() -> String {
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos());
+    format!("{:x}-{}-{}", nanos, std::process::id(), WRITES.fetch_add(1, Ordering::Relaxed))
+}
+
+/// `value` percent-encoded for a path segment, leaving unreserved URI characters.
+fn escape_segment(value: &str) -> Cow<'_, str> {
+    let unreserved = |b: u8| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~');
+    if value.bytes().all(unreserved) {
+        return Cow::Borrowed(value);
+    }
+    let mut escaped = String::with_capacity(value.len() * 3);
+    for b in value.bytes() {
+        match unreserved(b) {
+            true => escaped.push(b as char),
+            false => escaped.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Convert a statement of a CBOR response to Arrow IPC bytes, without pyarrow.
+///
+/// Returns the IPC stream format, which `pyarrow.ipc.open_stream` reads, or with
+/// `file=True` the IPC file format, which is Feather version 2. `compression`
+/// is `"lz4"` or `"zstd"` to compress the buffers, or `None` (default) to leave
+/// them uncompressed. A statement without records cannot be written unless
+/// `empty_schema` names its columns. Accepts the keyword options of
+/// `cbor_to_arrow` other than `output`.
+#[pyfunction]
+#[pyo3(signature = (data, statement=0, file=false, compression=None, **options))]
+pub(crate) fn cbor_to_ipc(
+    py: Python,
+    data: CborInput,
+    statement: isize,
+    file: bool,
+    compression: Option<&str>,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<PyObject> {
+    let write_options = ipc_options(compression)?;
+    let opts = frame_options("cbor_to_ipc", options, OutputMode::Reader)?;
+    opts.check_bytes(&data)?;
+    let payload = Arc::new(Payload::load(py, data, true, &opts)?);
+    let reader = statement_reader(py, &[payload], statement, &opts, false)?;
+    let bytes = py.allow_threads(|| write_ipc(reader, file, write_options))?;
+    Ok(PyBytes::new(py, &bytes).into_any().unbind())
+}
+
+/// The IPC write options compressing buffers with `compression`.
+fn ipc_options(compression: Option<&str>) -> PyResult<IpcWriteOptions> {
+    let codec = match compression {
+        None => None,
+        Some("lz4") => Some(CompressionType::LZ4_FRAME),
+        Some("zstd") => Some(CompressionType::ZSTD),
+        Some(other) => {
+            return Err(PyValueError::new_err(format!(
+                "unknown compression {:?}; expected \"lz4\", \"zstd\" or None",
+                other
+            )))
+        }
+    };
+    IpcWriteOptions::default().try_with_compression(codec).map_err(write_error)
+}
+
+fn write_ipc(mut reader: Box<dyn RecordBatchReader + Send>, file: bool, options: IpcWriteOptions) -> PyResult<Vec<u8>> {
+    let schema = reader.schema();
+    if file {
+        let mut writer = FileWriter::try_new_with_options(Vec::new(), &schema, options).map_err(write_error)?;
+        reader.try_for_each(|batch| writer.write(&batch.map_err(batch_error)?).map_err(write_error))?;
+        writer.into_inner().map_err(write_error)
+    } else {
+        let mut writer = StreamWriter::try_new_with_options(Vec::new(), &schema, options).map_err(write_error)?;
+        reader.try_for_each(|batch| writer.write(&batch.map_err(batch_error)?).map_err(write_error))?;
+        writer.into_inner().map_err(write_error)
+    }
+}
+
+/// Convert a statement of a CBOR response and write it to a CSV file.
+///
+/// Each batch is written as it is converted. Columns the output would nest
+/// (structs, lists, maps and unions) hold the JSON text of their values, and
+/// other SurrealDB values their string form: record ids as `table:key`,
+/// datetimes in RFC 3339. `delimiter` is a single ASCII character, and
+/// `header=False` leaves out the row of column names. A statement without
+/// records cannot be written unless `empty_schema` names its columns. Accepts
+/// the keyword options of `cbor_to_arrow` other than `output`.
+#[pyfunction]
+#[pyo3(signature = (data, path, delimiter=",", header=true, statement=0, **options))]
+pub(crate) fn cbor_to_csv(
+    py: Python,
+    data: CborInput,
+    path: PathBuf,
+    delimiter: &str,
+    header: bool,
+    statement: isize,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<()> {
+    let &[delimiter] = delimiter.as_bytes() else {
+        return Err(PyValueError::new_err("delimiter must be a single ASCII character"));
+    };
+    let opts = frame_options("cbor_to_csv", options, OutputMode::Reader)?;
+    opts.check_bytes(&data)?;
+    let payload = Arc::new(Payload::load(py, data, true, &opts)?);
+    let mut reader = statement_reader(py, &[payload], statement, &opts, true)?;
+    py.allow_threads(|| {
+        let out = BufWriter::new(File::create(path)?);
+        let mut writer = WriterBuilder::new().with_delimiter(delimiter).with_header(header).build(out);
+        reader.try_for_each(|batch| writer.write(&batch.map_err(batch_error)?).map_err(write_error))
+    })
+}
+
+/// Writes the records of successive CBOR frames to one Parquet file, a row
+/// group or more per frame, so an export never has to be held whole.
+///
+/// Each frame is a complete RPC response (or, with `envelope=False`, a bare
+/// record array), such as a page of a paginated query; the records of all its
+/// statements are written in order. The file's schema is `schema` if given,
+/// otherwise the one inferred from the first frame with records, with every
+/// column nullable. Later frames are converted to it: values are coerced as for
+/// a declared `schema`, and fields it does not have are dropped. `path`, `compression` and
+/// `row_group_size` are as for `cbor_to_parquet`, and the remaining keyword
+/// options are those of `cbor_to_arrow` other than `output`.
+///
+/// `close()` finishes the file; the sink is also a context manager that closes
+/// it on exit. If no frame had records and no `schema` was given, no file is
+/// written.
+#[pyclass]
+pub(crate) struct ParquetSink {
+    /// Where the file goes, until it is opened.
+    target: Option<ParquetTarget>,
+    properties: WriterProperties,
+    envelope: bool,
+    opts: ConvertOptions,
+    /// The plan of the file's schema, once known.
+    plan: Option<BatchPlan>,
+    /// The file's writer, in a mutex only to share the sink between threads.
+    writer: Option<Mutex<ArrowWriter<Box<dyn Write + Send>>>>,
+    rows: usize,
+    closed: bool,
+}
+
+#[pymethods]
+impl ParquetSink {
+    #[new]
+    #[pyo3(signature = (path, schema=None, compression="zstd", row_group_size=None, envelope=true, **options))]
+    fn new(
+        path: ParquetTarget,
+        schema: Option<&Bound<'_, PyAny>>,
+        compression: &str,
+        row_group_size: Option<usize>,
+        envelope: bool,
+        options: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<Self> {
+        let properties = parquet_properties(compression, row_group_size)?;
+        let mut opts = frame_options("ParquetSink", options, OutputMode::Reader)?;
+        if let Some(schema) = schema {
+            opts.schema = Some(Arc::new(Schema::from_pyarrow_bound(schema)?));
+        }
+        let target = Some(path);
+        Ok(ParquetSink { target, properties, envelope, opts, plan: None, writer: None, rows: 0, closed: false })
+    }
+
+    /// Convert one frame and write its records. Returns the number of rows written.
+    fn write(&mut self, py: Python, data: CborInput) -> PyResult<usize> {
+        if self.closed {
+            return Err(PyValueError::new_err("write to a closed ParquetSink"));
+        }
+        self.opts.check_bytes(&data)?;
+        let payload = Arc::new(Payload::load(py, data, self.envelope, &self.opts)?);
+        let sources: Vec<RecordsAt> = match self.envelope {
+            true => (0..root_responses(&payload.root)?.len()).map(RecordsAt::Statement).collect(),
+            false => vec![RecordsAt::Bare],
+        };
+        let mut rows = Vec::with_capacity(sources.len());
+        for at in &sources {
+            rows.extend(result_rows(&payload, *at)?.0);
+        }
+        let total: usize = rows.iter().map(Rows::len).sum();
+        if total == 0 {
+            return Ok(0);
+        }
+        self.opts.check_rows(total)?;
+        let plan = match &self.plan {
+            Some(plan) => plan.clone(),
+            None => {
+                let opts = &self.opts;
+                let plan = py.allow_threads(|| match &opts.schema {
+                    Some(schema) => Ok(BatchPlan::declared(schema.clone(), rows[0], opts)),
+                    None => BatchPlan::infer_merged(&rows, opts),
+                })?;
+                self.opts.warn_losses(py)?;
+                self.opts.report_rows(py)?;
+                self.plan.insert(plan.pinned(&self.opts)).clone()
+            }
+        };
+        let opts = self.opts.clone();
+        let writer = self.writer(&plan)?;
+        py.allow_threads(|| {
+            for at in sources {
+                for batch in LazyBatches::new(payload.clone(), at, plan.clone(), opts.clone()) {
+                    writer.write(&batch.map_err(batch_error)?).map_err(parquet_error)?;
+                }
+            }
+            // End the row group, so no rows are held past the frame.
+            writer.flush().map_err(parquet_error)
+        })?;
+        self.rows += total;
+        Ok(total)
+    }
+
+    /// Finish the file. Closing again does nothing.
+    fn close(&mut self, py: Python) -> PyResult<()> {
+        if std::mem::replace(&mut self.closed, true) {
+            return Ok(());
+        }
+        if self.writer.is_none() {
+            let Some(schema) = self.opts.schema.clone() else {
+                return Ok(());
+            };
+            self.writer(&BatchPlan::declared(schema, Rows::Decoded(&[], None), &self.opts))?;
+        }
+        match self.writer.take() {
+            Some(writer) => py.allow_threads(|| {
+                let writer = writer.into_inner().unwrap_or_else(PoisonError::into_inner);
+                Ok(writer.into_inner().map_err(parquet_error)?.flush()?)
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    #[pyo3(signature = (*_exc))]
+    fn __exit__(&mut self, py: Python, _exc: &Bound<'_, PyTuple>) -> PyResult<bool> {
+        self.close(py)?;
+        Ok(false)
+    }
+
+    /// Number of rows written.
+    fn __len__(&self) -> usize {
+        self.rows
+    }
+}
+
+impl ParquetSink {
+    /// The file's writer, opened with the schema of `plan` on first use.
+    fn writer(&mut self, plan: &BatchPlan) -> PyResult<&mut ArrowWriter<Box<dyn Write + Send>>> {
+        if self.writer.is_none() {
+            let out = self.target.take().expect("a sink without a writer has its target").open()?;
+            let writer = ArrowWriter::try_new(out, plan.schema.clone(), Some(self.properties.clone()));
+            self.writer = Some(Mutex::new(writer.map_err(parquet_error)?));
+        }
+        Ok(self.writer.as_mut().unwrap().get_mut().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+/// The CBOR responses to write: one, or several written as one result.
+#[derive(FromPyObject)]
+pub(crate) enum Responses {
+    One(CborInput),
+    Many(Vec<CborInput>),
+}
+
+impl Responses {
+    pub(crate) fn load(self, py: Python, opts: &ConvertOptions) -> PyResult<Vec<Arc<Payload>>> {
+        let inputs = match self {
+            Responses::One(data) => vec![data],
+            Responses::Many(inputs) => inputs,
+        };
+        let mut loaded = Vec::with_capacity(inputs.len());
+        for data in inputs {
+            opts.check_bytes(&data)?;
+            loaded.push(Arc::new(Payload::load(py, data, true, opts)?));
+        }
+        Ok(loaded)
+    }
+}
+
+/// The batches statement `statement` of the loaded responses converts to, in
+/// order and built lazily; with `flat`, the columns the output would nest are
+/// built as JSON text. A failed statement raises, and without records there
+/// are no batches to write unless `empty_schema` is given.
+pub(crate) fn statement_reader(
+    py: Python,
+    payloads: &[Arc<Payload>],
+    statement: isize,
+    opts: &ConvertOptions,
+    flat: bool,
+) -> PyResult<Box<dyn RecordBatchReader + Send>> {
+    let mut sources = Vec::with_capacity(payloads.len());
+    for payload in payloads {
+        let len = root_responses(&payload.root)?.len();
+        if len > 0 {
+            sources.push((payload, RecordsAt::Statement(statement_index(statement, len)?)));
+        }
+    }
+    let plan = match sources.as_slice() {
+        [] => None,
+        [(payload, at)] => result_plan(py, payload, *at, opts)?.1,
+        _ => merged_plan(py, &sources, opts)?,
+    };
+    let plan = match flat {
+        true => plan.map(|plan| flat_plan(plan, opts)),
+        false => plan,
+    };
+    opts.warn_losses(py)?;
+    opts.report_rows(py)?;
+    match (plan, &opts.empty_schema) {
+        (Some(plan), _) => {
+            let schema = plan.schema.clone();
+            let parts: Vec<LazyBatches> = sources
+                .into_iter()
+                .map(|(payload, at)| LazyBatches::new(payload.clone(), at, plan.clone(), opts.clone()))
+                .collect();
+            Ok(Box::new(RecordBatchIterator::new(parts.into_iter().flatten(), schema)))
+        }
+        (None, Some(schema)) => Ok(Box::new(RecordBatchIterator::new(Vec::new(), schema.clone()))),
+        (None, None) => Err(PyValueError::new_err(
+            "the statement returned no records; pass empty_schema to write an empty file",
+        )),
+    }
+}
+
+/// One plan for the records of every source, as `merge_cbor_to_arrow` infers
+/// it, or `None` if none has records.
+fn merged_plan(py: Python, sources: &[(&Arc<Payload>, RecordsAt)], opts: &ConvertOptions) -> PyResult<Option<BatchPlan>> {
+    let mut rows = Vec::with_capacity(sources.len());
+    for (payload, at) in sources {
+        rows.extend(result_rows(payload, *at)?.0);
+    }
+    if rows.is_empty() {
+        return Ok(opts.empty_schema.clone().map(BatchPlan::empty));
+    }
+    let total: usize = rows.iter().map(Rows::len).sum();
+    opts.check_rows(total)?;
+    let plan = py.allow_threads(|| match &opts.schema {
+        Some(schema) => Ok(BatchPlan::declared(schema.clone(), rows[0], opts)),
+        None => BatchPlan::infer_merged(&rows, opts),
+    })?;
+    opts.check_cells(total, plan.fields.len())?;
+    Ok(Some(plan))
+}
+
+/// `plan` with the fields the output would nest built as text instead, which
+/// objects and arrays are coerced to as JSON.
+fn flat_plan(plan: BatchPlan, opts: &ConvertOptions) -> BatchPlan {
+    let fields = plan.fields.iter().map(|f| flat_field(f, opts.explodes(f.name()), opts.flatten)).collect();
+    BatchPlan { coerce: true, ..BatchPlan::new(fields, plan.split_ids, plan.value_column, opts) }
+}
+
+/// `field` with text in place of each nested type that is neither exploded nor
+/// flattened (`flatten` levels down from it).
+fn flat_field(field: &FieldRef, explode: bool, flatten: Option<usize>) -> FieldRef {
+    let data_type = match field.data_type() {
+        DataType::List(element) if explode => DataType::List(flat_field(element, false, flatten)),
+        DataType::LargeList(element) if explode => DataType::LargeList(flat_field(element, false, flatten)),
+        DataType::Struct(children) if flatten.is_some_and(|depth| depth > 0) && !children.is_empty() => {
+            DataType::Struct(children.iter().map(|c| flat_field(c, false, flatten.map(|depth| depth - 1))).collect())
+        }
+        data_type if data_type.is_nested() => DataType::LargeUtf8,
+        _ => return field.clone(),
+    };
+    Arc::new(field.as_ref().clone().with_data_type(data_type))
+}
+
+/// The error a batch failed to convert with, as raised.
+pub(crate) fn batch_error(error: ArrowError) -> PyErr {
+    match error {
+        ArrowError::ExternalError(error) => match error.downcast::<PyErr>() {
+            Ok(error) => *error,
+            Err(error) => write_error(ArrowError::ExternalError(error)),
+        },
+        error => write_error(error),
+    }
+}
+
+fn write_error(error: ArrowError) -> PyErr {
+    PyValueError::new_err(format!("Arrow write error: {}", error))
+}
+
+/// The error writing Parquet failed with: as raised by a Python file object
+/// written to, or as a `ValueError`.
+fn parquet_error(error: ParquetError) -> PyErr {
+    match error {
+        ParquetError::External(error) => match error.downcast::<io::Error>().map(|error| *error) {
+            Ok(error) if error.get_ref().is_some_and(|inner| inner.is::<PyErr>()) => {
+                *error.into_inner().unwrap().downcast::<PyErr>().unwrap()
+            }
+            Ok(error) => error.into(),
+            Err(error) => PyValueError::new_err(format!("Parquet write error: {}", error)),
+        },
+        error => PyValueError::new_err(format!("Parquet write error: {}", error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{Int64Array, RecordBatch, StringArray};
+    use arrow::ipc::reader::{FileReader, StreamReader};
+    use cbor4ii::core::Value;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use super::*;
+
+    fn numbers() -> RecordBatch {
+        RecordBatch::try_from_iter([("n", Arc::new(Int64Array::from_iter_values(0..1000)) as _)]).unwrap()
+    }
+
+    /// A new path in the temporary directory for the file `name`.
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("surrealengine-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+
+    /// The batches of the Parquet file at `path`, and its row group sizes.
+    fn read_parquet(path: &std::path::Path) -> (Vec<RecordBatch>, Vec<i64>) {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap()).unwrap();
+        let groups = builder.metadata().row_groups().iter().map(|group| group.num_rows()).collect();
+        (builder.build().unwrap().map(Result::unwrap).collect(), groups)
+    }
+
+    fn ipc(file: bool, compression: Option<&str>) -> Vec<u8> {
+        let batch = numbers();
+        let reader = RecordBatchIterator::new([Ok(batch.clone())], batch.schema());
+        write_ipc(Box::new(reader), file, ipc_options(compression).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn compressed_ipc_reads_back() {
+        pyo3::prepare_freethreaded_python();
+        let plain = ipc(false, None).len();
+        for compression in [None, Some("lz4"), Some("zstd")] {
+            let stream = ipc(false, compression);
+            let batches: Vec<_> = StreamReader::try_new(stream.as_slice(), None).unwrap().map(Result::unwrap).collect();
+            assert_eq!(batches, [numbers()]);
+            let file = ipc(true, compression);
+            let batches: Vec<_> = FileReader::try_new(std::io::Cursor::new(file), None).unwrap().map(Result::unwrap).collect();
+            assert_eq!(batches, [numbers()]);
+            if compression.is_some() {
+                assert!(stream.len() < plain, "{:?}", compression);
+            }
+        }
+        Python::with_gil(|py| assert!(ipc_options(Some("gzip")).unwrap_err().is_instance_of::<PyValueError>(py)));
+    }
+
+    #[test]
+    fn parquet_files_are_written_in_row_groups() {
+        pyo3::prepare_freethreaded_python();
+        let path = temp_path("numbers.parquet");
+        let batch = numbers();
+        let halves = [Ok(batch.slice(0, 500)), Ok(batch.slice(500, 500))];
+        let reader = RecordBatchIterator::new(halves, batch.schema());
+        let properties = parquet_properties("snappy", Some(300)).unwrap();
+        write_parquet(Box::new(reader), ParquetTarget::Path(path.clone()).open().unwrap(), properties).unwrap();
+        let (batches, groups) = read_parquet(&path);
+        assert_eq!(arrow::compute::concat_batches(&batch.schema(), &batches).unwrap(), batch);
+        assert_eq!(groups, [300, 300, 300, 100]);
+        Python::with_gil(|py| {
+            for (compression, rows) in [("gzip2", None), ("zstd", Some(0))] {
+                let err = parquet_properties(compression, rows).unwrap_err();
+                assert!(err.is_instance_of::<PyValueError>(py), "{}", compression);
+            }
+        });
+    }
+
+    #[test]
+    fn parquet_goes_to_python_file_objects() {
+        pyo3::prepare_freethreaded_python();
+        let path = temp_path("file-object.parquet");
+        let batch = numbers();
+        let file = Python::with_gil(|py| py.import("io")?.call_method1("open", (&path, "wb")).map(Bound::unbind)).unwrap();
+        let out = Python::with_gil(|py| ParquetTarget::File(file.clone_ref(py)).open()).unwrap();
+        let reader = RecordBatchIterator::new([Ok(batch.clone())], batch.schema());
+        write_parquet(Box::new(reader), out, parquet_properties("zstd", None).unwrap()).unwrap();
+        Python::with_gil(|py| file.call_method0(py, "close")).unwrap();
+        assert_eq!(read_parquet(&path).0, [batch]);
+    }
+
+    #[test]
+    fn partitioned_datasets_are_written_hive_style() {
+        pyo3::prepare_freethreaded_python();
+        let base_dir = temp_path("dataset");
+        let tenants = StringArray::from(vec![Some("acme"), Some("a/b"), None, Some("acme")]);
+        let batch = RecordBatch::try_from_iter([
+            ("tenant", Arc::new(tenants) as _),
+            ("n", Arc::new(Int64Array::from_iter_values(0..4)) as _),
+        ])
+        .unwrap();
+        let write = |columns: &[&str]| {
+            let reader = RecordBatchIterator::new([Ok(batch.clone())], batch.schema());
+            let properties = parquet_properties("zstd", None).unwrap();
+            write_dataset(Box::new(reader), &base_dir, properties, columns.iter().map(|c| c.to_string()).collect())
+        };
+        write(&["tenant"]).unwrap();
+        write(&["tenant"]).unwrap();
+        for (dir, numbers) in [("tenant=acme", vec![0, 3]), ("tenant=a%2Fb", vec![1]), ("tenant=__HIVE_DEFAULT_PARTITION__", vec![2])] {
+            let files: Vec<_> = std::fs::read_dir(base_dir.join(dir)).unwrap().map(|f| f.unwrap().path()).collect();
+            assert_eq!(files.len(), 2, "{}", dir);
+            for file in files {
+                let (batches, _) = read_parquet(&file);
+                let expected = RecordBatch::try_from_iter([("n", Arc::new(Int64Array::from(numbers.clone())) as _)]).unwrap();
+                assert_eq!(batches, [expected]);
+            }
+        }
+        Python::with_gil(|py| {
+            let err = write(&["region"]).unwrap_err();
+            assert_eq!(err.value(py).to_string(), "partition column region is not in the result");
+        });
+    }
+
+    #[test]
+    fn parquet_sinks_write_a_row_group_per_frame() {
+        pyo3::prepare_freethreaded_python();
+        let path = temp_path("sink.parquet");
+        let record = |fields: &[(&str, Value)]| {
+            Value::Map(fields.iter().map(|(k, v)| (Value::Text(k.to_string()), v.clone())).collect())
+        };
+        let frames = [
+            vec![record(&[("a", Value::Integer(1))]), record(&[("a", Value::Integer(2))])],
+            vec![record(&[("a", Value::Null)]), record(&[("a", Value::Integer(3)), ("b", Value::Text("x".to_string()))])],
+        ];
+        Python::with_gil(|py| {
+            let mut sink = ParquetSink::new(ParquetTarget::Path(path.clone()), None, "zstd", None, false, None).unwrap();
+            for frame in &frames {
+                let data = PyBytes::new(py, &crate::encode::encode(&Value::Array(frame.clone())));
+                assert_eq!(sink.write(py, data.extract().unwrap()).unwrap(), frame.len());
+            }
+            sink.close(py).unwrap();
+            assert_eq!(sink.__len__(), 4);
+            assert!(sink.write(py, PyBytes::new(py, b"\x80").extract().unwrap()).is_err());
+        });
+        let (batches, groups) = read_parquet(&path);
+        assert_eq!(groups, [2, 2]);
+        let batch = arrow::compute::concat_batches(&batches[0].schema(), &batches).unwrap();
+        assert_eq!(batch.schema().fields().len(), 1);
+        assert_eq!(batch.column(0).as_ref(), &Int64Array::from(vec![Some(1), Some(2), None, Some(3)]) as &dyn Array);
+    }
+
+    #[test]
+    fn csv_files_hold_nested_values_as_json() {
+        pyo3::prepare_freethreaded_python();
+        let text = |s: &str| Value::Text(s.to_string());
+        let records = vec![
+            Value::Map(vec![
+                (text("n"), Value::Integer(1)),
+                (text("point"), Value::Map(vec![(text("x"), Value::Integer(2))])),
+                (text("tags"), Value::Array(vec![text("a"), text("b")])),
+            ]),
+            Value::Map(vec![(text("n"), Value::Integer(3)), (text("point"), Value::Null), (text("tags"), Value::Null)]),
+        ];
+        let statement = Value::Map(vec![(text("status"), text("OK")), (text("result"), Value::Array(records))]);
+        let data = crate::encode::encode(&Value::Map(vec![(text("result"), Value::Array(vec![statement]))]));
+        let path = temp_path("records.csv");
+        Python::with_gil(|py| {
+            let write = |delimiter, header| {
+                cbor_to_csv(py, PyBytes::new(py, &data).extract().unwrap(), path.clone(), delimiter, header, 0, None)
+            };
+            write(",", true).unwrap();
+            let expected = "n,point,tags\n1,\"{\"\"x\"\":2}\",\"[\"\"a\"\",\"\"b\"\"]\"\n3,,\n";
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+            write(";", false).unwrap();
+            let expected = "1;\"{\"\"x\"\":2}\";\"[\"\"a\"\",\"\"b\"\"]\"\n3;;\n";
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+            let err = write("::", true).unwrap_err();
+            assert!(err.is_instance_of::<PyValueError>(py), "{}", err);
+        });
+    }
+}
@@ -0,0 +1,66 @@
+//! Flattening of struct columns into dotted top-level columns for `flatten`.
+//!
+//! Records are built with their nested structs as usual and the struct columns
+//! are then replaced by their children, so `{address: {city, zip}}` becomes the
+//! columns `address.city` and `address.zip`. A child is null wherever its
+//! struct is.
+
+use std::sync::Arc;
+
+use arrow::array::{make_array, Array, ArrayRef, StructArray};
+use arrow::buffer::NullBuffer;
+use arrow::datatypes::{DataType, Field, FieldRef};
+use arrow::error::ArrowError;
+
+/// `fields` with struct fields replaced by their children, down to `depth` levels.
+pub(crate) fn flatten_fields(fields: &[FieldRef], depth: usize) -> Vec<FieldRef> {
+    let mut flat = Vec::with_capacity(fields.len());
+    for field in fields {
+        match field.data_type() {
+            DataType::Struct(children) if depth > 0 && !children.is_empty() => {
+                let children: Vec<FieldRef> = children
+                    .iter()
+                    .map(|child| {
+                        let name = format!("{}.{}", field.name(), child.name());
+                        let nullable = child.is_nullable() || field.is_nullable();
+                        Arc::new(Field::clone(child).with_name(name).with_nullable(nullable))
+                    })
+                    .collect();
+                flat.extend(flatten_fields(&children, depth - 1));
+            }
+            _ => flat.push(field.clone()),
+        }
+    }
+    flat
+}
+
+/// The columns of `flatten_fields(fields, depth)` from the columns of `fields`.
+pub(crate) fn flatten_arrays(fields: &[FieldRef], arrays: Vec<ArrayRef>, depth: usize) -> Result<Vec<ArrayRef>, ArrowError> {
+    let mut flat = Vec::with_capacity(arrays.len());
+    for (field, array) in fields.iter().zip(arrays) {
+        match field.data_type() {
+            DataType::Struct(children) if depth > 0 && !children.is_empty() => {
+                let array = array.as_any().downcast_ref::<StructArray>().ok_or_else(|| {
+                    ArrowError::SchemaError(format!("column {} is not a struct array", field.name()))
+                })?;
+                let columns = array
+                    .columns()
+                    .iter()
+                    .map(|child| with_parent_nulls(child, array.nulls()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                flat.extend(flatten_arrays(children, columns, depth - 1)?);
+            }
+            _ => flat.push(array),
+        }
+    }
+    Ok(flat)
+}
+
+/// `child` with its struct's nulls added to its own.
+fn with_parent_nulls(child: &ArrayRef, parent: Option<&NullBuffer>) -> Result<ArrayRef, ArrowError> {
+    if parent.is_none_or(|nulls| nulls.null_count() == 0) {
+        return Ok(child.clone());
+    }
+    let nulls = NullBuffer::union(parent, child.nulls());
+    Ok(make_array(child.to_data().into_builder().nulls(nulls).build()?))
+}
@@ -0,0 +1,353 @@
+//! `SurrealFlightServer`, an Arrow Flight endpoint serving SurrealDB query
+//! results, with arrow-flight and tonic.
+//!
+//! Built with the `flight` feature. Flight clients (Spark, DuckDB, other
+//! services) fetch results with `DoGet`. A ticket is either SurrealQL, run on
+//! the server's `Connection`, or the ticket the `push` action returned for a
+//! CBOR response pushed to the server. Either way the response is converted as
+//! `cbor_to_arrow` converts it, on a blocking thread, and each batch is sent as
+//! soon as it is converted. The server runs on the runtime of the connections.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use arrow::array::RecordBatchReader;
+use arrow_flight::encode::FlightDataEncoderBuilder;
+use arrow_flight::error::FlightError;
+use arrow_flight::flight_service_server::{FlightService, FlightServiceServer};
+use arrow_flight::{
+    Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightInfo, HandshakeRequest, HandshakeResponse,
+    PollInfo, PutResult, SchemaResult, Ticket,
+};
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use pyo3::exceptions::{PyConnectionError, PyOSError, PyRuntimeError, PyTimeoutError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, watch};
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::aio::block_on;
+use crate::client::Connection;
+use crate::export::statement_reader;
+use crate::frames::frame_options;
+use crate::pull::Payload;
+use crate::{ConvertOptions, OutputMode};
+
+/// The prefix of the tickets of pushed responses; any other ticket is SurrealQL.
+const PUSHED_PREFIX: &[u8] = b"pushed:";
+
+/// The most converted batches of a stream waiting to be sent.
+const BUFFERED_BATCHES: usize = 2;
+
+/// An Arrow Flight server converting SurrealDB responses to Arrow streams.
+///
+/// ```python
+/// conn = Connection.connect("ws://localhost:8000")
+/// conn.signin(username="root", password="root")
+/// conn.use_ns_db("test", "test")
+/// server = SurrealFlightServer("grpc://0.0.0.0:8815", conn)
+/// server.serve()
+///
+/// # elsewhere
+/// client = pyarrow.flight.connect("grpc://localhost:8815")
+/// table = client.do_get(pyarrow.flight.Ticket(b"SELECT * FROM person")).read_all()
+/// ```
+///
+/// `location` is the `grpc://host:port` address to listen on; port 0 picks a
+/// free port, read back from `port`. Without a `connection`, only pushed
+/// responses are served. `statement` is the statement of each response to
+/// stream (negative counts from the end), and the keyword options are those of
+/// `cbor_to_arrow` other than `output`.
+///
+/// The action `push` stores the CBOR RPC response of its body and returns the
+/// ticket to fetch it with; `drop` forgets the response of the ticket in its
+/// body. `serve` blocks with the GIL released until `shutdown` is called.
+#[pyclass(frozen)]
+pub(crate) struct SurrealFlightServer {
+    service: Arc<Service>,
+    port: u16,
+    /// The socket listened on, until served.
+    listener: Mutex<Option<TcpListener>>,
+    shutdown: watch::Sender<bool>,
+}
+
+struct Service {
+    connection: Option<Py<Connection>>,
+    statement: isize,
+    opts: ConvertOptions,
+    /// The responses pushed, by ticket.
+    pushed: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+    next_ticket: AtomicU64,
+}
+
+#[pymethods]
+impl SurrealFlightServer {
+    #[new]
+    #[pyo3(signature = (location="grpc://0.0.0.0:8815", connection=None, statement=0, **options))]
+    fn new(
+        py: Python,
+        location: &str,
+        connection: Option<Py<Connection>>,
+        statement: isize,
+        options: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<Self> {
+        let opts = frame_options("SurrealFlightServer", options, OutputMode::Reader)?;
+        let address = ["grpc://", "grpc+tcp://"]
+            .iter()
+            .find_map(|scheme| location.strip_prefix(scheme))
+            .ok_or_else(|| PyOSError::new_err(format!("cannot listen on {:?}: expected a grpc:// location", location)))?;
+        let listener = block_on(py, TcpListener::bind(address.trim_end_matches('/')))
+            .map_err(|e| PyOSError::new_err(format!("cannot listen on {:?}: {}", location, e)))?;
+        let service = Service {
+            connection,
+            statement,
+            opts,
+            pushed: Mutex::new(HashMap::new()),
+            next_ticket: AtomicU64::new(1),
+        };
+        Ok(SurrealFlightServer {
+            service: Arc::new(service),
+            port: listener.local_addr()?.port(),
+            listener: Mutex::new(Some(listener)),
+            shutdown: watch::channel(false).0,
+        })
+    }
+
+    /// The port listened on.
+    #[getter]
+    fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Serve Flight clients until `shutdown` is called.
+    fn serve(&self, py: Python) -> PyResult<()> {
+        let listener = self.listener.lock().unwrap().take();
+        let listener = listener.ok_or_else(|| PyRuntimeError::new_err("the server was served or shut down already"))?;
+        let incoming = TcpIncoming::from_listener(listener, true, None).map_err(|e| PyOSError::new_err(e.to_string()))?;
+        let mut shutdown = self.shutdown.subscribe();
+        let router = tonic::transport::Server::builder().add_service(FlightServiceServer::from_arc(self.service.clone()));
+        let stopped = async move {
+            let _ = shutdown.wait_for(|stop| *stop).await;
+        };
+        block_on(py, router.serve_with_incoming_shutdown(incoming, stopped))
+            .map_err(|e| PyRuntimeError::new_err(format!("the Flight server failed: {}", e)))
+    }
+
+    /// Stop serving, once the requests under way are answered.
+    fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+        self.listener.lock().unwrap().take();
+    }
+}
+
+impl Service {
+    /// The CBOR response a ticket stands for.
+    async fn response(&self, ticket: &[u8]) -> Result<Vec<u8>, Status> {
+        if ticket.starts_with(PUSHED_PREFIX) {
+            let pushed = self.pushed.lock().unwrap().get(ticket).cloned();
+            return pushed.ok_or_else(|| Status::not_found(format!("unknown ticket {}", String::from_utf8_lossy(ticket))));
+        }
+        let Some(connection) = &self.connection else {
+            return Err(Status::failed_precondition("this server has no SurrealDB connection to run queries on"));
+        };
+        let sql = std::str::from_utf8(ticket).map_err(|_| Status::invalid_argument("the ticket is not UTF-8 SurrealQL"))?;
+        connection.get().query_response(sql).await.map_err(py_status)
+    }
+
+    /// The reader of the batches of `response`, converted on a blocking thread.
+    async fn reader(&self, response: Vec<u8>) -> Result<Box<dyn RecordBatchReader + Send>, Status> {
+        let (statement, opts) = (self.statement, self.opts.clone());
+        let reader = tokio::task::spawn_blocking(move || {
+            Python::with_gil(|py| {
+                let payload = Arc::new(Payload::load_owned(py, response, true, &opts)?);
+                statement_reader(py, &[payload], statement, &opts, false)
+            })
+        });
+        reader.await.map_err(|e| Status::internal(e.to_string()))?.map_err(py_status)
+    }
+}
+
+#[tonic::async_trait]
+impl FlightService for Service {
+    type HandshakeStream = BoxStream<'static, Result<HandshakeResponse, Status>>;
+    type ListFlightsStream = BoxStream<'static, Result<FlightInfo, Status>>;
+    type DoGetStream = BoxStream<'static, Result<FlightData, Status>>;
+    type DoPutStream = BoxStream<'static, Result<PutResult, Status>>;
+    type DoExchangeStream = BoxStream<'static, Result<FlightData, Status>>;
+    type DoActionStream = BoxStream<'static, Result<arrow_flight::Result, Status>>;
+    type ListActionsStream = BoxStream<'static, Result<ActionType, Status>>;
+
+    async fn do_get(&self, request: Request<Ticket>) -> Result<Response<Self::DoGetStream>, Status> {
+        let response = self.response(&request.into_inner().ticket).await?;
+        let reader = self.reader(response).await?;
+        let schema = reader.schema();
+        let (tx, rx) = mpsc::channel(BUFFERED_BATCHES);
+        tokio::task::spawn_blocking(move || {
+            for batch in reader {
+                let failed = batch.is_err();
+                if tx.blocking_send(batch.map_err(FlightError::from)).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        let batches = stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|batch| (batch, rx)) });
+        let data = FlightDataEncoderBuilder::new().with_schema(schema).build(batches);
+        Ok(Response::new(data.map_err(|e| Status::internal(e.to_string())).boxed()))
+    }
+
+    async fn do_action(&self, request: Request<Action>) -> Result<Response<Self::DoActionStream>, Status> {
+        let action = request.into_inner();
+        match action.r#type.as_str() {
+            "push" => {
+                let mut ticket = PUSHED_PREFIX.to_vec();
+                ticket.extend_from_slice(self.next_ticket.fetch_add(1, Ordering::Relaxed).to_string().as_bytes());
+                self.pushed.lock().unwrap().insert(ticket.clone(), action.body.to_vec());
+                let result = arrow_flight::Result { body: ticket.into() };
+                Ok(Response::new(stream::once(async { Ok(result) }).boxed()))
+            }
+            "drop" => {
+                self.pushed.lock().unwrap().remove(action.body.as_ref());
+                Ok(Response::new(stream::empty().boxed()))
+            }
+            other => Err(Status::invalid_argument(format!("unknown action {:?}; expected \"push\" or \"drop\"", other))),
+        }
+    }
+
+    async fn list_actions(&self, _request: Request<Empty>) -> Result<Response<Self::ListActionsStream>, Status> {
+        let actions = [
+            ("push", "Store a CBOR response; returns the ticket to fetch it with"),
+            ("drop", "Forget a pushed response"),
+        ]
+        .map(|(name, description)| ActionType { r#type: name.to_string(), description: description.to_string() });
+        Ok(Response::new(stream::iter(actions).map(Ok).boxed()))
+    }
+
+    async fn handshake(
+        &self,
+        _request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        Err(unsupported("Handshake"))
+    }
+
+    async fn list_flights(&self, _request: Request<Criteria>) -> Result<Response<Self::ListFlightsStream>, Status> {
+        Err(unsupported("ListFlights"))
+    }
+
+    async fn get_flight_info(&self, _request: Request<FlightDescriptor>) -> Result<Response<FlightInfo>, Status> {
+        Err(unsupported("GetFlightInfo"))
+    }
+
+    async fn poll_flight_info(&self, _request: Request<FlightDescriptor>) -> Result<Response<PollInfo>, Status> {
+        Err(unsupported("PollFlightInfo"))
+    }
+
+    async fn get_schema(&self, _request: Request<FlightDescriptor>) -> Result<Response<SchemaResult>, Status> {
+        Err(unsupported("GetSchema"))
+    }
+
+    async fn do_put(&self, _request: Request<Streaming<FlightData>>) -> Result<Response<Self::DoPutStream>, Status> {
+        Err(unsupported("DoPut"))
+    }
+
+    async fn do_exchange(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        Err(unsupported("DoExchange"))
+    }
+}
+
+fn unsupported(method: &str) -> Status {
+    Status::unimplemented(format!("{} is not supported; fetch results with DoGet", method))
+}
+
+/// The status of a request failing with `error`.
+fn py_status(error: PyErr) -> Status {
+    Python::with_gil(|py| {
+        let message = error.to_string();
+        if error.is_instance_of::<PyTimeoutError>(py) {
+            Status::deadline_exceeded(message)
+        } else if error.is_instance_of::<PyConnectionError>(py) {
+            Status::unavailable(message)
+        } else {
+            Status::invalid_argument(message)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{AsArray, RecordBatch};
+    use arrow::datatypes::Int64Type;
+    use arrow_flight::FlightClient;
+    use cbor4ii::core::Value;
+    use tonic::transport::Channel;
+    use tonic::Code;
+
+    use super::*;
+    use crate::aio::runtime;
+    use crate::encode::encode;
+
+    fn response() -> Vec<u8> {
+        let text = |s: &str| Value::Text(s.to_string());
+        let records = (0..3).map(|n| Value::Map(vec![(text("n"), Value::Integer(n))])).collect();
+        let statement = Value::Map(vec![
+            (text("status"), text("OK")),
+            (text("time"), text("1ms")),
+            (text("result"), Value::Array(records)),
+        ]);
+        encode(&Value::Map(vec![(text("id"), Value::Integer(1)), (text("result"), Value::Array(vec![statement]))]))
+    }
+
+    fn code<T>(result: Result<T, arrow_flight::error::FlightError>) -> Code {
+        match result {
+            Err(FlightError::Tonic(status)) => status.code(),
+            Err(e) => panic!("{}", e),
+            Ok(_) => panic!("no error"),
+        }
+    }
+
+    #[test]
+    fn pushed_responses_are_streamed() {
+        pyo3::prepare_freethreaded_python();
+        let (server, serving) = Python::with_gil(|py| {
+            let server = SurrealFlightServer::new(py, "grpc://127.0.0.1:0", None, 0, None).unwrap();
+            let server = Py::new(py, server).unwrap();
+            (server.clone_ref(py), server)
+        });
+        let port = server.get().port;
+        let served = std::thread::spawn(move || Python::with_gil(|py| serving.get().serve(py)));
+        runtime().block_on(async {
+            let channel = Channel::from_shared(format!("http://127.0.0.1:{}", port)).unwrap();
+            let mut client = FlightClient::new(channel.connect().await.unwrap());
+            let pushed: Vec<_> =
+                client.do_action(Action::new("push", response())).await.unwrap().try_collect().await.unwrap();
+            let ticket = pushed[0].clone();
+            assert_eq!(ticket, "pushed:1");
+
+            let batches: Vec<RecordBatch> =
+                client.do_get(Ticket::new(ticket.clone())).await.unwrap().try_collect().await.unwrap();
+            let n: Vec<_> =
+                batches.iter().flat_map(|b| b.column(0).as_primitive::<Int64Type>().values().to_vec()).collect();
+            assert_eq!(n, [0, 1, 2]);
+
+            let dropped = client.do_action(Action::new("drop", ticket.clone())).await.unwrap();
+            assert!(dropped.try_collect::<Vec<_>>().await.unwrap().is_empty());
+            assert_eq!(code(client.do_get(Ticket::new(ticket)).await), Code::NotFound);
+            // Without a connection there is nothing to run SurrealQL on.
+            assert_eq!(code(client.do_get(Ticket::new("SELECT * FROM person")).await), Code::FailedPrecondition);
+            assert_eq!(code(client.do_action(Action::new("run", "")).await), Code::InvalidArgument);
+            // A pushed response that is no RPC response fails to convert.
+            let pushed: Vec<_> =
+                client.do_action(Action::new("push", "not cbor")).await.unwrap().try_collect().await.unwrap();
+            assert_eq!(code(client.do_get(Ticket::new(pushed[0].clone())).await), Code::InvalidArgument);
+        });
+        server.get().shutdown();
+        served.join().unwrap().unwrap();
+        let err = Python::with_gil(|py| server.get().serve(py).unwrap_err().to_string());
+        assert_eq!(err, "RuntimeError: the server was served or shut down already");
+    }
+}
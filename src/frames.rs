@@ -0,0 +1,220 @@
+//! Conversion of results to the DataFrames and arrays of Python libraries.
+//!
+//! A statement is converted as `cbor_to_arrow` does and the result handed straight
+//! to the library, so callers do not go through pyarrow themselves. pandas takes
+//! a `pyarrow.Table`, which is not kept, so pandas releases its buffers as the
+//! columns are converted. polars DataFrames are built with pyo3-polars, from
+//! columns imported over the Arrow C data interface.
+
+use std::sync::Arc;
+
+#[cfg(feature = "polars")]
+use arrow::array::{new_empty_array, ArrayRef, RecordBatchReader};
+#[cfg(feature = "polars")]
+use arrow::datatypes::Field;
+#[cfg(feature = "polars")]
+use arrow::ffi::{FFI_ArrowArray, FFI_ArrowSchema};
+#[cfg(feature = "polars")]
+use polars_arrow::ffi as polars_ffi;
+#[cfg(feature = "polars")]
+use polars_core::prelude::{Column, DataFrame, IntoColumn, Series};
+#[cfg(feature = "polars")]
+use pyo3::exceptions::PyValueError;
+use pyo3::exceptions::PyTypeError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+#[cfg(feature = "polars")]
+use pyo3_arrow::PyRecordBatchReader;
+#[cfg(feature = "polars")]
+use pyo3_polars::PyDataFrame;
+
+#[cfg(feature = "polars")]
+use crate::export::batch_error;
+use crate::pull::Payload;
+use crate::{convert_statement_into, numpy, CborInput, ConvertOptions, OutputMode};
+
+/// Convert a statement of a CBOR response to a `pandas.DataFrame`.
+///
+/// `types_mapper` is passed on to `pyarrow.Table.to_pandas`: `pd.ArrowDtype` keeps
+/// every column Arrow-backed instead of converting it to a NumPy dtype. A
+/// statement without records gives an empty DataFrame, unless `empty_schema`
+/// names its columns, and a scalar under `scalar_as="python"` is returned as is.
+/// Accepts the keyword options of `cbor_to_arrow` other than `output`.
+#[pyfunction]
+#[pyo3(signature = (data, statement=0, types_mapper=None, **options))]
+pub(crate) fn cbor_to_pandas(
+    py: Python,
+    data: CborInput,
+    statement: isize,
+    types_mapper: Option<PyObject>,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<PyObject> {
+    let opts = frame_options("cbor_to_pandas", options, OutputMode::Table)?;
+    opts.check_bytes(&data)?;
+    let payload = Arc::new(Payload::load(py, data, true, &opts)?);
+    convert_statement_into(py, &payload, statement, &opts, |result| {
+        let pandas = py.import("pandas")?;
+        let result = result.into_bound(py);
+        if result.is_none() {
+            return Ok(pandas.getattr("DataFrame")?.call0()?.unbind());
+        }
+        if !result.is_instance(&py.import("pyarrow")?.getattr("Table")?)? {
+            return Ok(result.unbind());
+        }
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("types_mapper", types_mapper)?;
+        kwargs.set_item("split_blocks", true)?;
+        kwargs.set_item("self_destruct", true)?;
+        Ok(result.call_method("to_pandas", (), Some(&kwargs))?.unbind())
+    })
+}
+
+/// Convert a statement of a CBOR response to a `polars.DataFrame`.
+///
+/// The DataFrame is built in the accelerator with pyo3-polars, its columns
+/// imported over the Arrow C data interface without copying, so pyarrow is not
+/// needed. A statement without records gives an empty DataFrame, unless
+/// `empty_schema` names its columns, and a scalar under `scalar_as="python"` is
+/// returned as is. Accepts the keyword options of `cbor_to_arrow` other than
+/// `output`.
+#[cfg(feature = "polars")]
+#[pyfunction]
+#[pyo3(signature = (data, statement=0, **options))]
+pub(crate) fn cbor_to_polars(
+    py: Python,
+    data: CborInput,
+    statement: isize,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<PyObject> {
+    let opts = frame_options("cbor_to_polars", options, OutputMode::Stream)?;
+    opts.check_bytes(&data)?;
+    let payload = Arc::new(Payload::load(py, data, true, &opts)?);
+    convert_statement_into(py, &payload, statement, &opts, |result| {
+        let result = result.into_bound(py);
+        if result.is_none() {
+            return Ok(PyDataFrame(DataFrame::empty()).into_pyobject(py)?.unbind());
+        }
+        if !result.hasattr("__arrow_c_stream__")? {
+            return Ok(result.unbind());
+        }
+        let reader = result.extract::<PyRecordBatchReader>()?.into_reader()?;
+        let frame = py.allow_threads(|| data_frame(reader))?;
+        Ok(PyDataFrame(frame).into_pyobject(py)?.unbind())
+    })
+}
+
+/// The batches of `reader` as a polars DataFrame, a column of chunks per field.
+#[cfg(feature = "polars")]
+fn data_frame(reader: Box<dyn RecordBatchReader + Send>) -> PyResult<DataFrame> {
+    let schema = reader.schema();
+    let batches = reader.collect::<Result<Vec<_>, _>>().map_err(batch_error)?;
+    let columns = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match batches.as_slice() {
+            [] => polars_column(field, &[new_empty_array(field.data_type())]),
+            _ => polars_column(field, &batches.iter().map(|batch| batch.column(i).clone()).collect::<Vec<_>>()),
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+    DataFrame::new(columns).map_err(polars_error)
+}
+
+/// Import the chunks of a column into polars over the Arrow C data interface,
+/// sharing their buffers.
+#[cfg(feature = "polars")]
+fn polars_column(field: &Field, chunks: &[ArrayRef]) -> PyResult<Column> {
+    let schema = FFI_ArrowSchema::try_from(field).map_err(polars_error)?;
+    // SAFETY: arrow and polars-arrow define the structs of the C data interface
+    // with the same layout, and polars takes over releasing what was exported.
+    let schema: polars_ffi::ArrowSchema = unsafe { std::mem::transmute(schema) };
+    let field = unsafe { polars_ffi::import_field_from_c(&schema) }.map_err(polars_error)?;
+    let chunks = chunks
+        .iter()
+        .map(|chunk| {
+            let array: polars_ffi::ArrowArray = unsafe { std::mem::transmute(FFI_ArrowArray::new(&chunk.to_data())) };
+            unsafe { polars_ffi::import_array_from_c(array, field.dtype.clone()) }
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(polars_error)?;
+    Ok(Series::try_from((&field, chunks)).map_err(polars_error)?.into_column())
+}
+
+#[cfg(feature = "polars")]
+fn polars_error(error: impl std::fmt::Display) -> PyErr {
+    PyValueError::new_err(format!("cannot convert to polars: {}", error))
+}
+
+/// Convert a statement of a CBOR response to NumPy arrays, for flat numeric results
+/// where Arrow is more than is needed.
+///
+/// Returns what `cbor_to_arrow` does with `output="numpy"`: a dict from column
+/// names to arrays, masked arrays for columns with nulls. `structured=True`
+/// returns a structured array with a field per column instead, which cannot hold
+/// nulls. A statement without records gives `None`, unless `empty_schema` names
+/// its columns. Accepts the keyword options of `cbor_to_arrow` other than `output`.
+#[pyfunction]
+#[pyo3(signature = (data, statement=0, structured=false, **options))]
+pub(crate) fn cbor_to_numpy(
+    py: Python,
+    data: CborInput,
+    statement: isize,
+    structured: bool,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<PyObject> {
+    let opts = frame_options("cbor_to_numpy", options, OutputMode::Numpy)?;
+    opts.check_bytes(&data)?;
+    let payload = Arc::new(Payload::load(py, data, true, &opts)?);
+    convert_statement_into(py, &payload, statement, &opts, |result| match result.downcast_bound::<PyDict>(py) {
+        Ok(arrays) if structured => Ok(numpy::structured(arrays)?.unbind()),
+        _ => Ok(result),
+    })
+}
+
+/// The options for the conversion function `function`, which converts to `output`
+/// whatever the caller asks.
+pub(crate) fn frame_options(function: &str, options: Option<&Bound<'_, PyDict>>, output: OutputMode) -> PyResult<ConvertOptions> {
+    if let Some(options) = options {
+        if options.contains("output")? {
+            return Err(PyTypeError::new_err(format!("{}() does not take the 'output' option", function)));
+        }
+    }
+    Ok(ConvertOptions { output, ..ConvertOptions::from_kwargs(options)? })
+}
+
+#[cfg(all(test, feature = "polars"))]
+mod tests {
+    use arrow::array::{Int64Array, RecordBatch, RecordBatchIterator, StringArray, StructArray};
+    use arrow::datatypes::DataType;
+    use polars_core::prelude::DataType as PolarsType;
+
+    use super::*;
+
+    fn batch(start: i64) -> RecordBatch {
+        let n = Arc::new(Int64Array::from(vec![Some(start), None])) as ArrayRef;
+        let name = Arc::new(StringArray::from(vec!["a", "b"])) as ArrayRef;
+        let point = StructArray::from(vec![(Arc::new(Field::new("x", DataType::Int64, true)), n.clone())]);
+        RecordBatch::try_from_iter([("n", n), ("name", name), ("point", Arc::new(point) as _)]).unwrap()
+    }
+
+    #[test]
+    fn data_frames_take_every_batch() {
+        let batches = [batch(1), batch(3)];
+        let reader = RecordBatchIterator::new(batches.clone().map(Ok), batches[0].schema());
+        let frame = data_frame(Box::new(reader)).unwrap();
+        assert_eq!(frame.shape(), (4, 3));
+        let n: Vec<_> = frame.column("n").unwrap().i64().unwrap().into_iter().collect();
+        assert_eq!(n, [Some(1), None, Some(3), None]);
+        let names: Vec<_> = frame.column("name").unwrap().str().unwrap().into_iter().flatten().collect();
+        assert_eq!(names, ["a", "b", "a", "b"]);
+        assert!(matches!(frame.column("point").unwrap().dtype(), PolarsType::Struct(_)));
+    }
+
+    #[test]
+    fn empty_readers_keep_their_columns() {
+        let reader = RecordBatchIterator::new(Vec::new(), batch(0).schema());
+        let frame = data_frame(Box::new(reader)).unwrap();
+        assert_eq!(frame.shape(), (0, 3));
+        assert_eq!(frame.get_column_names_str(), ["n", "name", "point"]);
+    }
+}
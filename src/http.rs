@@ -0,0 +1,118 @@
+//! The HTTP transport of `Connection`, for where WebSockets are blocked, with reqwest.
+//!
+//! Each request is a `POST` of its CBOR frame to SurrealDB's `/rpc` endpoint,
+//! asking for a CBOR response. HTTP requests share no session, so the namespace,
+//! database and token a WebSocket session would hold are kept here and sent as
+//! headers with every request. reqwest keeps connections alive between
+//! requests, and several requests may be under way at once.
+
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use cbor4ii::core::Value;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::{Client, RequestBuilder, StatusCode};
+use rustls::ClientConfig;
+
+use crate::rpc::{next_id, request_with_id};
+use crate::websocket::Endpoint;
+
+pub(crate) struct HttpSession {
+    client: Client,
+    url: String,
+    pub(crate) namespace: Option<String>,
+    pub(crate) database: Option<String>,
+    pub(crate) token: Option<String>,
+}
+
+impl HttpSession {
+    /// A session with `endpoint`, pinged to check that it can be reached.
+    /// `timeout` bounds each request, response included.
+    pub(crate) async fn connect(
+        endpoint: &Endpoint,
+        connect_timeout: Option<Duration>,
+        timeout: Option<Duration>,
+        tls: Option<Arc<ClientConfig>>,
+    ) -> io::Result<HttpSession> {
+        let mut builder = Client::builder();
+        if let Some(connect_timeout) = connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if let Some(timeout) = timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(tls) = tls {
+            builder = builder.use_preconfigured_tls(ClientConfig::clone(&tls));
+        }
+        let client = builder.build().map_err(http_error)?;
+        let session = HttpSession { client, url: endpoint.url(), namespace: None, database: None, token: None };
+        send(session.request(request_with_id(Value::Text(next_id()), "ping", Vec::new()))?).await?;
+        Ok(session)
+    }
+
+    /// The request posting `frame`, with the headers of the session.
+    pub(crate) fn request(&self, frame: Vec<u8>) -> io::Result<RequestBuilder> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/cbor")
+            .header(ACCEPT, "application/cbor")
+            .body(frame);
+        let session = [
+            ("Surreal-NS", "", &self.namespace),
+            ("Surreal-DB", "", &self.database),
+            ("Authorization", "Bearer ", &self.token),
+        ];
+        for (name, prefix, value) in session {
+            let Some(value) = value else { continue };
+            if value.contains(['\r', '\n']) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("line break in the {} header", name)));
+            }
+            request = request.header(name, format!("{}{}", prefix, value));
+        }
+        Ok(request)
+    }
+}
+
+/// Send `request` and return the response body. Once the request was sent the
+/// server may have run it, so the connection lost before the response is an
+/// error.
+pub(crate) async fn send(request: RequestBuilder) -> io::Result<Vec<u8>> {
+    let response = request.send().await.map_err(http_error)?;
+    let status = response.status();
+    let is_cbor = response.headers().get(CONTENT_TYPE).is_some_and(|v| v.as_bytes().starts_with(b"application/cbor"));
+    let body = response.bytes().await.map_err(http_error)?;
+    match (status, is_cbor) {
+        // Errors of RPC requests come as CBOR responses, whatever the status.
+        (StatusCode::OK, _) | (_, true) => Ok(body.into()),
+        _ => Err(io::Error::other(format!(
+            "HTTP status {}: {}",
+            status.as_u16(),
+            String::from_utf8_lossy(&body).trim()
+        ))),
+    }
+}
+
+/// A reqwest error as an I/O error: `ConnectionRefused` when the request was
+/// not sent, `TimedOut`, `InvalidInput` when it could not be built, and
+/// `ConnectionAborted` when the connection was lost after it was sent.
+fn http_error(error: reqwest::Error) -> io::Error {
+    let kind = if error.is_connect() {
+        io::ErrorKind::ConnectionRefused
+    } else if error.is_timeout() {
+        io::ErrorKind::TimedOut
+    } else if error.is_builder() {
+        io::ErrorKind::InvalidInput
+    } else {
+        io::ErrorKind::ConnectionAborted
+    };
+    // reqwest keeps the cause out of its message.
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(&error);
+    while let Some(cause) = source {
+        message.push_str(&format!(": {}", cause));
+        source = cause.source();
+    }
+    io::Error::new(kind, message)
+}
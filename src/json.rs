@@ -0,0 +1,132 @@
+//! JSON rendering of decoded responses for `cbor_to_json`.
+//!
+//! SurrealDB values are rendered in their canonical text forms (record ids as
+//! `table:key`, datetimes in RFC 3339, durations as SurrealQL literals, UUIDs
+//! hyphenated, decimals and ranges as SurrealQL writes them), so the output
+//! reads like the response did. Unlike the Arrow conversion nothing is dropped:
+//! unknown tags give their payload, and non-text object keys their string form.
+//! `cbor_to_ndjson` writes the records of a statement the same way, one per line.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use cbor4ii::core::Value;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use serde::ser::{SerializeMap, SerializeSeq};
+use serde::{Serialize, Serializer};
+
+use crate::errors::SurrealEngineError;
+use crate::tags::{self, SurrealTag};
+use crate::{coerce, envelope_records, key_string, surrealql, CborInput, MapKeys};
+
+/// Render a CBOR response, or any CBOR value, as JSON text; indented with
+/// `pretty=True`.
+///
+/// Bytes become lowercase hex strings and integers beyond 128 bits strings of
+/// their digits. For logging, debugging and tools that only read JSON.
+#[pyfunction]
+#[pyo3(signature = (data, pretty=false))]
+pub(crate) fn cbor_to_json(py: Python, data: CborInput, pretty: bool) -> PyResult<String> {
+    let root = data.decode(py)?;
+    py.allow_threads(|| match pretty {
+        true => serde_json::to_string_pretty(&Json(&root)),
+        false => serde_json::to_string(&Json(&root)),
+    })
+    .map_err(|e| SurrealEngineError::new_err(format!("JSON rendering error: {}", e)))
+}
+
+/// Write the records of a statement of a CBOR response as JSON Lines (NDJSON):
+/// one record per line, rendered as `cbor_to_json` renders values.
+///
+/// The lines are streamed to the file at `path`, which is replaced, or returned
+/// as `bytes` if `path` is `None`. With `envelope=False`, `data` is a bare array
+/// of records instead of an RPC response. For bulk loaders that read JSON Lines.
+#[pyfunction]
+#[pyo3(signature = (data, path=None, statement=0, envelope=true))]
+pub(crate) fn cbor_to_ndjson(
+    py: Python,
+    data: CborInput,
+    path: Option<PathBuf>,
+    statement: isize,
+    envelope: bool,
+) -> PyResult<Option<PyObject>> {
+    let root = data.decode(py)?;
+    let records = envelope_records(&root, statement, envelope)?.unwrap_or_default();
+    match path {
+        Some(path) => {
+            py.allow_threads(|| -> std::io::Result<()> {
+                let mut out = BufWriter::new(File::create(path)?);
+                write_lines(records, &mut out)?;
+                out.flush()
+            })?;
+            Ok(None)
+        }
+        None => {
+            let mut out = Vec::new();
+            py.allow_threads(|| write_lines(records, &mut out))?;
+            Ok(Some(PyBytes::new(py, &out).into_any().unbind()))
+        }
+    }
+}
+
+fn write_lines(records: &[Value], out: &mut impl Write) -> std::io::Result<()> {
+    for record in records {
+        serde_json::to_writer(&mut *out, &Json(record))?;
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// A value serialized as `cbor_to_json` renders it.
+struct Json<'a>(&'a Value);
+
+impl Serialize for Json<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Value::Null => serializer.serialize_none(),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Integer(i) => serializer.serialize_i128(*i),
+            Value::Float(f) => serializer.serialize_f64(*f),
+            Value::Text(s) => serializer.serialize_str(s),
+            Value::Bytes(b) => serializer.serialize_str(&b.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+            Value::Array(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(&Json(item))?;
+                }
+                seq.end()
+            }
+            Value::Map(entries) => {
+                let mut m = serializer.serialize_map(Some(entries.len()))?;
+                for (k, v) in entries {
+                    m.serialize_entry(&key_string(k, MapKeys::Display), &Json(v))?;
+                }
+                m.end()
+            }
+            Value::Tag(tag, payload) => match SurrealTag::of(*tag) {
+                SurrealTag::None => serializer.serialize_none(),
+                SurrealTag::BigNum => match tags::bignum_i128(*tag, payload) {
+                    Some(i) => serializer.serialize_i128(i),
+                    None => match tags::bignum_string(*tag, payload) {
+                        Some(digits) => serializer.serialize_str(&digits),
+                        None => Json(payload).serialize(serializer),
+                    },
+                },
+                SurrealTag::Range => {
+                    let mut text = String::new();
+                    match surrealql::write_value(self.0, &mut text) {
+                        Some(()) => serializer.serialize_str(&text),
+                        None => Json(payload).serialize(serializer),
+                    }
+                }
+                _ => match coerce::to_text(self.0) {
+                    Some(text) => serializer.serialize_str(&text),
+                    None => Json(payload).serialize(serializer),
+                },
+            },
+            _ => serializer.serialize_none(),
+        }
+    }
+}
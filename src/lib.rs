@@ -1,26 +1,821 @@
 use pyo3::prelude::*;
 use pyo3::wrap_pyfunction;
-use pyo3::types::PyBytes;
-use arrow::pyarrow::ToPyArrow;
-use arrow::datatypes::{FieldRef, Schema};
-use arrow::array::RecordBatch;
-use serde_json::Value as JsonValue; // Keep for fallback if needed, but we try not to use it
+use pyo3::exceptions::{PyIndexError, PyRuntimeError, PyTypeError, PyValueError};
+use pyo3::buffer::PyBuffer;
+use pyo3::types::{PyDict, PyList};
+use arrow::pyarrow::{FromPyArrow, ToPyArrow};
+use arrow::datatypes::{
+    DataType, Decimal256Type, DecimalType, Field, FieldRef, Schema, SchemaRef, TimeUnit, DECIMAL128_MAX_PRECISION,
+    DECIMAL256_MAX_PRECISION,
+};
+use arrow::array::{ArrayRef, RecordBatch, RecordBatchIterator};
+use arrow::compute::concat_batches;
 use serde_arrow::schema::{SchemaLike, TracingOptions};
-use std::sync::Arc;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, PoisonError};
+use std::time::Duration;
 use serde::{Serialize, Serializer};
-use cbor4ii::core::{Value, utils::SliceReader, dec::Decode};
+use rayon::prelude::*;
+use cbor4ii::core::Value;
+
+#[cfg(feature = "asyncio")]
+mod aio;
+mod builder;
+mod cache;
+#[cfg(feature = "client")]
+mod client;
+mod coerce;
+#[cfg(feature = "deltalake")]
+mod delta;
+mod encode;
+mod errors;
+mod explode;
+mod export;
+mod flatten;
+#[cfg(feature = "flight")]
+mod flight;
+mod frames;
+#[cfg(feature = "client")]
+mod http;
+mod json;
+mod live;
+mod numpy;
+mod output;
+mod paginate;
+#[cfg(feature = "client")]
+mod pool;
+mod pull;
+mod pyvalue;
+#[cfg(feature = "datafusion")]
+mod query;
+mod reader;
+mod router;
+mod rpc;
+mod schema;
+mod select;
+mod stream;
+mod surrealql;
+mod tags;
+#[cfg(feature = "client")]
+mod tls;
+#[cfg(feature = "client")]
+mod websocket;
+
+use errors::{ArrowBuildError, CborDecodeError, LossyConversionWarning, QueryStatusError, SchemaInferenceError, SurrealEngineError};
+use pull::{Payload, Rows, Spans};
+use select::Selection;
+use tags::{tag_kind, SurrealTag};
+
+/// How UUID-tagged values are emitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum UuidMode {
+    /// Canonical lowercase hyphenated string (Utf8 column).
+    #[default]
+    String,
+    /// Raw 16 bytes (FixedSizeBinary(16) column).
+    Binary,
+}
+
+/// How RecordID-tagged values are emitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum RecordIdMode {
+    /// `"table:id"` strings.
+    #[default]
+    String,
+    /// A `{tb, id}` struct of strings.
+    Struct,
+    /// Top-level record id columns become two string columns, e.g. `id__tb` and
+    /// `id__key`. Nested links fall back to strings.
+    Split,
+}
+
+/// Column name suffixes used by `RecordIdMode::Split`.
+#[derive(Debug, Clone)]
+struct RecordIdSuffixes {
+    table: String,
+    key: String,
+}
+
+impl Default for RecordIdSuffixes {
+    fn default() -> Self {
+        Self {
+            table: "__tb".to_string(),
+            key: "__key".to_string(),
+        }
+    }
+}
+
+/// What to do with CBOR tags that are not part of the SurrealDB protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum UnknownTagPolicy {
+    /// Drop the tag and convert the payload.
+    #[default]
+    Ignore,
+    /// Fail the conversion.
+    Error,
+    /// Keep a `{tag, value}` struct so protocol drift stays visible.
+    Raw,
+}
+
+/// Which inferred string columns are dictionary-encoded as `Dictionary<Int32, Utf8>`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+enum DictionaryColumns {
+    #[default]
+    None,
+    All,
+    Named(Vec<String>),
+}
+
+impl DictionaryColumns {
+    fn encodes(&self, name: &str) -> bool {
+        match self {
+            DictionaryColumns::None => false,
+            DictionaryColumns::All => true,
+            DictionaryColumns::Named(names) => names.iter().any(|n| n == name),
+        }
+    }
+}
+
+/// How object fields are converted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum ObjectsAs {
+    #[default]
+    Struct,
+    /// Arrow maps; the same as `map_as_struct=False`.
+    Map,
+    /// JSON text.
+    Json,
+}
+
+/// How non-text keys of CBOR maps become field names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum MapKeys {
+    /// The string form of the key: `5`, `true`, a record id as `table:key`.
+    #[default]
+    Display,
+    /// The JSON text of the key.
+    Json,
+    /// Fail the conversion.
+    Error,
+}
+
+/// How integers outside the `i64` and `u64` ranges are converted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum BigInt {
+    /// Fail the conversion.
+    #[default]
+    Error,
+    /// The column holding them becomes `Decimal256(76, 0)`.
+    Decimal,
+    /// The column holding them becomes a string column of the decimal digits.
+    String,
+    /// They become nulls.
+    Null,
+}
+
+/// Which entry of an object with a repeated key is converted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum DuplicateKeys {
+    /// The last value, in the position of the first, as a Python dict keeps it.
+    #[default]
+    Last,
+    First,
+    /// Fail the conversion.
+    Error,
+}
+
+/// Order of the top-level columns of an inferred schema.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+enum ColumnOrder {
+    /// By name.
+    #[default]
+    Sorted,
+    /// In the order the records first have each field.
+    Insertion,
+    /// The named fields first, in this order, then the others by name.
+    Listed(Vec<String>),
+}
+
+/// How schema inference handles a field whose type differs between records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum TypeConflicts {
+    /// Fail the conversion.
+    #[default]
+    Error,
+    /// Widen integers to floats; anything else that does not combine becomes text.
+    Promote,
+    /// Turn every conflicting field into text.
+    Stringify,
+    /// Widen integers to floats; anything else that does not combine becomes the
+    /// JSON text of each value, in an `arrow.json` column.
+    Json,
+    /// Widen integers to floats; anything else that does not combine becomes an
+    /// Arrow union with a variant per type.
+    Union,
+}
+
+/// What to do when a statement in a response has a non-`OK` status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum StatementErrorPolicy {
+    /// Fail the whole conversion.
+    #[default]
+    Raise,
+    /// Return `None` in place of the failed statement.
+    Skip,
+    /// Like `Skip`, and also return the errors alongside the results.
+    Collect,
+}
+
+/// What to do with a record that does not convert to the schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum RowErrorPolicy {
+    /// Fail the whole conversion.
+    #[default]
+    Raise,
+    /// Leave the record out of the result.
+    Skip,
+    /// Convert the record as a row of nulls.
+    Null,
+}
+
+/// A way a value is converted with a loss of information, counted in
+/// `ConvertOptions::losses` and warned about with `LossyConversionWarning`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Loss {
+    /// A tag converted as its bare payload: an unknown tag under
+    /// `on_unknown_tag="ignore"`, or a future, stray range bound or undecodable
+    /// record id without `strict_tags`.
+    Tag,
+    /// A non-text object key converted as its string form.
+    Key,
+    /// A value of a repeated object key left out as `duplicate_keys` says.
+    RepeatedKey,
+    /// An integer outside the 64-bit ranges converted as a null.
+    BigInt,
+}
+
+impl Loss {
+    const ALL: [Loss; 4] = [Loss::Tag, Loss::Key, Loss::RepeatedKey, Loss::BigInt];
+
+    fn describe(self, opts: &ConvertOptions) -> &'static str {
+        match self {
+            Loss::Tag => "tagged values converted as their untagged payload",
+            Loss::Key => "non-string object keys converted as strings",
+            Loss::RepeatedKey if opts.duplicate_keys == DuplicateKeys::First => {
+                "repeated object keys converted with only their first value"
+            }
+            Loss::RepeatedKey => "repeated object keys converted with only their last value",
+            Loss::BigInt => "integers outside the 64-bit ranges converted as nulls",
+        }
+    }
+}
+
+/// How a statement whose result is a single scalar (e.g. `RETURN count(...)`) is returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum ScalarMode {
+    /// A 1x1 table.
+    #[default]
+    Table,
+    /// The plain Python value.
+    Python,
+}
+
+/// What the conversion functions return for each result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum OutputMode {
+    /// A single batch exposing `__arrow_c_array__`.
+    #[default]
+    Batch,
+    /// A single `pyarrow.RecordBatch`.
+    PyArrow,
+    /// A `pyarrow.RecordBatchReader` converting the records lazily.
+    Reader,
+    /// A lazily converted stream exposing `__arrow_c_stream__`.
+    Stream,
+    /// A `pyarrow.Table`, chunked by `max_rows_per_batch`.
+    Table,
+    /// A dict of NumPy arrays, one per column.
+    Numpy,
+}
+
+/// Smallest number of rows worth handing to a separate conversion thread.
+const PARALLEL_MIN_ROWS: usize = 50_000;
+
+/// Column name used for scalar results when `value_column` is not given.
+const DEFAULT_VALUE_COLUMN: &str = "value";
+
+/// Keyword options accepted by the conversion functions.
+#[derive(Debug, Clone, Default)]
+struct ConvertOptions {
+    uuid_mode: UuidMode,
+    record_id_mode: RecordIdMode,
+    record_id_suffixes: RecordIdSuffixes,
+    /// Raise instead of degrading tags that cannot be converted losslessly.
+    strict_tags: bool,
+    on_unknown_tag: UnknownTagPolicy,
+    on_statement_error: StatementErrorPolicy,
+    on_row_error: RowErrorPolicy,
+    /// The caller's list the records left out or nulled under `on_row_error` are
+    /// appended to; without one they are warned about.
+    row_errors: Option<Arc<Py<PyList>>>,
+    /// The row index and error of each record left out or nulled since they were
+    /// last reported; shared by clones of the options.
+    rejected_rows: Arc<Mutex<Vec<(usize, String)>>>,
+    /// Tag WKB geometry columns with the `geoarrow.wkb` extension type.
+    geoarrow: bool,
+    /// Embed each statement's `time` and `status` in the schema metadata.
+    with_metadata: bool,
+    /// Schema of the empty batch returned for statements without records.
+    empty_schema: Option<SchemaRef>,
+    /// Declared schema to convert to instead of inferring one.
+    schema: Option<SchemaRef>,
+    /// Remember the inferred schema under this key and reuse it while records fit it.
+    schema_cache_key: Option<String>,
+    /// Column name for scalar results such as `SELECT VALUE`.
+    value_column: Option<String>,
+    scalar_as: ScalarMode,
+    output: OutputMode,
+    /// Split each result into batches of at most this many rows.
+    max_rows_per_batch: Option<usize>,
+    /// Worker threads for converting large results; defaults to the available parallelism.
+    num_threads: Option<usize>,
+    /// serde_arrow options for schema inference. `map_as_struct` is applied after tracing.
+    tracing: TracingOptions,
+    /// Infer the schema from only this many leading records; `None` traces them all.
+    infer_samples: Option<usize>,
+    type_conflicts: TypeConflicts,
+    big_int: BigInt,
+    /// Set on the copy of the options records are traced with, so that big
+    /// integers trace as integers and `refine_field` picks their column type.
+    tracing_records: bool,
+    /// Build `type_conflicts="union"` columns as sparse unions instead of dense ones.
+    sparse_unions: bool,
+    dictionary: DictionaryColumns,
+    /// Top-level fields to convert; the other members of each record are skipped.
+    columns: Option<Vec<String>>,
+    column_order: ColumnOrder,
+    objects_as: ObjectsAs,
+    map_keys: MapKeys,
+    duplicate_keys: DuplicateKeys,
+    /// The number of values of each kind of `Loss` converted since they were last
+    /// warned about; shared by clones of the options.
+    losses: Arc<[AtomicUsize; Loss::ALL.len()]>,
+    /// List columns whose elements become rows.
+    explode: Vec<String>,
+    /// Replace struct columns by their children down to this many levels.
+    flatten: Option<usize>,
+    /// Output names of top-level fields, by field name.
+    rename: HashMap<String, String>,
+    /// Replace the characters of output column names other than ASCII letters,
+    /// digits and `_` with this.
+    sanitize_names: Option<String>,
+    /// Members selected by the `include` and `exclude` patterns.
+    select: Option<Selection>,
+    /// Infer 32-bit offset string, binary and list types instead of serde_arrow's
+    /// 64-bit ones (`large_types=False`).
+    small_types: bool,
+    /// Resource limits: rows per result, input bytes, and rows times columns per result.
+    max_rows: Option<usize>,
+    max_bytes: Option<usize>,
+    max_cells: Option<usize>,
+}
+
+impl ConvertOptions {
+    fn from_kwargs(kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let mut opts = Self::default();
+        let Some(kwargs) = kwargs else {
+            return Ok(opts);
+        };
+        let mut unknown_tag_given = false;
+        let (mut include, mut exclude) = (Vec::new(), Vec::new());
+        // `mixed_types`, the first option for columns of mixed types, is an alias of
+        // `type_conflicts="json"` and `"error"`.
+        let (mut type_conflicts, mut mixed_types) = (None, None);
+        for (key, value) in kwargs.iter() {
+            let key: String = key.extract()?;
+            match key.as_str() {
+                "uuid_mode" => {
+                    opts.uuid_mode = parse_choice(&key, &value, &[("string", UuidMode::String), ("binary", UuidMode::Binary)])?
+                }
+                "record_id_mode" => {
+                    opts.record_id_mode = parse_choice(
+                        &key,
+                        &value,
+                        &[
+                            ("string", RecordIdMode::String),
+                            ("struct", RecordIdMode::Struct),
+                            ("split", RecordIdMode::Split),
+                        ],
+                    )?
+                }
+                "record_id_suffixes" => {
+                    let (table, key): (String, String) = value.extract()?;
+                    opts.record_id_suffixes = RecordIdSuffixes { table, key };
+                }
+                "geoarrow" => opts.geoarrow = value.extract()?,
+                "strict_tags" => opts.strict_tags = value.extract()?,
+                "with_metadata" => opts.with_metadata = value.extract()?,
+                "value_column" => opts.value_column = Some(value.extract()?),
+                "scalar_as" => {
+                    opts.scalar_as = parse_choice(&key, &value, &[("table", ScalarMode::Table), ("python", ScalarMode::Python)])?
+                }
+                "output" => {
+                    opts.output = parse_choice(
+                        &key,
+                        &value,
+                        &[
+                            ("batch", OutputMode::Batch),
+                            ("numpy", OutputMode::Numpy),
+                            ("pyarrow", OutputMode::PyArrow),
+                            ("reader", OutputMode::Reader),
+                            ("stream", OutputMode::Stream),
+                            ("table", OutputMode::Table),
+                        ],
+                    )?
+                }
+                "max_rows_per_batch" => opts.max_rows_per_batch = Some(parse_positive(&key, &value)?),
+                "num_threads" => opts.num_threads = Some(parse_positive(&key, &value)?),
+                "max_rows" => opts.max_rows = Some(parse_positive(&key, &value)?),
+                "max_bytes" => opts.max_bytes = Some(parse_positive(&key, &value)?),
+                "max_cells" => opts.max_cells = Some(parse_positive(&key, &value)?),
+                "empty_schema" => opts.empty_schema = Some(Arc::new(Schema::from_pyarrow_bound(&value)?)),
+                "schema" => opts.schema = Some(Arc::new(Schema::from_pyarrow_bound(&value)?)),
+                "schema_cache_key" => opts.schema_cache_key = Some(value.extract()?),
+                "coerce_numbers" => opts.tracing.coerce_numbers = value.extract()?,
+                "allow_to_string" => opts.tracing.allow_to_string = value.extract()?,
+                "allow_null_fields" => opts.tracing.allow_null_fields = value.extract()?,
+                "string_dictionary_encoding" => opts.tracing.string_dictionary_encoding = value.extract()?,
+                "guess_dates" => opts.tracing.guess_dates = value.extract()?,
+                "map_as_struct" => opts.tracing.map_as_struct = value.extract()?,
+                "explode" => opts.explode = value.extract()?,
+                "flatten" => {
+                    opts.flatten = match value.extract::<bool>() {
+                        Ok(true) => Some(usize::MAX),
+                        Ok(false) => None,
+                        Err(_) => Some(parse_positive(&key, &value)?),
+                    }
+                }
+                "objects_as" => {
+                    opts.objects_as = parse_choice(
+                        &key,
+                        &value,
+                        &[("struct", ObjectsAs::Struct), ("map", ObjectsAs::Map), ("json", ObjectsAs::Json)],
+                    )?;
+                    opts.tracing.map_as_struct = opts.objects_as != ObjectsAs::Map;
+                }
+                "map_keys" => {
+                    opts.map_keys = parse_choice(
+                        &key,
+                        &value,
+                        &[("display", MapKeys::Display), ("json", MapKeys::Json), ("error", MapKeys::Error)],
+                    )?
+                }
+                "duplicate_keys" => {
+                    opts.duplicate_keys = parse_choice(
+                        &key,
+                        &value,
+                        &[("last", DuplicateKeys::Last), ("first", DuplicateKeys::First), ("error", DuplicateKeys::Error)],
+                    )?
+                }
+                "infer_samples" => {
+                    opts.infer_samples = match value.extract::<String>() {
+                        Ok(s) if s == "all" => None,
+                        Ok(s) => {
+                            return Err(PyValueError::new_err(format!(
+                                "infer_samples must be a positive integer or 'all', got '{}'",
+                                s
+                            )))
+                        }
+                        Err(_) => Some(parse_positive(&key, &value)?),
+                    }
+                }
+                "columns" => opts.columns = Some(value.extract()?),
+                "rename" => opts.rename = value.extract()?,
+                "sanitize_names" => {
+                    opts.sanitize_names = match value.extract::<bool>() {
+                        Ok(sanitize) => sanitize.then(|| "_".to_string()),
+                        Err(_) => {
+                            let replacement: String = value.extract().map_err(|_| {
+                                PyTypeError::new_err("sanitize_names must be a bool or a replacement string")
+                            })?;
+                            // Anything else could leave names unsafe, or empty.
+                            if !is_safe_name(&replacement) {
+                                return Err(PyValueError::new_err(format!(
+                                    "the sanitize_names replacement must be ASCII letters, digits and '_', \
+                                     not starting with a digit, got {:?}",
+                                    replacement
+                                )));
+                            }
+                            Some(replacement)
+                        }
+                    }
+                }
+                "column_order" => {
+                    opts.column_order = match value.extract::<Vec<String>>() {
+                        Ok(names) if !value.is_instance_of::<pyo3::types::PyString>() => ColumnOrder::Listed(names),
+                        _ => match parse_choice(&key, &value, &[("sorted", false), ("insertion", true)])? {
+                            true => ColumnOrder::Insertion,
+                            false => ColumnOrder::Sorted,
+                        },
+                    }
+                }
+                "include" => include = select::parse_patterns(&key, &value)?,
+                "exclude" => exclude = select::parse_patterns(&key, &value)?,
+                "large_types" => opts.small_types = !value.extract::<bool>()?,
+                "dictionary" => {
+                    opts.dictionary = match value.extract::<bool>() {
+                        Ok(true) => DictionaryColumns::All,
+                        Ok(false) => DictionaryColumns::None,
+                        Err(_) => DictionaryColumns::Named(value.extract().map_err(|_| {
+                            PyTypeError::new_err("dictionary must be a bool or a list of column names")
+                        })?),
+                    }
+                }
+                "on_statement_error" => {
+                    opts.on_statement_error = parse_choice(
+                        &key,
+                        &value,
+                        &[
+                            ("raise", StatementErrorPolicy::Raise),
+                            ("skip", StatementErrorPolicy::Skip),
+                            ("collect", StatementErrorPolicy::Collect),
+                        ],
+                    )?
+                }
+                "on_row_error" => {
+                    opts.on_row_error = parse_choice(
+                        &key,
+                        &value,
+                        &[("raise", RowErrorPolicy::Raise), ("skip", RowErrorPolicy::Skip), ("null", RowErrorPolicy::Null)],
+                    )?
+                }
+                "row_errors" => {
+                    let list = value.downcast::<PyList>().map_err(|_| PyTypeError::new_err("row_errors must be a list"))?;
+                    opts.row_errors = Some(Arc::new(list.clone().unbind()));
+                }
+                "big_int" => {
+                    opts.big_int = parse_choice(
+                        &key,
+                        &value,
+                        &[
+                            ("error", BigInt::Error),
+                            ("decimal", BigInt::Decimal),
+                            ("string", BigInt::String),
+                            ("null", BigInt::Null),
+                        ],
+                    )?
+                }
+                "type_conflicts" => {
+                    let choice = parse_choice(
+                        &key,
+                        &value,
+                        &[
+                            ("error", TypeConflicts::Error),
+                            ("promote", TypeConflicts::Promote),
+                            ("stringify", TypeConflicts::Stringify),
+                            ("json", TypeConflicts::Json),
+                            ("union", TypeConflicts::Union),
+                        ],
+                    )?;
+                    type_conflicts = Some((value.extract::<String>()?, choice));
+                }
+                "union_mode" => opts.sparse_unions = parse_choice(&key, &value, &[("dense", false), ("sparse", true)])?,
+                "mixed_types" => {
+                    let choice =
+                        parse_choice(&key, &value, &[("error", TypeConflicts::Error), ("json", TypeConflicts::Json)])?;
+                    mixed_types = Some((value.extract::<String>()?, choice));
+                }
+                "on_unknown_tag" => {
+                    unknown_tag_given = true;
+                    opts.on_unknown_tag = parse_choice(
+                        &key,
+                        &value,
+                        &[
+                            ("ignore", UnknownTagPolicy::Ignore),
+                            ("error", UnknownTagPolicy::Error),
+                            ("raw", UnknownTagPolicy::Raw),
+                        ],
+                    )?
+                }
+                _ => return Err(PyTypeError::new_err(format!("unexpected keyword argument '{}'", key))),
+            }
+        }
+        opts.type_conflicts = match (type_conflicts, mixed_types) {
+            (Some((given, choice)), Some((mixed, other))) if choice != other => {
+                return Err(PyValueError::new_err(format!(
+                    "type_conflicts='{}' and mixed_types='{}' disagree; pass only type_conflicts",
+                    given, mixed
+                )))
+            }
+            (Some((_, choice)), _) | (None, Some((_, choice))) => choice,
+            (None, None) => opts.type_conflicts,
+        };
+        if opts.strict_tags && !unknown_tag_given {
+            opts.on_unknown_tag = UnknownTagPolicy::Error;
+        }
+        if opts.empty_schema.is_none() {
+            opts.empty_schema = opts.schema.clone();
+        }
+        opts.select = Selection::new(include, exclude, opts.map_keys);
+        Ok(opts)
+    }
+
+    /// A hash of the options an inferred plan depends on, so that a plan cached
+    /// under a `schema_cache_key` is not reused with other options.
+    fn plan_fingerprint(&self) -> u64 {
+        let rename: BTreeMap<_, _> = self.rename.iter().collect();
+        let shaping = format!(
+            "{:?}",
+            (
+                (self.uuid_mode, self.record_id_mode, &self.record_id_suffixes, self.strict_tags, self.on_unknown_tag),
+                (self.on_row_error, self.geoarrow, &self.value_column, &self.tracing, self.infer_samples),
+                (self.type_conflicts, self.big_int, self.sparse_unions, &self.dictionary, &self.columns),
+                (&self.column_order, self.objects_as, self.map_keys, self.duplicate_keys, &self.explode),
+                (self.flatten, rename, &self.sanitize_names, &self.select, self.small_types),
+            )
+        );
+        let mut hasher = DefaultHasher::new();
+        shaping.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// The output fields for `fields`: exploded, renamed as `rename` says, then flattened.
+    fn output_fields(&self, fields: &[FieldRef]) -> Vec<FieldRef> {
+        let renamed: Vec<FieldRef> = explode::explode_fields(fields, self)
+            .iter()
+            .map(|f| match self.rename.get(f.name()) {
+                Some(name) => Arc::new(f.as_ref().clone().with_name(name)),
+                None => f.clone(),
+            })
+            .collect();
+        let fields = match self.flatten {
+            Some(depth) => flatten::flatten_fields(&renamed, depth),
+            None => renamed,
+        };
+        match &self.sanitize_names {
+            Some(replacement) => sanitize_names(fields, replacement),
+            None => fields,
+        }
+    }
+
+    fn explodes(&self, name: &str) -> bool {
+        self.explode.iter().any(|n| n == name)
+    }
+
+    /// True if the top-level field `name` is converted under `columns`.
+    fn selects(&self, name: &str) -> bool {
+        self.columns.as_ref().is_none_or(|columns| columns.iter().any(|c| c == name))
+    }
+
+    /// Count `n` values converted with `loss`.
+    fn lose(&self, loss: Loss, n: usize) {
+        self.losses[loss as usize].fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// A copy of the options counting losses apart from these, for a conversion
+    /// attempt whose losses only count if it succeeds.
+    fn counting_apart(&self) -> Self {
+        ConvertOptions { losses: Arc::default(), ..self.clone() }
+    }
+
+    /// Add the losses counted by `attempt` to these.
+    fn add_losses(&self, attempt: &ConvertOptions) {
+        for loss in Loss::ALL {
+            self.lose(loss, attempt.losses[loss as usize].load(Ordering::Relaxed));
+        }
+    }
+
+    /// Warn, once, with a `LossyConversionWarning` if values were converted with a
+    /// loss of information since the last call, counting them by kind.
+    fn warn_losses(&self, py: Python) -> PyResult<()> {
+        let counts: Vec<String> = Loss::ALL
+            .iter()
+            .map(|&loss| (loss, self.losses[loss as usize].swap(0, Ordering::Relaxed)))
+            .filter(|&(_, n)| n > 0)
+            .map(|(loss, n)| format!("{} {}", n, loss.describe(self)))
+            .collect();
+        if counts.is_empty() {
+            return Ok(());
+        }
+        let message = format!("values were converted with a loss of information: {}", counts.join(", "));
+        let message = std::ffi::CString::new(message).expect("no nul bytes");
+        PyErr::warn(py, &py.get_type::<LossyConversionWarning>(), &message, 1)
+    }
+
+    /// Note that record `row` of a result was left out or nulled under `on_row_error`.
+    fn reject_row(&self, row: usize, error: String) {
+        self.rejected_rows.lock().unwrap_or_else(PoisonError::into_inner).push((row, error));
+    }
+
+    /// Report the records left out or nulled since the last call: append a
+    /// `{row, error}` dict for each to `row_errors`, or warn about them without it.
+    fn report_rows(&self, py: Python) -> PyResult<()> {
+        let mut rejected = std::mem::take(&mut *self.rejected_rows.lock().unwrap_or_else(PoisonError::into_inner));
+        rejected.sort_by_key(|(row, _)| *row);
+        let Some((row, error)) = rejected.first() else {
+            return Ok(());
+        };
+        if let Some(list) = &self.row_errors {
+            for (row, error) in &rejected {
+                let dict = PyDict::new(py);
+                dict.set_item("row", row)?;
+                dict.set_item("error", error)?;
+                list.bind(py).append(dict)?;
+            }
+            return Ok(());
+        }
+        let what = if self.on_row_error == RowErrorPolicy::Null { "converted as nulls" } else { "left out" };
+        let message = format!(
+            "{} records could not be converted and were {}; the first, row {}: {}. Pass row_errors=[] to collect them",
+            rejected.len(),
+            what,
+            row,
+            error
+        );
+        let message = std::ffi::CString::new(message.replace('\0', "")).expect("no nul bytes");
+        PyErr::warn(py, &py.get_type::<pyo3::exceptions::PyUserWarning>(), &message, 1)
+    }
+
+    /// Enforce `max_bytes` on an input payload.
+    fn check_bytes(&self, data: &CborInput) -> PyResult<()> {
+        self.check_len(data.0.len_bytes())
+    }
+
+    /// Enforce `max_bytes` on a payload of `len` bytes.
+    fn check_len(&self, len: usize) -> PyResult<()> {
+        match self.max_bytes {
+            Some(max) if len > max => Err(SurrealEngineError::new_err(format!(
+                "CBOR payload is {} bytes, exceeding max_bytes={}",
+                len, max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Enforce `max_rows` on the records of one result.
+    fn check_rows(&self, rows: usize) -> PyResult<()> {
+        match self.max_rows {
+            Some(max) if rows > max => Err(SurrealEngineError::new_err(format!(
+                "Result has {} rows, exceeding max_rows={}",
+                rows, max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Enforce `max_cells` on a result of `rows` rows and `columns` top-level columns.
+    fn check_cells(&self, rows: usize, columns: usize) -> PyResult<()> {
+        match self.max_cells {
+            Some(max) if rows.saturating_mul(columns) > max => Err(SurrealEngineError::new_err(format!(
+                "Result has {} cells ({} rows x {} columns), exceeding max_cells={}",
+                rows.saturating_mul(columns),
+                rows,
+                columns,
+                max
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Extract an integer option that must be at least 1.
+fn parse_positive(key: &str, value: &Bound<'_, PyAny>) -> PyResult<usize> {
+    match value.extract::<usize>()? {
+        0 => Err(PyValueError::new_err(format!("{} must be positive", key))),
+        n => Ok(n),
+    }
+}
+
+/// Extract a string option and map it onto one of the allowed choices.
+fn parse_choice<T: Copy>(key: &str, value: &Bound<'_, PyAny>, choices: &[(&str, T)]) -> PyResult<T> {
+    let given: String = value.extract()?;
+    choices
+        .iter()
+        .find(|(name, _)| *name == given)
+        .map(|(_, v)| *v)
+        .ok_or_else(|| {
+            let names: Vec<&str> = choices.iter().map(|(name, _)| *name).collect();
+            PyValueError::new_err(format!("{} must be one of {:?}, got '{}'", key, names, given))
+        })
+}
 
 /// Wrapper around cbor4ii::core::Value to implement custom Serialize logic
 /// specifically for SurrealDB types like RecordID (Tag 8).
-#[derive(Debug, Clone)]
-struct SurrealValue(Value);
+///
+/// Borrows the value so serializing a record never copies its contents.
+#[derive(Debug, Clone, Copy)]
+struct SurrealValueRef<'a>(&'a Value, &'a ConvertOptions);
 
-impl Serialize for SurrealValue {
+impl Serialize for SurrealValueRef<'_> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        match &self.0 {
+        let opts = self.1;
+        match self.0 {
             Value::Null => serializer.serialize_none(),
             Value::Bool(b) => serializer.serialize_bool(*b),
             Value::Integer(i) => {
@@ -32,7 +827,17 @@ impl Serialize for SurrealValue {
                  if let Ok(u64_val) = u64::try_from(v) {
                      return serializer.serialize_u64(u64_val);
                  }
-                 serializer.serialize_i128(v)
+                 match opts.big_int {
+                     // Only the type matters when tracing, and an i64 merges
+                     // with the small integers around it.
+                     BigInt::Decimal | BigInt::String if opts.tracing_records => serializer.serialize_i64(i64::MIN),
+                     BigInt::Decimal | BigInt::String => serializer.serialize_str(&v.to_string()),
+                     BigInt::Null => {
+                         opts.lose(Loss::BigInt, 1);
+                         serializer.serialize_none()
+                     }
+                     BigInt::Error => serializer.serialize_i128(v),
+                 }
             }
             Value::Float(f) => serializer.serialize_f64(*f),
             Value::Bytes(b) => serializer.serialize_bytes(b),
@@ -41,183 +846,3368 @@ impl Serialize for SurrealValue {
                 use serde::ser::SerializeSeq;
                 let mut seq = serializer.serialize_seq(Some(arr.len()))?;
                 for element in arr {
-                    seq.serialize_element(&SurrealValue(element.clone()))?;
+                    seq.serialize_element(&SurrealValueRef(element, opts))?;
                 }
                 seq.end()
             }
             Value::Map(map) => {
                 use serde::ser::SerializeMap;
                 let mut m = serializer.serialize_map(Some(map.len()))?;
-                for (k, v) in map {
-                    // keys in CBOR can be any type, but JSON/Arrow expects string keys usually.
-                    // stringify key if not string
-                    let key_str = match k {
-                        Value::Text(s) => s.clone(),
-                        _ => format!("{:?}", k),
-                    };
-                    m.serialize_entry(&key_str, &SurrealValue(v.clone()))?;
+                let map = object_entries(map, opts).map_err(serde::ser::Error::custom)?;
+                for (k, v) in map.iter() {
+                    m.serialize_entry(&key_string(k, opts.map_keys), &SurrealValueRef(v, opts))?;
                 }
                 m.end()
             }
-            Value::Tag(tag, value) => {
-                if *tag == 8 {
-                    // RecordID: Table:ID
-                    // Usually value is Array(2) [table, id] (both strings/text)
-                    if let Value::Array(arr) = value.as_ref() {
-                        if arr.len() == 2 {
-                             let table = match &arr[0] {
-                                 Value::Text(s) => s,
-                                 _ => "",
-                             };
-                             let id = match &arr[1] {
-                                 Value::Text(s) => s,
-                                 Value::Integer(i) => return serializer.serialize_str(&format!("{}:{}", table, i)),
-                                 _ => "",
-                             };
-                             return serializer.serialize_str(&format!("{}:{}", table, id));
-                        }
-                    }
-                }
-                // Fallback for other tags: ignore tag, serialize value
-                SurrealValue(*value.clone()).serialize(serializer)
-            }
+            Value::Tag(tag, value) => tags::serialize_tag(*tag, value, opts, serializer),
             _ => serializer.serialize_unit(), // Simple/Msg?
         }
     }
 }
 
-/// Formats the sum of two numbers as string.
-#[pyfunction]
-fn sum_as_string(a: usize, b: usize) -> PyResult<String> {
-    Ok((a + b).to_string())
+/// keys in CBOR can be any type, but JSON/Arrow expects string keys usually.
+/// stringify key if not string, as `map_keys` says (`MapKeys::Error` is caught
+/// by `check_keys`)
+fn key_string(k: &Value, keys: MapKeys) -> Cow<'_, str> {
+    match k {
+        Value::Text(s) => Cow::Borrowed(s),
+        Value::Null if keys != MapKeys::Json => Cow::Borrowed("null"),
+        Value::Bytes(b) if keys != MapKeys::Json => Cow::Owned(b.iter().map(|b| format!("{:02x}", b)).collect()),
+        _ => match keys {
+            MapKeys::Json => Cow::Owned(coerce::to_json(k, &ConvertOptions::default()).unwrap_or_default()),
+            _ => coerce::to_text(k)
+                .or_else(|| coerce::to_json(k, &ConvertOptions::default()).map(Cow::Owned))
+                .unwrap_or_default(),
+        },
+    }
 }
 
-/// Convert CBOR bytes to an Arrow RecordBatch (as a PyArrow Table/batch).
-#[pyfunction]
-fn cbor_to_arrow(py: Python, data: &Bound<'_, PyBytes>) -> PyResult<PyObject> {
-    let bytes = data.as_bytes();
+/// The entries of an object to convert, one per key as `duplicate_keys` says.
+/// Fails for a non-text key under `map_keys="error"`, a non-text key named the
+/// same as another key, or a repeated key under `duplicate_keys="error"`.
+fn object_entries<'a>(map: &'a [(Value, Value)], opts: &ConvertOptions) -> Result<Entries<'a>, String> {
+    let keys = opts.map_keys;
+    let all_text = map.iter().all(|(k, _)| matches!(k, Value::Text(_)));
+    if all_text && !repeats_text_key(map) {
+        return Ok(Entries::All(map));
+    }
+    if keys == MapKeys::Error {
+        if let Some((k, _)) = map.iter().find(|(k, _)| !matches!(k, Value::Text(_))) {
+            return Err(format!("object key {} is not a string", key_string(k, MapKeys::Display)));
+        }
+    }
+    opts.lose(Loss::Key, map.iter().filter(|(k, _)| !matches!(k, Value::Text(_))).count());
+    let describe = |k: &Value| match k {
+        Value::Text(s) => format!("{:?}", s),
+        _ => key_string(k, keys).into_owned(),
+    };
+    let mut entries: Vec<(&Value, &Value)> = Vec::with_capacity(map.len());
+    let mut seen: HashMap<Cow<'_, str>, usize> = HashMap::with_capacity(map.len());
+    for (k, v) in map {
+        let name = key_string(k, keys);
+        let Some(&at) = seen.get(&name) else {
+            seen.insert(name, entries.len());
+            entries.push((k, v));
+            continue;
+        };
+        let other = entries[at].0;
+        if !matches!((other, k), (Value::Text(_), Value::Text(_))) {
+            return Err(format!("object keys {} and {} both become the field name '{}'", describe(other), describe(k), name));
+        }
+        match opts.duplicate_keys {
+            DuplicateKeys::Error => return Err(format!("object key '{}' appears more than once", name)),
+            DuplicateKeys::First => {}
+            DuplicateKeys::Last => entries[at].1 = v,
+        }
+        opts.lose(Loss::RepeatedKey, 1);
+    }
+    Ok(Entries::Kept(entries))
+}
 
-    // 1. Decode to cbor4ii::core::Value (Low level)
-    let mut reader = SliceReader::new(bytes);
-    
-    // cbor4ii 0.3.x: Value::decode(&mut reader)
-    let root: Value = Value::decode(&mut reader)
-         .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("CBOR decode error: {:?}", e)))?;
-
-    // 2. Extract inner data
-    // Path: root -> "result" (Array) -> [0] -> "result" (Array of records)
-    // Check for "result" array in root
-    // Check for "error" key first
-    if let Value::Map(ref map) = root {
-        if let Some((_, v)) = map.iter().find(|(k, _)| matches!(k, Value::Text(s) if s == "error")) {
-            // Found top-level error (e.g. { "id": ..., "error": { "code": ..., "message": ... } })
-            let msg = if let Value::Map(err_map) = v {
-                 let message = err_map.iter()
-                    .find(|(k, _)| matches!(k, Value::Text(s) if s == "message"))
-                    .map(|(_, v)| match v {
-                        Value::Text(s) => s.clone(),
-                        _ => format!("{:?}", v),
-                    })
-                    .unwrap_or_else(|| format!("{:?}", v));
-                 let code = err_map.iter()
-                    .find(|(k, _)| matches!(k, Value::Text(s) if s == "code"))
-                    .map(|(_, v)| format!("{:?}", v))
-                    .unwrap_or_else(|| "?".to_string());
-                 format!("SurrealDB Error ({}): {}", code, message)
-            } else {
-                 format!("SurrealDB Error: {:?}", v)
-            };
-            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(msg));
+/// The entries `object_entries` keeps: all of an object's, or the pairs left
+/// once non-text and repeated keys are dealt with.
+enum Entries<'a> {
+    All(&'a [(Value, Value)]),
+    Kept(Vec<(&'a Value, &'a Value)>),
+}
+
+impl<'a> Entries<'a> {
+    fn len(&self) -> usize {
+        match self {
+            Entries::All(map) => map.len(),
+            Entries::Kept(entries) => entries.len(),
         }
     }
 
-    let root_result_arr = if let Value::Map(ref map) = root {
-         map.iter()
-            .find(|(k, _)| matches!(k, Value::Text(s) if s == "result"))
-            .map(|(_, v)| v)
-    } else {
-        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>("CBOR Root is not a Map"));
-    };
+    /// The key and value of entry `i`.
+    fn get(&self, i: usize) -> (&'a Value, &'a Value) {
+        match self {
+            Entries::All(map) => (&map[i].0, &map[i].1),
+            Entries::Kept(entries) => entries[i],
+        }
+    }
 
-    let responses = match root_result_arr {
-        Some(Value::Array(arr)) => arr,
-        Some(_) => return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>("Root 'result' is not an array")),
-        None => return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>("Root 'result' key not found")),
-    };
+    fn iter(&self) -> impl Iterator<Item = (&'a Value, &'a Value)> + '_ {
+        (0..self.len()).map(|i| self.get(i))
+    }
+}
 
-    if responses.is_empty() {
-        return Ok(py.None());
+impl Default for Entries<'_> {
+    fn default() -> Self {
+        Entries::All(&[])
     }
+}
 
-    // Check first response
-    let first_response = &responses[0];
-    let first_response_map = if let Value::Map(map) = first_response {
-        map
-    } else {
-        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>("First response is not a Map"));
-    };
-    
-    // Check status
-    let status_opt = first_response_map.iter()
-        .find(|(k, _)| matches!(k, Value::Text(s) if s == "status"))
-        .map(|(_, v)| v);
-        
-    if let Some(Value::Text(status)) = status_opt {
-        if status != "OK" {
-            // Try to find "detail" or "message" to include in error
-            let detail = first_response_map.iter()
-                .find(|(k, _)| matches!(k, Value::Text(s) if s == "detail" || s == "message"))
-                .map(|(_, v)| format!("{:?}", v))
-                .unwrap_or_else(|| "Unknown error".to_string());
-            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Database returned error status '{}': {}", status, detail)));
+/// True if a text key of `map` appears twice.
+fn repeats_text_key(map: &[(Value, Value)]) -> bool {
+    // Most objects are small enough that comparing every pair is faster than hashing.
+    if map.len() <= 32 {
+        return map.iter().enumerate().any(|(i, (a, _))| {
+            map[..i].iter().any(|(b, _)| matches!((a, b), (Value::Text(a), Value::Text(b)) if a == b))
+        });
+    }
+    let mut seen = HashSet::with_capacity(map.len());
+    !map.iter().all(|(k, _)| match k {
+        Value::Text(s) => seen.insert(s.as_str()),
+        _ => true,
+    })
+}
+
+/// A field value with its objects, and those in its arrays, serialized as JSON
+/// text for `objects_as="json"`.
+struct ObjectsAsJson<'a>(&'a Value, &'a ConvertOptions);
+
+impl Serialize for ObjectsAsJson<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self.0 {
+            Value::Map(_) => {
+                let json = serde_json::to_string(&SurrealValueRef(self.0, self.1)).map_err(serde::ser::Error::custom)?;
+                serializer.serialize_str(&json)
+            }
+            Value::Array(arr) => {
+                use serde::ser::SerializeSeq;
+                let mut seq = serializer.serialize_seq(Some(arr.len()))?;
+                for element in arr {
+                    seq.serialize_element(&ObjectsAsJson(element, self.1))?;
+                }
+                seq.end()
+            }
+            value => SurrealValueRef(value, self.1).serialize(serializer),
         }
     }
+}
 
-    // Get inner result
-    let inner_result_opt = first_response_map.iter()
-        .find(|(k, _)| matches!(k, Value::Text(s) if s == "result"))
-        .map(|(_, v)| v);
+/// A top-level record. Applies row-level options such as record id splitting
+/// before handing each field to `SurrealValueRef`.
+struct SurrealRecord<'a> {
+    value: &'a Value,
+    opts: &'a ConvertOptions,
+    /// Top-level columns holding record ids, split in `RecordIdMode::Split`.
+    split_ids: &'a [String],
+    /// Serialize a scalar record as `{column: value}`.
+    value_column: Option<&'a str>,
+}
 
-    let records_arr = match inner_result_opt {
-        Some(Value::Array(arr)) => arr,
-        Some(_) => return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>("Inner 'result' is not an array")),
-        None => {
-            // If status is OK but no result, maybe it's valid empty? or just missing.
-            // Check keys to be helpful
-            let keys: Vec<String> = first_response_map.iter().map(|(k, _)| format!("{:?}", k)).collect();
-            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Inner 'result' key not found. Available keys: {:?}", keys)));
+impl Serialize for SurrealRecord<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeMap;
+        if let Some(column) = self.value_column {
+            let mut m = serializer.serialize_map(Some(1))?;
+            self.serialize_field(&mut m, column, self.value)?;
+            return m.end();
         }
-    };
+        let Value::Map(map) = self.value else {
+            return SurrealValueRef(self.value, self.opts).serialize(serializer);
+        };
+        let map = object_entries(map, self.opts).map_err(serde::ser::Error::custom)?;
+        let mut m = serializer.serialize_map(None)?;
+        for (k, v) in map.iter() {
+            let key = key_string(k, self.opts.map_keys);
+            if self.opts.selects(&key) {
+                self.serialize_field(&mut m, &key, v)?;
+            }
+        }
+        m.end()
+    }
+}
 
-    if records_arr.is_empty() {
-        return Ok(py.None());
+impl SurrealRecord<'_> {
+    fn serialize_field<M: serde::ser::SerializeMap>(&self, m: &mut M, key: &str, v: &Value) -> Result<(), M::Error> {
+        if !self.split_ids.iter().any(|id| id == key) {
+            if self.opts.objects_as == ObjectsAs::Json {
+                return m.serialize_entry(key, &ObjectsAsJson(v, self.opts));
+            }
+            return m.serialize_entry(key, &SurrealValueRef(v, self.opts));
+        }
+        let suffixes = &self.opts.record_id_suffixes;
+        let (tb, id) = match v {
+            Value::Tag(_, payload) => match tags::record_id_parts(payload) {
+                Some((tb, id)) => (Some(tb), Some(id)),
+                None => (None, None),
+            },
+            _ => (None, None),
+        };
+        m.serialize_entry(&format!("{}{}", key, suffixes.table), &tb)?;
+        m.serialize_entry(&format!("{}{}", key, suffixes.key), &id)
     }
+}
 
-    // 3. Wrap in SurrealValue
-    let wrapped_records: Vec<SurrealValue> = records_arr.iter()
-        .map(|v| SurrealValue(v.clone()))
-        .collect();
+/// Finds the top-level columns whose non-null values are all record ids, which
+/// `RecordIdMode::Split` splits. Records can be observed in several chunks.
+#[derive(Default)]
+struct SplitScan {
+    /// Column name, whether a non-null value was seen, and whether all were record ids.
+    columns: Vec<(String, bool, bool)>,
+}
+
+impl SplitScan {
+    /// Observe records; with `value_column` each record is that column's value.
+    fn observe(&mut self, records: &[Value], value_column: Option<&str>, keys: MapKeys) {
+        for record in records {
+            let values: Box<dyn Iterator<Item = (Cow<'_, str>, &Value)>> = match (record, value_column) {
+                (_, Some(column)) => Box::new(std::iter::once((Cow::Borrowed(column), record))),
+                (Value::Map(map), None) => Box::new(map.iter().map(move |(k, v)| (key_string(k, keys), v))),
+                _ => continue,
+            };
+            for (key, value) in values {
+                let index = match self.columns.iter().position(|(name, _, _)| *name == key) {
+                    Some(index) => index,
+                    None => {
+                        self.columns.push((key.into_owned(), false, true));
+                        self.columns.len() - 1
+                    }
+                };
+                if !is_null(value) {
+                    let (_, seen, all_ids) = &mut self.columns[index];
+                    *seen = true;
+                    *all_ids &= tag_kind(value) == Some(SurrealTag::RecordId);
+                }
+            }
+        }
+    }
+
+    /// The columns to split, leaving out those `columns` does not select.
+    fn columns(self, opts: &ConvertOptions) -> Vec<String> {
+        self.columns
+            .into_iter()
+            .filter(|(name, seen, all_ids)| *seen && *all_ids && opts.selects(name))
+            .map(|(name, _, _)| name)
+            .collect()
+    }
+}
 
-    // 4. Infer Schema
-    let fields = Vec::<FieldRef>::from_samples(&wrapped_records, TracingOptions::default())
-        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Schema inference error: {}", e)))?;
+/// The top-level fields in the order records first have them, for
+/// `column_order="insertion"`. Records can be observed in several chunks.
+#[derive(Default)]
+struct KeyOrder {
+    names: Vec<String>,
+    seen: HashSet<String>,
+}
 
-    // 5. Convert
-    let arrays = serde_arrow::to_arrow(&fields, &wrapped_records)
-         .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Arrow array conversion error: {}", e)))?;
+impl KeyOrder {
+    fn observe(&mut self, records: &[Value], keys: MapKeys) {
+        for record in records {
+            let Value::Map(map) = record else {
+                continue;
+            };
+            for (k, _) in map {
+                let key = key_string(k, keys);
+                if !self.seen.contains(key.as_ref()) {
+                    self.seen.insert(key.clone().into_owned());
+                    self.names.push(key.into_owned());
+                }
+            }
+        }
+    }
+}
 
-    // 6. Build Batch
-    let schema = Arc::new(Schema::new(fields));
-    let batch = RecordBatch::try_new(schema, arrays)
-        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("RecordBatch creation error: {}", e)))?;
+/// Sort inferred top-level `fields` as `column_order` says. `insertion` is the
+/// observed field order for `"insertion"`.
+fn order_fields(fields: &mut [FieldRef], split_ids: &[String], insertion: &KeyOrder, opts: &ConvertOptions) {
+    fields.sort_by(|a, b| a.name().cmp(b.name()));
+    let suffixes = &opts.record_id_suffixes;
+    // A split column is placed where its record id column would be.
+    let member = |name: &str| -> String {
+        split_ids
+            .iter()
+            .find(|id| [&suffixes.table, &suffixes.key].iter().any(|suffix| name == format!("{}{}", id, suffix)))
+            .map_or_else(|| name.to_string(), |id| id.clone())
+    };
+    let names = match &opts.column_order {
+        ColumnOrder::Sorted => return,
+        ColumnOrder::Insertion => &insertion.names,
+        ColumnOrder::Listed(names) => names,
+    };
+    fields.sort_by_cached_key(|f| {
+        let member = member(f.name());
+        names.iter().position(|n| *n == member).unwrap_or(usize::MAX)
+    });
+}
 
-    batch.to_pyarrow(py)
+/// `fields` with names safe for SQL engines: every character other than an ASCII
+/// letter, digit or `_` is replaced, a leading digit is prefixed with the
+/// replacement and names made equal are numbered (`a_b`, `a_b_2`). Changed
+/// fields keep their name in the `original_name` metadata.
+fn sanitize_names(fields: Vec<FieldRef>, replacement: &str) -> Vec<FieldRef> {
+    let mut taken: HashSet<String> = HashSet::with_capacity(fields.len());
+    // Names that are already safe keep them.
+    taken.extend(fields.iter().filter(|f| is_safe_name(f.name())).map(|f| f.name().clone()));
+    fields
+        .into_iter()
+        .map(|field| {
+            if is_safe_name(field.name()) {
+                return field;
+            }
+            let mut base = String::with_capacity(field.name().len());
+            for c in field.name().chars() {
+                match c.is_ascii_alphanumeric() || c == '_' {
+                    true => base.push(c),
+                    false => base.push_str(replacement),
+                }
+            }
+            if base.is_empty() || base.starts_with(|c: char| c.is_ascii_digit()) {
+                base.insert_str(0, replacement);
+            }
+            let (mut name, mut n) = (base.clone(), 1);
+            while taken.contains(&name) {
+                n += 1;
+                name = format!("{}_{}", base, n);
+            }
+            taken.insert(name.clone());
+            let mut metadata = field.metadata().clone();
+            metadata.insert("original_name".to_string(), field.name().clone());
+            Arc::new(field.as_ref().clone().with_name(name).with_metadata(metadata))
+        })
+        .collect()
 }
 
-/// A Python module implemented in Rust.
-#[pymodule]
-fn surrealengine_accelerator(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
-    m.add_function(wrap_pyfunction!(sum_as_string, m)?)?;
-    m.add_function(wrap_pyfunction!(cbor_to_arrow, m)?)?;
-    Ok(())
+/// Whether `name` is safe for SQL engines as it is: ASCII letters, digits and `_`,
+/// not starting with a digit.
+fn is_safe_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Find the value stored under a text key in a CBOR map.
+fn map_get<'a>(map: &'a [(Value, Value)], key: &str) -> Option<&'a Value> {
+    map.iter()
+        .find(|(k, _)| matches!(k, Value::Text(s) if s == key))
+        .map(|(_, v)| v)
+}
+
+/// Rewrite traced fields using the SurrealDB semantics of the sampled values.
+///
+/// serde_arrow only sees the primitives emitted by `SurrealValueRef`, so e.g. a datetime
+/// is traced as Int64. This walks the traced schema alongside the map values at the
+/// same position and restores the semantic Arrow type.
+fn refine_fields<'a, F>(fields: F, values: &[&Value], opts: &ConvertOptions) -> Vec<FieldRef>
+where
+    F: IntoIterator<Item = &'a FieldRef>,
+{
+    fields
+        .into_iter()
+        .map(|child| {
+            let child_values: Vec<&Value> = values
+                .iter()
+                .filter_map(|v| match v {
+                    Value::Map(map) => {
+                        map.iter().find(|(k, _)| key_string(k, opts.map_keys) == *child.name()).map(|(_, v)| v)
+                    }
+                    Value::Tag(tags::TAG_RANGE, range) => tags::range_bound(range, child.name()),
+                    _ => None,
+                })
+                .collect();
+            refine_field(child, &child_values, opts)
+        })
+        .collect()
+}
+
+fn refine_field(field: &FieldRef, values: &[&Value], opts: &ConvertOptions) -> FieldRef {
+    let data_type = match field.data_type() {
+        DataType::Struct(children) => DataType::Struct(refine_fields(children, values, opts).into()),
+        DataType::List(element) | DataType::LargeList(element) => {
+            let elements: Vec<&Value> = values
+                .iter()
+                .filter_map(|v| match v {
+                    Value::Array(arr) => Some(arr.iter()),
+                    _ => None,
+                })
+                .flatten()
+                .collect();
+            let element = refine_field(element, &elements, opts);
+            match field.data_type() {
+                DataType::List(_) => DataType::List(element),
+                _ => DataType::LargeList(element),
+            }
+        }
+        DataType::Int64 | DataType::UInt64
+            if matches!(opts.big_int, BigInt::Decimal | BigInt::String)
+                && values.iter().any(|v| is_big_int(v)) =>
+        {
+            match opts.big_int {
+                BigInt::Decimal => DataType::Decimal256(DECIMAL256_MAX_PRECISION, 0),
+                _ => DataType::LargeUtf8,
+            }
+        }
+        DataType::Utf8 | DataType::LargeUtf8
+            if all_non_null(values, |v| tag_kind(v) == Some(SurrealTag::DecimalFraction)) =>
+        {
+            match decimal_type(values) {
+                Some(data_type) => data_type,
+                None => return field.clone(),
+            }
+        }
+        DataType::Int64 if all_non_null(values, |v| tag_kind(v) == Some(SurrealTag::Datetime)) => {
+            DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into()))
+        }
+        DataType::Int64 if all_non_null(values, |v| tag_kind(v) == Some(SurrealTag::Duration)) => {
+            DataType::Duration(TimeUnit::Nanosecond)
+        }
+        DataType::Binary | DataType::LargeBinary
+            if opts.uuid_mode == UuidMode::Binary
+                && all_non_null(values, |v| tag_kind(v) == Some(SurrealTag::Uuid)) =>
+        {
+            DataType::FixedSizeBinary(16)
+        }
+        DataType::Binary | DataType::LargeBinary
+            if opts.geoarrow && all_non_null(values, |v| tag_kind(v) == Some(SurrealTag::Geometry)) =>
+        {
+            let mut metadata = field.metadata().clone();
+            metadata.insert("ARROW:extension:name".to_string(), "geoarrow.wkb".to_string());
+            metadata.insert("ARROW:extension:metadata".to_string(), "{}".to_string());
+            return Arc::new(field.as_ref().clone().with_metadata(metadata));
+        }
+        _ => return field.clone(),
+    };
+    Arc::new(Field::new(field.name(), data_type, field.is_nullable()).with_metadata(field.metadata().clone()))
+}
+
+/// The Decimal128 of the widest precision, or else the Decimal256, that holds all
+/// the decimal fractions in `values` exactly; `None` if neither does.
+fn decimal_type(values: &[&Value]) -> Option<DataType> {
+    let fractions: Vec<&Value> = values
+        .iter()
+        .filter_map(|v| match v {
+            Value::Tag(_, payload) => Some(payload.as_ref()),
+            _ => None,
+        })
+        .collect();
+    let scale = fractions.iter().map(|v| tags::decimal_fraction_scale(v)).collect::<Option<Vec<_>>>()?;
+    let scale = i8::try_from(scale.into_iter().max().unwrap_or(0)).ok()?;
+    let unscaled = fractions
+        .iter()
+        .map(|v| tags::decimal_fraction(v).and_then(|(exponent, mantissa)| tags::rescale(mantissa, exponent, scale)))
+        .collect::<Option<Vec<_>>>()?;
+    let fits = |precision: u8| {
+        scale as u8 <= precision && unscaled.iter().all(|n| Decimal256Type::is_valid_decimal_precision(*n, precision))
+    };
+    match () {
+        _ if fits(DECIMAL128_MAX_PRECISION) => Some(DataType::Decimal128(DECIMAL128_MAX_PRECISION, scale)),
+        _ if fits(DECIMAL256_MAX_PRECISION) => Some(DataType::Decimal256(DECIMAL256_MAX_PRECISION, scale)),
+        _ => None,
+    }
+}
+
+/// True if `value` is an integer or bignum that fits neither `i64` nor `u64`.
+fn is_big_int(value: &Value) -> bool {
+    let i = match value {
+        Value::Integer(i) => *i,
+        Value::Tag(tag, payload) if SurrealTag::of(*tag) == SurrealTag::BigNum => match tags::bignum_i128(*tag, payload) {
+            Some(i) => i,
+            None => return true,
+        },
+        _ => return false,
+    };
+    i64::try_from(i).is_err() && u64::try_from(i).is_err()
+}
+
+/// CBOR null and SurrealDB `NONE` both map to an Arrow null.
+fn is_null(value: &Value) -> bool {
+    matches!(value, Value::Null | Value::Tag(tags::TAG_NONE, _))
+}
+
+/// True if there is at least one non-null value and all non-null values match `pred`.
+fn all_non_null(values: &[&Value], pred: impl Fn(&Value) -> bool) -> bool {
+    let mut seen = false;
+    for v in values.iter().filter(|v| !is_null(v)) {
+        if !pred(v) {
+            return false;
+        }
+        seen = true;
+    }
+    seen
+}
+
+/// Formats the sum of two numbers as string.
+#[pyfunction]
+fn sum_as_string(a: usize, b: usize) -> PyResult<String> {
+    Ok((a + b).to_string())
+}
+
+/// Parse a SurrealQL record id (e.g. `"user:⟨john-doe⟩"`) into `(table, key)`.
+///
+/// Escaped keys are unescaped, bare numeric keys become `int`, and array or
+/// object keys become `list`/`dict`.
+#[pyfunction]
+fn parse_record_id(py: Python, record_id: &str) -> PyResult<(String, PyObject)> {
+    let (table, key) = surrealql::parse_record_id(record_id).map_err(PyValueError::new_err)?;
+    Ok((table, pyvalue::value_to_py(py, &key)?))
+}
+
+/// Format a table and key as a SurrealQL record id, escaping either as needed.
+///
+/// `key` may be a `str`, `int`, `float`, or a `list`/`tuple`/`dict` of plain values.
+#[pyfunction]
+fn format_record_id(table: &str, key: &Bound<'_, PyAny>) -> PyResult<String> {
+    let key = pyvalue::py_to_value(key)?;
+    surrealql::render_record_id(table, &key)
+        .ok_or_else(|| PyValueError::new_err("Record id key cannot be rendered as SurrealQL"))
+}
+
+/// Escape a table or field name for SurrealQL: plain identifiers are returned
+/// as-is, anything else is wrapped in backticks.
+#[pyfunction]
+fn escape_ident(name: &str) -> String {
+    surrealql::escape_ident(name).into_owned()
+}
+
+/// Quote a string as a SurrealQL string literal, escaping it as needed.
+#[pyfunction]
+fn quote_string(value: &str) -> String {
+    surrealql::quote_str(value)
+}
+
+/// Escape the key part of a record id, the `key` of `table:key`: plain string
+/// keys are returned as-is, others are wrapped in `⟨⟩`.
+///
+/// `key` may also be an `int`, `float`, or a `list`/`tuple`/`dict` of plain values.
+#[pyfunction]
+fn escape_record_key(key: &Bound<'_, PyAny>) -> PyResult<String> {
+    let key = pyvalue::py_to_value(key)?;
+    surrealql::render_record_key(&key)
+        .ok_or_else(|| PyValueError::new_err("Record id key cannot be rendered as SurrealQL"))
+}
+
+/// Convert CBOR bytes to Arrow: a batch implementing the Arrow PyCapsule interface by
+/// default (`output="batch"`), a `pyarrow.RecordBatch`, `pyarrow.Table` or
+/// `pyarrow.RecordBatchReader` for `"pyarrow"`, `"table"` or `"reader"`, a PyCapsule stream
+/// for `"stream"`, or a dict of NumPy arrays for `"numpy"`.
+///
+/// Keyword options:
+/// - `uuid_mode`: `"string"` (default) or `"binary"` for `FixedSizeBinary(16)` UUID columns.
+/// - `record_id_mode`: `"string"` (default, `"table:id"`), `"struct"` for `{tb, id}` columns
+///   (applied to every record link including nested ones), or `"split"` to emit top-level
+///   record id columns as two string columns.
+/// - `record_id_suffixes`: `(table_suffix, key_suffix)` for `"split"`, default `("__tb", "__key")`.
+/// - `geoarrow`: mark WKB geometry columns with the `geoarrow.wkb` extension type.
+/// - `strict_tags`: raise instead of degrading unsupported SurrealDB tags to their payload.
+/// - `on_unknown_tag`: `"ignore"` (default, `"error"` with `strict_tags`), `"error"`, or
+///   `"raw"` to keep non-SurrealDB tags as a `{tag, value}` struct.
+/// - `with_metadata`: store the statement's `time` and `status` in the schema metadata
+///   under `surrealdb.time` and `surrealdb.status`.
+/// - `on_statement_error`: `"raise"` (default) fails on a non-`OK` statement, `"skip"` returns
+///   `None` for it, and `"collect"` also returns a list of `{statement, status, message, time}`
+///   dicts, making the result a `(result, errors)` tuple.
+/// - `on_row_error`: `"raise"` (default) fails on a record that does not convert to the
+///   schema, inferred or declared; `"skip"` leaves such records out and `"null"` converts
+///   each as a row of nulls (all columns are then nullable). Records left out or nulled are
+///   appended to the `row_errors` list, if given, as `{row, error}` dicts with the record's
+///   index in its result; otherwise a `UserWarning` says how many there were. Records that
+///   conflict while the schema is inferred are a matter for `type_conflicts`.
+/// - `empty_schema`: a `pyarrow.Schema`; statements without records return an empty batch
+///   with this schema instead of `None`.
+/// - `schema`: a `pyarrow.Schema` to convert to instead of inferring one. Values are coerced
+///   to the declared types where possible (`"42"` to an integer, `42` to a string, ...) and
+///   fields missing from a record are null. Also the default `empty_schema`.
+/// - `columns`: a list of the top-level fields to convert. Other members of each record are
+///   skipped without being traced or built; a selected record id column is still split in
+///   `record_id_mode="split"`. A declared `schema` already selects its own fields.
+/// - `column_order`: `"sorted"` (default) orders inferred columns by name, `"insertion"` in
+///   the order the records first have each field, and a list of field names puts those
+///   first, in that order, followed by the rest by name. A split record id column's two
+///   columns take its place. A declared `schema` keeps its own order.
+/// - `rename`: a dict from field names to the column names to output them under, e.g.
+///   `{"usr_nm": "user_name"}`. Other options (`columns`, `dictionary`, `schema`, ...) name
+///   fields as they are in the records.
+/// - `sanitize_names`: `True` makes output column names safe for SQL engines by replacing
+///   characters other than ASCII letters, digits and `_` (dots, spaces, quotes, ...) with
+///   `_`, or with the given string, itself of ASCII letters, digits and `_` and not starting
+///   with a digit. Names that start with a digit get the replacement as a prefix and names
+///   that end up equal are numbered. A renamed column keeps its name in the `original_name`
+///   field metadata. Applies after `rename` and `flatten`.
+/// - `include`, `exclude`: lists of patterns matched against the dotted path of each object
+///   member, such as `"meta.created"`. Strings are globs (`*` within a path segment, `**`
+///   across segments, `?` one character); compiled `re` patterns match the whole path.
+///   Excluded members are dropped with everything in them. With `include`, only matching
+///   members are kept, along with the objects containing them. Objects in arrays share the
+///   array's path. Records of large responses are filtered while decoding.
+/// - `schema_cache_key`: a string naming the query. The schema inferred on the first call
+///   is reused by later calls with the same key and the same options shaping the schema,
+///   as long as their records fit it (no new fields or types); otherwise it is inferred and
+///   cached again. Calls with other options cache a schema of their own under the key.
+///   `clear_schema_cache()` forgets cached schemas.
+/// - `coerce_numbers`, `allow_to_string`, `allow_null_fields`, `string_dictionary_encoding`,
+///   `guess_dates`: serde_arrow tracing options for schema inference, all default `False`.
+///   They widen mixed number columns to one number type, mixed scalar columns to strings,
+///   keep null-only fields as `null` columns instead of failing, dictionary-encode strings,
+///   and parse ISO 8601 datetime strings into timestamps.
+/// - `map_as_struct`: `False` infers nested objects as Arrow maps (string keys, one value
+///   type) instead of structs; default `True`.
+/// - `objects_as`: `"struct"` (default) converts object fields to structs, `"map"` to Arrow
+///   maps as `map_as_struct=False` does, and `"json"` to JSON text, for free-form objects
+///   with different keys in each record. With `"json"` a field holding objects, or arrays of
+///   them, becomes a string column (a list of strings).
+/// - `map_keys`: how non-text object keys become field names: `"display"` (default) uses
+///   their string form (`5`, `true`, `table:key`), `"json"` their JSON text, and `"error"`
+///   fails. Keys of one object that end up with the same name are an error.
+/// - `duplicate_keys`: for an object with a repeated key, `"last"` (default) converts the
+///   last value and `"first"` the first, with a `LossyConversionWarning`; `"error"` fails.
+/// - `explode`: a list of list columns to unnest: each element becomes its own row, with
+///   the other columns repeated. Columns exploded together must have lists of the same
+///   length in each row; an empty or null list gives one row with a null.
+/// - `flatten`: `True` replaces struct columns by their children under dotted names, so
+///   `{address: {city, zip}}` becomes `address.city` and `address.zip`; an integer flattens
+///   only that many levels of nesting. A child is null wherever its struct is. Structs in
+///   lists and maps are kept.
+/// - `type_conflicts`: what to do with a field whose type differs between records: `"error"`
+///   (default) fails, `"promote"` widens integers to floats and turns anything else that does
+///   not combine (including objects) into text, `"stringify"` turns every conflicting field
+///   into text. Text is the value's string form, or JSON for objects and arrays. `"json"`
+///   widens integers to floats and stores the values of any other conflicting field as
+///   compact JSON text (strings quoted), in a column with the `arrow.json` extension type.
+///   `"union"` widens integers to floats and builds any other conflicting field as an Arrow
+///   union with one variant per type (`"string"`, `"struct"`, ...), so a field holding a
+///   string in some records and an object in others keeps both.
+/// - `big_int`: integers outside the 64-bit ranges fail by default (`"error"`). `"decimal"`
+///   converts an integer column holding one to `Decimal256(76, 0)`, `"string"` to a string
+///   column of decimal digits (other integers in it as well), and `"null"` makes those
+///   values null. CBOR bignums (tags 2 and 3) that fit 64 bits are plain integers and
+///   larger ones follow the same policy; `"decimal"` fails for those beyond 76 digits.
+/// - `union_mode`: `"dense"` (default) or `"sparse"` unions for `type_conflicts="union"`.
+/// - `mixed_types`: `"json"` is the same as `type_conflicts="json"`; `"error"` (default) fails.
+///   Given with `type_conflicts`, the two must agree.
+/// - `large_types`: inferred string, binary and list columns are `LargeUtf8`, `LargeBinary`
+///   and `LargeList`, whose 64-bit offsets hold more than 2 GiB per column (default `True`).
+///   `False` infers `Utf8`, `Binary` and `List` for consumers that only take those.
+/// - `dictionary`: `True` to dictionary-encode every inferred top-level string column as
+///   `Dictionary<Int32, Utf8>`, or a list of the column names to encode; default `False`.
+///   Worth it for low-cardinality fields such as statuses and categories.
+/// - `infer_samples`: infer the schema from only the first `N` records of each result, or
+///   `"all"` (default) to scan every record. Sampled fields are all nullable; fields first
+///   seen after the sample are left out, and values of other types fail to convert.
+/// - `value_column`: column name for scalar results (`SELECT VALUE ...`), default `"value"`.
+/// - `scalar_as`: `"table"` (default) returns a lone scalar result as a 1x1 table, `"python"`
+///   as the plain Python value.
+/// - `output`: `"batch"` (default) returns a batch implementing the Arrow PyCapsule interface
+///   (`__arrow_c_array__`), so pyarrow is not required; `"pyarrow"` a `pyarrow.RecordBatch`;
+///   `"reader"` a `pyarrow.RecordBatchReader` that converts the records in batches as it is
+///   consumed; `"stream"` the same as a PyCapsule stream (`__arrow_c_stream__`); `"table"` a
+///   `pyarrow.Table`; `"numpy"` a dict from column names to NumPy arrays (masked arrays for
+///   columns with nulls) for flat results of numbers, booleans, strings, bytes, datetimes and
+///   durations.
+/// - `max_rows_per_batch`: convert each result in slices of at most this many rows and
+///   return a list of batches (or the chunks of a `"table"`); also sets the batch size of
+///   `"reader"`/`"stream"` outputs.
+/// - `num_threads`: threads used to convert large results, default the number of CPUs;
+///   `1` converts on the calling thread. Conversion runs with the GIL released.
+/// - `max_rows`, `max_bytes`, `max_cells`: raise `SurrealEngineError`, a subclass of
+///   `ValueError`, instead of converting when a result has more rows, the payload more
+///   bytes, or a result more rows x columns than given.
+///
+/// Values converted with a loss of information (tags degraded to their payload, non-text
+/// object keys stringified, repeated keys dropped, big integers nulled) are counted by kind
+/// in a `LossyConversionWarning`, a `UserWarning`, issued once per call.
+///
+/// `statement` selects which statement of a multi-statement response to convert
+/// (negative values count from the end); the others are not converted.
+#[pyfunction]
+#[pyo3(signature = (data, statement=0, **options))]
+fn cbor_to_arrow(py: Python, data: CborInput, statement: isize, options: Option<&Bound<'_, PyDict>>) -> PyResult<PyObject> {
+    let opts = ConvertOptions::from_kwargs(options)?;
+    opts.check_bytes(&data)?;
+    let payload = Arc::new(Payload::load(py, data, true, &opts)?);
+    convert_statement(py, &payload, statement, &opts)
+}
+
+/// Convert the responses of a CBOR sequence (RFC 8742), RPC responses written
+/// back to back into one buffer as when frames are concatenated.
+///
+/// Returns a list with what `cbor_to_arrow` returns for each response, or with
+/// `merge=True` one result holding the statement of every response, as
+/// `merge_cbor_to_arrow` does. Accepts the same keyword options as `cbor_to_arrow`.
+#[pyfunction]
+#[pyo3(signature = (data, statement=0, merge=false, **options))]
+fn cbor_sequence_to_arrow(
+    py: Python,
+    data: CborInput,
+    statement: isize,
+    merge: bool,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<PyObject> {
+    let opts = ConvertOptions::from_kwargs(options)?;
+    opts.check_bytes(&data)?;
+    let loaded = Payload::load_sequence(py, data, true, &opts)?;
+    if merge {
+        return merge_payloads(py, &loaded, statement, &opts);
+    }
+    let results = loaded
+        .into_iter()
+        .map(|payload| convert_statement(py, &Arc::new(payload), statement, &opts))
+        .collect::<PyResult<Vec<_>>>()?;
+    Ok(results.into_pyobject(py)?.into_any().unbind())
+}
+
+/// Convert statement `statement` of a loaded response, as `cbor_to_arrow` does.
+fn convert_statement(py: Python, payload: &Arc<Payload>, statement: isize, opts: &ConvertOptions) -> PyResult<PyObject> {
+    convert_statement_into(py, payload, statement, opts, Ok)
+}
+
+/// `convert_statement`, handing the result to `into` before pairing it with the
+/// errors collected under `on_statement_error="collect"`.
+fn convert_statement_into(
+    py: Python,
+    payload: &Arc<Payload>,
+    statement: isize,
+    opts: &ConvertOptions,
+    into: impl FnOnce(PyObject) -> PyResult<PyObject>,
+) -> PyResult<PyObject> {
+    let responses = root_responses(&payload.root)?;
+
+    if responses.is_empty() {
+        return into(empty_result(py, opts)?);
+    }
+
+    let index = statement_index(statement, responses.len())?;
+    let mut errors = Vec::new();
+    let result = convert_or_collect(py, payload, index, opts, &mut errors)?;
+    finish(py, into(result)?, errors, opts)
+}
+
+/// Infer the schema `cbor_to_arrow` would convert a statement to, without
+/// building any arrays.
+///
+/// Returns a `pyarrow.Schema`, or `None` for a statement without records (the
+/// `empty_schema` if given). A failed statement raises. Accepts the same keyword
+/// options as `cbor_to_arrow`.
+#[pyfunction]
+#[pyo3(signature = (data, statement=0, **options))]
+fn infer_schema(py: Python, data: CborInput, statement: isize, options: Option<&Bound<'_, PyDict>>) -> PyResult<PyObject> {
+    let opts = ConvertOptions::from_kwargs(options)?;
+    opts.check_bytes(&data)?;
+    let payload = Payload::load(py, data, true, &opts)?;
+    let responses = root_responses(&payload.root)?;
+    let plan = match responses.len() {
+        0 => opts.empty_schema.clone().map(BatchPlan::empty),
+        len => result_plan(py, &payload, RecordsAt::Statement(statement_index(statement, len)?), &opts)?.1,
+    };
+    opts.report_rows(py)?;
+    match plan {
+        Some(plan) => plan.schema.to_pyarrow(py),
+        None => Ok(py.None()),
+    }
+}
+
+/// A non-negative number of seconds given as argument `name`.
+pub(crate) fn seconds(name: &str, value: f64) -> PyResult<Duration> {
+    Duration::try_from_secs_f64(value)
+        .map_err(|_| PyValueError::new_err(format!("{} must be a non-negative number of seconds", name)))
+}
+
+/// Resolve a possibly negative `statement` index into a response of `len` statements.
+fn statement_index(statement: isize, len: usize) -> PyResult<usize> {
+    let index = if statement < 0 { statement + len as isize } else { statement };
+    usize::try_from(index).ok().filter(|&i| i < len).ok_or_else(|| {
+        PyIndexError::new_err(format!("statement {} out of range for a response with {} statements", statement, len))
+    })
+}
+
+/// Convert every statement of a multi-statement CBOR response.
+///
+/// Returns a list with one entry per statement, in order: a RecordBatch, or
+/// `None` for a statement with no records. Accepts the same keyword options as
+/// `cbor_to_arrow`.
+#[pyfunction]
+#[pyo3(signature = (data, **options))]
+fn cbor_to_arrow_all(py: Python, data: CborInput, options: Option<&Bound<'_, PyDict>>) -> PyResult<PyObject> {
+    let opts = ConvertOptions::from_kwargs(options)?;
+    opts.check_bytes(&data)?;
+    let payload = Arc::new(Payload::load(py, data, true, &opts)?);
+    let mut errors = Vec::new();
+    let results = (0..root_responses(&payload.root)?.len())
+        .map(|i| convert_or_collect(py, &payload, i, &opts, &mut errors))
+        .collect::<PyResult<Vec<_>>>()?;
+    finish(py, results.into_pyobject(py)?.into_any().unbind(), errors, &opts)
+}
+
+/// Convert statement `index`, or apply `on_statement_error` if it failed.
+fn convert_or_collect(
+    py: Python,
+    payload: &Arc<Payload>,
+    index: usize,
+    opts: &ConvertOptions,
+    errors: &mut Vec<PyObject>,
+) -> PyResult<PyObject> {
+    let response = &root_responses(&payload.root)?[index];
+    if opts.on_statement_error != StatementErrorPolicy::Raise {
+        if let Some(error) = statement_error(response) {
+            if opts.on_statement_error == StatementErrorPolicy::Collect {
+                errors.push(error.to_py(py, index)?);
+            }
+            return Ok(py.None());
+        }
+    }
+    if opts.scalar_as == ScalarMode::Python {
+        if let Some(value) = statement_scalar(response) {
+            return pyvalue::value_to_py(py, value);
+        }
+    }
+    convert_result(py, payload, RecordsAt::Statement(index), opts)
+}
+
+/// Pair `result` with the collected errors when `on_statement_error="collect"`.
+fn finish(py: Python, result: PyObject, errors: Vec<PyObject>, opts: &ConvertOptions) -> PyResult<PyObject> {
+    if opts.on_statement_error == StatementErrorPolicy::Collect {
+        Ok((result, errors).into_pyobject(py)?.into_any().unbind())
+    } else {
+        Ok(result)
+    }
+}
+
+/// Convert a bare CBOR array of records, without the RPC `result`/`status` envelope.
+///
+/// Accepts the same keyword options as `cbor_to_arrow`; a lone object or scalar is
+/// converted like a single-statement result.
+#[pyfunction]
+#[pyo3(signature = (data, **options))]
+fn records_cbor_to_arrow(py: Python, data: CborInput, options: Option<&Bound<'_, PyDict>>) -> PyResult<PyObject> {
+    let opts = ConvertOptions::from_kwargs(options)?;
+    opts.check_bytes(&data)?;
+    let payload = Arc::new(Payload::load(py, data, false, &opts)?);
+    if opts.scalar_as == ScalarMode::Python && !matches!(payload.root, Value::Array(_) | Value::Map(_)) {
+        return pyvalue::value_to_py(py, &payload.root);
+    }
+    convert_result(py, &payload, RecordsAt::Bare, &opts)
+}
+
+/// Convert the same statement of several CBOR responses, such as the pages of a
+/// paginated query, into one result with a single schema.
+///
+/// The schema is inferred across the records of all responses: fields missing
+/// from some of them are nullable, and types are combined as within one response
+/// (see `coerce_numbers`). Returns one batch holding every response's records in
+/// order, a `pyarrow.Table` with one chunk per response for `output="table"`, or a
+/// reader over them for `"reader"`/`"stream"`; `None` if no response has records.
+/// Accepts the same keyword options as `cbor_to_arrow`.
+#[pyfunction]
+#[pyo3(signature = (payloads, statement=0, **options))]
+fn merge_cbor_to_arrow(
+    py: Python,
+    payloads: Vec<CborInput>,
+    statement: isize,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<PyObject> {
+    let opts = ConvertOptions::from_kwargs(options)?;
+    let mut loaded = Vec::with_capacity(payloads.len());
+    for data in payloads {
+        opts.check_bytes(&data)?;
+        loaded.push(Payload::load(py, data, true, &opts)?);
+    }
+    merge_payloads(py, &loaded, statement, &opts)
+}
+
+/// Convert statement `statement` of every loaded response into one result, as
+/// `merge_cbor_to_arrow` does.
+fn merge_payloads(py: Python, loaded: &[Payload], statement: isize, opts: &ConvertOptions) -> PyResult<PyObject> {
+    let mut sources = Vec::with_capacity(loaded.len());
+    for payload in loaded {
+        let len = root_responses(&payload.root)?.len();
+        if len == 0 {
+            continue;
+        }
+        let at = RecordsAt::Statement(statement_index(statement, len)?);
+        sources.extend(result_rows(payload, at)?.0);
+    }
+    if sources.is_empty() {
+        return empty_result(py, opts);
+    }
+    let rows: usize = sources.iter().map(Rows::len).sum();
+    opts.check_rows(rows)?;
+    let plan = py.allow_threads(|| match &opts.schema {
+        Some(schema) => Ok(BatchPlan::declared(schema.clone(), sources[0], opts)),
+        None => BatchPlan::infer_merged(&sources, opts),
+    })?;
+    opts.check_cells(rows, plan.fields.len())?;
+    let batches = py.allow_threads(|| {
+        let mut batches = Vec::with_capacity(sources.len());
+        for rows in &sources {
+            match opts.max_rows_per_batch {
+                Some(max_rows) if !rows.is_empty() => batches.extend(plan.build_chunks(*rows, 0, max_rows, opts)?),
+                _ => batches.push(plan.build_rows(*rows, 0, opts)?),
+            }
+        }
+        Ok::<_, PyErr>(batches)
+    })?;
+    match opts.output {
+        OutputMode::Reader | OutputMode::Stream => {
+            let reader = RecordBatchIterator::new(batches.into_iter().map(Ok), plan.schema.clone());
+            output::emit_reader(py, Box::new(reader), opts)
+        }
+        _ if opts.max_rows_per_batch.is_some() || opts.output == OutputMode::Table => {
+            output::emit_batches(py, batches, plan.schema.clone(), opts)
+        }
+        _ => {
+            let batch = concat_batches(&plan.schema, &batches)
+                .map_err(|e| ArrowBuildError::new_err(format!("RecordBatch creation error: {}", e)))?;
+            output::emit_batch(py, batch, opts)
+        }
+    }
+}
+
+/// CBOR input taken from any object supporting the buffer protocol (`bytes`,
+/// `bytearray`, `memoryview`, `mmap`, numpy `uint8` arrays), without copying.
+struct CborInput(PyBuffer<u8>);
+
+impl<'py> FromPyObject<'py> for CborInput {
+    fn extract_bound(obj: &Bound<'py, PyAny>) -> PyResult<Self> {
+        let buffer = PyBuffer::<u8>::get(obj)
+            .map_err(|_| PyTypeError::new_err("CBOR data must be bytes or a byte buffer"))?;
+        if !buffer.is_c_contiguous() {
+            return Err(PyValueError::new_err("CBOR data buffer must be C-contiguous"));
+        }
+        Ok(CborInput(buffer))
+    }
+}
+
+impl CborInput {
+    fn as_bytes(&self) -> &[u8] {
+        // SAFETY: the buffer is contiguous and stays exported (so the memory is
+        // kept alive and cannot be resized) for as long as `self` lives.
+        unsafe { std::slice::from_raw_parts(self.0.buf_ptr() as *const u8, self.0.len_bytes()) }
+    }
+
+    /// Decode the buffer. The GIL is released for read-only buffers; writable
+    /// ones (e.g. `bytearray`) could be modified by other threads meanwhile.
+    fn decode(&self, py: Python) -> PyResult<Value> {
+        if self.0.readonly() {
+            py.allow_threads(|| decode_root(self.as_bytes()))
+        } else {
+            decode_root(self.as_bytes())
+        }
+    }
+
+    /// Decode each item of a CBOR sequence, releasing the GIL as `decode` does.
+    fn decode_sequence(&self, py: Python) -> PyResult<Vec<Value>> {
+        if self.0.readonly() {
+            py.allow_threads(|| pull::decode_sequence(self.as_bytes()))
+        } else {
+            pull::decode_sequence(self.as_bytes())
+        }
+    }
+}
+
+/// Decode the single CBOR value `bytes` holds.
+///
+/// Decoding and conversion never touch Python objects, so callers run them
+/// inside `py.allow_threads` and only hold the GIL to build the result.
+fn decode_root(bytes: &[u8]) -> PyResult<Value> {
+    pull::decode(bytes)
+}
+
+/// The per-statement responses of an RPC envelope (`root["result"]`).
+fn root_responses(root: &Value) -> PyResult<&[Value]> {
+    // An RPC error has no "result" to look for.
+    if let Some(err) = response_error(root) {
+        return Err(err);
+    }
+
+    let root_result_arr = if let Value::Map(map) = root {
+         map.iter()
+            .find(|(k, _)| matches!(k, Value::Text(s) if s == "result"))
+            .map(|(_, v)| v)
+    } else {
+        return Err(CborDecodeError::new_err("CBOR Root is not a Map"));
+    };
+
+    let responses = match root_result_arr {
+        Some(Value::Array(arr)) => arr.as_slice(),
+        Some(_) => return Err(CborDecodeError::new_err("Root 'result' is not an array")),
+        None => return Err(CborDecodeError::new_err("Root 'result' key not found")),
+    };
+
+    Ok(responses)
+}
+
+/// The error of an RPC response failed as a whole, if it is one
+/// (e.g. `{ "id": ..., "error": { "code": ..., "message": ... } }`).
+fn response_error(root: &Value) -> Option<PyErr> {
+    let Value::Map(map) = root else {
+        return None;
+    };
+    let v = map_get(map, "error")?;
+    Some(if let Value::Map(err_map) = v {
+         let message = err_map.iter()
+            .find(|(k, _)| matches!(k, Value::Text(s) if s == "message"))
+            .map(|(_, v)| match v {
+                Value::Text(s) => s.clone(),
+                _ => format!("{:?}", v),
+            })
+            .unwrap_or_else(|| format!("{:?}", v));
+         let code = map_get(err_map, "code");
+         let code_text = code.map(|v| format!("{:?}", v)).unwrap_or_else(|| "?".to_string());
+         query_status_error(format!("SurrealDB Error ({}): {}", code_text, message), code, &message, None, None)
+    } else {
+         let message = format!("{:?}", v);
+         query_status_error(format!("SurrealDB Error: {}", message), None, &message, None, None)
+    })
+}
+
+/// A `QueryStatusError` whose instance carries the SurrealDB error `code` (none
+/// for a failed statement), the error `message`, the index of the failed
+/// `statement` (none for an error of the whole request), and the statement's
+/// `status` and `time`.
+fn query_status_error(
+    text: String,
+    code: Option<&Value>,
+    message: &str,
+    statement: Option<usize>,
+    error: Option<&StatementError>,
+) -> PyErr {
+    Python::with_gil(|py| {
+        let err = QueryStatusError::new_err(text);
+        let attributes = || -> PyResult<()> {
+            let value = err.value(py);
+            value.setattr("code", code.map(|code| pyvalue::value_to_py(py, code)).transpose()?)?;
+            value.setattr("message", message)?;
+            value.setattr("statement", statement)?;
+            value.setattr("status", error.map(|error| &error.status))?;
+            value.setattr("time", error.and_then(|error| error.time.as_ref()))?;
+            Ok(())
+        };
+        match attributes() {
+            Ok(()) => err,
+            Err(e) => e,
+        }
+    })
+}
+
+/// A statement that finished with a non-`OK` status.
+struct StatementError {
+    status: String,
+    message: String,
+    time: Option<String>,
+}
+
+impl StatementError {
+    fn to_py(&self, py: Python, index: usize) -> PyResult<PyObject> {
+        let dict = PyDict::new(py);
+        dict.set_item("statement", index)?;
+        dict.set_item("status", &self.status)?;
+        dict.set_item("message", &self.message)?;
+        dict.set_item("time", &self.time)?;
+        Ok(dict.into_any().unbind())
+    }
+}
+
+/// The error of a statement response whose status is not `OK`.
+fn statement_error(response: &Value) -> Option<StatementError> {
+    let Value::Map(response_map) = response else {
+        return None;
+    };
+    let status = match map_get(response_map, "status") {
+        Some(Value::Text(status)) if status != "OK" => status.clone(),
+        _ => return None,
+    };
+    // Try to find "detail" or "message" to include in error; SurrealDB itself
+    // puts the error text in "result".
+    let message = response_map.iter()
+        .find(|(k, _)| matches!(k, Value::Text(s) if s == "detail" || s == "message"))
+        .map(|(_, v)| match v {
+            Value::Text(s) => s.clone(),
+            _ => format!("{:?}", v),
+        })
+        .or_else(|| match map_get(response_map, "result") {
+            Some(Value::Text(s)) => Some(s.clone()),
+            _ => None,
+        })
+        .unwrap_or_else(|| "Unknown error".to_string());
+    let time = match map_get(response_map, "time") {
+        Some(Value::Text(time)) => Some(time.clone()),
+        _ => None,
+    };
+    Some(StatementError { status, message, time })
+}
+
+/// Check a statement response's status and return its records, or `None` if empty.
+fn statement_records(response: &Value, index: usize) -> PyResult<Option<&[Value]>> {
+    let response_map = if let Value::Map(map) = response {
+        map
+    } else {
+        return Err(CborDecodeError::new_err("Statement response is not a Map"));
+    };
+    
+    // Check status
+    if let Some(error) = statement_error(response) {
+        let text = format!("Database returned error status '{}': {}", error.status, error.message);
+        return Err(query_status_error(text, None, &error.message, Some(index), Some(&error)));
+    }
+
+    // Get inner result
+    let inner_result_opt = response_map.iter()
+        .find(|(k, _)| matches!(k, Value::Text(s) if s == "result"))
+        .map(|(_, v)| v);
+
+    match inner_result_opt {
+        Some(value) => Ok(result_records(value)),
+        None => {
+            // If status is OK but no result, maybe it's valid empty? or just missing.
+            // Check keys to be helpful
+            let keys: Vec<String> = response_map.iter().map(|(k, _)| format!("{:?}", k)).collect();
+            Err(CborDecodeError::new_err(format!("Inner 'result' key not found. Available keys: {:?}", keys)))
+        }
+    }
+}
+
+/// The rows of a query result, or `None` if it is empty.
+fn result_records(result: &Value) -> Option<&[Value]> {
+    let records_arr = match result {
+        Value::Array(arr) => arr.as_slice(),
+        value if is_null(value) => return None,
+        // A lone object (`SELECT * FROM ONLY ...`) or scalar (`RETURN count(...)`)
+        // becomes a single row.
+        value => std::slice::from_ref(value),
+    };
+    if records_arr.is_empty() {
+        return None;
+    }
+    Some(records_arr)
+}
+
+/// The records of statement `statement` of an RPC response, or with
+/// `envelope=False` of `root` itself, a bare result; `None` if there are none.
+fn envelope_records(root: &Value, statement: isize, envelope: bool) -> PyResult<Option<&[Value]>> {
+    match envelope {
+        true => {
+            let responses = root_responses(root)?;
+            let index = statement_index(statement, responses.len())?;
+            statement_records(&responses[index], index)
+        }
+        false => Ok(result_records(root)),
+    }
+}
+
+/// The result of a successful statement that returned a single scalar.
+fn statement_scalar(response: &Value) -> Option<&Value> {
+    let Value::Map(map) = response else {
+        return None;
+    };
+    if statement_error(response).is_some() {
+        return None;
+    }
+    match map_get(map, "result")? {
+        Value::Array(_) | Value::Map(_) => None,
+        value => Some(value),
+    }
+}
+
+/// Convert the result at `at`, honouring `output`, `empty_schema` and `with_metadata`.
+fn convert_result(py: Python, payload: &Arc<Payload>, at: RecordsAt, opts: &ConvertOptions) -> PyResult<PyObject> {
+    let (records, Some(plan)) = result_plan(py, payload, at, opts)? else {
+        return empty_result(py, opts);
+    };
+    match opts.output {
+        OutputMode::Batch | OutputMode::PyArrow | OutputMode::Table | OutputMode::Numpy => {
+            let rows = records.unwrap_or(Rows::Decoded(&[], None));
+            let mut batches = py.allow_threads(|| match opts.max_rows_per_batch {
+                Some(max_rows) if !rows.is_empty() => plan.build_chunks(rows, 0, max_rows, opts),
+                _ => Ok(vec![plan.build_rows(rows, 0, opts)?]),
+            })?;
+            if opts.max_rows_per_batch.is_some() || opts.output == OutputMode::Table {
+                output::emit_batches(py, batches, plan.schema.clone(), opts)
+            } else {
+                output::emit_batch(py, batches.remove(0), opts)
+            }
+        }
+        OutputMode::Reader | OutputMode::Stream => {
+            let reader = reader::LazyBatches::new(payload.clone(), at, plan, opts.clone());
+            output::emit_reader(py, Box::new(reader), opts)
+        }
+    }
+}
+
+/// The records at `at` and the plan to convert them with: inferred, or from
+/// `empty_schema` if there are no records (`None` without one). The schema
+/// carries the statement's metadata with `with_metadata`.
+fn result_plan<'a>(
+    py: Python,
+    payload: &'a Payload,
+    at: RecordsAt,
+    opts: &ConvertOptions,
+) -> PyResult<(Option<Rows<'a>>, Option<BatchPlan>)> {
+    let (records, response) = result_rows(payload, at)?;
+    let mut plan = match (records, &opts.empty_schema) {
+        (Some(rows), _) => {
+            opts.check_rows(rows.len())?;
+            let plan = py.allow_threads(|| BatchPlan::for_rows(rows, opts))?;
+            opts.check_cells(rows.len(), plan.fields.len())?;
+            plan
+        }
+        (None, Some(schema)) => BatchPlan::empty(schema.clone()),
+        (None, None) => return Ok((None, None)),
+    };
+    if opts.with_metadata {
+        if let Some(Value::Map(map)) = response {
+            let mut metadata: HashMap<String, String> = plan.schema.metadata().clone();
+            metadata.extend(["time", "status"].into_iter().filter_map(|key| match map_get(map, key)? {
+                Value::Text(s) => Some((format!("surrealdb.{}", key), s.clone())),
+                _ => None,
+            }));
+            plan.schema = Arc::new(plan.schema.as_ref().clone().with_metadata(metadata));
+        }
+    }
+    Ok((records, Some(plan)))
+}
+
+/// The records at `at` (`None` if the result holds none) and the statement
+/// response they came from. A failed statement is an error.
+fn result_rows(payload: &Payload, at: RecordsAt) -> PyResult<(Option<Rows<'_>>, Option<&Value>)> {
+    let root = &payload.root;
+    let (records, response) = match at {
+        RecordsAt::Statement(i) => {
+            let response = &root_responses(root)?[i];
+            (statement_records(response, i)?, Some(response))
+        }
+        RecordsAt::Bare => (result_records(root), None),
+    };
+    Ok((payload.encoded(at).or(records.map(|records| Rows::Decoded(records, payload.spans(at)))), response))
+}
+
+/// The value returned for a result without records: `None`, or an empty
+/// `empty_schema` batch (or reader).
+fn empty_result(py: Python, opts: &ConvertOptions) -> PyResult<PyObject> {
+    let Some(schema) = &opts.empty_schema else {
+        return Ok(py.None());
+    };
+    match opts.output {
+        OutputMode::Batch | OutputMode::PyArrow | OutputMode::Table | OutputMode::Numpy => {
+            output::emit_batch(py, RecordBatch::new_empty(schema.clone()), opts)
+        }
+        OutputMode::Reader | OutputMode::Stream => {
+            let reader = RecordBatchIterator::new(Vec::new(), schema.clone());
+            output::emit_reader(py, Box::new(reader), opts)
+        }
+    }
+}
+
+/// Where the records of one result live inside a decoded response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RecordsAt {
+    /// The records of statement `n` of an RPC response.
+    Statement(usize),
+    /// A bare record array (no envelope).
+    Bare,
+}
+
+/// The records at `at`, or no rows if there are none.
+fn records_at(payload: &Payload, at: RecordsAt) -> Rows<'_> {
+    if let Some(rows) = payload.encoded(at) {
+        return rows;
+    }
+    let root = &payload.root;
+    let records = match at {
+        RecordsAt::Statement(i) => root_responses(root)
+            .ok()
+            .and_then(|responses| responses.get(i))
+            .and_then(|response| statement_records(response, i).ok().flatten()),
+        RecordsAt::Bare => result_records(root),
+    };
+    Rows::Decoded(records.unwrap_or(&[]), payload.spans(at))
+}
+
+/// A schema inferred once for a result, used to convert it whole or in slices.
+#[derive(Clone)]
+struct BatchPlan {
+    /// The fields records are converted with, named as in the records.
+    fields: Vec<FieldRef>,
+    /// The output schema: `fields` exploded, under their `rename` names and flattened.
+    schema: SchemaRef,
+    /// Top-level record id columns split in `RecordIdMode::Split`.
+    split_ids: Vec<String>,
+    /// Set when the records are scalars wrapped into this single column.
+    value_column: Option<String>,
+    /// Set when values may not have the schema's types (a caller's `schema`, or
+    /// `type_conflicts` other than `"error"`), so they are coerced to it.
+    coerce: bool,
+}
+
+impl BatchPlan {
+    fn new(fields: Vec<FieldRef>, split_ids: Vec<String>, value_column: Option<String>, opts: &ConvertOptions) -> Self {
+        let fields = match opts.on_row_error {
+            RowErrorPolicy::Null => schema::all_nullable(&fields),
+            _ => fields,
+        };
+        let fields = match opts.small_types {
+            true => schema::small_types(&fields),
+            false => fields,
+        };
+        let fields: Vec<FieldRef> = fields
+            .into_iter()
+            .map(|f| match f.data_type() {
+                DataType::Utf8 | DataType::LargeUtf8 if opts.dictionary.encodes(f.name()) => {
+                    let encoded = DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+                    Arc::new(f.as_ref().clone().with_data_type(encoded))
+                }
+                _ => f,
+            })
+            .collect();
+        let schema = Arc::new(Schema::new(opts.output_fields(&fields)));
+        let coerce = opts.type_conflicts != TypeConflicts::Error;
+        BatchPlan { fields, schema, split_ids, value_column, coerce }
+    }
+
+    /// The plan for `rows`: the declared `schema` if there is one, otherwise the one
+    /// cached under `schema_cache_key` if the rows fit it, otherwise inferred.
+    fn for_rows(rows: Rows, opts: &ConvertOptions) -> PyResult<Self> {
+        if let Some(schema) = &opts.schema {
+            return Ok(Self::declared(schema.clone(), rows, opts));
+        }
+        let Some(key) = &opts.schema_cache_key else {
+            return Self::infer_sampled(rows, opts);
+        };
+        let shape = opts.plan_fingerprint();
+        if let Some(plan) = cache::get(key, shape) {
+            if plan.admits(rows, opts)? {
+                return Ok(plan);
+            }
+        }
+        let plan = Self::infer_sampled(rows, opts)?;
+        if !rows.is_empty() {
+            cache::insert(key, shape, plan.clone());
+        }
+        Ok(plan)
+    }
+
+    /// Infer the schema from the first `infer_samples` rows, or all of them.
+    fn infer_sampled(rows: Rows, opts: &ConvertOptions) -> PyResult<Self> {
+        let Some(samples) = opts.infer_samples.filter(|&n| n < rows.len()) else {
+            return Self::infer_rows(rows, opts);
+        };
+        let plan = Self::infer_rows(rows.slice(0, samples), opts)?;
+        // Records past the sample may lack any field.
+        Ok(Self::new(schema::all_nullable(&plan.fields), plan.split_ids, plan.value_column, opts))
+    }
+
+    /// True if `rows` convert with this plan as they would with a freshly inferred one.
+    fn admits(&self, rows: Rows, opts: &ConvertOptions) -> PyResult<bool> {
+        if scalar_column(rows, opts) != self.value_column {
+            return Ok(false);
+        }
+        for chunk in rows.chunks(pull::DECODE_CHUNK_ROWS) {
+            let records_arr = chunk.decode()?;
+            let fits = |record: &Value| match (record, &self.value_column) {
+                (_, Some(_)) if !self.split_ids.is_empty() => {
+                    is_null(record) || tag_kind(record) == Some(SurrealTag::RecordId)
+                }
+                (_, Some(_)) => schema::value_conforms(&self.fields[0], Some(record), opts),
+                (Value::Map(map), None) => schema::conforms(&self.fields, map, &self.split_ids, opts),
+                _ => false,
+            };
+            if !records_arr.iter().all(fits) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// A plan converting `rows` to `schema`. In `RecordIdMode::Split` a record id
+    /// column is split when the schema has its two suffixed columns but not itself.
+    fn declared(schema: SchemaRef, rows: Rows, opts: &ConvertOptions) -> Self {
+        let value_column = scalar_column(rows, opts);
+        let suffixes = &opts.record_id_suffixes;
+        let has = |name: &str| schema.fields().iter().any(|f| f.name() == name);
+        let split_ids = match opts.record_id_mode {
+            RecordIdMode::Split => schema
+                .fields()
+                .iter()
+                .filter_map(|f| f.name().strip_suffix(suffixes.table.as_str()))
+                .filter(|id| !has(id) && has(&format!("{}{}", id, suffixes.key)))
+                .map(str::to_string)
+                .collect(),
+            _ => Vec::new(),
+        };
+        let fields = match opts.on_row_error {
+            RowErrorPolicy::Null => schema::all_nullable(schema.fields()),
+            _ => schema.fields().to_vec(),
+        };
+        let schema = Arc::new(Schema::new_with_metadata(opts.output_fields(&fields), schema.metadata().clone()));
+        BatchPlan { fields, schema, split_ids, value_column, coerce: true }
+    }
+
+    /// This plan kept for the later batches of a stream. An inferred schema
+    /// becomes all nullable, since later batches may lack any value of a column,
+    /// and values are coerced to it.
+    fn pinned(self, opts: &ConvertOptions) -> Self {
+        if opts.schema.is_some() {
+            return self;
+        }
+        let plan = BatchPlan::new(schema::all_nullable(&self.fields), self.split_ids, self.value_column, opts);
+        BatchPlan { coerce: true, ..plan }
+    }
+
+    /// Infer the schema for `records_arr`.
+    fn infer(records_arr: &[Value], opts: &ConvertOptions) -> PyResult<Self> {
+        let value_column = scalar_column(Rows::Decoded(records_arr, None), opts);
+        let mut split = SplitScan::default();
+        if opts.record_id_mode == RecordIdMode::Split {
+            split.observe(records_arr, value_column.as_deref(), opts.map_keys);
+        }
+        let split_ids = split.columns(opts);
+        let mut fields = trace_resolving(records_arr, &split_ids, value_column.as_deref(), opts, opts.tracing.clone())?;
+        let mut insertion = KeyOrder::default();
+        if opts.column_order == ColumnOrder::Insertion {
+            insertion.observe(records_arr, opts.map_keys);
+        }
+        order_fields(&mut fields, &split_ids, &insertion, opts);
+        Ok(Self::new(fields, split_ids, value_column, opts))
+    }
+
+    /// Infer the schema for `rows`. Encoded records are decoded and traced a chunk
+    /// at a time and the chunk schemas merged.
+    fn infer_rows(rows: Rows, opts: &ConvertOptions) -> PyResult<Self> {
+        if let Rows::Decoded(records_arr, _) = rows {
+            return Self::infer(records_arr, opts);
+        }
+        Self::infer_merged(&[rows], opts)
+    }
+
+    /// Infer one schema covering the records of every result in `sources`, tracing
+    /// them a chunk at a time and merging the chunk schemas.
+    fn infer_merged(sources: &[Rows], opts: &ConvertOptions) -> PyResult<Self> {
+        // Scalars only if no result holds objects
+        let value_column = match sources.iter().any(Rows::any_map) {
+            true => None,
+            false => scalar_column(Rows::Decoded(&[], None), opts),
+        };
+        let mut split = SplitScan::default();
+        let mut insertion = KeyOrder::default();
+        let mut fields: Option<Vec<FieldRef>> = None;
+        for chunk in sources.iter().flat_map(|rows| rows.chunks(pull::DECODE_CHUNK_ROWS)) {
+            let records_arr = chunk.decode()?;
+            if opts.record_id_mode == RecordIdMode::Split {
+                split.observe(&records_arr, value_column.as_deref(), opts.map_keys);
+            }
+            if opts.column_order == ColumnOrder::Insertion {
+                insertion.observe(&records_arr, opts.map_keys);
+            }
+            // Split columns are only known after the last chunk; until then record
+            // ids are traced as the strings they are in unsplit columns.
+            let tracing = opts.tracing.clone().allow_null_fields(true);
+            let chunk_fields = trace_resolving(&records_arr, &[], value_column.as_deref(), opts, tracing)?;
+            fields = Some(match fields {
+                Some(fields) => schema::merge_fields(&fields, &chunk_fields, opts)
+                    .map_err(|e| SchemaInferenceError::new_err(format!("Schema inference error: {}", e)))?,
+                None => chunk_fields,
+            });
+        }
+        let mut fields = fields.unwrap_or_default();
+        let split_ids = split.columns(opts);
+        let suffixes = &opts.record_id_suffixes;
+        for id in &split_ids {
+            fields.retain(|f| f.name() != id);
+            for suffix in [&suffixes.table, &suffixes.key] {
+                fields.push(Arc::new(Field::new(format!("{}{}", id, suffix), DataType::LargeUtf8, true)));
+            }
+        }
+        order_fields(&mut fields, &split_ids, &insertion, opts);
+        if let Some(name) = schema::null_only_field(&fields).filter(|_| !opts.tracing.allow_null_fields) {
+            return Err(null_only_error(name));
+        }
+        Ok(Self::new(fields, split_ids, value_column, opts))
+    }
+
+    /// A plan for a result without records.
+    fn empty(schema: SchemaRef) -> Self {
+        BatchPlan { fields: schema.fields().to_vec(), schema, split_ids: Vec::new(), value_column: None, coerce: false }
+    }
+
+    /// Convert `records_arr` (all or part of the inferred records) into a batch.
+    fn build(&self, records_arr: &[Value], opts: &ConvertOptions) -> PyResult<RecordBatch> {
+        self.build_rows(Rows::Decoded(records_arr, None), 0, opts)
+    }
+
+    /// Convert `rows`, the records of a result from index `first` on, into a single
+    /// batch, splitting large inputs across worker threads and decoding encoded
+    /// records a chunk at a time.
+    fn build_rows(&self, rows: Rows, first: usize, opts: &ConvertOptions) -> PyResult<RecordBatch> {
+        let mut chunk_rows = rows.len().div_ceil(worker_count(rows.len(), opts));
+        if let Rows::Encoded(..) = rows {
+            chunk_rows = chunk_rows.min(pull::DECODE_CHUNK_ROWS);
+        }
+        if chunk_rows >= rows.len() {
+            return self.build_serial(&rows.decode()?, rows.spans(), first, opts);
+        }
+        let batches = self.build_chunks(rows, first, chunk_rows, opts)?;
+        concat_batches(&self.schema, &batches)
+            .map_err(|e| ArrowBuildError::new_err(format!("RecordBatch creation error: {}", e)))
+    }
+
+    /// Convert `rows`, from index `first` of their result, into batches of at most
+    /// `chunk_rows` rows, handing contiguous runs of chunks to the rayon pool, or
+    /// to a pool of `num_threads` threads if that is set.
+    fn build_chunks(&self, rows: Rows, first: usize, chunk_rows: usize, opts: &ConvertOptions) -> PyResult<Vec<RecordBatch>> {
+        let chunks: Vec<(usize, Rows)> =
+            rows.chunks(chunk_rows).enumerate().map(|(i, chunk)| (first + i * chunk_rows, chunk)).collect();
+        let build = |(first, chunk): &(usize, Rows)| self.build_serial(&chunk.decode()?, chunk.spans(), *first, opts);
+        let threads = worker_count(rows.len(), opts).min(chunks.len());
+        if threads <= 1 {
+            return chunks.iter().map(build).collect();
+        }
+        // One contiguous run of chunks for each of `threads` workers
+        let run = chunks.len().div_ceil(threads);
+        let convert = || chunks.par_iter().with_min_len(run).map(build).collect::<PyResult<Vec<_>>>();
+        match opts.num_threads {
+            Some(num_threads) => thread_pool(num_threads)?.install(convert),
+            None => convert(),
+        }
+    }
+
+    /// Convert `records_arr`, the records of a result from index `first` on, into a
+    /// single batch on the current thread, applying `on_row_error` to those that
+    /// do not convert.
+    fn build_serial(&self, records_arr: &[Value], spans: Option<Spans>, first: usize, opts: &ConvertOptions) -> PyResult<RecordBatch> {
+        if records_arr.is_empty() {
+            return Ok(RecordBatch::new_empty(self.schema.clone()));
+        }
+        let error = match self.convert(records_arr, spans, opts) {
+            Ok(batch) => return Ok(batch),
+            Err(error) if opts.on_row_error == RowErrorPolicy::Raise => return Err(ArrowBuildError::new_err(error)),
+            Err(error) => error,
+        };
+        let mut kept = Vec::with_capacity(records_arr.len());
+        if self.sift(records_arr, spans, first, opts, &mut kept) == 0 {
+            // Only the records together fail, such as overflowing list offsets.
+            return Err(ArrowBuildError::new_err(error));
+        }
+        self.convert(&kept, None, opts).map_err(ArrowBuildError::new_err)
+    }
+
+    /// Add to `kept` the records of `records_arr` (from index `first` of their
+    /// result) that convert, and a null record for each that does not under
+    /// `on_row_error="null"`, rejecting those. Records that do not convert
+    /// together are sifted in halves. Returns the number rejected.
+    fn sift(&self, records_arr: &[Value], spans: Option<Spans>, first: usize, opts: &ConvertOptions, kept: &mut Vec<Value>) -> usize {
+        let error = match self.convert(records_arr, spans, &opts.counting_apart()) {
+            Ok(_) => {
+                kept.extend_from_slice(records_arr);
+                return 0;
+            }
+            Err(error) => error,
+        };
+        if let [_] = records_arr {
+            opts.reject_row(first, error);
+            if opts.on_row_error == RowErrorPolicy::Null {
+                kept.push(if self.value_column.is_some() { Value::Null } else { Value::Map(Vec::new()) });
+            }
+            return 1;
+        }
+        let half = records_arr.len() / 2;
+        let (a, b) = records_arr.split_at(half);
+        let spans_a = spans.map(|spans| spans.slice(0, half));
+        let spans_b = spans.map(|spans| spans.slice(half, records_arr.len()));
+        self.sift(a, spans_a, first, opts, kept) + self.sift(b, spans_b, first + half, opts, kept)
+    }
+
+    /// Convert `records_arr` into a single batch, or return the error text. Errors
+    /// say where a record that does not fit is in the input, given its `spans`.
+    /// Only the losses of the conversion that succeeds are counted.
+    fn convert(&self, records_arr: &[Value], spans: Option<Spans>, opts: &ConvertOptions) -> Result<RecordBatch, String> {
+        if records_arr.is_empty() {
+            return Ok(RecordBatch::new_empty(self.schema.clone()));
+        }
+        // Convert straight into Arrow arrays when every column type allows it
+        let value_column = self.value_column.as_deref();
+        let attempt = opts.counting_apart();
+        let direct = builder::build_columns(&self.fields, records_arr, spans, &self.split_ids, value_column, self.coerce, &attempt)
+            .and_then(|arrays| self.batch(arrays, opts).map_err(|e| builder::BuildError::Other(e.to_string())));
+        let direct_error = match direct {
+            Ok(batch) => {
+                opts.add_losses(&attempt);
+                return Ok(batch);
+            }
+            Err(e) => e,
+        };
+        let attempt = opts.counting_apart();
+        let wrapped_records: Vec<SurrealRecord> = records_arr.iter()
+            .map(|value| SurrealRecord { value, opts: &attempt, split_ids: &self.split_ids, value_column })
+            .collect();
+        // The builder's error names the value that did not fit, and serde_arrow's
+        // does not say where it is.
+        let arrays = serde_arrow::to_arrow(&self.fields, &wrapped_records)
+            .map_err(|e| match direct_error {
+                builder::BuildError::Record(_) => direct_error.to_string(),
+                _ if self.coerce => direct_error.to_string(),
+                _ => e.to_string(),
+            })
+            .map_err(|e| format!("Arrow array conversion error: {}", e))?;
+
+        let batch = self.batch(arrays, opts).map_err(|e| format!("RecordBatch creation error: {}", e))?;
+        opts.add_losses(&attempt);
+        Ok(batch)
+    }
+
+    /// The output batch for the arrays built for `fields`.
+    fn batch(&self, arrays: Vec<ArrayRef>, opts: &ConvertOptions) -> Result<RecordBatch, arrow::error::ArrowError> {
+        let (fields, arrays) = match opts.explode.is_empty() {
+            true => (Cow::Borrowed(&self.fields[..]), arrays),
+            false => {
+                let arrays = explode::explode_arrays(&self.fields, arrays, opts)?;
+                (Cow::Owned(explode::explode_fields(&self.fields, opts)), arrays)
+            }
+        };
+        let arrays = match opts.flatten {
+            Some(depth) => flatten::flatten_arrays(&fields, arrays, depth)?,
+            None => arrays,
+        };
+        RecordBatch::try_new(self.schema.clone(), arrays)
+    }
+}
+
+/// The column scalar results are wrapped into, or `None` if the records are objects.
+fn scalar_column(rows: Rows, opts: &ConvertOptions) -> Option<String> {
+    // Scalar results (`SELECT VALUE ...`) become a single column
+    (!rows.any_map()).then(|| opts.value_column.as_deref().unwrap_or(DEFAULT_VALUE_COLUMN).to_string())
+}
+
+/// Trace the fields of `records` and refine them to their SurrealDB types.
+fn trace(
+    records: &[&Value],
+    split_ids: &[String],
+    value_column: Option<&str>,
+    opts: &ConvertOptions,
+    tracing: TracingOptions,
+) -> PyResult<Vec<FieldRef>> {
+    // Losses count when the records are built, not when they are traced.
+    let tracing_records = matches!(opts.big_int, BigInt::Decimal | BigInt::String);
+    let opts = &ConvertOptions { tracing_records, ..opts.counting_apart() };
+
+    let wrapped_records: Vec<SurrealRecord> = records.iter()
+        .map(|&value| SurrealRecord { value, opts, split_ids, value_column })
+        .collect();
+
+    // The records themselves must trace as structs, so nested
+    // objects are turned into maps afterwards when `map_as_struct` is off.
+    let fields = match Vec::<FieldRef>::from_samples(&wrapped_records, tracing.clone().map_as_struct(true)) {
+        Ok(fields) => fields,
+        Err(e) if opts.on_row_error != RowErrorPolicy::Raise => {
+            // Leave out the records that do not trace on their own: they do not
+            // convert either, and `on_row_error` deals with them then.
+            let alone = tracing.clone().map_as_struct(true).allow_null_fields(true);
+            let traceable: Vec<&Value> = wrapped_records
+                .iter()
+                .filter(|record| Vec::<FieldRef>::from_samples(std::slice::from_ref(*record), alone.clone()).is_ok())
+                .map(|record| record.value)
+                .collect();
+            if traceable.is_empty() || traceable.len() == records.len() {
+                return Err(SchemaInferenceError::new_err(format!("Schema inference error: {}", e)));
+            }
+            return trace(&traceable, split_ids, value_column, opts, tracing);
+        }
+        Err(e) => return Err(SchemaInferenceError::new_err(format!("Schema inference error: {}", e))),
+    };
+    let fields: Vec<FieldRef> = match value_column {
+        // The scalars themselves are the column values
+        Some(_) if split_ids.is_empty() => fields.iter().map(|f| refine_field(f, records, opts)).collect(),
+        Some(_) => fields,
+        None => refine_fields(&fields, records, opts),
+    };
+    if tracing.map_as_struct {
+        return Ok(fields);
+    }
+    fields
+        .iter()
+        .map(|f| schema::structs_to_maps(f, opts))
+        .collect::<Result<_, _>>()
+        .map_err(|e| SchemaInferenceError::new_err(format!("Schema inference error: {}", e)))
+}
+
+/// `trace`, resolving type conflicts between records as `type_conflicts` says:
+/// records that do not trace together are traced in halves and the halves merged.
+fn trace_resolving(
+    records_arr: &[Value],
+    split_ids: &[String],
+    value_column: Option<&str>,
+    opts: &ConvertOptions,
+    tracing: TracingOptions,
+) -> PyResult<Vec<FieldRef>> {
+    let tracing = match opts.type_conflicts {
+        TypeConflicts::Promote | TypeConflicts::Json | TypeConflicts::Union => tracing.coerce_numbers(true),
+        _ => tracing,
+    };
+    let record_refs: Vec<&Value> = records_arr.iter().collect();
+    let traced = trace(&record_refs, split_ids, value_column, opts, tracing.clone());
+    if traced.is_ok() || opts.type_conflicts == TypeConflicts::Error || records_arr.len() < 2 {
+        return traced;
+    }
+    // A half may only hold nulls for a field the other half has values for.
+    let halves = tracing.clone().allow_null_fields(true);
+    let (a, b) = records_arr.split_at(records_arr.len() / 2);
+    let a = trace_resolving(a, split_ids, value_column, opts, halves.clone())?;
+    let b = trace_resolving(b, split_ids, value_column, opts, halves)?;
+    let fields = schema::merge_fields(&a, &b, opts)
+        .map_err(|e| SchemaInferenceError::new_err(format!("Schema inference error: {}", e)))?;
+    match schema::null_only_field(&fields) {
+        Some(name) if !tracing.allow_null_fields => Err(null_only_error(name)),
+        _ => Ok(fields),
+    }
+}
+
+fn null_only_error(name: &str) -> PyErr {
+    SchemaInferenceError::new_err(format!("Schema inference error: Encountered null only field {}", name))
+}
+
+/// The rayon pools of `num_threads` threads, started once for each number of threads.
+static THREAD_POOLS: LazyLock<Mutex<HashMap<usize, Arc<rayon::ThreadPool>>>> = LazyLock::new(Default::default);
+
+/// The pool of `num_threads` threads to convert with, started on first use.
+fn thread_pool(num_threads: usize) -> PyResult<Arc<rayon::ThreadPool>> {
+    // A pool is only ever inserted whole, so a panic elsewhere leaves the map intact.
+    let mut pools = THREAD_POOLS.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(pool) = pools.get(&num_threads) {
+        return Ok(pool.clone());
+    }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .build()
+        .map_err(|e| PyRuntimeError::new_err(format!("cannot start conversion threads: {}", e)))?;
+    let pool = Arc::new(pool);
+    pools.insert(num_threads, pool.clone());
+    Ok(pool)
+}
+
+/// Number of threads to convert `rows` records with, given at least
+/// `PARALLEL_MIN_ROWS` rows per thread.
+fn worker_count(rows: usize, opts: &ConvertOptions) -> usize {
+    let threads = opts
+        .num_threads
+        .unwrap_or_else(rayon::current_num_threads);
+    threads.min(rows / PARALLEL_MIN_ROWS).max(1)
+}
+
+/// Infer a schema for the records (unless one is declared) and build a single RecordBatch.
+fn records_to_batch(records_arr: &[Value], opts: &ConvertOptions) -> PyResult<RecordBatch> {
+    opts.check_rows(records_arr.len())?;
+    let plan = BatchPlan::for_rows(Rows::Decoded(records_arr, None), opts)?;
+    opts.check_cells(records_arr.len(), plan.fields.len())?;
+    plan.build(records_arr, opts)
+}
+
+/// A Python module implemented in Rust.
+#[pymodule]
+fn surrealengine_accelerator(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(sum_as_string, m)?)?;
+    m.add_function(wrap_pyfunction!(cbor_to_arrow, m)?)?;
+    #[cfg(feature = "asyncio")]
+    m.add_function(wrap_pyfunction!(aio::cbor_to_arrow_async, m)?)?;
+    m.add_function(wrap_pyfunction!(cbor_to_arrow_all, m)?)?;
+    m.add_function(wrap_pyfunction!(records_cbor_to_arrow, m)?)?;
+    m.add_function(wrap_pyfunction!(infer_schema, m)?)?;
+    m.add_function(wrap_pyfunction!(merge_cbor_to_arrow, m)?)?;
+    m.add_function(wrap_pyfunction!(cbor_sequence_to_arrow, m)?)?;
+    m.add_function(wrap_pyfunction!(frames::cbor_to_pandas, m)?)?;
+    #[cfg(feature = "polars")]
+    m.add_function(wrap_pyfunction!(frames::cbor_to_polars, m)?)?;
+    m.add_function(wrap_pyfunction!(frames::cbor_to_numpy, m)?)?;
+    #[cfg(feature = "datafusion")]
+    m.add_function(wrap_pyfunction!(query::query_arrow, m)?)?;
+    m.add_function(wrap_pyfunction!(pyvalue::cbor_to_dicts, m)?)?;
+    m.add_function(wrap_pyfunction!(pyvalue::encode_params, m)?)?;
+    m.add_function(wrap_pyfunction!(json::cbor_to_json, m)?)?;
+    m.add_function(wrap_pyfunction!(json::cbor_to_ndjson, m)?)?;
+    m.add_function(wrap_pyfunction!(export::cbor_to_parquet, m)?)?;
+    m.add_function(wrap_pyfunction!(export::cbor_to_ipc, m)?)?;
+    m.add_function(wrap_pyfunction!(export::cbor_to_csv, m)?)?;
+    #[cfg(feature = "deltalake")]
+    m.add_function(wrap_pyfunction!(delta::cbor_to_delta, m)?)?;
+    m.add_function(wrap_pyfunction!(encode::arrow_to_cbor, m)?)?;
+    m.add_function(wrap_pyfunction!(rpc::build_query, m)?)?;
+    m.add_function(wrap_pyfunction!(rpc::build_use, m)?)?;
+    m.add_function(wrap_pyfunction!(rpc::build_signin, m)?)?;
+    m.add_function(wrap_pyfunction!(rpc::build_signup, m)?)?;
+    m.add_function(wrap_pyfunction!(rpc::build_authenticate, m)?)?;
+    m.add_function(wrap_pyfunction!(rpc::build_invalidate, m)?)?;
+    m.add_function(wrap_pyfunction!(rpc::build_let, m)?)?;
+    m.add_function(wrap_pyfunction!(rpc::build_unset, m)?)?;
+    m.add_function(wrap_pyfunction!(rpc::build_live, m)?)?;
+    m.add_function(wrap_pyfunction!(rpc::build_kill, m)?)?;
+    m.add_function(wrap_pyfunction!(rpc::build_request, m)?)?;
+    m.add_function(wrap_pyfunction!(rpc::build_insert_payload, m)?)?;
+    m.add_function(wrap_pyfunction!(rpc::batch_to_insert_sql, m)?)?;
+    m.add_function(wrap_pyfunction!(rpc::build_relate_payload, m)?)?;
+    m.add_function(wrap_pyfunction!(rpc::build_merge_payload, m)?)?;
+    m.add_function(wrap_pyfunction!(live::notification_to_arrow, m)?)?;
+    m.add_function(wrap_pyfunction!(live::py_apply_patches, m)?)?;
+    m.add_function(wrap_pyfunction!(paginate::stream_query, m)?)?;
+    m.add_class::<live::LiveTable>()?;
+    m.add_class::<stream::StreamingConverter>()?;
+    m.add_class::<export::ParquetSink>()?;
+    #[cfg(feature = "client")]
+    m.add_class::<client::Connection>()?;
+    #[cfg(feature = "client")]
+    m.add_class::<pool::ConnectionPool>()?;
+    #[cfg(feature = "client")]
+    m.add_class::<pool::PoolCheckout>()?;
+    #[cfg(feature = "flight")]
+    m.add_class::<flight::SurrealFlightServer>()?;
+    m.add_class::<paginate::QueryPages>()?;
+    m.add_class::<router::FrameRouter>()?;
+    m.add_class::<router::PendingResponse>()?;
+    m.add_function(wrap_pyfunction!(parse_record_id, m)?)?;
+    m.add_function(wrap_pyfunction!(format_record_id, m)?)?;
+    m.add_function(wrap_pyfunction!(escape_ident, m)?)?;
+    m.add_function(wrap_pyfunction!(quote_string, m)?)?;
+    m.add_function(wrap_pyfunction!(escape_record_key, m)?)?;
+    m.add_function(wrap_pyfunction!(cache::clear_schema_cache, m)?)?;
+    errors::register(m)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{Array, AsArray};
+    use arrow::compute::cast;
+    use arrow::datatypes::{DurationNanosecondType, Int64Type, TimestampNanosecondType};
+    use pyo3::types::{PyBytes, PyCFunction};
+    use pyo3_arrow::{PyRecordBatch, PyRecordBatchReader};
+
+    use super::*;
+    use crate::export::batch_error;
+
+    /// The options `kwargs` make, given as Python keyword arguments.
+    fn options(py: Python, kwargs: &str) -> PyResult<ConvertOptions> {
+        let kwargs = py.eval(&std::ffi::CString::new(format!("dict({})", kwargs)).unwrap(), None, None)?;
+        ConvertOptions::from_kwargs(Some(kwargs.downcast()?))
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    fn tagged(tag: u64, value: Value) -> Value {
+        Value::Tag(tag, Box::new(value))
+    }
+
+    /// The values of a string column, whatever its offsets.
+    fn strings(column: &ArrayRef) -> Vec<Option<String>> {
+        let column = cast(column, &DataType::Utf8).unwrap();
+        column.as_string::<i32>().iter().map(|s| s.map(str::to_string)).collect()
+    }
+
+    /// A record of the fields `fields`.
+    fn record(fields: &[(&str, Value)]) -> Value {
+        Value::Map(fields.iter().map(|(name, value)| (text(name), value.clone())).collect())
+    }
+
+    /// An `OK` statement whose result is `result`.
+    fn ok(result: Value) -> Value {
+        record(&[("status", text("OK")), ("time", text("1ms")), ("result", result)])
+    }
+
+    /// The RPC response of the statements `statements`.
+    fn rpc(statements: Vec<Value>) -> Vec<u8> {
+        encode::encode(&record(&[("id", Value::Integer(1)), ("result", Value::Array(statements))]))
+    }
+
+    /// The RPC response of one `OK` statement whose result is `result`.
+    fn response(result: Value) -> Vec<u8> {
+        rpc(vec![ok(result)])
+    }
+
+    /// The keyword arguments `kwargs`, as a dict.
+    fn kwargs<'py>(py: Python<'py>, kwargs: &str) -> Bound<'py, PyDict> {
+        let kwargs = std::ffi::CString::new(format!("dict({})", kwargs)).unwrap();
+        py.eval(&kwargs, None, None).unwrap().downcast_into().unwrap()
+    }
+
+    /// Call the conversion function `f` on `data` with the keyword arguments `kwargs`.
+    fn call<'py>(
+        f: &Bound<'py, PyCFunction>,
+        data: &[u8],
+        kwargs: &Bound<'py, PyDict>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        f.call((PyBytes::new(f.py(), data),), Some(kwargs))
+    }
+
+    /// The batch of a result converted with the default `output`.
+    fn batch(result: &Bound<PyAny>) -> RecordBatch {
+        result.extract::<PyRecordBatch>().unwrap().into_inner()
+    }
+
+    /// `data` converted by `cbor_to_arrow` with the keyword options `kwargs`, as one batch.
+    fn convert_bytes(py: Python, data: &[u8], options: &str) -> PyResult<RecordBatch> {
+        let options = kwargs(py, &format!("output='stream', {}", options));
+        let converted = call(&wrap_pyfunction!(cbor_to_arrow, py)?, data, &options)?;
+        let reader = converted.extract::<PyRecordBatchReader>()?.into_reader()?;
+        let schema = reader.schema();
+        let batches = reader.collect::<Result<Vec<_>, _>>().map_err(batch_error)?;
+        concat_batches(&schema, &batches).map_err(batch_error)
+    }
+
+    /// The result of `f` and the messages of the warnings it issued.
+    fn warnings_of<T>(py: Python, f: impl FnOnce() -> T) -> (T, Vec<String>) {
+        let warnings = py.import("warnings").unwrap();
+        let catcher = warnings.call_method("catch_warnings", (), Some(&kwargs(py, "record=True"))).unwrap();
+        let caught = catcher.call_method0("__enter__").unwrap();
+        warnings.call_method1("simplefilter", ("always",)).unwrap();
+        let result = f();
+        catcher.call_method1("__exit__", (py.None(), py.None(), py.None())).unwrap();
+        let messages = caught.try_iter().unwrap().map(|w| w.unwrap().getattr("message").unwrap().to_string());
+        (result, messages.collect())
+    }
+
+    /// The records `records` converted by `cbor_to_arrow`, as one batch.
+    fn convert(py: Python, records: Vec<Value>, kwargs: &str) -> PyResult<RecordBatch> {
+        convert_bytes(py, &response(Value::Array(records)), kwargs)
+    }
+
+    #[test]
+    fn datetimes_become_utc_timestamps() {
+        pyo3::prepare_freethreaded_python();
+        let records = vec![
+            record(&[("at", tagged(tags::TAG_DATETIME_COMPACT, Value::Array(vec![Value::Integer(86_400), Value::Integer(5)])))]),
+            record(&[("at", tagged(tags::TAG_DATETIME, text("1970-01-02T00:00:01.5+01:00")))]),
+            record(&[("at", Value::Null)]),
+        ];
+        Python::with_gil(|py| {
+            let batch = convert(py, records, "").unwrap();
+            assert_eq!(batch.schema().field(0).data_type(), &DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into())));
+            let at = batch.column(0).as_primitive::<TimestampNanosecondType>();
+            assert_eq!(at.values()[..2], [86_400_000_000_005, 82_801_500_000_000]);
+            assert!(at.is_null(2));
+        });
+    }
+
+    #[test]
+    fn mixed_types_must_agree_with_type_conflicts() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let agreeing = ["type_conflicts='json', mixed_types='json'", "mixed_types='error', type_conflicts='error'"];
+            for kwargs in agreeing {
+                options(py, kwargs).unwrap();
+            }
+            assert!(options(py, "mixed_types='json'").unwrap().type_conflicts == TypeConflicts::Json);
+            let err = options(py, "mixed_types='json', type_conflicts='union'").err().unwrap();
+            assert!(err.is_instance_of::<PyValueError>(py));
+            assert_eq!(
+                err.value(py).to_string(),
+                "type_conflicts='union' and mixed_types='json' disagree; pass only type_conflicts"
+            );
+        });
+    }
+
+    #[test]
+    fn schema_cache_keeps_a_plan_per_options() {
+        pyo3::prepare_freethreaded_python();
+        let text = |s: &str| Value::Text(s.to_string());
+        let record = Value::Map(vec![(text("a"), Value::Integer(1))]);
+        let statement = Value::Map(vec![(text("status"), text("OK")), (text("result"), Value::Array(vec![record]))]);
+        let response = encode::encode(&Value::Map(vec![(text("result"), Value::Array(vec![statement]))]));
+        Python::with_gil(|py| {
+            let columns = |kwargs: &str| -> Vec<String> {
+                let opts = options(py, kwargs).unwrap();
+                let payload = pull::Payload::load_owned(py, response.clone(), true, &opts).unwrap();
+                let (_, plan) = result_plan(py, &payload, RecordsAt::Statement(0), &opts).unwrap();
+                plan.unwrap().schema.fields().iter().map(|f| f.name().clone()).collect()
+            };
+            let key = "schema_cache_keeps_a_plan_per_options";
+            assert_eq!(columns(&format!("schema_cache_key={:?}", key)), ["a"]);
+            assert_eq!(columns(&format!("schema_cache_key={:?}, rename={{'a': 'b'}}", key)), ["b"]);
+            assert_eq!(columns(&format!("schema_cache_key={:?}", key)), ["a"]);
+            let shape = options(py, "").unwrap().plan_fingerprint();
+            assert!(cache::get(key, shape).is_some());
+            cache::clear_schema_cache(Some(key));
+            assert!(cache::get(key, shape).is_none());
+        });
+    }
+
+    #[test]
+    fn record_id_helpers_round_trip() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let keys = py.eval(c"['a\\\\', 'a⟩b', '⟨a⟩', '\\\\⟩', '12', 7, ['x\\\\', 1], {'k': 'v⟩'}]", None, None).unwrap();
+            for key in keys.try_iter().unwrap() {
+                let key = key.unwrap();
+                let formatted = format_record_id("t", &key).unwrap();
+                let (table, parsed) = parse_record_id(py, &formatted).unwrap();
+                assert_eq!(table, "t");
+                assert!(parsed.bind(py).eq(&key).unwrap(), "{} parsed back as {}", formatted, parsed);
+            }
+            assert_eq!(format_record_id("t", &"a\\".into_pyobject(py).unwrap()).unwrap(), "t:⟨a\\\\⟩");
+        });
+    }
+
+    #[test]
+    fn escaped_record_keys_cannot_break_out() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let cases = [
+                ("a\\", "⟨a\\\\⟩"),
+                ("a⟩b", "⟨a\\⟩b⟩"),
+                ("\\⟩", "⟨\\\\\\⟩⟩"),
+                ("a\0b", "⟨a\0b⟩"),
+                ("x\\⟩; DELETE user; --", "⟨x\\\\\\⟩; DELETE user; --⟩"),
+            ];
+            for (key, escaped) in cases {
+                let key = key.into_pyobject(py).unwrap().into_any();
+                assert_eq!(escape_record_key(&key).unwrap(), escaped);
+                let (table, parsed) = parse_record_id(py, &format!("user:{}", escaped)).unwrap();
+                assert_eq!(table, "user");
+                assert!(parsed.bind(py).eq(&key).unwrap(), "{} parsed back as {}", escaped, parsed);
+            }
+        });
+    }
+
+    #[test]
+    fn parallel_chunks_keep_row_order() {
+        pyo3::prepare_freethreaded_python();
+        let rows = 2 * PARALLEL_MIN_ROWS + 1;
+        let records: Vec<Value> =
+            (0..rows).map(|i| Value::Map(vec![(Value::Text("n".to_string()), Value::Integer(i as i128))])).collect();
+        let opts = Python::with_gil(|py| options(py, "num_threads=2")).unwrap();
+        let plan = BatchPlan::infer(&records, &opts).unwrap();
+        let batches = plan.build_chunks(Rows::Decoded(&records, None), 0, 10_000, &opts).unwrap();
+        assert_eq!(batches.len(), rows.div_ceil(10_000));
+        let numbers = batches.iter().flat_map(|batch| batch.column(0).as_primitive::<Int64Type>().values().to_vec());
+        assert!(numbers.eq(0..rows as i64));
+        // Converted on a pool started once, and kept for the next conversions.
+        let pool = THREAD_POOLS.lock().unwrap().get(&2).cloned().unwrap();
+        assert_eq!(pool.current_num_threads(), 2);
+        plan.build_chunks(Rows::Decoded(&records, None), 0, 10_000, &opts).unwrap();
+        assert!(Arc::ptr_eq(&pool, &thread_pool(2).unwrap()));
+    }
+
+    #[test]
+    fn limits_raise_surrealengine_errors() {
+        pyo3::prepare_freethreaded_python();
+        let records: Vec<Value> = (0..3).map(|i| Value::Map(vec![(Value::Text("n".to_string()), Value::Integer(i))])).collect();
+        Python::with_gil(|py| {
+            for kwargs in ["max_rows=2", "max_cells=2"] {
+                let err = records_to_batch(&records, &options(py, kwargs).unwrap()).unwrap_err();
+                assert!(err.is_instance_of::<SurrealEngineError>(py), "{}", kwargs);
+                assert!(err.is_instance_of::<PyValueError>(py), "{}", kwargs);
+            }
+            records_to_batch(&records, &options(py, "max_rows=3, max_cells=3").unwrap()).unwrap();
+        });
+    }
+
+    #[test]
+    fn object_entries_borrow_the_kept_pairs() {
+        pyo3::prepare_freethreaded_python();
+        let text = |s: &str| Value::Text(s.to_string());
+        let map = vec![(text("a"), Value::Integer(1)), (text("b"), Value::Integer(2)), (text("a"), Value::Integer(3))];
+        Python::with_gil(|py| {
+            let pairs = |kwargs: &str| {
+                let entries = object_entries(&map, &options(py, kwargs).unwrap()).unwrap();
+                entries.iter().map(|(k, v)| (k as *const Value, v as *const Value)).collect::<Vec<_>>()
+            };
+            let at = |i: usize| (&map[i].0 as *const Value, &map[i].1 as *const Value);
+            assert_eq!(pairs("duplicate_keys='last'"), [(at(0).0, at(2).1), at(1)]);
+            assert_eq!(pairs("duplicate_keys='first'"), [at(0), at(1)]);
+            assert!(object_entries(&map, &options(py, "duplicate_keys='error'").unwrap()).is_err());
+            let unique = &map[..2];
+            assert!(matches!(object_entries(unique, &options(py, "").unwrap()), Ok(Entries::All(all)) if std::ptr::eq(all, unique)));
+        });
+    }
+
+    #[test]
+    fn untraceable_records_are_left_out_of_the_schema() {
+        pyo3::prepare_freethreaded_python();
+        let record = |v: Value| Value::Map(vec![(Value::Text("a".to_string()), v)]);
+        let mixed = record(Value::Array(vec![Value::Integer(1), Value::Text("x".to_string())]));
+        let records = [record(Value::Integer(1)), mixed, record(Value::Integer(2))];
+        let refs: Vec<&Value> = records.iter().collect();
+        Python::with_gil(|py| {
+            let opts = options(py, "on_row_error='skip'").unwrap();
+            let fields = trace(&refs, &[], None, &opts, opts.tracing.clone()).unwrap();
+            assert_eq!(fields.len(), 1);
+            assert_eq!(fields[0].data_type(), &DataType::Int64);
+            let opts = options(py, "").unwrap();
+            let err = trace(&refs, &[], None, &opts, opts.tracing.clone()).unwrap_err();
+            assert!(err.is_instance_of::<SchemaInferenceError>(py));
+        });
+    }
+
+    #[test]
+    fn sanitize_names_rejects_unsafe_replacements() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            // An empty replacement leaves "1a" starting with a digit and "..." empty.
+            for replacement in ["", "1", "-", "a b", "é"] {
+                let err = options(py, &format!("sanitize_names={:?}", replacement)).err().unwrap();
+                assert!(err.is_instance_of::<PyValueError>(py), "replacement {:?}", replacement);
+            }
+            assert_eq!(options(py, "sanitize_names='x_'").unwrap().sanitize_names.as_deref(), Some("x_"));
+            assert_eq!(options(py, "sanitize_names=True").unwrap().sanitize_names.as_deref(), Some("_"));
+            assert!(options(py, "sanitize_names=False").unwrap().sanitize_names.is_none());
+            assert!(options(py, "sanitize_names=1.5").err().unwrap().is_instance_of::<PyTypeError>(py));
+        });
+    }
+
+    #[test]
+    fn sanitized_names_are_safe() {
+        let names = |replacement: &str, names: &[&str]| -> Vec<String> {
+            let fields = names.iter().map(|name| Arc::new(Field::new(*name, DataType::Int64, true))).collect();
+            sanitize_names(fields, replacement).iter().map(|f| f.name().clone()).collect()
+        };
+        let sanitized = names("_", &["1a", "...", "a.b", "a b", "a_b_2", "ok"]);
+        assert_eq!(sanitized, ["_1a", "___", "a_b", "a_b_3", "a_b_2", "ok"]);
+        assert_eq!(names("x", &["1a", "..."]), ["x1a", "xxx"]);
+        assert!(names("_", &["", "9", "+"]).iter().all(|name| is_safe_name(name)));
+    }
+
+    #[test]
+    fn uuids_become_strings_or_16_bytes() {
+        pyo3::prepare_freethreaded_python();
+        let bytes: Vec<u8> = (0..16).collect();
+        let records = vec![
+            record(&[("id", tagged(tags::TAG_UUID, Value::Bytes(bytes.clone())))]),
+            record(&[("id", tagged(tags::TAG_UUID_STRING, text("0F0E0D0C-0B0A-0908-0706-050403020100")))]),
+        ];
+        Python::with_gil(|py| {
+            let batch = convert(py, records.clone(), "").unwrap();
+            let uuids = strings(batch.column(0));
+            assert_eq!(uuids[0].as_deref(), Some("00010203-0405-0607-0809-0a0b0c0d0e0f"));
+            assert_eq!(uuids[1].as_deref(), Some("0f0e0d0c-0b0a-0908-0706-050403020100"));
+
+            let batch = convert(py, records, "uuid_mode='binary'").unwrap();
+            assert_eq!(batch.schema().field(0).data_type(), &DataType::FixedSizeBinary(16));
+            assert_eq!(batch.column(0).as_fixed_size_binary().value(0), bytes.as_slice());
+            let err = convert(py, vec![], "uuid_mode='hex'").unwrap_err();
+            assert!(err.is_instance_of::<PyValueError>(py));
+        });
+    }
+
+    #[test]
+    fn durations_become_nanosecond_durations() {
+        pyo3::prepare_freethreaded_python();
+        let compact = |parts| tagged(tags::TAG_DURATION_COMPACT, Value::Array(parts));
+        let records = vec![
+            record(&[("took", tagged(tags::TAG_DURATION, text("1h30m5ms")))]),
+            record(&[("took", compact(vec![Value::Integer(2), Value::Integer(7)]))]),
+            record(&[("took", compact(vec![]))]),
+        ];
+        Python::with_gil(|py| {
+            let batch = convert(py, records, "").unwrap();
+            assert_eq!(batch.schema().field(0).data_type(), &DataType::Duration(TimeUnit::Nanosecond));
+            let took = batch.column(0).as_primitive::<DurationNanosecondType>();
+            assert_eq!(took.values()[..], [5_400_005_000_000, 2_000_000_007, 0]);
+
+            let bad = vec![record(&[("took", tagged(tags::TAG_DURATION, text("5 parsecs")))])];
+            assert!(convert(py, bad, "").is_err());
+        });
+    }
+
+    #[test]
+    fn none_is_null() {
+        pyo3::prepare_freethreaded_python();
+        let records = vec![
+            record(&[("n", Value::Integer(1))]),
+            record(&[("n", tagged(tags::TAG_NONE, Value::Null))]),
+            // The payload of NONE is ignored.
+            record(&[("n", tagged(tags::TAG_NONE, text("junk")))]),
+        ];
+        Python::with_gil(|py| {
+            let batch = convert(py, records, "").unwrap();
+            let field = batch.schema().field(0).clone();
+            assert_eq!(field.data_type(), &DataType::Int64);
+            assert!(field.is_nullable());
+            let n = batch.column(0).as_primitive::<Int64Type>();
+            assert_eq!((n.value(0), n.is_null(1), n.is_null(2)), (1, true, true));
+        });
+    }
+
+    #[test]
+    fn tables_become_their_names() {
+        pyo3::prepare_freethreaded_python();
+        let records = vec![record(&[("tb", tagged(tags::TAG_TABLE, text("person")))]), record(&[("tb", Value::Null)])];
+        Python::with_gil(|py| {
+            let batch = convert(py, records, "").unwrap();
+            assert_eq!(strings(batch.column(0)), [Some("person".to_string()), None]);
+            let bad = vec![record(&[("tb", tagged(tags::TAG_TABLE, Value::Integer(1)))])];
+            let err = convert(py, bad, "").unwrap_err();
+            assert!(err.to_string().contains("Invalid SurrealDB table name"), "{}", err);
+        });
+    }
+
+    #[test]
+    fn geometries_become_wkb() {
+        pyo3::prepare_freethreaded_python();
+        let point =
+            |x: f64, y: f64| tagged(tags::TAG_GEOMETRY_POINT, Value::Array(vec![Value::Float(x), Value::Float(y)]));
+        let line = tagged(tags::TAG_GEOMETRY_LINE, Value::Array(vec![point(0.0, 0.0), point(1.0, 2.0)]));
+        let records = vec![record(&[("geo", point(1.5, -2.0))]), record(&[("geo", line)])];
+        let mut wkb_point = vec![1, 1, 0, 0, 0];
+        wkb_point.extend(1.5f64.to_le_bytes());
+        wkb_point.extend((-2.0f64).to_le_bytes());
+        Python::with_gil(|py| {
+            let batch = convert(py, records.clone(), "").unwrap();
+            let geo = cast(batch.column(0), &DataType::Binary).unwrap();
+            assert_eq!(geo.as_binary::<i32>().value(0), wkb_point.as_slice());
+            // A line: its type, 2 points, then their coordinates.
+            let wkb_line = geo.as_binary::<i32>().value(1);
+            assert_eq!(wkb_line[..9], [1, 2, 0, 0, 0, 2, 0, 0, 0]);
+            assert_eq!(wkb_line.len(), 9 + 4 * 8);
+            assert!(batch.schema().field(0).metadata().is_empty());
+
+            let batch = convert(py, records, "geoarrow=True").unwrap();
+            let metadata = batch.schema().field(0).metadata().clone();
+            assert_eq!(metadata.get("ARROW:extension:name").map(String::as_str), Some("geoarrow.wkb"));
+        });
+    }
+
+    #[test]
+    fn ranges_become_bound_structs() {
+        pyo3::prepare_freethreaded_python();
+        let range = |start: Value, end: Value| tagged(tags::TAG_RANGE, Value::Array(vec![start, end]));
+        let included = |n| tagged(tags::TAG_BOUND_INCLUDED, Value::Integer(n));
+        let excluded = |n| tagged(tags::TAG_BOUND_EXCLUDED, Value::Integer(n));
+        let records = vec![
+            record(&[("r", range(included(1), excluded(5)))]),
+            record(&[("r", range(Value::Null, included(9)))]),
+        ];
+        Python::with_gil(|py| {
+            let batch = convert(py, records, "").unwrap();
+            let r = batch.column(0).as_struct();
+            let member = |name: &str| r.column_by_name(name).unwrap().clone();
+            let start = member("start");
+            let start = start.as_primitive::<Int64Type>();
+            assert_eq!((start.value(0), start.is_null(1)), (1, true));
+            assert_eq!(member("end").as_primitive::<Int64Type>().values()[..], [5, 9]);
+            let inclusive = |name: &str| member(name).as_boolean().iter().collect::<Vec<_>>();
+            assert_eq!(inclusive("start_inclusive"), [Some(true), None]);
+            assert_eq!(inclusive("end_inclusive"), [Some(false), Some(true)]);
+
+            let bad = vec![record(&[("r", tagged(tags::TAG_RANGE, Value::Integer(1)))])];
+            assert!(convert(py, bad, "").is_err());
+        });
+    }
+
+    #[test]
+    fn strict_tags_refuse_lossy_fallbacks() {
+        pyo3::prepare_freethreaded_python();
+        let lossy = [
+            tagged(tags::TAG_FUTURE, text("{ time::now() }")),
+            tagged(tags::TAG_BOUND_INCLUDED, Value::Integer(1)),
+            tagged(tags::TAG_RECORDID, Value::Integer(1)),
+        ];
+        Python::with_gil(|py| {
+            for value in lossy {
+                let records = vec![record(&[("v", value.clone())])];
+                assert!(convert(py, records.clone(), "").is_ok(), "{:?}", value);
+                let err = convert(py, records, "strict_tags=True").unwrap_err();
+                assert!(err.is_instance_of::<PyValueError>(py), "{:?}", value);
+            }
+            // Decimals stay verbatim text either way.
+            let decimal = vec![record(&[("d", tagged(tags::TAG_DECIMAL, text("1.10")))])];
+            let batch = convert(py, decimal, "strict_tags=True").unwrap();
+            assert_eq!(strings(batch.column(0)), [Some("1.10".to_string())]);
+        });
+    }
+
+    #[test]
+    fn unknown_tags_follow_on_unknown_tag() {
+        pyo3::prepare_freethreaded_python();
+        let records = vec![record(&[("v", tagged(1000, Value::Integer(7)))])];
+        Python::with_gil(|py| {
+            let batch = convert(py, records.clone(), "").unwrap();
+            assert_eq!(batch.column(0).as_primitive::<Int64Type>().value(0), 7);
+
+            let err = convert(py, records.clone(), "on_unknown_tag='error'").unwrap_err();
+            assert!(err.to_string().contains("Unsupported CBOR tag 1000"), "{}", err);
+
+            let batch = convert(py, records.clone(), "on_unknown_tag='raw'").unwrap();
+            let raw = batch.column(0).as_struct();
+            let tag = cast(raw.column_by_name("tag").unwrap(), &DataType::UInt64).unwrap();
+            assert_eq!(tag.as_primitive::<arrow::datatypes::UInt64Type>().value(0), 1000);
+            assert_eq!(raw.column_by_name("value").unwrap().as_primitive::<Int64Type>().value(0), 7);
+
+            assert!(convert(py, records, "on_unknown_tag='drop'").unwrap_err().is_instance_of::<PyValueError>(py));
+        });
+    }
+
+    #[test]
+    fn record_ids_become_structs() {
+        pyo3::prepare_freethreaded_python();
+        let link = |tb: &str, id: Value| tagged(tags::TAG_RECORDID, Value::Array(vec![text(tb), id]));
+        let records = vec![record(&[
+            ("id", link("person", text("tobie"))),
+            ("friends", Value::Array(vec![link("person", Value::Integer(7))])),
+        ])];
+        Python::with_gil(|py| {
+            let batch = convert(py, records.clone(), "").unwrap();
+            let schema = batch.schema();
+            let id = schema.index_of("id").unwrap();
+            assert_eq!(strings(batch.column(id)), [Some("person:tobie".to_string())]);
+
+            let batch = convert(py, records, "record_id_mode='struct'").unwrap();
+            let id = batch.column(batch.schema().index_of("id").unwrap()).as_struct().clone();
+            assert_eq!(strings(id.column_by_name("tb").unwrap()), [Some("person".to_string())]);
+            assert_eq!(strings(id.column_by_name("id").unwrap()), [Some("tobie".to_string())]);
+            // Nested links are structs too.
+            let friends = batch.column(batch.schema().index_of("friends").unwrap());
+            let friend = match friends.data_type() {
+                DataType::List(_) => friends.as_list::<i32>().value(0),
+                _ => friends.as_list::<i64>().value(0),
+            };
+            assert_eq!(strings(friend.as_struct().column_by_name("id").unwrap()), [Some("7".to_string())]);
+        });
+    }
+
+    #[test]
+    fn record_ids_split_into_columns() {
+        pyo3::prepare_freethreaded_python();
+        let link = |tb: &str, id: &str| tagged(tags::TAG_RECORDID, Value::Array(vec![text(tb), text(id)]));
+        let records = vec![record(&[("id", link("person", "tobie")), ("name", text("Tobie"))])];
+        Python::with_gil(|py| {
+            let columns =
+                |batch: &RecordBatch| batch.schema().fields().iter().map(|f| f.name().clone()).collect::<Vec<_>>();
+            let batch = convert(py, records.clone(), "record_id_mode='split'").unwrap();
+            // Sorted by name, like every inferred column.
+            assert_eq!(columns(&batch), ["id__key", "id__tb", "name"]);
+            assert_eq!(strings(batch.column(0)), [Some("tobie".to_string())]);
+            assert_eq!(strings(batch.column(1)), [Some("person".to_string())]);
+
+            let batch = convert(py, records, "record_id_mode='split', record_id_suffixes=('_table', '_key')").unwrap();
+            assert_eq!(columns(&batch), ["id_key", "id_table", "name"]);
+        });
+    }
+
+    /// The values of the `n` column of a batch.
+    fn numbers(batch: &RecordBatch) -> Vec<i64> {
+        batch.column_by_name("n").unwrap().as_primitive::<Int64Type>().values().to_vec()
+    }
+
+    /// Records of an `n` field for each of `ns`.
+    fn numbered(ns: &[i128]) -> Value {
+        Value::Array(ns.iter().map(|n| record(&[("n", Value::Integer(*n))])).collect())
+    }
+
+    #[test]
+    fn every_statement_converts() {
+        pyo3::prepare_freethreaded_python();
+        let data = rpc(vec![ok(numbered(&[1, 2])), ok(Value::Array(vec![])), ok(numbered(&[3]))]);
+        Python::with_gil(|py| {
+            let results = call(&wrap_pyfunction!(cbor_to_arrow_all, py).unwrap(), &data, &kwargs(py, "")).unwrap();
+            let results: Vec<Bound<PyAny>> = results.extract().unwrap();
+            assert_eq!(results.len(), 3);
+            assert_eq!(numbers(&batch(&results[0])), [1, 2]);
+            assert!(results[1].is_none());
+            assert_eq!(numbers(&batch(&results[2])), [3]);
+        });
+    }
+
+    #[test]
+    fn statement_picks_one_statement() {
+        pyo3::prepare_freethreaded_python();
+        // The first statement's field changes type, so it fails to convert.
+        let conflicting = Value::Array(vec![record(&[("n", Value::Integer(1))]), record(&[("n", text("x"))])]);
+        let data = rpc(vec![ok(conflicting), ok(numbered(&[1])), ok(numbered(&[2]))]);
+        Python::with_gil(|py| {
+            assert_eq!(numbers(&convert_bytes(py, &data, "statement=2").unwrap()), [2]);
+            assert_eq!(numbers(&convert_bytes(py, &data, "statement=-2").unwrap()), [1]);
+            assert!(convert_bytes(py, &data, "statement=0").is_err());
+            for statement in [3, -4] {
+                let err = convert_bytes(py, &data, &format!("statement={}", statement)).unwrap_err();
+                assert!(err.is_instance_of::<PyIndexError>(py), "{}", err);
+            }
+        });
+    }
+
+    #[test]
+    fn metadata_carries_time_and_status() {
+        pyo3::prepare_freethreaded_python();
+        let data = response(numbered(&[1]));
+        Python::with_gil(|py| {
+            assert!(convert_bytes(py, &data, "").unwrap().schema().metadata().is_empty());
+            let batch = convert_bytes(py, &data, "with_metadata=True").unwrap();
+            let metadata = batch.schema().metadata().clone();
+            assert_eq!(metadata.get("surrealdb.time").map(String::as_str), Some("1ms"));
+            assert_eq!(metadata.get("surrealdb.status").map(String::as_str), Some("OK"));
+        });
+    }
+
+    #[test]
+    fn failed_statements_follow_on_statement_error() {
+        pyo3::prepare_freethreaded_python();
+        let failed = record(&[("status", text("ERR")), ("time", text("2ms")), ("result", text("boom"))]);
+        let data = rpc(vec![ok(numbered(&[1])), failed]);
+        Python::with_gil(|py| {
+            let all = wrap_pyfunction!(cbor_to_arrow_all, py).unwrap();
+            let err = call(&all, &data, &kwargs(py, "")).unwrap_err();
+            assert!(err.is_instance_of::<QueryStatusError>(py), "{}", err);
+
+            let skipped = call(&all, &data, &kwargs(py, "on_statement_error='skip'")).unwrap();
+            let results: Vec<Bound<PyAny>> = skipped.extract().unwrap();
+            assert_eq!(numbers(&batch(&results[0])), [1]);
+            assert!(results[1].is_none());
+
+            let collected = call(&all, &data, &kwargs(py, "on_statement_error='collect'")).unwrap();
+            let (results, errors): (Vec<Bound<PyAny>>, Vec<Bound<PyDict>>) = collected.extract().unwrap();
+            assert!(results[1].is_none());
+            let item = |key: &str| errors[0].get_item(key).unwrap().unwrap();
+            assert_eq!(item("statement").extract::<usize>().unwrap(), 1);
+            for (key, value) in [("status", "ERR"), ("message", "boom"), ("time", "2ms")] {
+                assert_eq!(item(key).extract::<String>().unwrap(), value);
+            }
+        });
+    }
+
+    #[test]
+    fn empty_results_take_empty_schema() {
+        pyo3::prepare_freethreaded_python();
+        let data = response(Value::Array(vec![]));
+        let schema = Arc::new(Schema::new(vec![Field::new("n", DataType::Int64, true)]));
+        Python::with_gil(|py| {
+            let convert = wrap_pyfunction!(cbor_to_arrow, py).unwrap();
+            assert!(call(&convert, &data, &kwargs(py, "")).unwrap().is_none());
+            let options = kwargs(py, "");
+            let empty_schema = Bound::new(py, pyo3_arrow::PySchema::new(schema.clone())).unwrap();
+            options.set_item("empty_schema", empty_schema).unwrap();
+            let empty = batch(&call(&convert, &data, &options).unwrap());
+            assert_eq!((empty.num_rows(), empty.schema()), (0, schema));
+        });
+    }
+
+    #[test]
+    fn scalars_become_value_columns() {
+        pyo3::prepare_freethreaded_python();
+        let values = Value::Array(vec![Value::Integer(1), Value::Integer(2)]);
+        Python::with_gil(|py| {
+            let batch = convert_bytes(py, &response(values.clone()), "").unwrap();
+            assert_eq!(batch.schema().field(0).name(), "value");
+            assert_eq!(batch.column(0).as_primitive::<Int64Type>().values()[..], [1, 2]);
+            let batch = convert_bytes(py, &response(values), "value_column='n'").unwrap();
+            assert_eq!(numbers(&batch), [1, 2]);
+
+            // `RETURN count(...)`: one value.
+            let count = response(Value::Integer(5));
+            let batch = convert_bytes(py, &count, "").unwrap();
+            assert_eq!((batch.num_rows(), batch.num_columns()), (1, 1));
+            let convert = wrap_pyfunction!(cbor_to_arrow, py).unwrap();
+            let value = call(&convert, &count, &kwargs(py, "scalar_as='python'")).unwrap();
+            assert_eq!(value.extract::<i64>().unwrap(), 5);
+        });
+    }
+
+    #[test]
+    fn lone_objects_become_one_row() {
+        pyo3::prepare_freethreaded_python();
+        let data = response(record(&[("n", Value::Integer(7)), ("name", text("john"))]));
+        Python::with_gil(|py| {
+            let batch = convert_bytes(py, &data, "").unwrap();
+            assert_eq!(batch.num_rows(), 1);
+            assert_eq!(numbers(&batch), [7]);
+            assert_eq!(strings(batch.column_by_name("name").unwrap()), [Some("john".to_string())]);
+        });
+    }
+
+    #[test]
+    fn bare_records_convert_without_the_envelope() {
+        pyo3::prepare_freethreaded_python();
+        let data = encode::encode(&numbered(&[4, 5]));
+        Python::with_gil(|py| {
+            let bare = wrap_pyfunction!(records_cbor_to_arrow, py).unwrap();
+            assert_eq!(numbers(&batch(&call(&bare, &data, &kwargs(py, "")).unwrap())), [4, 5]);
+            // cbor_to_arrow looks for the envelope a bare array lacks.
+            assert!(convert_bytes(py, &data, "").is_err());
+        });
+    }
+
+    #[test]
+    fn streams_yield_batches_lazily() {
+        pyo3::prepare_freethreaded_python();
+        let data = response(numbered(&[1, 2, 3, 4, 5]));
+        Python::with_gil(|py| {
+            let convert = wrap_pyfunction!(cbor_to_arrow, py).unwrap();
+            let stream = call(&convert, &data, &kwargs(py, "output='stream', max_rows_per_batch=2")).unwrap();
+            assert!(stream.hasattr("__arrow_c_stream__").unwrap());
+            let reader = stream.extract::<PyRecordBatchReader>().unwrap().into_reader().unwrap();
+            let batches: Vec<RecordBatch> = reader.map(Result::unwrap).collect();
+            assert_eq!(batches.iter().map(RecordBatch::num_rows).collect::<Vec<_>>(), [2, 2, 1]);
+            assert_eq!(batches.iter().flat_map(numbers).collect::<Vec<_>>(), [1, 2, 3, 4, 5]);
+        });
+    }
+
+    #[test]
+    fn results_are_capsule_objects() {
+        pyo3::prepare_freethreaded_python();
+        let data = response(numbered(&[1]));
+        Python::with_gil(|py| {
+            let convert = wrap_pyfunction!(cbor_to_arrow, py).unwrap();
+            let result = call(&convert, &data, &kwargs(py, "")).unwrap();
+            assert!(result.hasattr("__arrow_c_array__").unwrap());
+            assert!(result.hasattr("__arrow_c_schema__").unwrap());
+            assert_eq!(numbers(&batch(&result)), [1]);
+
+            let pyarrow = py.import("pyarrow");
+            let converted = call(&convert, &data, &kwargs(py, "output='pyarrow'"));
+            match pyarrow {
+                Ok(pyarrow) => {
+                    assert!(converted.unwrap().is_instance(&pyarrow.getattr("RecordBatch").unwrap()).unwrap())
+                }
+                // Only output="pyarrow" needs pyarrow.
+                Err(_) => assert!(converted.unwrap_err().is_instance_of::<pyo3::exceptions::PyImportError>(py)),
+            }
+        });
+    }
+
+    #[test]
+    fn results_split_into_batches_of_max_rows() {
+        pyo3::prepare_freethreaded_python();
+        let data = response(numbered(&[1, 2, 3, 4, 5]));
+        Python::with_gil(|py| {
+            let convert = wrap_pyfunction!(cbor_to_arrow, py).unwrap();
+            let batches: Vec<Bound<PyAny>> =
+                call(&convert, &data, &kwargs(py, "max_rows_per_batch=2")).unwrap().extract().unwrap();
+            let batches: Vec<RecordBatch> = batches.iter().map(batch).collect();
+            assert_eq!(batches.iter().map(RecordBatch::num_rows).collect::<Vec<_>>(), [2, 2, 1]);
+            assert!(batches.iter().all(|b| b.schema() == batches[0].schema()));
+            assert_eq!(batches.iter().flat_map(numbers).collect::<Vec<_>>(), [1, 2, 3, 4, 5]);
+            let err = call(&convert, &data, &kwargs(py, "max_rows_per_batch=0")).unwrap_err();
+            assert!(err.is_instance_of::<PyValueError>(py), "{}", err);
+        });
+    }
+
+    #[test]
+    fn tables_are_chunked_by_max_rows() {
+        pyo3::prepare_freethreaded_python();
+        let data = response(numbered(&[1, 2, 3, 4, 5]));
+        Python::with_gil(|py| {
+            let convert = wrap_pyfunction!(cbor_to_arrow, py).unwrap();
+            let table = call(&convert, &data, &kwargs(py, "output='table', max_rows_per_batch=2"));
+            let Ok(pyarrow) = py.import("pyarrow") else {
+                assert!(table.unwrap_err().is_instance_of::<pyo3::exceptions::PyImportError>(py));
+                return;
+            };
+            let table = table.unwrap();
+            assert!(table.is_instance(&pyarrow.getattr("Table").unwrap()).unwrap());
+            assert_eq!(table.getattr("num_rows").unwrap().extract::<usize>().unwrap(), 5);
+            let chunks = table.call_method1("column", ("n",)).unwrap().getattr("num_chunks").unwrap();
+            assert_eq!(chunks.extract::<usize>().unwrap(), 3);
+        });
+    }
+
+    #[test]
+    fn conversions_release_the_gil() {
+        pyo3::prepare_freethreaded_python();
+        let data = response(Value::Array((0..100_000).map(|n| record(&[("n", Value::Integer(n))])).collect()));
+        Python::with_gil(|py| {
+            // A thread holding the GIL throughout would keep `count` still.
+            let code = c"import threading, time\n\
+                         def progress(convert, data):\n    \
+                             state = {'count': 0, 'done': False}\n    \
+                             def tick():\n        \
+                                 while not state['done']:\n            \
+                                     state['count'] += 1\n            \
+                                     time.sleep(0.0005)\n    \
+                             ticker = threading.Thread(target=tick)\n    \
+                             ticker.start()\n    \
+                             while state['count'] == 0:\n        \
+                                 time.sleep(0.0005)\n    \
+                             before = state['count']\n    \
+                             convert(data, output='stream')\n    \
+                             after = state['count']\n    \
+                             state['done'] = True\n    \
+                             ticker.join()\n    \
+                             return after - before\n";
+            let module = PyModule::from_code(py, code, c"gil_test.py", c"gil_test").unwrap();
+            let convert = wrap_pyfunction!(cbor_to_arrow, py).unwrap();
+            let progress = module.getattr("progress").unwrap();
+            let ticks: usize = progress.call1((convert, PyBytes::new(py, &data))).unwrap().extract().unwrap();
+            assert!(ticks > 1, "the other thread ticked {} times", ticks);
+        });
+    }
+
+    #[test]
+    fn byte_buffers_are_input() {
+        pyo3::prepare_freethreaded_python();
+        let data = response(numbered(&[1, 2]));
+        Python::with_gil(|py| {
+            let convert = wrap_pyfunction!(cbor_to_arrow, py).unwrap();
+            let bytes = PyBytes::new(py, &data);
+            let buffers = [
+                bytes.clone().into_any(),
+                pyo3::types::PyByteArray::new(py, &data).into_any(),
+                py.eval(c"memoryview", None, None).unwrap().call1((&bytes,)).unwrap(),
+            ];
+            for buffer in buffers {
+                let result = convert.call((&buffer,), Some(&kwargs(py, ""))).unwrap();
+                assert_eq!(numbers(&batch(&result)), [1, 2], "{}", buffer);
+            }
+            let err = convert.call1(("not bytes",)).unwrap_err();
+            assert!(err.is_instance_of::<PyTypeError>(py), "{}", err);
+            // Every other byte of the data is no contiguous buffer.
+            let strided = py.eval(c"memoryview", None, None).unwrap().call1((&bytes,)).unwrap();
+            let strided = strided.get_item(pyo3::types::PySlice::new(py, 0, data.len() as isize, 2)).unwrap();
+            let err = convert.call1((strided,)).unwrap_err();
+            assert!(err.is_instance_of::<PyValueError>(py), "{}", err);
+        });
+    }
+
+    #[test]
+    fn nested_values_serialize_by_reference() {
+        let nested = record(&[
+            ("list", Value::Array(vec![Value::Integer(1), tagged(tags::TAG_NONE, Value::Null)])),
+            ("deep", record(&[("at", tagged(tags::TAG_DATETIME_COMPACT, Value::Array(vec![Value::Integer(1)])))])),
+            ("tb", tagged(tags::TAG_TABLE, text("person"))),
+        ]);
+        let opts = ConvertOptions::default();
+        let json = serde_json::to_string(&SurrealValueRef(&nested, &opts)).unwrap();
+        assert_eq!(json, r#"{"list":[1,null],"deep":{"at":1000000000},"tb":"person"}"#);
+    }
+
+    #[test]
+    fn declared_schemas_skip_inference() {
+        pyo3::prepare_freethreaded_python();
+        let data = response(Value::Array(vec![
+            record(&[("n", Value::Integer(1)), ("name", text("a")), ("extra", Value::Bool(true))]),
+            record(&[("n", Value::Integer(2))]),
+        ]));
+        Python::with_gil(|py| {
+            let declared = Schema::new(vec![
+                Field::new("name", DataType::Utf8, true),
+                Field::new("n", DataType::Float64, true),
+                Field::new("missing", DataType::Int64, true),
+            ]);
+            let options = kwargs(py, "output='stream'");
+            let schema = Bound::new(py, pyo3_arrow::PySchema::new(Arc::new(declared.clone()))).unwrap();
+            options.set_item("schema", schema).unwrap();
+            let convert = wrap_pyfunction!(cbor_to_arrow, py).unwrap();
+            let reader = call(&convert, &data, &options).unwrap().extract::<PyRecordBatchReader>().unwrap();
+            let batches: Vec<RecordBatch> = reader.into_reader().unwrap().map(Result::unwrap).collect();
+            let batch = &batches[0];
+            assert_eq!(batch.schema().as_ref(), &declared);
+            assert_eq!(strings(batch.column(0)), [Some("a".to_string()), None]);
+            assert_eq!(batch.column(1).as_primitive::<arrow::datatypes::Float64Type>().values().to_vec(), [1.0, 2.0]);
+            assert_eq!(batch.column(2).null_count(), 2);
+        });
+    }
+
+    #[test]
+    fn schemas_are_inferred_alone() {
+        pyo3::prepare_freethreaded_python();
+        let data = response(Value::Array(vec![record(&[("n", Value::Integer(1)), ("name", text("a"))])]));
+        Python::with_gil(|py| {
+            let opts = ConvertOptions::default();
+            let payload = Payload::load_owned(py, data.clone(), true, &opts).unwrap();
+            let (_, plan) = result_plan(py, &payload, RecordsAt::Statement(0), &opts).unwrap();
+            let schema = plan.unwrap().schema;
+            let names: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+            assert_eq!(names, ["n", "name"]);
+            assert_eq!(schema.field(0).data_type(), &DataType::Int64);
+
+            let infer = wrap_pyfunction!(infer_schema, py).unwrap();
+            let inferred = call(&infer, &data, &kwargs(py, ""));
+            match py.import("pyarrow") {
+                Ok(_) => assert_eq!(Schema::from_pyarrow_bound(&inferred.unwrap()).unwrap(), *schema),
+                Err(_) => assert!(inferred.unwrap_err().is_instance_of::<pyo3::exceptions::PyImportError>(py)),
+            }
+            let err = call(&infer, &data, &kwargs(py, "statement=1")).unwrap_err();
+            assert!(err.is_instance_of::<PyIndexError>(py), "{}", err);
+            let empty = call(&infer, &rpc(Vec::new()), &kwargs(py, "")).unwrap();
+            assert!(empty.is_none());
+        });
+    }
+
+    #[test]
+    fn tracing_options_shape_inference() {
+        pyo3::prepare_freethreaded_python();
+        let mixed = || vec![record(&[("n", Value::Integer(1))]), record(&[("n", Value::Float(1.5))])];
+        let labels = || vec![record(&[("label", text("a"))]), record(&[("label", text("a"))])];
+        let nested = || vec![record(&[("m", record(&[("k", Value::Integer(1))]))])];
+        Python::with_gil(|py| {
+            let data_type = |records: Vec<Value>, kwargs: &str| {
+                convert(py, records, kwargs).unwrap().schema().field(0).data_type().clone()
+            };
+            assert_eq!(data_type(mixed(), "coerce_numbers=True"), DataType::Float64);
+            assert!(convert(py, mixed(), "coerce_numbers=False, type_conflicts='error'").is_err());
+            assert!(matches!(data_type(labels(), "string_dictionary_encoding=True"), DataType::Dictionary(..)));
+            assert_eq!(data_type(labels(), ""), DataType::LargeUtf8);
+            assert!(matches!(data_type(nested(), ""), DataType::Struct(_)));
+            assert!(matches!(data_type(nested(), "map_as_struct=False"), DataType::Map(..)));
+            let err = options(py, "guess_dates='yes'").err().unwrap();
+            assert!(err.is_instance_of::<PyTypeError>(py), "{}", err);
+        });
+    }
+
+    #[test]
+    fn infer_samples_bounds_tracing() {
+        pyo3::prepare_freethreaded_python();
+        let records = || {
+            let mut records: Vec<Value> = (0..3).map(|n| record(&[("n", Value::Integer(n))])).collect();
+            records.push(record(&[("n", Value::Integer(3)), ("late", text("x"))]));
+            records
+        };
+        Python::with_gil(|py| {
+            let names = |kwargs: &str| {
+                let batch = convert(py, records(), kwargs).unwrap();
+                batch.schema().fields().iter().map(|f| f.name().clone()).collect::<Vec<_>>()
+            };
+            assert_eq!(names("infer_samples='all'"), ["late", "n"]);
+            assert_eq!(names("infer_samples=4"), ["late", "n"]);
+            // Fields first seen past the sample are left out, and the sampled fields nullable.
+            let sampled = convert(py, records(), "infer_samples=2").unwrap();
+            assert_eq!(names("infer_samples=2"), ["n"]);
+            assert!(sampled.schema().field(0).is_nullable());
+            assert_eq!(numbers(&sampled), [0, 1, 2, 3]);
+            for bad in ["infer_samples='some'", "infer_samples=0"] {
+                let err = options(py, bad).err().unwrap();
+                assert!(err.is_instance_of::<PyValueError>(py), "{}: {}", bad, err);
+            }
+        });
+    }
+
+    #[test]
+    fn responses_merge_into_one_schema() {
+        pyo3::prepare_freethreaded_python();
+        let first = response(Value::Array(vec![record(&[("n", Value::Integer(1))])]));
+        let second = response(Value::Array(vec![record(&[("n", Value::Integer(2)), ("name", text("b"))])]));
+        let empty = response(Value::Array(Vec::new()));
+        Python::with_gil(|py| {
+            let merge = wrap_pyfunction!(merge_cbor_to_arrow, py).unwrap();
+            let payloads = vec![PyBytes::new(py, &first), PyBytes::new(py, &empty), PyBytes::new(py, &second)];
+            let merged = batch(&merge.call1((payloads,)).unwrap());
+            assert_eq!(numbers(&merged), [1, 2]);
+            assert_eq!(strings(merged.column_by_name("name").unwrap()), [None, Some("b".to_string())]);
+
+            let pages = vec![PyBytes::new(py, &first), PyBytes::new(py, &second)];
+            let reader = merge.call((pages,), Some(&kwargs(py, "output='stream'"))).unwrap();
+            let batches: Vec<RecordBatch> =
+                reader.extract::<PyRecordBatchReader>().unwrap().into_reader().unwrap().map(Result::unwrap).collect();
+            assert_eq!(batches.iter().map(RecordBatch::num_rows).collect::<Vec<_>>(), [1, 1]);
+            assert!(merge.call1((Vec::<Bound<PyBytes>>::new(),)).unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn conflicting_types_follow_type_conflicts() {
+        pyo3::prepare_freethreaded_python();
+        let records = || {
+            vec![
+                record(&[("n", Value::Integer(1))]),
+                record(&[("n", Value::Null)]),
+                record(&[("n", Value::Float(1.5))]),
+            ]
+        };
+        Python::with_gil(|py| {
+            let promoted = convert(py, records(), "type_conflicts='promote'").unwrap();
+            let column = promoted.column(0);
+            assert_eq!(column.data_type(), &DataType::Float64);
+            let values = column.as_primitive::<arrow::datatypes::Float64Type>();
+            assert_eq!((values.value(0), values.is_null(1), values.value(2)), (1.0, true, 1.5));
+
+            let mut strings_too = records();
+            strings_too.push(record(&[("n", text("x"))]));
+            let stringified = convert(py, strings_too.clone(), "type_conflicts='stringify'").unwrap();
+            assert_eq!(stringified.column(0).data_type(), &DataType::LargeUtf8);
+            let expected = [Some("1"), None, Some("1.5"), Some("x")].map(|s| s.map(str::to_string));
+            assert_eq!(strings(stringified.column(0)), expected);
+
+            // Text is the last resort of promotion too.
+            let promoted = convert(py, strings_too, "type_conflicts='promote'").unwrap();
+            assert_eq!(strings(promoted.column(0)), expected);
+            assert!(convert(py, records(), "type_conflicts='error'").is_err());
+        });
+    }
+
+    #[test]
+    fn dictionary_encodes_string_columns() {
+        pyo3::prepare_freethreaded_python();
+        let records = || {
+            ["open", "closed", "open"]
+                .iter()
+                .map(|status| record(&[("name", text("a")), ("status", text(status))]))
+                .collect::<Vec<_>>()
+        };
+        let dictionary = DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+        Python::with_gil(|py| {
+            let types = |kwargs: &str| {
+                let batch = convert(py, records(), kwargs).unwrap();
+                batch.schema().fields().iter().map(|f| f.data_type().clone()).collect::<Vec<_>>()
+            };
+            assert_eq!(types("dictionary=True"), [dictionary.clone(), dictionary.clone()]);
+            assert_eq!(types("dictionary=['status']"), [DataType::LargeUtf8, dictionary.clone()]);
+            assert_eq!(types("dictionary=False"), [DataType::LargeUtf8, DataType::LargeUtf8]);
+
+            let batch = convert(py, records(), "dictionary=['status']").unwrap();
+            let status = batch.column(1).as_dictionary::<arrow::datatypes::Int32Type>();
+            assert_eq!(status.values().len(), 2);
+            assert_eq!(strings(batch.column(1)), ["open", "closed", "open"].map(|s| Some(s.to_string())));
+            let err = options(py, "dictionary=1").err().unwrap();
+            assert!(err.is_instance_of::<PyTypeError>(py), "{}", err);
+        });
+    }
+
+    #[test]
+    fn large_types_use_64_bit_offsets() {
+        pyo3::prepare_freethreaded_python();
+        let records = || {
+            vec![record(&[
+                ("blob", Value::Bytes(vec![1, 2])),
+                ("list", Value::Array(vec![Value::Integer(1)])),
+                ("name", text("a")),
+            ])]
+        };
+        Python::with_gil(|py| {
+            let types = |kwargs: &str| {
+                let batch = convert(py, records(), kwargs).unwrap();
+                batch.schema().fields().iter().map(|f| f.data_type().clone()).collect::<Vec<_>>()
+            };
+            let large = types("large_types=True");
+            assert_eq!(large[0], DataType::LargeBinary);
+            assert!(matches!(large[1], DataType::LargeList(_)));
+            assert_eq!(large[2], DataType::LargeUtf8);
+            assert_eq!(types(""), large);
+            let small = types("large_types=False");
+            assert_eq!(small[0], DataType::Binary);
+            assert!(matches!(small[1], DataType::List(_)));
+            assert_eq!(small[2], DataType::Utf8);
+        });
+    }
+
+    #[test]
+    fn columns_project_fields() {
+        pyo3::prepare_freethreaded_python();
+        let records = || {
+            vec![
+                record(&[("n", Value::Integer(1)), ("name", text("a")), ("wide", record(&[("x", Value::Bool(true))]))]),
+                // Unselected members are never traced, so their types may differ.
+                record(&[("n", Value::Integer(2)), ("name", text("b")), ("wide", text("skipped"))]),
+            ]
+        };
+        Python::with_gil(|py| {
+            let batch = convert(py, records(), "columns=['name', 'n', 'missing'], type_conflicts='error'").unwrap();
+            let names: Vec<&str> = batch.schema_ref().fields().iter().map(|f| f.name().as_str()).collect();
+            assert_eq!(names, ["n", "name"]);
+            assert_eq!(numbers(&batch), [1, 2]);
+            assert!(convert(py, records(), "type_conflicts='error'").is_err());
+        });
+    }
+
+    #[test]
+    fn patterns_select_members() {
+        pyo3::prepare_freethreaded_python();
+        let records = || {
+            vec![record(&[
+                ("_rev", Value::Integer(3)),
+                ("meta", record(&[("created", text("today")), ("by", text("me"))])),
+                ("n", Value::Integer(1)),
+            ])]
+        };
+        Python::with_gil(|py| {
+            let paths = |kwargs: &str| {
+                let batch = convert(py, records(), &format!("flatten=True, {}", kwargs)).unwrap();
+                batch.schema().fields().iter().map(|f| f.name().clone()).collect::<Vec<_>>()
+            };
+            assert_eq!(paths("exclude=['_*', 'meta.*']"), ["meta", "n"].map(str::to_string));
+            assert_eq!(paths("exclude=['_*', 'meta']"), ["n"]);
+            assert_eq!(paths("include=['meta.created']"), ["meta.created"]);
+            assert_eq!(paths("include=['**']"), ["_rev", "meta.by", "meta.created", "n"]);
+            assert_eq!(paths("include=[__import__('re').compile('m.*')], exclude=['meta.by']"), ["meta.created"]);
+            let err = options(py, "include=[1]").err().unwrap();
+            assert!(err.is_instance_of::<PyTypeError>(py), "{}", err);
+        });
+    }
+
+    #[test]
+    fn columns_are_renamed() {
+        pyo3::prepare_freethreaded_python();
+        let records = || vec![record(&[("usr_nm", text("a")), ("n", Value::Integer(1))])];
+        Python::with_gil(|py| {
+            let batch = convert(py, records(), "rename={'usr_nm': 'user_name'}, column_order=['usr_nm']").unwrap();
+            let names: Vec<&str> = batch.schema_ref().fields().iter().map(|f| f.name().as_str()).collect();
+            assert_eq!(names, ["user_name", "n"]);
+            assert_eq!(strings(batch.column_by_name("user_name").unwrap()), [Some("a".to_string())]);
+            assert!(options(py, "rename=['usr_nm']").is_err());
+        });
+    }
+
+    #[test]
+    fn column_order_is_stable() {
+        pyo3::prepare_freethreaded_python();
+        let records = || {
+            vec![
+                record(&[("zeta", Value::Integer(1)), ("alpha", Value::Integer(2))]),
+                record(&[("mid", Value::Integer(3)), ("zeta", Value::Integer(4))]),
+            ]
+        };
+        Python::with_gil(|py| {
+            let names = |kwargs: &str| {
+                let batch = convert(py, records(), kwargs).unwrap();
+                batch.schema().fields().iter().map(|f| f.name().clone()).collect::<Vec<_>>()
+            };
+            assert_eq!(names(""), ["alpha", "mid", "zeta"]);
+            assert_eq!(names("column_order='sorted'"), ["alpha", "mid", "zeta"]);
+            assert_eq!(names("column_order='insertion'"), ["zeta", "alpha", "mid"]);
+            assert_eq!(names("column_order=['zeta']"), ["zeta", "alpha", "mid"]);
+            let err = options(py, "column_order='random'").err().unwrap();
+            assert!(err.is_instance_of::<PyValueError>(py), "{}", err);
+        });
+    }
+
+    #[test]
+    fn objects_convert_as_chosen() {
+        pyo3::prepare_freethreaded_python();
+        let records = || {
+            vec![
+                record(&[("attrs", record(&[("a", Value::Integer(1))]))]),
+                record(&[("attrs", record(&[("b", Value::Integer(2))]))]),
+            ]
+        };
+        Python::with_gil(|py| {
+            let column = |kwargs: &str| convert(py, records(), kwargs).unwrap().column(0).clone();
+            let DataType::Struct(fields) = column("objects_as='struct'").data_type().clone() else { panic!() };
+            assert_eq!(fields.len(), 2);
+
+            let maps = column("objects_as='map'");
+            let maps = maps.as_map();
+            assert_eq!(maps.value_offsets(), [0, 1, 2]);
+            assert_eq!(strings(maps.keys()), [Some("a".to_string()), Some("b".to_string())]);
+
+            let json = column("objects_as='json'");
+            assert_eq!(strings(&json), [Some(r#"{"a":1}"#.to_string()), Some(r#"{"b":2}"#.to_string())]);
+            let err = options(py, "objects_as='dict'").err().unwrap();
+            assert!(err.is_instance_of::<PyValueError>(py), "{}", err);
+        });
+    }
+
+    #[test]
+    fn structs_flatten_into_dotted_columns() {
+        pyo3::prepare_freethreaded_python();
+        let records = || {
+            let geo = record(&[("lat", Value::Float(1.0))]);
+            vec![
+                record(&[("address", record(&[("city", text("Paris")), ("geo", geo)]))]),
+                record(&[("address", Value::Null)]),
+            ]
+        };
+        Python::with_gil(|py| {
+            let names = |batch: &RecordBatch| {
+                batch.schema().fields().iter().map(|f| f.name().clone()).collect::<Vec<_>>()
+            };
+            let flat = convert(py, records(), "flatten=True").unwrap();
+            assert_eq!(names(&flat), ["address.city", "address.geo.lat"]);
+            // A child is null wherever its struct is.
+            assert_eq!(strings(flat.column(0)), [Some("Paris".to_string()), None]);
+            assert!(flat.column(1).is_null(1));
+
+            let one_level = convert(py, records(), "flatten=1").unwrap();
+            assert_eq!(names(&one_level), ["address.city", "address.geo"]);
+            assert!(matches!(one_level.column(1).data_type(), DataType::Struct(_)));
+            assert_eq!(names(&convert(py, records(), "flatten=False").unwrap()), ["address"]);
+        });
+    }
+
+    #[test]
+    fn lists_explode_into_rows() {
+        pyo3::prepare_freethreaded_python();
+        let tags = |tags: &[&str]| Value::Array(tags.iter().map(|tag| text(tag)).collect());
+        let records = || {
+            vec![
+                record(&[("n", Value::Integer(1)), ("tags", tags(&["a", "b"]))]),
+                record(&[("n", Value::Integer(2)), ("tags", tags(&[]))]),
+                record(&[("n", Value::Integer(3)), ("tags", tags(&["c"]))]),
+            ]
+        };
+        Python::with_gil(|py| {
+            let exploded = convert(py, records(), "explode=['tags']").unwrap();
+            assert_eq!(numbers(&exploded), [1, 1, 2, 3]);
+            let expected = [Some("a"), Some("b"), None, Some("c")].map(|s| s.map(str::to_string));
+            assert_eq!(strings(exploded.column_by_name("tags").unwrap()), expected);
+
+            let mut uneven = records();
+            uneven.iter_mut().for_each(|r| {
+                let Value::Map(fields) = r else { unreachable!() };
+                fields.push((text("more"), tags(&["x"])));
+            });
+            assert!(convert(py, uneven, "explode=['tags', 'more']").is_err());
+        });
+    }
+
+    #[test]
+    fn conflicting_types_become_unions() {
+        use arrow::datatypes::UnionMode;
+        pyo3::prepare_freethreaded_python();
+        let records = || {
+            vec![
+                record(&[("v", text("old"))]),
+                record(&[("v", record(&[("n", Value::Integer(1))]))]),
+                record(&[("v", text("older"))]),
+            ]
+        };
+        Python::with_gil(|py| {
+            for (kwargs, mode) in [("", UnionMode::Dense), ("union_mode='sparse'", UnionMode::Sparse)] {
+                let batch = convert(py, records(), &format!("type_conflicts='union', {}", kwargs)).unwrap();
+                let DataType::Union(fields, union_mode) = batch.column(0).data_type() else {
+                    panic!("{:?}", batch.column(0).data_type())
+                };
+                assert_eq!(*union_mode, mode);
+                assert_eq!(fields.len(), 2);
+                let unions = batch.column(0).as_union();
+                let type_ids: Vec<i8> = (0..3).map(|i| unions.type_id(i)).collect();
+                assert_eq!(type_ids[0], type_ids[2]);
+                assert_ne!(type_ids[0], type_ids[1]);
+                assert_eq!(strings(&unions.value(2)), [Some("older".to_string())]);
+            }
+            assert!(options(py, "union_mode='mixed'").is_err());
+        });
+    }
+
+    #[test]
+    fn repeated_keys_follow_duplicate_keys() {
+        pyo3::prepare_freethreaded_python();
+        let records = || vec![Value::Map(vec![(text("k"), Value::Integer(1)), (text("k"), Value::Integer(2))])];
+        Python::with_gil(|py| {
+            for (kwargs, kept, loss) in [("", 2, "last"), ("duplicate_keys='first'", 1, "first")] {
+                let (batch, warnings) = warnings_of(py, || convert(py, records(), kwargs).unwrap());
+                assert_eq!(batch.column(0).as_primitive::<Int64Type>().values().to_vec(), [kept]);
+                assert_eq!(warnings.len(), 1, "{:?}", warnings);
+                assert!(warnings[0].contains(&format!("1 repeated object keys converted with only their {}", loss)));
+            }
+            let err = convert(py, records(), "duplicate_keys='error'").unwrap_err();
+            assert!(err.is_instance_of::<SurrealEngineError>(py), "{}", err);
+            let (_, warnings) = warnings_of(py, || convert(py, vec![record(&[("n", Value::Integer(1))])], "").unwrap());
+            assert!(warnings.is_empty(), "{:?}", warnings);
+        });
+    }
+
+    #[test]
+    fn big_integers_follow_big_int() {
+        pyo3::prepare_freethreaded_python();
+        let huge = -(1i128 << 64);
+        let records = || vec![record(&[("n", Value::Integer(1))]), record(&[("n", Value::Integer(huge))])];
+        Python::with_gil(|py| {
+            let err = convert(py, records(), "").unwrap_err();
+            assert!(err.is_instance_of::<SurrealEngineError>(py), "{}", err);
+
+            let decimal = convert(py, records(), "big_int='decimal'").unwrap();
+            assert_eq!(decimal.column(0).data_type(), &DataType::Decimal256(76, 0));
+            let decimals = decimal.column(0).as_primitive::<arrow::datatypes::Decimal256Type>();
+            assert_eq!(decimals.value(1), arrow::datatypes::i256::from_i128(huge));
+
+            let string = convert(py, records(), "big_int='string'").unwrap();
+            assert_eq!(strings(string.column(0)), [Some("1".to_string()), Some(huge.to_string())]);
+
+            let (null, warnings) = warnings_of(py, || convert(py, records(), "big_int='null'").unwrap());
+            assert_eq!(numbers(&null)[0], 1);
+            assert!(null.column(0).is_null(1));
+            assert!(warnings[0].contains("1 integers outside the 64-bit ranges converted as nulls"), "{:?}", warnings);
+        });
+    }
+
+    #[test]
+    fn bignums_decode_as_integers() {
+        pyo3::prepare_freethreaded_python();
+        let bignum = |tag, bytes: &[u8]| tagged(tag, Value::Bytes(bytes.to_vec()));
+        // 2^80, past every 64-bit integer.
+        let mut huge = vec![1];
+        huge.extend([0; 10]);
+        let records = || {
+            vec![
+                record(&[("n", bignum(tags::TAG_BIGNUM, &[1, 0]))]),
+                record(&[("n", bignum(tags::TAG_NEGATIVE_BIGNUM, &[0x0f]))]),
+            ]
+        };
+        Python::with_gil(|py| {
+            // Bignums that fit 64 bits are plain integers.
+            assert_eq!(numbers(&convert(py, records(), "").unwrap()), [256, -16]);
+
+            let mut big = records();
+            big.push(record(&[("n", bignum(tags::TAG_NEGATIVE_BIGNUM, &huge))]));
+            assert!(convert(py, big.clone(), "").is_err());
+            let string = convert(py, big.clone(), "big_int='string'").unwrap();
+            let expected = ["256", "-16", "-1208925819614629174706177"].map(|s| Some(s.to_string()));
+            assert_eq!(strings(string.column(0)), expected);
+            let decimal = convert(py, big, "big_int='decimal'").unwrap();
+            let decimals = decimal.column(0).as_primitive::<arrow::datatypes::Decimal256Type>();
+            assert_eq!(decimals.value(2), arrow::datatypes::i256::from_i128(-(1i128 << 80) - 1));
+        });
+    }
+
+    #[test]
+    fn decimal_fractions_become_decimals() {
+        pyo3::prepare_freethreaded_python();
+        let fraction = |exponent: i128, mantissa: i128| {
+            tagged(tags::TAG_DECIMAL_FRACTION, Value::Array(vec![Value::Integer(exponent), Value::Integer(mantissa)]))
+        };
+        let records = vec![
+            record(&[("d", fraction(-2, 12345))]),
+            record(&[("d", fraction(-1, -5))]),
+            record(&[("d", Value::Null)]),
+        ];
+        Python::with_gil(|py| {
+            let batch = convert(py, records, "").unwrap();
+            let column = batch.column(0);
+            assert!(matches!(column.data_type(), DataType::Decimal128(_, 2)), "{:?}", column.data_type());
+            let expected = [Some("123.45"), Some("-0.50"), None].map(|s| s.map(str::to_string));
+            assert_eq!(strings(column), expected);
+        });
+    }
+
+    #[test]
+    fn self_described_cbor_is_unwrapped() {
+        pyo3::prepare_freethreaded_python();
+        let envelope = record(&[("id", Value::Integer(1)), ("result", Value::Array(vec![ok(numbered(&[1, 2]))]))]);
+        let described = encode::encode(&tagged(tags::TAG_SELF_DESCRIBED, envelope));
+        let bare = encode::encode(&tagged(tags::TAG_SELF_DESCRIBED, numbered(&[3])));
+        Python::with_gil(|py| {
+            assert_eq!(numbers(&convert_bytes(py, &described, "").unwrap()), [1, 2]);
+            let records = wrap_pyfunction!(records_cbor_to_arrow, py).unwrap();
+            assert_eq!(numbers(&batch(&call(&records, &bare, &kwargs(py, "")).unwrap())), [3]);
+        });
+    }
+
+    #[test]
+    fn cbor_sequences_convert_each_item() {
+        pyo3::prepare_freethreaded_python();
+        let mut data = response(numbered(&[1, 2]));
+        data.extend(response(Value::Array(vec![record(&[("n", Value::Integer(3)), ("name", text("c"))])])));
+        Python::with_gil(|py| {
+            let sequence = wrap_pyfunction!(cbor_sequence_to_arrow, py).unwrap();
+            let results: Vec<Bound<PyAny>> = call(&sequence, &data, &kwargs(py, "")).unwrap().extract().unwrap();
+            let batches: Vec<RecordBatch> = results.iter().map(batch).collect();
+            assert_eq!(batches.iter().map(numbers).collect::<Vec<_>>(), [vec![1, 2], vec![3]]);
+
+            let merged = batch(&call(&sequence, &data, &kwargs(py, "merge=True")).unwrap());
+            assert_eq!(numbers(&merged), [1, 2, 3]);
+            assert_eq!(merged.num_columns(), 2);
+
+            // The other conversions refuse to drop the items after the first.
+            let err = convert_bytes(py, &data, "").unwrap_err();
+            assert!(err.is_instance_of::<CborDecodeError>(py), "{}", err);
+            assert!(err.to_string().contains("convert it with cbor_sequence_to_arrow"), "{}", err);
+        });
+    }
+
+    #[test]
+    fn errors_locate_the_bad_value() {
+        pyo3::prepare_freethreaded_python();
+        let tags = Value::Array(vec![text("a"), text("b"), text("~~")]);
+        let records = vec![record(&[("n", Value::Integer(1))]), record(&[("meta", record(&[("tags", tags)]))])];
+        let mut data = response(Value::Array(records));
+        // Make the last tag invalid UTF-8.
+        let at = data.windows(2).position(|w| w == b"~~").unwrap();
+        data[at] = 0xff;
+        Python::with_gil(|py| {
+            let err = convert_bytes(py, &data, "").unwrap_err();
+            assert!(err.is_instance_of::<CborDecodeError>(py), "{}", err);
+            let message = err.value(py).to_string();
+            assert!(message.starts_with("CBOR decode error: "), "{}", message);
+            // The offset is that of the text string's head.
+            let location = format!("at offset {} in result[0].result[1].meta.tags[2]", at - 1);
+            assert!(message.ends_with(&location), "{}", message);
+        });
+    }
+
+    #[test]
+    fn failures_raise_specific_exceptions() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "accelerator").unwrap();
+            errors::register(&module).unwrap();
+            let base = py.get_type::<SurrealEngineError>();
+            for name in ["CborDecodeError", "QueryStatusError", "SchemaInferenceError", "ArrowBuildError"] {
+                let class = module.getattr(name).unwrap().downcast_into::<pyo3::types::PyType>().unwrap();
+                assert!(class.is_subclass(&base).unwrap(), "{}", name);
+            }
+            assert!(base.is_subclass_of::<PyValueError>().unwrap());
+
+            let err = convert_bytes(py, b"\xff", "").unwrap_err();
+            assert!(err.is_instance_of::<CborDecodeError>(py), "{}", err);
+            let err = convert_bytes(py, &encode::encode(&Value::Integer(1)), "").unwrap_err();
+            assert!(err.is_instance_of::<CborDecodeError>(py), "{}", err);
+            let failed = record(&[("status", text("ERR")), ("result", text("boom"))]);
+            let err = convert_bytes(py, &rpc(vec![failed]), "").unwrap_err();
+            assert!(err.is_instance_of::<QueryStatusError>(py), "{}", err);
+            let err = convert(py, vec![record(&[("n", Value::Integer(1))])], "max_rows=0").unwrap_err();
+            assert!(err.is_instance_of::<PyValueError>(py) && !err.is_instance_of::<SurrealEngineError>(py));
+        });
+    }
+
+    #[test]
+    fn query_errors_carry_code_and_message() {
+        pyo3::prepare_freethreaded_python();
+        let error = record(&[("code", Value::Integer(-32000)), ("message", text("There was a problem"))]);
+        let failed = encode::encode(&record(&[("id", Value::Integer(1)), ("error", error)]));
+        let statement = record(&[("status", text("ERR")), ("time", text("3ms")), ("message", text("boom"))]);
+        Python::with_gil(|py| {
+            let attribute = |err: &PyErr, name: &str| err.value(py).getattr(name).unwrap();
+            let err = convert_bytes(py, &failed, "").unwrap_err();
+            assert!(err.is_instance_of::<QueryStatusError>(py), "{}", err);
+            assert_eq!(attribute(&err, "code").extract::<i64>().unwrap(), -32000);
+            assert_eq!(attribute(&err, "message").extract::<String>().unwrap(), "There was a problem");
+            assert!(attribute(&err, "statement").is_none());
+
+            let err = convert_bytes(py, &rpc(vec![ok(numbered(&[1])), statement]), "statement=1").unwrap_err();
+            assert!(err.is_instance_of::<QueryStatusError>(py), "{}", err);
+            assert!(attribute(&err, "code").is_none());
+            assert_eq!(attribute(&err, "message").extract::<String>().unwrap(), "boom");
+            assert_eq!(attribute(&err, "statement").extract::<usize>().unwrap(), 1);
+            assert_eq!(attribute(&err, "status").extract::<String>().unwrap(), "ERR");
+            assert_eq!(attribute(&err, "time").extract::<String>().unwrap(), "3ms");
+        });
+    }
+
+    #[test]
+    fn lossy_conversions_warn() {
+        pyo3::prepare_freethreaded_python();
+        let records = || {
+            vec![
+                record(&[("a", tagged(999, Value::Integer(1)))]),
+                record(&[("a", tagged(999, Value::Integer(2)))]),
+                record(&[("b", Value::Map(vec![(Value::Integer(5), Value::Bool(true))]))]),
+            ]
+        };
+        Python::with_gil(|py| {
+            let (batch, warnings) = warnings_of(py, || convert(py, records(), "on_unknown_tag='ignore'").unwrap());
+            assert_eq!(batch.num_rows(), 3);
+            assert_eq!(
+                warnings,
+                ["values were converted with a loss of information: 2 tagged values converted as their untagged \
+                  payload, 1 non-string object keys converted as strings"]
+            );
+            let err = warnings_of(py, || convert(py, records(), "on_unknown_tag='error'")).0.unwrap_err();
+            assert!(err.is_instance_of::<SurrealEngineError>(py), "{}", err);
+        });
+    }
+
+    #[test]
+    fn results_convert_to_pandas() {
+        pyo3::prepare_freethreaded_python();
+        let data = response(numbered(&[1, 2]));
+        Python::with_gil(|py| {
+            let to_pandas = wrap_pyfunction!(frames::cbor_to_pandas, py).unwrap();
+            let err = call(&to_pandas, &data, &kwargs(py, "output='table'")).unwrap_err();
+            assert!(err.is_instance_of::<PyTypeError>(py), "{}", err);
+            assert_eq!(err.value(py).to_string(), "cbor_to_pandas() does not take the 'output' option");
+
+            let frame = call(&to_pandas, &data, &kwargs(py, ""));
+            if py.import("pandas").is_err() || py.import("pyarrow").is_err() {
+                assert!(frame.unwrap_err().is_instance_of::<pyo3::exceptions::PyImportError>(py));
+                return;
+            }
+            let frame = frame.unwrap();
+            let n: Vec<i64> = frame.get_item("n").unwrap().call_method0("tolist").unwrap().extract().unwrap();
+            assert_eq!(n, [1, 2]);
+        });
+    }
+
+    #[test]
+    fn records_convert_to_dicts() {
+        pyo3::prepare_freethreaded_python();
+        let id = tagged(tags::TAG_RECORDID, Value::Array(vec![text("person"), Value::Integer(1)]));
+        let at = tagged(tags::TAG_DATETIME_COMPACT, Value::Array(vec![Value::Integer(1), Value::Integer(500_000)]));
+        let uuid = tagged(tags::TAG_UUID, Value::Bytes(vec![0x12; 16]));
+        let nested = record(&[("xs", Value::Array(vec![Value::Integer(1), tagged(tags::TAG_NONE, Value::Null)]))]);
+        let records = vec![record(&[("id", id), ("at", at), ("uuid", uuid), ("nested", nested)])];
+        let data = response(Value::Array(records.clone()));
+        Python::with_gil(|py| {
+            let to_dicts = wrap_pyfunction!(pyvalue::cbor_to_dicts, py).unwrap();
+            let expected = c"[{'id': 'person:1', 'uuid': uuid.UUID('12' * 16), 'nested': {'xs': [1, None]}, \
+                               'at': datetime.datetime(1970, 1, 1, 0, 0, 1, 500, datetime.timezone.utc)}]";
+            let modules = kwargs(py, "uuid=__import__('uuid'), datetime=__import__('datetime')");
+            let expected = py.eval(expected, None, Some(&modules)).unwrap();
+            let dicts = call(&to_dicts, &data, &kwargs(py, "")).unwrap();
+            assert!(dicts.eq(&expected).unwrap(), "{}", dicts);
+            let bare = call(&to_dicts, &encode::encode(&Value::Array(records)), &kwargs(py, "envelope=False")).unwrap();
+            assert!(bare.eq(&expected).unwrap(), "{}", bare);
+            let empty = call(&to_dicts, &response(Value::Array(Vec::new())), &kwargs(py, "")).unwrap();
+            assert_eq!(empty.len().unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn results_convert_to_numpy() {
+        pyo3::prepare_freethreaded_python();
+        let data = response(Value::Array(vec![
+            record(&[("n", Value::Integer(1)), ("x", Value::Float(0.5))]),
+            record(&[("n", Value::Integer(2)), ("x", Value::Null)]),
+        ]));
+        Python::with_gil(|py| {
+            let to_numpy = wrap_pyfunction!(frames::cbor_to_numpy, py).unwrap();
+            let err = call(&to_numpy, &data, &kwargs(py, "output='numpy'")).unwrap_err();
+            assert!(err.is_instance_of::<PyTypeError>(py), "{}", err);
+
+            let arrays = call(&to_numpy, &data, &kwargs(py, ""));
+            if py.import("numpy").is_err() {
+                assert!(arrays.unwrap_err().is_instance_of::<pyo3::exceptions::PyImportError>(py));
+                return;
+            }
+            let arrays = arrays.unwrap();
+            let n: Vec<i64> = arrays.get_item("n").unwrap().call_method0("tolist").unwrap().extract().unwrap();
+            assert_eq!(n, [1, 2]);
+            // Columns with nulls are masked arrays.
+            let x = arrays.get_item("x").unwrap().call_method0("tolist").unwrap();
+            assert_eq!(x.extract::<Vec<Option<f64>>>().unwrap(), [Some(0.5), None]);
+            let structured = call(&to_numpy, &response(numbered(&[3])), &kwargs(py, "structured=True")).unwrap();
+            let n = structured.get_item("n").unwrap().call_method0("tolist").unwrap();
+            assert_eq!(n.extract::<Vec<i64>>().unwrap(), [3]);
+        });
+    }
+
+    #[test]
+    fn responses_render_as_json() {
+        pyo3::prepare_freethreaded_python();
+        let record_id = tagged(tags::TAG_RECORDID, Value::Array(vec![text("person"), text("tobie")]));
+        let at = tagged(tags::TAG_DATETIME_COMPACT, Value::Array(vec![Value::Integer(1), Value::Integer(500_000_000)]));
+        let took = tagged(tags::TAG_DURATION_COMPACT, Value::Array(vec![Value::Integer(90)]));
+        let uuid = tagged(tags::TAG_UUID, Value::Bytes(vec![0xab; 16]));
+        let data = response(Value::Array(vec![record(&[
+            ("id", record_id),
+            ("at", at),
+            ("took", took),
+            ("uuid", uuid),
+            ("blob", Value::Bytes(vec![0x0f, 0xa0])),
+        ])]));
+        Python::with_gil(|py| {
+            let json = json::cbor_to_json(py, PyBytes::new(py, &data).extract().unwrap(), false).unwrap();
+            let expected = r#"{"id":1,"result":[{"status":"OK","time":"1ms","result":["#.to_string()
+                + r#"{"id":"person:tobie","#
+                + r#""at":"1970-01-01T00:00:01.500Z","took":"1m30s","uuid":"abababab-abab-abab-abab-abababababab","#
+                + r#""blob":"0fa0"}]}]}"#;
+            assert_eq!(json, expected);
+            let pretty = json::cbor_to_json(py, PyBytes::new(py, &data).extract().unwrap(), true).unwrap();
+            assert!(pretty.starts_with("{\n  \"id\": 1,\n"), "{}", pretty);
+            let parsed: serde_json::Value = serde_json::from_str(&pretty).unwrap();
+            assert_eq!(parsed, serde_json::from_str::<serde_json::Value>(&json).unwrap());
+        });
+    }
+
+    #[test]
+    fn records_export_as_json_lines() {
+        pyo3::prepare_freethreaded_python();
+        let data = response(Value::Array(vec![
+            record(&[("n", Value::Integer(1)), ("tb", tagged(tags::TAG_TABLE, text("person")))]),
+            record(&[("n", Value::Integer(2)), ("tb", Value::Null)]),
+        ]));
+        let expected = "{\"n\":1,\"tb\":\"person\"}\n{\"n\":2,\"tb\":null}\n";
+        let path = std::env::temp_dir().join(format!("surrealengine-{}-records.ndjson", std::process::id()));
+        Python::with_gil(|py| {
+            let to_ndjson = wrap_pyfunction!(json::cbor_to_ndjson, py).unwrap();
+            let lines = call(&to_ndjson, &data, &kwargs(py, "")).unwrap();
+            assert_eq!(lines.extract::<Vec<u8>>().unwrap(), expected.as_bytes());
+
+            let written = to_ndjson.call((PyBytes::new(py, &data), &path), None).unwrap();
+            assert!(written.is_none());
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+            let empty = call(&to_ndjson, &response(Value::Array(Vec::new())), &kwargs(py, "")).unwrap();
+            assert_eq!(empty.extract::<Vec<u8>>().unwrap(), b"");
+        });
+        std::fs::remove_file(path).unwrap();
+    }
 }
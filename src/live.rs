@@ -0,0 +1,552 @@
+//! Live query notification frames.
+//!
+//! SurrealDB pushes live query changes as `{result: {id, action, result}}`
+//! frames, without the per-statement `status` envelope of query responses.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, RecordBatch, StringBuilder};
+use arrow::datatypes::{DataType, Field, Schema};
+use cbor4ii::core::Value;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::errors::{ArrowBuildError, CborDecodeError, SurrealEngineError};
+use crate::tags::{self, SurrealTag};
+use crate::{map_get, output, pyvalue, records_to_batch, result_records, select, CborInput, ConvertOptions, SurrealValueRef};
+
+/// A decoded live query notification.
+pub(crate) struct Notification<'a> {
+    /// The live query UUID.
+    pub(crate) id: String,
+    /// `CREATE`, `UPDATE`, `DELETE`, or `KILLED`/`CLOSE` when the query ends.
+    pub(crate) action: String,
+    /// The affected record id, when the server includes it.
+    pub(crate) record: Option<&'a Value>,
+    /// The record, or the patch list in DIFF mode.
+    pub(crate) result: &'a Value,
+}
+
+/// Locate the notification inside a push frame.
+pub(crate) fn parse_notification(root: &Value) -> PyResult<Notification<'_>> {
+    let Value::Map(frame) = root else {
+        return Err(CborDecodeError::new_err("Notification frame is not a Map"));
+    };
+    let Some(Value::Map(body)) = map_get(frame, "result") else {
+        return Err(CborDecodeError::new_err("Notification 'result' is not a Map"));
+    };
+    let Some(id) = map_get(body, "id").and_then(uuid_string) else {
+        return Err(CborDecodeError::new_err("Notification 'id' is not a UUID"));
+    };
+    let Some(Value::Text(action)) = map_get(body, "action") else {
+        return Err(CborDecodeError::new_err("Notification 'action' is not a string"));
+    };
+    Ok(Notification {
+        id,
+        action: action.clone(),
+        record: map_get(body, "record"),
+        result: map_get(body, "result").unwrap_or(&Value::Null),
+    })
+}
+
+/// Convert a live query notification frame into `(live_id, action, batch)`.
+///
+/// The batch holds the affected record as a single row, or is `None` when the
+/// notification carries no record (e.g. `KILLED`). For `LIVE SELECT DIFF`
+/// queries it holds one row per patch operation instead, with `op`, `path` and
+/// `value` (JSON text) columns. Accepts the same keyword options as
+/// `cbor_to_arrow`.
+#[pyfunction]
+#[pyo3(signature = (data, **options))]
+pub(crate) fn notification_to_arrow(
+    py: Python,
+    data: CborInput,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<(String, String, PyObject)> {
+    let opts = ConvertOptions::from_kwargs(options)?;
+    opts.check_bytes(&data)?;
+    let root = data.decode(py)?;
+    let notification = parse_notification(&root)?;
+    let batch = notification_batch(py, &notification, &opts)?;
+    Ok((notification.id, notification.action, batch))
+}
+
+/// The batch of a notification, as `notification_to_arrow` returns it.
+pub(crate) fn notification_batch(py: Python, notification: &Notification<'_>, opts: &ConvertOptions) -> PyResult<PyObject> {
+    match patch_ops(notification.result) {
+        Some(ops) => output::emit_batch(py, patches_to_batch(ops, opts)?, opts),
+        None => match result_records(notification.result) {
+            Some(records_arr) => {
+                let records_arr = select::records(opts.select.as_ref(), records_arr);
+                output::emit_batch(py, py.allow_threads(|| records_to_batch(&records_arr, opts))?, opts)
+            }
+            None => Ok(py.None()),
+        },
+    }
+}
+
+/// The canonical string form of a live query UUID.
+pub(crate) fn uuid_string(id: &Value) -> Option<String> {
+    match id {
+        Value::Tag(tag, payload) if SurrealTag::of(*tag) == SurrealTag::Uuid => {
+            tags::uuid_bytes(*tag, payload).map(|b| tags::format_uuid(&b))
+        }
+        Value::Text(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+/// The canonical string form of a record id value.
+fn id_string(id: &Value) -> Option<String> {
+    match id {
+        Value::Tag(tag, payload) if SurrealTag::of(*tag) == SurrealTag::RecordId => tags::record_id_string(payload),
+        Value::Text(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+/// The `id` field of a record.
+fn record_key(record: &Value) -> Option<String> {
+    match record {
+        Value::Map(map) => id_string(map_get(map, "id")?),
+        _ => None,
+    }
+}
+
+/// The record id of the record a notification is about.
+fn notification_key(notification: &Notification<'_>) -> PyResult<String> {
+    record_key(notification.result)
+        .or_else(|| id_string(notification.record?))
+        .ok_or_else(|| {
+            CborDecodeError::new_err(format!(
+                "{} notification has no record id",
+                notification.action
+            ))
+        })
+}
+
+/// An in-memory table kept up to date from live query notifications.
+///
+/// Records are keyed by record id: `CREATE` and `UPDATE` upsert, `DELETE`
+/// removes. `LIVE SELECT DIFF` patches are applied to the stored record.
+/// `snapshot()` converts the current records into a RecordBatch using the
+/// keyword options given to the constructor.
+#[pyclass]
+pub(crate) struct LiveTable {
+    opts: ConvertOptions,
+    /// `(record id, record)` in arrival order, with deletes swapped out.
+    records: Vec<(String, Value)>,
+    index: HashMap<String, usize>,
+}
+
+#[pymethods]
+impl LiveTable {
+    #[new]
+    #[pyo3(signature = (**options))]
+    fn new(options: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        Ok(LiveTable { opts: ConvertOptions::from_kwargs(options)?, records: Vec::new(), index: HashMap::new() })
+    }
+
+    /// Fold one notification frame into the table and return its action.
+    fn apply(&mut self, py: Python, data: CborInput) -> PyResult<String> {
+        self.opts.check_bytes(&data)?;
+        let root = data.decode(py)?;
+        let notification = parse_notification(&root)?;
+        match notification.action.as_str() {
+            "CREATE" | "UPDATE" if patch_ops(notification.result).is_some() => {
+                let key = notification_key(&notification)?;
+                let slot = match self.index.get(&key) {
+                    Some(&i) => i,
+                    None => {
+                        self.index.insert(key.clone(), self.records.len());
+                        self.records.push((key, Value::Null));
+                        self.records.len() - 1
+                    }
+                };
+                apply_patches(&mut self.records[slot].1, notification.result)
+                    .map_err(SurrealEngineError::new_err)?;
+            }
+            "CREATE" | "UPDATE" => {
+                if !matches!(notification.result, Value::Map(_)) {
+                    return Err(CborDecodeError::new_err(format!(
+                        "{} notification does not carry a record",
+                        notification.action
+                    )));
+                }
+                let key = notification_key(&notification)?;
+                let record = notification.result.clone();
+                match self.index.get(&key) {
+                    Some(&i) => self.records[i].1 = record,
+                    None => {
+                        self.index.insert(key.clone(), self.records.len());
+                        self.records.push((key, record));
+                    }
+                }
+            }
+            "DELETE" => {
+                let key = notification_key(&notification)?;
+                if let Some(i) = self.index.remove(&key) {
+                    self.records.swap_remove(i);
+                    // The last record moved into the freed slot
+                    if let Some((moved_key, _)) = self.records.get(i) {
+                        self.index.insert(moved_key.clone(), i);
+                    }
+                }
+            }
+            // KILLED / CLOSE end the live query and leave the table as is.
+            _ => {}
+        }
+        Ok(notification.action)
+    }
+
+    /// The current records as a RecordBatch, or `None` (or an empty batch with
+    /// `empty_schema`) when the table is empty.
+    fn snapshot(&self, py: Python) -> PyResult<PyObject> {
+        match (self.records.is_empty(), &self.opts.empty_schema) {
+            (false, _) => {
+                // Patches address the whole record, so the selection is only applied here.
+                let records: Vec<Value> = match &self.opts.select {
+                    Some(select) => self.records.iter().map(|(_, record)| select.record(record)).collect(),
+                    None => self.records.iter().map(|(_, record)| record.clone()).collect(),
+                };
+                output::emit_batch(py, py.allow_threads(|| records_to_batch(&records, &self.opts))?, &self.opts)
+            }
+            (true, Some(schema)) => output::emit_batch(py, RecordBatch::new_empty(schema.clone()), &self.opts),
+            (true, None) => Ok(py.None()),
+        }
+    }
+
+    /// Remove all records.
+    fn clear(&mut self) {
+        self.records.clear();
+        self.index.clear();
+    }
+
+    fn __len__(&self) -> usize {
+        self.records.len()
+    }
+}
+
+/// Apply the patches of a `LIVE SELECT DIFF` notification to a record dict.
+///
+/// `data` is either a notification frame or a bare CBOR patch list. Returns the
+/// patched record; `record` itself is left unchanged.
+#[pyfunction]
+#[pyo3(name = "apply_patches")]
+pub(crate) fn py_apply_patches(py: Python, record: &Bound<'_, PyAny>, data: CborInput) -> PyResult<PyObject> {
+    let root = data.decode(py)?;
+    let patches = match &root {
+        Value::Map(_) => parse_notification(&root)?.result,
+        other => other,
+    };
+    let mut target = pyvalue::py_to_value(record)?;
+    apply_patches(&mut target, patches).map_err(SurrealEngineError::new_err)?;
+    pyvalue::value_to_py(py, &target)
+}
+
+/// The patch operations of a `LIVE SELECT DIFF` notification result.
+fn patch_ops(result: &Value) -> Option<&[Value]> {
+    match result {
+        Value::Array(ops) if !ops.is_empty() && ops.iter().all(|op| match op {
+            Value::Map(op) => matches!(map_get(op, "op"), Some(Value::Text(_))) && matches!(map_get(op, "path"), Some(Value::Text(_))),
+            _ => false,
+        }) => Some(ops),
+        _ => None,
+    }
+}
+
+/// Build the `op`, `path`, `value` table for a list of patch operations.
+fn patches_to_batch(ops: &[Value], opts: &ConvertOptions) -> PyResult<RecordBatch> {
+    let mut op_col = StringBuilder::new();
+    let mut path_col = StringBuilder::new();
+    let mut value_col = StringBuilder::new();
+    for op in ops {
+        let Value::Map(op) = op else { continue };
+        let text = |key| match map_get(op, key) {
+            Some(Value::Text(s)) => Some(s.as_str()),
+            _ => None,
+        };
+        op_col.append_option(text("op"));
+        path_col.append_option(text("path"));
+        match map_get(op, "value") {
+            Some(value) => {
+                let json = serde_json::to_string(&SurrealValueRef(value, opts))
+                    .map_err(|e| ArrowBuildError::new_err(format!("Patch value error: {}", e)))?;
+                value_col.append_value(json);
+            }
+            None => value_col.append_null(),
+        }
+    }
+    let schema = Schema::new(vec![
+        Field::new("op", DataType::Utf8, false),
+        Field::new("path", DataType::Utf8, false),
+        Field::new("value", DataType::Utf8, true),
+    ]);
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(op_col.finish()),
+        Arc::new(path_col.finish()),
+        Arc::new(value_col.finish()),
+    ];
+    RecordBatch::try_new(Arc::new(schema), columns)
+        .map_err(|e| ArrowBuildError::new_err(format!("RecordBatch creation error: {}", e)))
+}
+
+/// Apply the patch operations in `patches` (JSON Patch, RFC 6902) to `target`.
+fn apply_patches(target: &mut Value, patches: &Value) -> Result<(), String> {
+    let Some(ops) = patch_ops(patches) else {
+        return Err("Patch payload is not a list of operations".to_string());
+    };
+    for op in ops {
+        let Value::Map(op) = op else { continue };
+        let text = |key| match map_get(op, key) {
+            Some(Value::Text(s)) => Ok(s.as_str()),
+            _ => Err(format!("Patch operation is missing '{}'", key)),
+        };
+        let path = parse_pointer(text("path")?)?;
+        let value = || map_get(op, "value").cloned().ok_or_else(|| "Patch operation is missing 'value'".to_string());
+        match text("op")? {
+            "add" => insert_at(target, &path, value()?)?,
+            "replace" => *pointer_mut(target, &path)? = value()?,
+            "remove" => {
+                remove_at(target, &path)?;
+            }
+            "move" => {
+                let moved = remove_at(target, &parse_pointer(text("from")?)?)?;
+                insert_at(target, &path, moved)?;
+            }
+            "copy" => {
+                let copied = pointer_mut(target, &parse_pointer(text("from")?)?)?.clone();
+                insert_at(target, &path, copied)?;
+            }
+            "test" => {
+                if *pointer_mut(target, &path)? != value()? {
+                    return Err(format!("Patch test failed at '{}'", text("path")?));
+                }
+            }
+            other => return Err(format!("Unsupported patch operation '{}'", other)),
+        }
+    }
+    Ok(())
+}
+
+/// Split a JSON Pointer into unescaped segments.
+fn parse_pointer(path: &str) -> Result<Vec<String>, String> {
+    if path.is_empty() || path == "/" {
+        return Ok(Vec::new());
+    }
+    let Some(rest) = path.strip_prefix('/') else {
+        return Err(format!("Invalid patch path '{}'", path));
+    };
+    Ok(rest.split('/').map(|s| s.replace("~1", "/").replace("~0", "~")).collect())
+}
+
+fn child_mut<'a>(value: &'a mut Value, segment: &str) -> Result<&'a mut Value, String> {
+    match value {
+        Value::Map(map) => map
+            .iter_mut()
+            .find(|(k, _)| matches!(k, Value::Text(s) if s == segment))
+            .map(|(_, v)| v)
+            .ok_or_else(|| format!("Patch path segment '{}' not found", segment)),
+        Value::Array(items) => segment
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| items.get_mut(i))
+            .ok_or_else(|| format!("Patch path index '{}' out of range", segment)),
+        _ => Err(format!("Patch path segment '{}' does not address a container", segment)),
+    }
+}
+
+fn pointer_mut<'a>(value: &'a mut Value, path: &[String]) -> Result<&'a mut Value, String> {
+    path.iter().try_fold(value, |v, segment| child_mut(v, segment))
+}
+
+fn insert_at(target: &mut Value, path: &[String], value: Value) -> Result<(), String> {
+    let Some((last, parent)) = path.split_last() else {
+        *target = value;
+        return Ok(());
+    };
+    match pointer_mut(target, parent)? {
+        Value::Map(map) => match map.iter_mut().find(|(k, _)| matches!(k, Value::Text(s) if s == last)) {
+            Some((_, slot)) => *slot = value,
+            None => map.push((Value::Text(last.clone()), value)),
+        },
+        Value::Array(items) if last == "-" => items.push(value),
+        Value::Array(items) => match last.parse::<usize>() {
+            Ok(i) if i <= items.len() => items.insert(i, value),
+            _ => return Err(format!("Patch path index '{}' out of range", last)),
+        },
+        _ => return Err(format!("Patch path segment '{}' does not address a container", last)),
+    }
+    Ok(())
+}
+
+fn remove_at(target: &mut Value, path: &[String]) -> Result<Value, String> {
+    let Some((last, parent)) = path.split_last() else {
+        return Ok(std::mem::replace(target, Value::Null));
+    };
+    match pointer_mut(target, parent)? {
+        Value::Map(map) => match map.iter().position(|(k, _)| matches!(k, Value::Text(s) if s == last)) {
+            Some(i) => Ok(map.remove(i).1),
+            None => Err(format!("Patch path segment '{}' not found", last)),
+        },
+        Value::Array(items) => match last.parse::<usize>() {
+            Ok(i) if i < items.len() => Ok(items.remove(i)),
+            _ => Err(format!("Patch path index '{}' out of range", last)),
+        },
+        _ => Err(format!("Patch path segment '{}' does not address a container", last)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::AsArray;
+    use arrow::datatypes::Int64Type;
+    use pyo3::types::PyBytes;
+    use pyo3_arrow::PyRecordBatch;
+
+    use super::*;
+    use crate::encode::encode;
+
+    const LIVE_ID: [u8; 16] = [7; 16];
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    /// The record id `person:<key>`.
+    fn link(key: &str) -> Value {
+        Value::Tag(tags::TAG_RECORDID, Box::new(Value::Array(vec![text("person"), text(key)])))
+    }
+
+    /// The record `person:<key>` with the number `n`.
+    fn person(key: &str, n: i128) -> Value {
+        Value::Map(vec![(text("id"), link(key)), (text("n"), Value::Integer(n))])
+    }
+
+    /// A notification frame of the live query `LIVE_ID`.
+    fn frame(action: &str, result: Value) -> Vec<u8> {
+        let id = Value::Tag(tags::TAG_UUID, Box::new(Value::Bytes(LIVE_ID.to_vec())));
+        let body = Value::Map(vec![(text("id"), id), (text("action"), text(action)), (text("result"), result)]);
+        encode(&Value::Map(vec![(text("result"), body)]))
+    }
+
+    fn numbers(batch: &Bound<PyAny>) -> Vec<i64> {
+        let batch = batch.extract::<PyRecordBatch>().unwrap().into_inner();
+        batch.column_by_name("n").unwrap().as_primitive::<Int64Type>().values().to_vec()
+    }
+
+    #[test]
+    fn notifications_convert_to_their_record() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let convert = wrap_pyfunction!(notification_to_arrow, py).unwrap();
+            let (id, action, batch): (String, String, Bound<PyAny>) =
+                convert.call1((PyBytes::new(py, &frame("CREATE", person("a", 1))),)).unwrap().extract().unwrap();
+            assert_eq!(id, tags::format_uuid(&LIVE_ID));
+            assert_eq!(action, "CREATE");
+            assert_eq!(numbers(&batch), [1]);
+
+            let (_, action, batch): (String, String, Bound<PyAny>) =
+                convert.call1((PyBytes::new(py, &frame("KILLED", Value::Null)),)).unwrap().extract().unwrap();
+            assert_eq!(action, "KILLED");
+            assert!(batch.is_none());
+
+            let err = convert.call1((PyBytes::new(py, &encode(&person("a", 1))),)).unwrap_err();
+            assert!(err.is_instance_of::<CborDecodeError>(py), "{}", err);
+        });
+    }
+
+    #[test]
+    fn live_tables_fold_notifications() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let table = Bound::new(py, LiveTable::new(None).unwrap()).unwrap();
+            let apply = |action: &str, result: Value| {
+                let applied = table.call_method1("apply", (PyBytes::new(py, &frame(action, result)),)).unwrap();
+                applied.extract::<String>().unwrap()
+            };
+            assert!(table.call_method0("snapshot").unwrap().is_none());
+            apply("CREATE", person("a", 1));
+            apply("CREATE", person("b", 2));
+            apply("CREATE", person("c", 3));
+            assert_eq!(apply("UPDATE", person("a", 10)), "UPDATE");
+            apply("DELETE", person("b", 2));
+            // The last record moves into the slot of the deleted one, and stays addressable.
+            apply("UPDATE", person("c", 30));
+            assert_eq!(table.len().unwrap(), 2);
+            assert_eq!(numbers(&table.call_method0("snapshot").unwrap()), [10, 30]);
+
+            apply("KILLED", Value::Null);
+            assert_eq!(table.len().unwrap(), 2);
+            table.call_method0("clear").unwrap();
+            assert_eq!(table.len().unwrap(), 0);
+
+            let anonymous = Value::Map(vec![(text("n"), Value::Integer(1))]);
+            let err = table.call_method1("apply", (PyBytes::new(py, &frame("CREATE", anonymous)),)).unwrap_err();
+            assert_eq!(err.value(py).to_string(), "CREATE notification has no record id");
+        });
+    }
+
+    /// A JSON Patch operation.
+    fn patch(op: &str, path: &str, value: Option<Value>) -> Value {
+        let mut fields = vec![(text("op"), text(op)), (text("path"), text(path))];
+        fields.extend(value.map(|value| (text("value"), value)));
+        Value::Map(fields)
+    }
+
+    #[test]
+    fn diff_patches_convert_and_apply() {
+        pyo3::prepare_freethreaded_python();
+        let patches = Value::Array(vec![
+            patch("replace", "/n", Some(Value::Integer(2))),
+            patch("add", "/tags", Some(Value::Array(vec![text("x")]))),
+            patch("add", "/tags/-", Some(text("y"))),
+            patch("remove", "/gone", None),
+        ]);
+        Python::with_gil(|py| {
+            let convert = wrap_pyfunction!(notification_to_arrow, py).unwrap();
+            let frame = PyBytes::new(py, &frame("UPDATE", patches.clone()));
+            let (_, _, batch): (String, String, Bound<PyAny>) = convert.call1((&frame,)).unwrap().extract().unwrap();
+            let batch = batch.extract::<PyRecordBatch>().unwrap().into_inner();
+            let column = |name: &str| {
+                let column = batch.column_by_name(name).unwrap().as_string::<i32>();
+                column.iter().map(|s| s.map(str::to_string)).collect::<Vec<_>>()
+            };
+            assert_eq!(column("op"), ["replace", "add", "add", "remove"].map(|s| Some(s.to_string())));
+            assert_eq!(column("path")[1].as_deref(), Some("/tags"));
+            let values = column("value");
+            assert_eq!(values[..3], ["2", "[\"x\"]", "\"y\""].map(|s| Some(s.to_string())));
+            assert_eq!(values[3], None);
+
+            let record = py.eval(c"{'n': 1, 'gone': True}", None, None).unwrap();
+            let patched = wrap_pyfunction!(py_apply_patches, py).unwrap().call1((&record, &frame)).unwrap();
+            assert!(patched.eq(py.eval(c"{'n': 2, 'tags': ['x', 'y']}", None, None).unwrap()).unwrap(), "{}", patched);
+            assert!(record.get_item("gone").unwrap().is_truthy().unwrap());
+
+            let bad = Value::Array(vec![patch("remove", "/missing", None)]);
+            let bad = PyBytes::new(py, &encode(&bad));
+            let err = wrap_pyfunction!(py_apply_patches, py).unwrap().call1((&record, bad)).unwrap_err();
+            assert!(err.is_instance_of::<SurrealEngineError>(py), "{}", err);
+        });
+    }
+
+    #[test]
+    fn live_tables_apply_diff_patches() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let table = Bound::new(py, LiveTable::new(None).unwrap()).unwrap();
+            table.call_method1("apply", (PyBytes::new(py, &frame("CREATE", person("a", 1))),)).unwrap();
+            // DIFF notifications name their record beside the patches.
+            let body = Value::Map(vec![
+                (text("id"), Value::Tag(tags::TAG_UUID, Box::new(Value::Bytes(LIVE_ID.to_vec())))),
+                (text("action"), text("UPDATE")),
+                (text("record"), link("a")),
+                (text("result"), Value::Array(vec![patch("replace", "/n", Some(Value::Integer(5)))])),
+            ]);
+            let update = encode(&Value::Map(vec![(text("result"), body)]));
+            table.call_method1("apply", (PyBytes::new(py, &update),)).unwrap();
+            assert_eq!(numbers(&table.call_method0("snapshot").unwrap()), [5]);
+        });
+    }
+}
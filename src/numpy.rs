@@ -0,0 +1,181 @@
+//! Conversion of batches to NumPy arrays for `output="numpy"`.
+//!
+//! Each column of a flat batch becomes a one-dimensional array. Numbers,
+//! timestamps and durations share the Arrow buffer holding their values, which
+//! is exposed to NumPy through the buffer protocol; booleans are unpacked into a
+//! copy, and strings and bytes become object arrays. NumPy has no nulls, so a
+//! column with some is a masked array masked where the values are null.
+//! Timestamps lose their time zone: NumPy datetimes are UTC.
+
+use std::ffi::{c_int, c_void};
+
+use arrow::array::{Array, ArrayRef, AsArray, RecordBatch};
+use arrow::buffer::Buffer;
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, TimeUnit};
+use pyo3::exceptions::{PyBufferError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyList};
+use pyo3::ffi;
+
+/// A dict from column names to NumPy arrays of the columns of `batch`.
+pub(crate) fn batch_to_numpy(py: Python, batch: &RecordBatch) -> PyResult<PyObject> {
+    let numpy = py.import("numpy")?;
+    let arrays = PyDict::new(py);
+    for (field, column) in batch.schema().fields().iter().zip(batch.columns()) {
+        arrays.set_item(field.name(), column_to_numpy(&numpy, field.name(), column)?)?;
+    }
+    Ok(arrays.into_any().unbind())
+}
+
+/// A structured array with a field for each array of `arrays`, a dict from
+/// `batch_to_numpy`. Structured arrays cannot be masked, so no column may have nulls.
+pub(crate) fn structured<'py>(arrays: &Bound<'py, PyDict>) -> PyResult<Bound<'py, PyAny>> {
+    let py = arrays.py();
+    let numpy = py.import("numpy")?;
+    let masked = numpy.getattr("ma")?.getattr("MaskedArray")?;
+    if let Some((name, _)) = arrays.iter().find(|(_, array)| array.is_instance(&masked).unwrap_or(false)) {
+        return Err(PyValueError::new_err(format!(
+            "column {} has nulls, which a structured array cannot hold",
+            name
+        )));
+    }
+    let kwargs = PyDict::new(py);
+    kwargs.set_item("names", arrays.keys())?;
+    numpy.getattr("rec")?.call_method("fromarrays", (arrays.values(),), Some(&kwargs))
+}
+
+/// The NumPy array of one column, masked where it is null.
+fn column_to_numpy<'py>(numpy: &Bound<'py, PyModule>, name: &str, array: &ArrayRef) -> PyResult<Bound<'py, PyAny>> {
+    let py = numpy.py();
+    let values = match array.data_type() {
+        DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View | DataType::Dictionary(..) => {
+            let strings = cast(array, &DataType::LargeUtf8).map_err(|_| unsupported(name, array))?;
+            let items: Vec<Option<&str>> = strings.as_string::<i64>().iter().collect();
+            return objects(numpy, PyList::new(py, items)?);
+        }
+        DataType::Binary | DataType::LargeBinary | DataType::BinaryView | DataType::FixedSizeBinary(_) => {
+            let bytes = cast(array, &DataType::LargeBinary).map_err(|_| unsupported(name, array))?;
+            let items = bytes.as_binary::<i64>().iter().map(|b| b.map(|b| PyBytes::new(py, b)));
+            return objects(numpy, PyList::new(py, items)?);
+        }
+        DataType::Null => return objects(numpy, PyList::new(py, (0..array.len()).map(|_| py.None()))?),
+        DataType::Boolean => {
+            let bits: Vec<u8> = array.as_boolean().values().iter().map(u8::from).collect();
+            frombuffer(numpy, PyBytes::new(py, &bits).into_any(), "bool")?
+        }
+        // NumPy dates are 64-bit.
+        DataType::Date32 => {
+            let days = cast(array, &DataType::Int64).map_err(|_| unsupported(name, array))?;
+            primitive(numpy, &days, "datetime64[D]")?
+        }
+        data_type => {
+            let dtype = match data_type {
+                DataType::Int8 => "int8".to_string(),
+                DataType::Int16 => "int16".to_string(),
+                DataType::Int32 => "int32".to_string(),
+                DataType::Int64 => "int64".to_string(),
+                DataType::UInt8 => "uint8".to_string(),
+                DataType::UInt16 => "uint16".to_string(),
+                DataType::UInt32 => "uint32".to_string(),
+                DataType::UInt64 => "uint64".to_string(),
+                DataType::Float16 => "float16".to_string(),
+                DataType::Float32 => "float32".to_string(),
+                DataType::Float64 => "float64".to_string(),
+                DataType::Date64 => "datetime64[ms]".to_string(),
+                DataType::Timestamp(unit, _) => format!("datetime64[{}]", unit_code(unit)),
+                DataType::Duration(unit) => format!("timedelta64[{}]", unit_code(unit)),
+                _ => return Err(unsupported(name, array)),
+            };
+            primitive(numpy, array, &dtype)?
+        }
+    };
+    let Some(nulls) = array.nulls().filter(|nulls| nulls.null_count() > 0) else {
+        return Ok(values);
+    };
+    let mask: Vec<u8> = nulls.iter().map(|valid| u8::from(!valid)).collect();
+    let mask = frombuffer(numpy, PyBytes::new(py, &mask).into_any(), "bool")?;
+    let kwargs = PyDict::new(py);
+    kwargs.set_item("mask", mask)?;
+    numpy.getattr("ma")?.getattr("MaskedArray")?.call((values,), Some(&kwargs))
+}
+
+/// The array of a fixed-width column, sharing its values buffer.
+fn primitive<'py>(numpy: &Bound<'py, PyModule>, array: &ArrayRef, dtype: &str) -> PyResult<Bound<'py, PyAny>> {
+    let data = array.to_data();
+    let width = array.data_type().primitive_width().expect("fixed-width type");
+    let buffer = data.buffers()[0].slice_with_length(data.offset() * width, data.len() * width);
+    frombuffer(numpy, Bound::new(numpy.py(), ArrowBuffer(buffer))?.into_any(), dtype)
+}
+
+fn frombuffer<'py>(numpy: &Bound<'py, PyModule>, buffer: Bound<'py, PyAny>, dtype: &str) -> PyResult<Bound<'py, PyAny>> {
+    let kwargs = PyDict::new(numpy.py());
+    kwargs.set_item("dtype", dtype)?;
+    numpy.call_method("frombuffer", (buffer,), Some(&kwargs))
+}
+
+/// An object array of `items`, which keeps nulls as `None`.
+fn objects<'py>(numpy: &Bound<'py, PyModule>, items: Bound<'py, PyList>) -> PyResult<Bound<'py, PyAny>> {
+    let kwargs = PyDict::new(numpy.py());
+    kwargs.set_item("dtype", "object")?;
+    numpy.call_method("array", (items,), Some(&kwargs))
+}
+
+fn unit_code(unit: &TimeUnit) -> &'static str {
+    match unit {
+        TimeUnit::Second => "s",
+        TimeUnit::Millisecond => "ms",
+        TimeUnit::Microsecond => "us",
+        TimeUnit::Nanosecond => "ns",
+    }
+}
+
+fn unsupported(name: &str, array: &ArrayRef) -> PyErr {
+    PyValueError::new_err(format!(
+        "column {} of type {} has no NumPy array type; output=\"numpy\" takes flat columns of numbers, \
+         booleans, strings, bytes, datetimes and durations",
+        name,
+        array.data_type()
+    ))
+}
+
+/// An Arrow buffer exposed read-only through the buffer protocol, kept alive by
+/// the NumPy arrays viewing it.
+#[pyclass(frozen)]
+struct ArrowBuffer(Buffer);
+
+#[pymethods]
+impl ArrowBuffer {
+    unsafe fn __getbuffer__(slf: Bound<'_, Self>, view: *mut ffi::Py_buffer, flags: c_int) -> PyResult<()> {
+        if view.is_null() {
+            return Err(PyBufferError::new_err("view is null"));
+        }
+        if flags & ffi::PyBUF_WRITABLE == ffi::PyBUF_WRITABLE {
+            return Err(PyBufferError::new_err("Arrow buffers are read-only"));
+        }
+        let bytes = slf.get().0.as_slice();
+        (*view).buf = bytes.as_ptr() as *mut c_void;
+        (*view).len = bytes.len() as isize;
+        (*view).readonly = 1;
+        (*view).itemsize = 1;
+        (*view).format = match flags & ffi::PyBUF_FORMAT == ffi::PyBUF_FORMAT {
+            true => c"B".as_ptr() as *mut _,
+            false => std::ptr::null_mut(),
+        };
+        (*view).ndim = 1;
+        (*view).shape = match flags & ffi::PyBUF_ND == ffi::PyBUF_ND {
+            true => &mut (*view).len,
+            false => std::ptr::null_mut(),
+        };
+        (*view).strides = match flags & ffi::PyBUF_STRIDES == ffi::PyBUF_STRIDES {
+            true => &mut (*view).itemsize,
+            false => std::ptr::null_mut(),
+        };
+        (*view).suboffsets = std::ptr::null_mut();
+        (*view).internal = std::ptr::null_mut();
+        (*view).obj = slf.into_any().into_ptr();
+        Ok(())
+    }
+
+    unsafe fn __releasebuffer__(&self, _view: *mut ffi::Py_buffer) {}
+}
@@ -0,0 +1,67 @@
+//! Python-facing result objects, selected by the `output` option.
+//!
+//! By default results are returned as objects implementing the Arrow PyCapsule
+//! interface (`__arrow_c_array__` / `__arrow_c_stream__`), which pyarrow, polars,
+//! duckdb and nanoarrow all consume without copying. `output="pyarrow"`,
+//! `output="reader"` and `output="table"` return pyarrow objects instead and
+//! require pyarrow. `output="numpy"` returns a dict of NumPy arrays.
+
+use arrow::array::{RecordBatch, RecordBatchReader};
+use arrow::datatypes::SchemaRef;
+use arrow::pyarrow::{IntoPyArrow, ToPyArrow};
+use pyo3::prelude::*;
+use pyo3::types::PyList;
+use pyo3_arrow::{PyRecordBatch, PyRecordBatchReader, PyTable};
+
+use crate::{numpy, ConvertOptions, OutputMode};
+
+/// Return a single converted batch.
+pub(crate) fn emit_batch(py: Python, batch: RecordBatch, opts: &ConvertOptions) -> PyResult<PyObject> {
+    opts.warn_losses(py)?;
+    opts.report_rows(py)?;
+    match opts.output {
+        OutputMode::Batch | OutputMode::Stream => {
+            Ok(PyRecordBatch::new(batch).into_pyobject(py)?.into_any().unbind())
+        }
+        OutputMode::PyArrow | OutputMode::Reader => batch.to_pyarrow(py),
+        OutputMode::Table => {
+            let schema = batch.schema();
+            emit_batches(py, vec![batch], schema, opts)
+        }
+        OutputMode::Numpy => numpy::batch_to_numpy(py, &batch),
+    }
+}
+
+/// Return a result converted in several batches: the chunks of a table for
+/// `output="table"`, otherwise a list of batches.
+pub(crate) fn emit_batches(
+    py: Python,
+    batches: Vec<RecordBatch>,
+    schema: SchemaRef,
+    opts: &ConvertOptions,
+) -> PyResult<PyObject> {
+    opts.warn_losses(py)?;
+    opts.report_rows(py)?;
+    if opts.output == OutputMode::Table {
+        return PyTable::try_new(batches, schema)?.to_pyarrow(py);
+    }
+    let batches = batches
+        .into_iter()
+        .map(|batch| emit_batch(py, batch, opts))
+        .collect::<PyResult<Vec<_>>>()?;
+    Ok(PyList::new(py, batches)?.into_any().unbind())
+}
+
+/// Return a lazily converted result for the streaming outputs.
+pub(crate) fn emit_reader(
+    py: Python,
+    reader: Box<dyn RecordBatchReader + Send>,
+    opts: &ConvertOptions,
+) -> PyResult<PyObject> {
+    opts.warn_losses(py)?;
+    opts.report_rows(py)?;
+    match opts.output {
+        OutputMode::Reader => reader.into_pyarrow(py),
+        _ => Ok(PyRecordBatchReader::new(reader).into_pyobject(py)?.into_any().unbind()),
+    }
+}
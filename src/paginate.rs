@@ -0,0 +1,267 @@
+//! `stream_query`, the result of a large query fetched and converted a page at a time.
+
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use regex::Regex;
+
+#[cfg(feature = "client")]
+use crate::client::Connection;
+#[cfg(feature = "client")]
+use crate::pool::ConnectionPool;
+use crate::{ConvertOptions, OutputMode};
+
+/// Default number of rows per page.
+const DEFAULT_PAGE_SIZE: usize = 50_000;
+
+/// The query variables holding the bounds of a page.
+const LIMIT_VAR: &str = "page_limit";
+const START_VAR: &str = "page_start";
+
+/// Run the `SELECT` statement `sql` a page of `page_size` rows at a time and
+/// iterate over the pages, each converted to one batch as `cbor_to_arrow`
+/// converts it with the keyword options.
+///
+/// `source` is a `Connection` or `ConnectionPool` to query, or a callable
+/// `source(sql, params)` returning the CBOR response of the query from another
+/// client. Each page is `sql` with `LIMIT $page_limit START $page_start`
+/// appended, so `sql` cannot have a `LIMIT` or `START` clause of its own, and
+/// should have an `ORDER BY` when the order of the rows is not stable. Pages are
+/// fetched one after the other as the iteration goes, until one comes back
+/// short; empty pages are not yielded.
+#[pyfunction]
+#[pyo3(signature = (source, sql, page_size=DEFAULT_PAGE_SIZE, params=None, **options))]
+pub(crate) fn stream_query(
+    py: Python,
+    source: PyObject,
+    sql: &str,
+    page_size: usize,
+    params: Option<&Bound<'_, PyDict>>,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<QueryPages> {
+    if page_size == 0 {
+        return Err(PyValueError::new_err("page_size must be positive"));
+    }
+    let bound = source.bind(py);
+    let is_client = is_client(bound);
+    if !is_client && !bound.is_callable() {
+        return Err(PyTypeError::new_err("source must be a Connection, a ConnectionPool or a callable"));
+    }
+    let opts = ConvertOptions::from_kwargs(options)?;
+    if !matches!(opts.output, OutputMode::Batch | OutputMode::PyArrow) || opts.max_rows_per_batch.is_some() {
+        return Err(PyValueError::new_err(
+            "stream_query yields one batch per page: output must be 'batch' or 'pyarrow', without max_rows_per_batch",
+        ));
+    }
+    let sql = sql.trim_end().trim_end_matches(';').trim_end();
+    let paged = Regex::new(r"(?i)\b(LIMIT(\s+BY)?|START(\s+AT)?)\s+[$\d(]").unwrap();
+    if paged.is_match(sql) {
+        return Err(PyValueError::new_err("sql already has a LIMIT or START clause"));
+    }
+    let vars = match params {
+        Some(params) => params.copy()?,
+        None => PyDict::new(py),
+    };
+    if vars.contains(LIMIT_VAR)? || vars.contains(START_VAR)? {
+        return Err(PyValueError::new_err(format!("params cannot hold {} or {}", LIMIT_VAR, START_VAR)));
+    }
+    vars.set_item(LIMIT_VAR, page_size)?;
+    Ok(QueryPages {
+        source,
+        is_client,
+        sql: format!("{} LIMIT ${} START ${}", sql, LIMIT_VAR, START_VAR),
+        page_size,
+        params: vars.unbind(),
+        options: options.map(|options| options.clone().unbind()),
+        start: 0,
+        done: false,
+    })
+}
+
+/// Whether `source` is a client of the accelerator's own.
+#[cfg(feature = "client")]
+fn is_client(source: &Bound<'_, PyAny>) -> bool {
+    source.is_instance_of::<Connection>() || source.is_instance_of::<ConnectionPool>()
+}
+
+#[cfg(not(feature = "client"))]
+fn is_client(_source: &Bound<'_, PyAny>) -> bool {
+    false
+}
+
+/// The pages of a `stream_query`, fetched as they are iterated over.
+#[pyclass]
+pub(crate) struct QueryPages {
+    source: PyObject,
+    is_client: bool,
+    sql: String,
+    page_size: usize,
+    params: Py<PyDict>,
+    options: Option<Py<PyDict>>,
+    /// The row the next page starts at.
+    start: usize,
+    done: bool,
+}
+
+#[pymethods]
+impl QueryPages {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python) -> PyResult<Option<PyObject>> {
+        if self.done {
+            return Ok(None);
+        }
+        let params = self.params.bind(py);
+        params.set_item(START_VAR, self.start)?;
+        let options = self.options.as_ref().map(|options| options.bind(py));
+        let source = self.source.bind(py);
+        let page = match self.is_client {
+            true => source.call_method("query", (self.sql.as_str(), params), options)?,
+            false => {
+                let response = source.call1((self.sql.as_str(), params))?;
+                crate::cbor_to_arrow(py, response.extract()?, 0, options)?.into_bound(py)
+            }
+        };
+        // A statement without records converts to `None`.
+        let rows: usize = match page.is_none() {
+            true => 0,
+            false => page.getattr("num_rows")?.extract()?,
+        };
+        self.start += rows;
+        self.done = rows < self.page_size;
+        Ok((rows > 0).then(|| page.unbind()))
+    }
+
+    /// The number of rows fetched so far.
+    #[getter]
+    fn rows(&self) -> usize {
+        self.start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use arrow::array::AsArray;
+    use arrow::datatypes::Int64Type;
+    use cbor4ii::core::Value;
+    use pyo3::types::{PyBytes, PyCFunction, PyTuple};
+    use pyo3_arrow::PyRecordBatch;
+
+    use super::*;
+    use crate::encode::encode;
+
+    /// The SQL and the page bounds of each call of a source.
+    type Calls = Arc<Mutex<Vec<(String, usize, usize)>>>;
+
+    fn text(value: &str) -> Value {
+        Value::Text(value.to_string())
+    }
+
+    /// A source of the query of `rows` rows numbered `n` from 0, answering
+    /// each call with the rows of its page.
+    fn source(py: Python, rows: usize) -> (Bound<PyAny>, Calls) {
+        let calls = Calls::default();
+        let logged = calls.clone();
+        let source = PyCFunction::new_closure(py, None, None, move |args: &Bound<PyTuple>, _kwargs| -> PyResult<_> {
+            let (sql, params): (String, Bound<PyDict>) = args.extract()?;
+            let bound = |name: &str| -> PyResult<usize> { params.get_item(name)?.unwrap().extract() };
+            let (start, limit) = (bound(START_VAR)?, bound(LIMIT_VAR)?);
+            logged.lock().unwrap().push((sql, start, limit));
+            let records = (start..rows.min(start + limit))
+                .map(|n| Value::Map(vec![(text("n"), Value::Integer(n as i128))]))
+                .collect();
+            let statement = vec![(text("status"), text("OK")), (text("result"), Value::Array(records))];
+            let statements = Value::Array(vec![Value::Map(statement)]);
+            let response = vec![(text("id"), Value::Integer(1)), (text("result"), statements)];
+            Ok(PyBytes::new(args.py(), &encode(&Value::Map(response))).unbind())
+        });
+        (source.unwrap().into_any(), calls)
+    }
+
+    fn stream<'py>(source: &Bound<'py, PyAny>, sql: &str, options: &Bound<'py, PyDict>) -> PyResult<Bound<'py, PyAny>> {
+        let py = source.py();
+        let kwargs = options.copy()?;
+        kwargs.set_item("output", "batch")?;
+        wrap_pyfunction!(stream_query, py)?.call((source, sql), Some(&kwargs))
+    }
+
+    /// The `n` column of each page.
+    fn pages(pages: &Bound<PyAny>) -> Vec<Vec<i64>> {
+        pages
+            .try_iter()
+            .unwrap()
+            .map(|page| {
+                let batch = page.unwrap().extract::<PyRecordBatch>().unwrap().into_inner();
+                batch.column_by_name("n").unwrap().as_primitive::<Int64Type>().values().to_vec()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn queries_are_fetched_a_page_at_a_time() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let (five, calls) = source(py, 5);
+            let options = PyDict::new(py);
+            options.set_item("page_size", 2).unwrap();
+            let params = PyDict::new(py);
+            params.set_item("min", 0).unwrap();
+            options.set_item("params", &params).unwrap();
+            let query = stream(&five, "SELECT * FROM person WHERE n >= $min ORDER BY n;", &options).unwrap();
+            assert_eq!(pages(&query), [vec![0, 1], vec![2, 3], vec![4]]);
+            assert_eq!(query.getattr("rows").unwrap().extract::<usize>().unwrap(), 5);
+            // The caller's params are left as they were.
+            assert_eq!(params.len(), 1);
+            let sql = "SELECT * FROM person WHERE n >= $min ORDER BY n LIMIT $page_limit START $page_start";
+            let expected: Vec<_> = [0, 2, 4].map(|start| (sql.to_string(), start, 2)).into();
+            assert_eq!(*calls.lock().unwrap(), expected);
+
+            // A last page that is empty ends the iteration without being yielded.
+            let (four, calls) = source(py, 4);
+            let query = stream(&four, "SELECT * FROM person", &options).unwrap();
+            assert_eq!(pages(&query), [vec![0, 1], vec![2, 3]]);
+            assert_eq!(calls.lock().unwrap().len(), 3);
+        });
+    }
+
+    #[test]
+    fn bad_queries_raise() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let (four, calls) = source(py, 4);
+            let message = |sql: &str, option: Option<(&str, Bound<PyAny>)>| {
+                let options = PyDict::new(py);
+                if let Some((name, value)) = option {
+                    options.set_item(name, value).unwrap();
+                }
+                stream(&four, sql, &options).unwrap_err().to_string()
+            };
+            let sql = "SELECT * FROM person";
+            let zero = 0i64.into_pyobject(py).unwrap().into_any();
+            assert_eq!(message(sql, Some(("page_size", zero))), "ValueError: page_size must be positive");
+            for paged in ["SELECT * FROM person LIMIT 10", "SELECT * FROM person START $at"] {
+                assert_eq!(message(paged, None), "ValueError: sql already has a LIMIT or START clause");
+            }
+            let params = PyDict::new(py);
+            params.set_item(START_VAR, 1).unwrap();
+            let err = message(sql, Some(("params", params.into_any())));
+            assert_eq!(err, "ValueError: params cannot hold page_limit or page_start");
+            let one = 1i64.into_pyobject(py).unwrap().into_any();
+            let err = message(sql, Some(("max_rows_per_batch", one)));
+            assert!(err.starts_with("ValueError: stream_query yields one batch per page"), "{}", err);
+
+            let stream_query = wrap_pyfunction!(stream_query, py).unwrap();
+            let options = PyDict::new(py);
+            options.set_item("output", "stream").unwrap();
+            let err = stream_query.call((&four, sql), Some(&options)).unwrap_err().to_string();
+            assert!(err.starts_with("ValueError: stream_query yields one batch per page"), "{}", err);
+            let err = stream_query.call1(("person", sql)).unwrap_err();
+            assert_eq!(err.to_string(), "TypeError: source must be a Connection, a ConnectionPool or a callable");
+            assert!(calls.lock().unwrap().is_empty());
+        });
+    }
+}
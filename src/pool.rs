@@ -0,0 +1,404 @@
+//! `ConnectionPool`, connections of the embedded client shared between threads.
+//!
+//! A pool opens connections as they are needed, up to its maximum, and keeps
+//! them signed in and switched to its namespace and database. Checking one out
+//! hands a connection to one caller at a time; checking it in makes it
+//! available to the next. Connections that were lost are dropped for new ones,
+//! and those idle for longer than the health check interval are pinged first.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use cbor4ii::core::Value;
+use pyo3::exceptions::{PyConnectionError, PyTimeoutError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyTuple};
+use rustls::ClientConfig;
+use tokio::sync::Notify;
+
+use crate::aio::{block_on, future_into_py};
+use crate::client::{signin_kwargs, Connection, Policy, Query};
+use crate::seconds;
+use crate::tls;
+
+/// A pool of `Connection`s to the SurrealDB at `url`.
+///
+/// ```python
+/// pool = ConnectionPool("ws://localhost:8000", max_size=8,
+///                       credentials={"username": "root", "password": "root"},
+///                       namespace="test", database="test")
+/// with pool.connection() as conn:
+///     table = conn.query("SELECT * FROM person")
+/// table = pool.query("SELECT * FROM person")  # the same, in one call
+/// ```
+///
+/// `min_size` connections are opened up front and more as they are needed, up
+/// to `max_size`. `credentials` are the keyword arguments of
+/// `Connection.signin`, given to each connection opened. A connection idle for
+/// longer than `health_check_interval` seconds is pinged before it is handed
+/// out. Waiting for a connection releases the GIL. `connect_timeout`,
+/// `timeout`, `retries`, `backoff` and the TLS options `ca_file`, `cert_file`
+/// and `key_file` are given to each connection, as to `Connection.connect`.
+/// `checkout_async` and `query_async` are the awaitable variants of `checkout`
+/// and `query`, which wait for a connection without holding up the running
+/// asyncio loop.
+#[pyclass(frozen)]
+pub(crate) struct ConnectionPool {
+    url: String,
+    policy: Policy,
+    tls: Option<Arc<ClientConfig>>,
+    max_size: usize,
+    /// The parameters of `signin` for each connection opened.
+    credentials: Option<Value>,
+    namespace: Option<String>,
+    database: Option<String>,
+    health_check_interval: Duration,
+    state: Mutex<PoolState>,
+    /// Notified when a connection is checked in or dropped, or the pool closed.
+    available: Notify,
+}
+
+struct PoolState {
+    /// Connections checked in, with when they were.
+    idle: Vec<(Py<Connection>, Instant)>,
+    /// The connections checked out, by address.
+    checked_out: HashSet<usize>,
+    /// Connections idle, checked out or being opened.
+    size: usize,
+    closed: bool,
+}
+
+#[pymethods]
+impl ConnectionPool {
+    #[new]
+    #[pyo3(signature = (
+        url, min_size=1, max_size=10, credentials=None, namespace=None, database=None, health_check_interval=30.0,
+        connect_timeout=None, timeout=None, retries=0, backoff=0.1, ca_file=None, cert_file=None, key_file=None
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        py: Python,
+        url: String,
+        min_size: usize,
+        max_size: usize,
+        credentials: Option<&Bound<'_, PyDict>>,
+        namespace: Option<String>,
+        database: Option<String>,
+        health_check_interval: f64,
+        connect_timeout: Option<f64>,
+        timeout: Option<f64>,
+        retries: u32,
+        backoff: f64,
+        ca_file: Option<&str>,
+        cert_file: Option<&str>,
+        key_file: Option<&str>,
+    ) -> PyResult<Self> {
+        if max_size == 0 || min_size > max_size {
+            return Err(PyValueError::new_err("the pool needs 0 <= min_size <= max_size and max_size >= 1"));
+        }
+        if namespace.is_some() != database.is_some() {
+            return Err(PyValueError::new_err("give both namespace and database, or neither"));
+        }
+        let pool = ConnectionPool {
+            url,
+            policy: Policy::new(connect_timeout, timeout, retries, backoff)?,
+            tls: tls::options(ca_file, cert_file, key_file)?,
+            max_size,
+            credentials: credentials.map(signin_kwargs).transpose()?,
+            namespace,
+            database,
+            health_check_interval: seconds("health_check_interval", health_check_interval)?,
+            state: Mutex::new(PoolState { idle: Vec::new(), checked_out: HashSet::new(), size: min_size, closed: false }),
+            available: Notify::new(),
+        };
+        let idle = block_on(py, async {
+            let mut idle = Vec::with_capacity(min_size);
+            for _ in 0..min_size {
+                idle.push((pool.open().await?, Instant::now()));
+            }
+            Ok::<_, PyErr>(idle)
+        })?;
+        pool.state.lock().unwrap().idle = idle;
+        Ok(pool)
+    }
+
+    /// Take a connection for the caller's use until it is checked in, waiting up
+    /// to `timeout` seconds (without limit if `None`) while all are in use.
+    #[pyo3(signature = (timeout=None))]
+    fn checkout(&self, py: Python, timeout: Option<f64>) -> PyResult<Py<Connection>> {
+        block_on(py, self.check_out(timeout))
+    }
+
+    /// `checkout`, awaitable.
+    #[pyo3(signature = (timeout=None))]
+    fn checkout_async<'py>(slf: &Bound<'py, Self>, timeout: Option<f64>) -> PyResult<Bound<'py, PyAny>> {
+        let pool = slf.clone().unbind();
+        future_into_py(slf.py(), async move { pool.get().check_out(timeout).await })
+    }
+
+    /// Return a connection taken with `checkout`. A closed connection, or any
+    /// once the pool is closed, is dropped from the pool.
+    fn checkin(&self, py: Python, conn: Py<Connection>) -> PyResult<()> {
+        py.allow_threads(|| self.check_in(conn))
+    }
+
+    /// A context manager checking a connection out on entry and in on exit.
+    #[pyo3(signature = (timeout=None))]
+    fn connection(slf: Py<Self>, timeout: Option<f64>) -> PoolCheckout {
+        PoolCheckout { pool: slf, timeout, conn: Mutex::new(None) }
+    }
+
+    /// Run `Connection.query` on a connection checked out for the call.
+    #[pyo3(signature = (sql, params=None, statement=0, idempotent=None, **options))]
+    fn query(
+        &self,
+        py: Python,
+        sql: &str,
+        params: Option<&Bound<'_, PyDict>>,
+        statement: isize,
+        idempotent: Option<bool>,
+        options: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<PyObject> {
+        let query = Query::new(sql, params, statement, idempotent, options)?;
+        block_on(py, self.run(query))
+    }
+
+    /// `query`, awaitable.
+    #[pyo3(signature = (sql, params=None, statement=0, idempotent=None, **options))]
+    fn query_async<'py>(
+        slf: &Bound<'py, Self>,
+        sql: &str,
+        params: Option<&Bound<'py, PyDict>>,
+        statement: isize,
+        idempotent: Option<bool>,
+        options: Option<&Bound<'py, PyDict>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let query = Query::new(sql, params, statement, idempotent, options)?;
+        let pool = slf.clone().unbind();
+        future_into_py(slf.py(), async move { pool.get().run(query).await })
+    }
+
+    /// Close the idle connections, and each checked out one as it is checked in.
+    fn close(&self, py: Python) {
+        let idle = {
+            let mut state = self.state.lock().unwrap();
+            state.closed = true;
+            state.size -= state.idle.len();
+            std::mem::take(&mut state.idle)
+        };
+        py.allow_threads(|| {
+            for (conn, _) in idle {
+                conn.get().shut();
+            }
+        });
+        self.available.notify_waiters();
+    }
+
+    /// The number of connections open or being opened.
+    #[getter]
+    fn size(&self) -> usize {
+        self.state.lock().unwrap().size
+    }
+
+    /// The number of connections waiting to be checked out.
+    #[getter]
+    fn idle(&self) -> usize {
+        self.state.lock().unwrap().idle.len()
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    #[pyo3(signature = (*_exc))]
+    fn __exit__(&self, py: Python, _exc: &Bound<'_, PyTuple>) {
+        self.close(py);
+    }
+}
+
+enum Taken {
+    Closed,
+    TimedOut,
+}
+
+impl ConnectionPool {
+    /// `checkout`, awaitable.
+    async fn check_out(&self, timeout: Option<f64>) -> PyResult<Py<Connection>> {
+        let deadline = match timeout {
+            Some(timeout) => Some(Instant::now() + seconds("timeout", timeout)?),
+            None => None,
+        };
+        loop {
+            let taken = self.take(deadline).await.map_err(|e| match e {
+                Taken::Closed => PyConnectionError::new_err("the pool is closed"),
+                Taken::TimedOut => PyTimeoutError::new_err(format!(
+                    "no connection became available within {} s",
+                    timeout.unwrap_or_default()
+                )),
+            })?;
+            // Given back, with its connection closed, if not handed out: on an error, or a cancelled task.
+            let mut slot = Slot { pool: self, conn: None };
+            match taken {
+                Some((conn, since)) => {
+                    let conn = slot.conn.insert(conn).get();
+                    let healthy = !conn.is_closed()
+                        && (since.elapsed() < self.health_check_interval || conn.ping_server().await.is_ok());
+                    if !healthy {
+                        continue;
+                    }
+                }
+                None => slot.conn = Some(self.open().await?),
+            }
+            return Ok(slot.hand_out());
+        }
+    }
+
+    fn check_in(&self, conn: Py<Connection>) -> PyResult<()> {
+        // Not with the pool locked: it waits for the connection's own locks.
+        let lost = conn.get().is_closed();
+        let mut state = self.state.lock().unwrap();
+        if !state.checked_out.remove(&(conn.as_ptr() as usize)) {
+            return Err(PyValueError::new_err("the connection was not checked out of this pool"));
+        }
+        let dropped = state.closed || lost;
+        if dropped {
+            state.size -= 1;
+            drop(state);
+            conn.get().shut();
+        } else {
+            state.idle.push((conn, Instant::now()));
+            drop(state);
+        }
+        self.available.notify_one();
+        Ok(())
+    }
+
+    /// Run `query` on a connection checked out for it.
+    async fn run(&self, query: Query) -> PyResult<PyObject> {
+        let conn = CheckedOut { pool: self, conn: Some(self.check_out(None).await?) };
+        query.run(conn.get()).await
+    }
+
+    /// An idle connection, or `None` when one may be opened, counted in `size`
+    /// already; waits while the pool is at its maximum and none is idle.
+    async fn take(&self, deadline: Option<Instant>) -> Result<Option<(Py<Connection>, Instant)>, Taken> {
+        loop {
+            // Created first, so a notification while the pool is looked at is not missed.
+            let available = self.available.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.closed {
+                    return Err(Taken::Closed);
+                }
+                if let Some(idle) = state.idle.pop() {
+                    return Ok(Some(idle));
+                }
+                if state.size < self.max_size {
+                    state.size += 1;
+                    return Ok(None);
+                }
+            }
+            match deadline {
+                Some(deadline) => {
+                    let deadline = tokio::time::Instant::from_std(deadline);
+                    tokio::time::timeout_at(deadline, available).await.map_err(|_| Taken::TimedOut)?;
+                }
+                None => available.await,
+            }
+        }
+    }
+
+    /// Forget a connection counted in `size`, making room for another.
+    fn discard(&self) {
+        self.state.lock().unwrap().size -= 1;
+        self.available.notify_one();
+    }
+
+    /// Open a connection, signed in and switched to the pool's namespace and database.
+    async fn open(&self) -> PyResult<Py<Connection>> {
+        let conn = Connection::connect_to(&self.url, self.policy, self.tls.clone()).await?;
+        if let Some(credentials) = &self.credentials {
+            conn.sign_in(credentials.clone()).await?;
+        }
+        if let (Some(namespace), Some(database)) = (&self.namespace, &self.database) {
+            conn.use_database(namespace.clone(), database.clone()).await?;
+        }
+        Python::with_gil(|py| Py::new(py, conn))
+    }
+}
+
+/// A place in a pool counted in `size`, with the connection being checked out
+/// for it. Dropped before it is handed out, as when the task checking it out
+/// is cancelled or fails, it closes the connection and makes room for another.
+struct Slot<'a> {
+    pool: &'a ConnectionPool,
+    conn: Option<Py<Connection>>,
+}
+
+impl Slot<'_> {
+    fn hand_out(mut self) -> Py<Connection> {
+        let conn = self.conn.take().expect("a connection was checked out");
+        self.pool.state.lock().unwrap().checked_out.insert(conn.as_ptr() as usize);
+        std::mem::forget(self);
+        conn
+    }
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            conn.get().shut();
+        }
+        self.pool.discard();
+    }
+}
+
+/// A connection checked out of a pool for one request, checked in when
+/// dropped, also when the task running the request is cancelled.
+struct CheckedOut<'a> {
+    pool: &'a ConnectionPool,
+    conn: Option<Py<Connection>>,
+}
+
+impl CheckedOut<'_> {
+    fn get(&self) -> &Connection {
+        self.conn.as_ref().expect("the connection is checked out").get()
+    }
+}
+
+impl Drop for CheckedOut<'_> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            // Checked out of this pool, so it is taken back.
+            let _ = self.pool.check_in(conn);
+        }
+    }
+}
+
+/// What `ConnectionPool.connection` returns: checks a connection out on entry
+/// and in on exit.
+#[pyclass(frozen)]
+pub(crate) struct PoolCheckout {
+    pool: Py<ConnectionPool>,
+    timeout: Option<f64>,
+    conn: Mutex<Option<Py<Connection>>>,
+}
+
+#[pymethods]
+impl PoolCheckout {
+    fn __enter__(&self, py: Python) -> PyResult<Py<Connection>> {
+        let conn = self.pool.get().checkout(py, self.timeout)?;
+        *self.conn.lock().unwrap() = Some(conn.clone_ref(py));
+        Ok(conn)
+    }
+
+    #[pyo3(signature = (*_exc))]
+    fn __exit__(&self, py: Python, _exc: &Bound<'_, PyTuple>) -> PyResult<()> {
+        let conn = self.conn.lock().unwrap().take();
+        match conn {
+            Some(conn) => self.pool.get().checkin(py, conn),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,989 @@
+//! Pull-based scanning of CBOR responses.
+//!
+//! Decoding a whole response with `Value::decode` materializes every record at
+//! once, which takes several times the size of the payload. The scanner here
+//! walks the RPC envelope item by item and, for large record arrays, only notes
+//! where each record starts. The records are decoded later in bounded chunks,
+//! so they are never all held in memory together.
+//!
+//! Streamed responses may use indefinite-length arrays, maps and strings.
+//! cbor4ii leaves the break that ends an indefinite-length string unread, so
+//! items holding one are decoded here instead (see `Scanner::decode`).
+//!
+//! Errors name the byte offset and the path of the value they are about, e.g.
+//! `result[0].result[1234].meta`. The path is found by walking the input again
+//! from its start (see `path_at`), which only failing conversions pay for.
+
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use cbor4ii::core::{dec::Decode, utils::SliceReader, Value};
+use pyo3::prelude::*;
+
+use crate::errors::CborDecodeError;
+use crate::select::{self, Member, Selection};
+use crate::tags;
+use crate::{key_string, CborInput, ConvertOptions, MapKeys, RecordsAt};
+
+/// Record arrays longer than this are left encoded and decoded in chunks.
+pub(crate) const DECODE_CHUNK_ROWS: usize = 65_536;
+
+/// Nesting depth at which scanning gives up, matching cbor4ii's decoder.
+const MAX_DEPTH: usize = 256;
+
+/// A response with its large record arrays left encoded.
+pub(crate) struct Payload {
+    /// Shared by the payloads of one CBOR sequence.
+    input: Arc<Input>,
+    /// The decoded response. Record arrays left encoded are empty here.
+    pub(crate) root: Value,
+    records: Vec<Records>,
+    /// Applied to records as they are decoded.
+    select: Option<Selection>,
+}
+
+/// Where the records of one record array are in the input.
+struct Records {
+    at: RecordsAt,
+    /// The start offset of each record followed by the end of the last.
+    offsets: Vec<usize>,
+    /// Whether any record holds an indefinite-length string.
+    chunked: bool,
+    /// Whether the records were left encoded.
+    encoded: bool,
+}
+
+enum Input {
+    Buffer(CborInput),
+    /// A copy of a writable buffer, which could otherwise change while the GIL is released.
+    Owned(Vec<u8>),
+}
+
+impl Input {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Input::Buffer(data) => data.as_bytes(),
+            Input::Owned(bytes) => bytes,
+        }
+    }
+}
+
+impl Payload {
+    /// Scan `data`. With `envelope` the records are looked for in the statement
+    /// results of an RPC response, otherwise the root is the record array. Data
+    /// holding more than one item is refused; see `load_sequence`.
+    pub(crate) fn load(py: Python, data: CborInput, envelope: bool, opts: &ConvertOptions) -> PyResult<Self> {
+        let mut items = Self::load_sequence(py, data, envelope, opts)?;
+        match items.len() {
+            1 => Ok(items.remove(0)),
+            n => Err(CborDecodeError::new_err(format!(
+                "CBOR input is a sequence of {} items; convert it with cbor_sequence_to_arrow",
+                n
+            ))),
+        }
+    }
+
+    /// Scan each item of `data`, a CBOR sequence (RFC 8742) of one or more items
+    /// written back to back.
+    pub(crate) fn load_sequence(py: Python, data: CborInput, envelope: bool, opts: &ConvertOptions) -> PyResult<Vec<Self>> {
+        let input = if data.0.readonly() { Input::Buffer(data) } else { Input::Owned(data.as_bytes().to_vec()) };
+        Self::scan(py, Arc::new(input), envelope, opts)
+    }
+
+    /// `load` for a response received in Rust, such as by `Connection`.
+    pub(crate) fn load_owned(py: Python, bytes: Vec<u8>, envelope: bool, opts: &ConvertOptions) -> PyResult<Self> {
+        let mut items = Self::scan(py, Arc::new(Input::Owned(bytes)), envelope, opts)?;
+        match items.len() {
+            1 => Ok(items.remove(0)),
+            n => Err(CborDecodeError::new_err(format!("response is a sequence of {} CBOR items", n))),
+        }
+    }
+
+    fn scan(py: Python, input: Arc<Input>, envelope: bool, opts: &ConvertOptions) -> PyResult<Vec<Self>> {
+        let bytes = input.bytes();
+        let select = opts.select.clone();
+        let items = py.allow_threads(|| {
+            let mut scanner = Scanner::new(bytes, select.as_ref());
+            let mut items = Vec::new();
+            loop {
+                scanner.self_described();
+                let mut records = Vec::new();
+                let root = if envelope {
+                    scanner.envelope(&mut records)?
+                } else {
+                    scanner.records(RecordsAt::Bare, &mut records)?
+                };
+                items.push((root, records));
+                if scanner.pos == bytes.len() {
+                    return Ok::<_, PyErr>(items);
+                }
+            }
+        })?;
+        Ok(items
+            .into_iter()
+            .map(|(root, records)| Payload { input: input.clone(), root, records, select: select.clone() })
+            .collect())
+    }
+
+    fn bytes(&self) -> &[u8] {
+        self.input.bytes()
+    }
+
+    /// The records at `at` if they were left encoded.
+    pub(crate) fn encoded(&self, at: RecordsAt) -> Option<Rows<'_>> {
+        self.records.iter().find(|records| records.at == at && records.encoded).map(|records| {
+            Rows::Encoded(Spans { bytes: self.bytes(), offsets: &records.offsets }, self.select.as_ref(), records.chunked)
+        })
+    }
+
+    /// Where the records at `at` are in the input, if they were a record array.
+    pub(crate) fn spans(&self, at: RecordsAt) -> Option<Spans<'_>> {
+        self.records
+            .iter()
+            .find(|records| records.at == at)
+            .map(|records| Spans { bytes: self.bytes(), offsets: &records.offsets })
+    }
+}
+
+/// Where a run of records is in the input: the input and the start offset of
+/// each record followed by the end of the last.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Spans<'a> {
+    bytes: &'a [u8],
+    offsets: &'a [usize],
+}
+
+impl<'a> Spans<'a> {
+    fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    pub(crate) fn slice(&self, start: usize, end: usize) -> Spans<'a> {
+        Spans { bytes: self.bytes, offsets: &self.offsets[start..=end] }
+    }
+
+    /// The path and byte offset of the value at `steps` inside record `row`, or
+    /// of the deepest value on the way to it that is in the input.
+    pub(crate) fn locate(&self, row: usize, steps: &[Step], keys: MapKeys) -> String {
+        let mut scanner = Scanner::walker(self.bytes);
+        scanner.pos = self.offsets[row];
+        let offset = scanner.find(steps, keys);
+        format!("{} at offset {}", path_at(self.bytes, offset), offset)
+    }
+}
+
+/// One step of the path to a value: an object member or an array element.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Step {
+    Member(String),
+    Index(usize),
+}
+
+/// Append `step` to `path`, e.g. `.meta` or `[2]`.
+pub(crate) fn push_step(path: &mut String, step: &Step) {
+    match step {
+        Step::Member(name) => {
+            select::push_member(path, name);
+        }
+        Step::Index(i) => path.push_str(&format!("[{}]", i)),
+    }
+}
+
+/// The path of the value at `offset` from the start of the input; for a
+/// sequence of several items it starts with the item's index.
+fn path_at(bytes: &[u8], offset: usize) -> String {
+    let mut scanner = Scanner::walker(bytes);
+    let mut path = String::new();
+    for index in 0.. {
+        let start = scanner.pos;
+        scanner.self_described();
+        let complete = scanner.skip(0).is_ok();
+        let more = complete && scanner.pos < bytes.len();
+        if !complete || offset < scanner.pos || !more {
+            if index > 0 || more {
+                push_step(&mut path, &Step::Index(index));
+            }
+            scanner.pos = start;
+            scanner.self_described();
+            scanner.descend(offset, &mut path, 0);
+            break;
+        }
+    }
+    path
+}
+
+/// The records of one result, decoded or still encoded.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Rows<'a> {
+    /// The records and where they are in the input, if known.
+    Decoded(&'a [Value], Option<Spans<'a>>),
+    /// Where the records are, the selection to decode them with, and whether any
+    /// record holds an indefinite-length string.
+    Encoded(Spans<'a>, Option<&'a Selection>, bool),
+}
+
+impl<'a> Rows<'a> {
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Rows::Decoded(values, _) => values.len(),
+            Rows::Encoded(spans, ..) => spans.len(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn slice(&self, start: usize, end: usize) -> Rows<'a> {
+        match self {
+            Rows::Decoded(values, spans) => {
+                Rows::Decoded(&values[start..end], spans.map(|spans| spans.slice(start, end)))
+            }
+            Rows::Encoded(spans, select, chunked) => Rows::Encoded(spans.slice(start, end), *select, *chunked),
+        }
+    }
+
+    /// Where the records are in the input, if known.
+    pub(crate) fn spans(&self) -> Option<Spans<'a>> {
+        match self {
+            Rows::Decoded(_, spans) => *spans,
+            Rows::Encoded(spans, ..) => Some(*spans),
+        }
+    }
+
+    /// Consecutive slices of at most `size` rows.
+    pub(crate) fn chunks(&self, size: usize) -> impl Iterator<Item = Rows<'a>> + '_ {
+        (0..self.len()).step_by(size).map(move |start| self.slice(start, (start + size).min(self.len())))
+    }
+
+    /// True if any record is a CBOR map, checked without decoding.
+    pub(crate) fn any_map(&self) -> bool {
+        match self {
+            Rows::Decoded(values, _) => values.iter().any(|v| matches!(v, Value::Map(_))),
+            Rows::Encoded(Spans { bytes, offsets }, ..) => {
+                offsets[..offsets.len() - 1].iter().any(|&at| bytes[at] >> 5 == 5)
+            }
+        }
+    }
+
+    pub(crate) fn decode(&self) -> PyResult<Cow<'a, [Value]>> {
+        let (Spans { bytes, offsets }, select) = match self {
+            Rows::Decoded(values, _) => return Ok(Cow::Borrowed(values)),
+            Rows::Encoded(spans, None, false) => {
+                let mut reader = SliceReader::new(&spans.bytes[spans.offsets[0]..spans.offsets[spans.len()]]);
+                // cbor4ii's errors do not say where they are, so a failure is decoded again below.
+                if let Ok(values) = (0..spans.len()).map(|_| Value::decode(&mut reader)).collect() {
+                    return Ok(Cow::Owned(values));
+                }
+                (spans, None)
+            }
+            Rows::Encoded(spans, select, _) => (spans, *select),
+        };
+        let mut scanner = Scanner::new(bytes, select);
+        offsets[..offsets.len() - 1]
+            .iter()
+            .map(|&at| {
+                scanner.pos = at;
+                scanner.record()
+            })
+            .collect::<PyResult<Vec<_>>>()
+            .map(Cow::Owned)
+    }
+}
+
+/// Decode `bytes`, which must hold exactly one item.
+pub(crate) fn decode(bytes: &[u8]) -> PyResult<Value> {
+    let mut items = decode_sequence(bytes)?;
+    match items.len() {
+        1 => Ok(items.remove(0)),
+        n => Err(CborDecodeError::new_err(format!("CBOR input is a sequence of {} items, expected one", n))),
+    }
+}
+
+/// Decode each item of a CBOR sequence (RFC 8742) of one or more items.
+pub(crate) fn decode_sequence(bytes: &[u8]) -> PyResult<Vec<Value>> {
+    let mut scanner = Scanner::new(bytes, None);
+    let mut items = Vec::new();
+    loop {
+        scanner.self_described();
+        items.push(scanner.value()?);
+        if scanner.pos == bytes.len() {
+            return Ok(items);
+        }
+    }
+}
+
+/// Walks CBOR items without decoding the ones it skips.
+struct Scanner<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /// Applied to the records of record arrays.
+    select: Option<&'a Selection>,
+    /// Set by `skip` on passing an indefinite-length string.
+    chunked: bool,
+    /// Whether errors name the path of the item they are in.
+    locate: bool,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(bytes: &'a [u8], select: Option<&'a Selection>) -> Self {
+        Scanner { bytes, pos: 0, select, chunked: false, locate: true }
+    }
+
+    /// A scanner for finding paths, whose errors do not look for their own.
+    fn walker(bytes: &'a [u8]) -> Self {
+        Scanner { locate: false, ..Scanner::new(bytes, None) }
+    }
+
+    fn error(&self, message: &str) -> PyErr {
+        let path = if self.locate { path_at(self.bytes, self.pos) } else { String::new() };
+        CborDecodeError::new_err(match path.is_empty() {
+            true => format!("CBOR decode error: {} at offset {}", message, self.pos),
+            false => format!("CBOR decode error: {} at offset {} in {}", message, self.pos, path),
+        })
+    }
+
+    fn byte(&mut self) -> PyResult<u8> {
+        let b = *self.bytes.get(self.pos).ok_or_else(|| self.error("unexpected end of input"))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn peek(&self) -> PyResult<u8> {
+        self.bytes.get(self.pos).copied().ok_or_else(|| self.error("unexpected end of input"))
+    }
+
+    fn advance(&mut self, n: u64) -> PyResult<()> {
+        let end = usize::try_from(n).ok().and_then(|n| self.pos.checked_add(n)).filter(|&end| end <= self.bytes.len());
+        self.pos = end.ok_or_else(|| self.error("unexpected end of input"))?;
+        Ok(())
+    }
+
+    /// Read an item head: the major type and its argument, `None` for indefinite length.
+    fn head(&mut self) -> PyResult<(u8, Option<u64>)> {
+        let b = self.byte()?;
+        let (major, info) = (b >> 5, b & 0x1f);
+        let width = match info {
+            0..=23 => return Ok((major, Some(u64::from(info)))),
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            31 if matches!(major, 2..=5 | 7) => return Ok((major, None)),
+            _ => {
+                self.pos -= 1;
+                return Err(self.error("invalid item header"));
+            }
+        };
+        let mut arg = 0u64;
+        for _ in 0..width {
+            arg = (arg << 8) | u64::from(self.byte()?);
+        }
+        Ok((major, Some(arg)))
+    }
+
+    /// Skip the self-described CBOR tags wrapping the next item.
+    fn self_described(&mut self) {
+        loop {
+            let start = self.pos;
+            if !matches!(self.head(), Ok((6, Some(tags::TAG_SELF_DESCRIBED)))) {
+                self.pos = start;
+                return;
+            }
+        }
+    }
+
+    /// Consume the break that ends an indefinite-length item, if it is next.
+    fn at_break(&mut self) -> PyResult<bool> {
+        let found = self.peek()? == 0xff;
+        if found {
+            self.pos += 1;
+        }
+        Ok(found)
+    }
+
+    /// Skip one complete item.
+    fn skip(&mut self, depth: usize) -> PyResult<()> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        let start = self.pos;
+        match self.head()? {
+            (0 | 1, _) | (7, Some(_)) => Ok(()),
+            (2 | 3, Some(len)) => self.advance(len),
+            (major @ (2 | 3), None) => {
+                self.chunked = true;
+                while !self.at_break()? {
+                    match self.head()? {
+                        (chunk, Some(len)) if chunk == major => self.advance(len)?,
+                        _ => return Err(self.error("invalid indefinite-length string chunk")),
+                    }
+                }
+                Ok(())
+            }
+            (major @ (4 | 5), Some(len)) => {
+                let items = if major == 5 { len.saturating_mul(2) } else { len };
+                for _ in 0..items {
+                    self.skip(depth + 1)?;
+                }
+                Ok(())
+            }
+            (4 | 5, None) => {
+                while !self.at_break()? {
+                    self.skip(depth + 1)?;
+                }
+                Ok(())
+            }
+            (6, _) => self.skip(depth + 1),
+            _ => {
+                self.pos = start;
+                Err(self.error("unexpected break"))
+            }
+        }
+    }
+
+    /// Decode the next item into a `Value`.
+    fn value(&mut self) -> PyResult<Value> {
+        let start = self.pos;
+        self.chunked = false;
+        self.skip(0)?;
+        if !self.chunked {
+            if let Ok(value) = Value::decode(&mut SliceReader::new(&self.bytes[start..self.pos])) {
+                return Ok(value);
+            }
+        }
+        // Decoded here on failure too, for an error that says where it is.
+        self.pos = start;
+        self.decode(0)
+    }
+
+    /// Decode the next item, joining the chunks of indefinite-length strings.
+    fn decode(&mut self, depth: usize) -> PyResult<Value> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        let start = self.pos;
+        let initial = self.peek()?;
+        Ok(match self.head()? {
+            (0, Some(n)) => Value::Integer(i128::from(n)),
+            (1, Some(n)) => Value::Integer(-1 - i128::from(n)),
+            (2, len) => Value::Bytes(self.string(2, len)?),
+            (3, len) => match String::from_utf8(self.string(3, len)?) {
+                Ok(text) => Value::Text(text),
+                Err(_) => {
+                    self.pos = start;
+                    return Err(self.error("invalid UTF-8 in text string"));
+                }
+            },
+            (4, len) => {
+                let mut items = Vec::new();
+                while self.more(len, items.len() as u64)? {
+                    items.push(self.decode(depth + 1)?);
+                }
+                Value::Array(items)
+            }
+            (5, len) => {
+                let mut entries = Vec::new();
+                while self.more(len, entries.len() as u64)? {
+                    let key = self.decode(depth + 1)?;
+                    entries.push((key, self.decode(depth + 1)?));
+                }
+                Value::Map(entries)
+            }
+            (6, Some(tag)) => Value::Tag(tag, Box::new(self.decode(depth + 1)?)),
+            (7, Some(bits)) => match initial {
+                0xf4 => Value::Bool(false),
+                0xf5 => Value::Bool(true),
+                0xf6 | 0xf7 => Value::Null,
+                0xf9 => Value::Float(half::f16::from_bits(bits as u16).to_f64()),
+                0xfa => Value::Float(f64::from(f32::from_bits(bits as u32))),
+                0xfb => Value::Float(f64::from_bits(bits)),
+                _ => {
+                    self.pos = start;
+                    return Err(self.error("unsupported simple value"));
+                }
+            },
+            _ => {
+                self.pos = start;
+                return Err(self.error("unexpected break"));
+            }
+        })
+    }
+
+    /// Skip the next item if it does not hold `offset`; otherwise stay at its start
+    /// and return true. An item that cannot be skipped is taken to hold it.
+    fn holds(&mut self, offset: usize, depth: usize) -> bool {
+        let start = self.pos;
+        let found = start <= offset && (self.skip(depth).is_err() || offset < self.pos);
+        if found {
+            self.pos = start;
+        }
+        found
+    }
+
+    /// Add to `path` the steps from the item at `self.pos` to the innermost item
+    /// holding `offset`. Tags add no step.
+    fn descend(&mut self, offset: usize, path: &mut String, depth: usize) {
+        if depth > MAX_DEPTH {
+            return;
+        }
+        match self.head() {
+            Ok((4, len)) => {
+                let mut seen = 0;
+                while self.pos <= offset {
+                    match self.more(len, seen) {
+                        Ok(true) => {}
+                        // An indefinite-length array cut off where its next element would start.
+                        Err(_) if self.pos == offset => return push_step(path, &Step::Index(seen as usize)),
+                        _ => return,
+                    }
+                    if self.holds(offset, depth + 1) {
+                        push_step(path, &Step::Index(seen as usize));
+                        return self.descend(offset, path, depth + 1);
+                    }
+                    seen += 1;
+                }
+            }
+            Ok((5, len)) => {
+                let mut seen = 0;
+                while self.pos <= offset && self.more(len, seen).unwrap_or(false) {
+                    let Ok(key) = self.value() else { return };
+                    if offset < self.pos {
+                        return;
+                    }
+                    if self.holds(offset, depth + 1) {
+                        push_step(path, &Step::Member(key_string(&key, MapKeys::Display).into_owned()));
+                        return self.descend(offset, path, depth + 1);
+                    }
+                    seen += 1;
+                }
+            }
+            Ok((6, _)) => self.descend(offset, path, depth + 1),
+            _ => {}
+        }
+    }
+
+    /// Follow `steps` from the item at `self.pos`, skipping tags, and return the
+    /// offset of the item reached, or of the last one found on the way.
+    fn find(&mut self, steps: &[Step], keys: MapKeys) -> usize {
+        let mut found = self.pos;
+        for step in steps {
+            while let Ok(6) = self.peek().map(|b| b >> 5) {
+                if self.head().is_err() {
+                    return found;
+                }
+            }
+            let reached = match step {
+                Step::Index(i) => matches!(self.container(4), Ok(Ok(len)) if self.element(len, *i as u64)),
+                Step::Member(name) => matches!(self.container(5), Ok(Ok(len)) if self.member_named(len, name, keys)),
+            };
+            if !reached {
+                break;
+            }
+            found = self.pos;
+        }
+        found
+    }
+
+    /// Move to element `i` of an array of length `len` whose head was read.
+    fn element(&mut self, len: Option<u64>, i: u64) -> bool {
+        for seen in 0..i {
+            if !self.more(len, seen).unwrap_or(false) || self.skip(0).is_err() {
+                return false;
+            }
+        }
+        self.more(len, i).unwrap_or(false)
+    }
+
+    /// Move to the value of the member `name` of a map of length `len` whose head was read.
+    fn member_named(&mut self, len: Option<u64>, name: &str, keys: MapKeys) -> bool {
+        let mut seen = 0;
+        while self.more(len, seen).unwrap_or(false) {
+            match self.value() {
+                Ok(key) if key_string(&key, keys) == name => return true,
+                Ok(_) if self.skip(0).is_ok() => seen += 1,
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    /// The contents of a byte or text string after its head, joining the chunks
+    /// of an indefinite-length one.
+    fn string(&mut self, major: u8, len: Option<u64>) -> PyResult<Vec<u8>> {
+        let Some(len) = len else {
+            let mut joined = Vec::new();
+            while !self.at_break()? {
+                match self.head()? {
+                    (chunk, Some(len)) if chunk == major => joined.extend_from_slice(self.take(len)?),
+                    _ => return Err(self.error("invalid indefinite-length string chunk")),
+                }
+            }
+            return Ok(joined);
+        };
+        Ok(self.take(len)?.to_vec())
+    }
+
+    /// The next `n` bytes.
+    fn take(&mut self, n: u64) -> PyResult<&'a [u8]> {
+        let start = self.pos;
+        self.advance(n)?;
+        Ok(&self.bytes[start..self.pos])
+    }
+
+    /// Decode the next record, leaving out the members not selected.
+    fn record(&mut self) -> PyResult<Value> {
+        match self.select {
+            Some(select) if self.peek()? >> 5 == 5 => {
+                Ok(self.selected(select, &mut String::new(), false, 0)?.unwrap_or(Value::Map(Vec::new())))
+            }
+            _ => self.value(),
+        }
+    }
+
+    /// Decode the selected part of the next item, the one at `path`, skipping the
+    /// rest; `None` if nothing in it is selected. Mirrors `Selection::record`.
+    fn selected(&mut self, select: &Selection, path: &mut String, included: bool, depth: usize) -> PyResult<Option<Value>> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        if let Ok(len) = self.container(5)? {
+            let (mut entries, mut seen) = (Vec::new(), 0);
+            while self.more(len, seen)? {
+                seen += 1;
+                let key = self.value()?;
+                let restore = select::push_member(path, &key_string(&key, select.keys));
+                match select.member(path, included) {
+                    Member::Drop => self.skip(depth + 1)?,
+                    Member::Keep => entries.push((key, self.value()?)),
+                    Member::Descend { included } => {
+                        if let Some(value) = self.selected(select, path, included, depth + 1)? {
+                            entries.push((key, value));
+                        }
+                    }
+                }
+                path.truncate(restore);
+            }
+            return Ok((included || !entries.is_empty()).then_some(Value::Map(entries)));
+        }
+        if let Ok(len) = self.container(4)? {
+            let mut items = Vec::new();
+            while self.more(len, items.len() as u64)? {
+                items.push(self.selected(select, path, included, depth + 1)?);
+            }
+            return Ok((included || items.iter().any(Option::is_some))
+                .then(|| Value::Array(items.into_iter().map(|item| item.unwrap_or(Value::Null)).collect())));
+        }
+        match included {
+            true => self.value().map(Some),
+            false => self.skip(depth).map(|_| None),
+        }
+    }
+
+    /// Read a map or array head, returning its length (`None` if indefinite),
+    /// or rewind and return `Err(())` if the next item is something else.
+    fn container(&mut self, expected: u8) -> PyResult<Result<Option<u64>, ()>> {
+        let start = self.pos;
+        match self.head()? {
+            (major, len) if major == expected => Ok(Ok(len)),
+            _ => {
+                self.pos = start;
+                Ok(Err(()))
+            }
+        }
+    }
+
+    /// True while entries remain in a container of length `len` after `seen` entries.
+    fn more(&mut self, len: Option<u64>, seen: u64) -> PyResult<bool> {
+        match len {
+            Some(len) => Ok(seen < len),
+            None => Ok(!self.at_break()?),
+        }
+    }
+
+    /// Scan an RPC response, leaving large statement results encoded.
+    fn envelope(&mut self, records: &mut Vec<Records>) -> PyResult<Value> {
+        let Ok(len) = self.container(5)? else {
+            return self.value();
+        };
+        let mut entries = Vec::new();
+        let mut seen_result = false;
+        while self.more(len, entries.len() as u64)? {
+            let key = self.value()?;
+            let is_result = !seen_result && matches!(&key, Value::Text(k) if k == "result");
+            seen_result |= is_result;
+            let value = if is_result { self.statements(records)? } else { self.value()? };
+            entries.push((key, value));
+        }
+        Ok(Value::Map(entries))
+    }
+
+    /// Scan the per-statement responses of an RPC result array.
+    fn statements(&mut self, records: &mut Vec<Records>) -> PyResult<Value> {
+        let Ok(len) = self.container(4)? else {
+            return self.value();
+        };
+        let mut responses = Vec::new();
+        while self.more(len, responses.len() as u64)? {
+            let at = RecordsAt::Statement(responses.len());
+            let Ok(entries_len) = self.container(5)? else {
+                responses.push(self.value()?);
+                continue;
+            };
+            let mut entries = Vec::new();
+            let mut seen_result = false;
+            while self.more(entries_len, entries.len() as u64)? {
+                let key = self.value()?;
+                let is_result = !seen_result && matches!(&key, Value::Text(k) if k == "result");
+                seen_result |= is_result;
+                let value = if is_result { self.records(at, records)? } else { self.value()? };
+                entries.push((key, value));
+            }
+            responses.push(Value::Map(entries));
+        }
+        Ok(Value::Array(responses))
+    }
+
+    /// Scan a record array and note it in `records`. Arrays of more than
+    /// `DECODE_CHUNK_ROWS` records are returned empty; anything else is decoded.
+    fn records(&mut self, at: RecordsAt, records: &mut Vec<Records>) -> PyResult<Value> {
+        let start = self.pos;
+        let Ok(len) = self.container(4)? else {
+            return self.value();
+        };
+        self.chunked = false;
+        let mut offsets = Vec::new();
+        while self.more(len, offsets.len() as u64)? {
+            offsets.push(self.pos);
+            self.skip(0)?;
+        }
+        let end = self.pos;
+        offsets.push(end - usize::from(len.is_none()));
+        let (encoded, chunked) = (offsets.len() > DECODE_CHUNK_ROWS + 1, self.chunked);
+        let value = match (encoded, self.select) {
+            (true, _) => Value::Array(Vec::new()),
+            (false, None) => {
+                self.pos = start;
+                self.value()?
+            }
+            (false, Some(_)) => Value::Array(
+                offsets[..offsets.len() - 1]
+                    .iter()
+                    .map(|&at| {
+                        self.pos = at;
+                        self.record()
+                    })
+                    .collect::<PyResult<Vec<_>>>()?,
+            ),
+        };
+        self.pos = end;
+        records.push(Records { at, offsets, chunked, encoded });
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{Array, Float64Array, RecordBatch};
+    use arrow::datatypes::DataType;
+
+    use super::*;
+    use crate::encode::encode;
+
+    /// The CBOR text string `s`, shorter than 24 bytes.
+    fn text(s: &str) -> Vec<u8> {
+        let mut out = vec![0x60 + s.len() as u8];
+        out.extend_from_slice(s.as_bytes());
+        out
+    }
+
+    /// An RPC response whose only statement returned the encoded `records`.
+    fn response(records: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = vec![0xa1];
+        out.extend(text("result"));
+        out.extend([0x81, 0xa2]);
+        out.extend(text("status"));
+        out.extend(text("OK"));
+        out.extend(text("result"));
+        out.push(0x80 + records.len() as u8);
+        records.iter().for_each(|record| out.extend(record));
+        out
+    }
+
+    /// The batch `cbor_to_arrow` builds from the first statement of `response`.
+    fn convert(py: Python, response: Vec<u8>) -> PyResult<RecordBatch> {
+        let opts = ConvertOptions::default();
+        let payload = Payload::load_owned(py, response, true, &opts)?;
+        let (rows, plan) = crate::result_plan(py, &payload, RecordsAt::Statement(0), &opts)?;
+        plan.unwrap().build_rows(rows.unwrap(), 0, &opts)
+    }
+
+    const HALVES: [(u16, f64); 5] = [
+        (0x0001, 5.960464477539063e-8),
+        (0x03ff, 6.097555160522461e-5),
+        (0x7c00, f64::INFINITY),
+        (0xfc00, f64::NEG_INFINITY),
+        (0x7e00, f64::NAN),
+    ];
+
+    fn same(a: f64, b: f64) -> bool {
+        a == b || (a.is_nan() && b.is_nan())
+    }
+
+    #[test]
+    fn half_floats_decode_to_f64() {
+        pyo3::prepare_freethreaded_python();
+        for (bits, expected) in HALVES {
+            let [hi, lo] = bits.to_be_bytes();
+            match decode(&[0xf9, hi, lo]).unwrap() {
+                Value::Float(f) => assert!(same(f, expected), "f9 {:04x} decoded to {}", bits, f),
+                other => panic!("f9 {:04x} decoded to {:?}", bits, other),
+            }
+        }
+    }
+
+    #[test]
+    fn half_floats_convert_to_float64_columns() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let records: Vec<Vec<u8>> = HALVES
+                .iter()
+                .map(|(bits, _)| {
+                    let mut record = vec![0xa1];
+                    record.extend(text("x"));
+                    record.push(0xf9);
+                    record.extend(bits.to_be_bytes());
+                    record
+                })
+                .collect();
+            let batch = convert(py, response(&records)).unwrap();
+            let column = batch.column_by_name("x").unwrap();
+            assert_eq!(column.data_type(), &DataType::Float64);
+            let column = column.as_any().downcast_ref::<Float64Array>().unwrap();
+            assert_eq!(column.null_count(), 0);
+            for (row, (bits, expected)) in HALVES.iter().enumerate() {
+                assert!(same(column.value(row), *expected), "f9 {:04x} converted to {}", bits, column.value(row));
+            }
+        });
+    }
+
+    #[test]
+    fn indefinite_items_decode() {
+        pyo3::prepare_freethreaded_python();
+        let cases: [(&[u8], Value); 5] = [
+            (b"\x9f\x01\x02\xff", Value::Array(vec![Value::Integer(1), Value::Integer(2)])),
+            (b"\xbf\x61a\x01\xff", Value::Map(vec![(Value::Text("a".into()), Value::Integer(1))])),
+            (b"\x5f\x42\x01\x02\x41\x03\xff", Value::Bytes(vec![1, 2, 3])),
+            (b"\x7f\x62ab\x61c\x60\xff", Value::Text("abc".into())),
+            // [{_ "k": [_ (_ "x" "y"), h'']}]
+            (
+                b"\x9f\xbf\x61k\x9f\x7f\x61x\x61y\xff\x5f\xff\xff\xff\xff",
+                Value::Array(vec![Value::Map(vec![(
+                    Value::Text("k".into()),
+                    Value::Array(vec![Value::Text("xy".into()), Value::Bytes(Vec::new())]),
+                )])]),
+            ),
+        ];
+        for (bytes, expected) in cases {
+            assert_eq!(decode(bytes).unwrap(), expected, "decoding {:02x?}", bytes);
+        }
+    }
+
+    #[test]
+    fn indefinite_records_convert() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            // {_ "name": (_ "a" "b"), "tags": [_ "t"]}, then with a definite name.
+            let records = vec![
+                b"\xbf\x64name\x7f\x61a\x61b\xff\x64tags\x9f\x61t\xff\xff".to_vec(),
+                b"\xa2\x64name\x61c\x64tags\x80".to_vec(),
+            ];
+            let batch = convert(py, response(&records)).unwrap();
+            let names = batch.column_by_name("name").unwrap();
+            let names = arrow::array::cast::as_largestring_array(names.as_ref());
+            assert_eq!((names.value(0), names.value(1)), ("ab", "c"));
+            let tags = batch.column_by_name("tags").unwrap();
+            let tags = arrow::array::cast::as_large_list_array(tags.as_ref());
+            assert_eq!((tags.value_length(0), tags.value_length(1)), (1, 0));
+        });
+    }
+
+    #[test]
+    fn malformed_indefinite_items_name_their_path() {
+        pyo3::prepare_freethreaded_python();
+        let cases: [(&[u8], &str); 9] = [
+            // A break ending a definite-length array.
+            (b"\x82\x01\xff", "unexpected break at offset 2 in [1]"),
+            // A break where the value of a member should be.
+            (b"\x81\xbf\x61a\x01\x61b\xff", "unexpected break at offset 7 in [0].b"),
+            (b"\xbf\x61a\xff", "unexpected break at offset 3 in a"),
+            // A break with nothing to end, after the item.
+            (b"\x9f\x01\xff\xff", "unexpected break at offset 3 in [1]"),
+            // A chunk of another type in an indefinite-length string.
+            (b"\x7f\x41\x00\xff", "invalid indefinite-length string chunk at offset 2"),
+            // Truncated streams.
+            (b"\x9f\x01\x02", "unexpected end of input at offset 3 in [2]"),
+            (b"\xbf\x61a\x9f\x01", "unexpected end of input at offset 5 in a[1]"),
+            (b"\xa1\x61a\x5f\x41\x01", "unexpected end of input at offset 6 in a"),
+            (b"\x9f\xbf\x61k\x9f\x7f\x61x\xff\x7f\x61y", "unexpected end of input at offset 12 in [0].k[1]"),
+        ];
+        Python::with_gil(|py| {
+            for (bytes, message) in cases {
+                let err = decode(bytes).unwrap_err();
+                assert!(err.is_instance_of::<CborDecodeError>(py), "{:02x?} raised {}", bytes, err);
+                assert_eq!(err.value(py).to_string(), format!("CBOR decode error: {}", message));
+            }
+        });
+    }
+
+    #[test]
+    fn malformed_indefinite_records_name_their_path() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let mut truncated = response(&[b"\xa1\x61a\x01".to_vec(), b"\xbf\x61a\x9f\x01".to_vec()]);
+            let err = convert(py, truncated.clone()).unwrap_err();
+            assert!(err.is_instance_of::<CborDecodeError>(py));
+            let message = err.value(py).to_string();
+            let at = format!("at offset {} in result[0].result[1].a[1]", truncated.len());
+            assert!(message.ends_with(&at), "{}", message);
+            // The array ends, the record holding it does not.
+            truncated.truncate(truncated.len() - 1);
+            truncated.push(0xff);
+            let err = convert(py, truncated.clone()).unwrap_err();
+            let message = err.value(py).to_string();
+            let at = format!("at offset {} in result[0].result[1]", truncated.len());
+            assert!(message.ends_with(&at), "{}", message);
+        });
+    }
+
+    #[test]
+    fn large_record_arrays_stay_encoded() {
+        pyo3::prepare_freethreaded_python();
+        let key = |s: &str| Value::Text(s.to_string());
+        let response = |rows: usize| {
+            let records = (0..rows).map(|n| Value::Map(vec![(key("n"), Value::Integer(n as i128))])).collect();
+            let statement = Value::Map(vec![(key("status"), key("OK")), (key("result"), Value::Array(records))]);
+            encode(&Value::Map(vec![(key("result"), Value::Array(vec![statement]))]))
+        };
+        Python::with_gil(|py| {
+            let opts = ConvertOptions::default();
+            let small = Payload::load_owned(py, response(3), true, &opts).unwrap();
+            assert!(small.encoded(RecordsAt::Statement(0)).is_none());
+
+            let rows = DECODE_CHUNK_ROWS + 1;
+            let large = Payload::load_owned(py, response(rows), true, &opts).unwrap();
+            // The decoded response holds none of the records.
+            let statements = crate::root_responses(&large.root).unwrap();
+            let Value::Map(statement) = &statements[0] else { panic!("{:?}", statements[0]) };
+            assert_eq!(crate::map_get(statement, "result"), Some(&Value::Array(Vec::new())));
+            let encoded = large.encoded(RecordsAt::Statement(0)).unwrap();
+            assert_eq!(encoded.len(), rows);
+            let chunks: Vec<usize> =
+                encoded.chunks(DECODE_CHUNK_ROWS).map(|rows| rows.decode().unwrap().len()).collect();
+            assert_eq!(chunks, [DECODE_CHUNK_ROWS, 1]);
+
+            let batch = convert(py, response(rows)).unwrap();
+            assert_eq!(batch.num_rows(), rows);
+        });
+    }
+}
@@ -0,0 +1,347 @@
+//! Conversions between Python objects and CBOR values.
+
+use chrono::{Datelike, Timelike};
+use cbor4ii::core::Value;
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{
+    PyBool, PyByteArray, PyBytes, PyDate, PyDateAccess, PyDateTime, PyDelta, PyDeltaAccess, PyDict, PyFloat, PyFrozenSet,
+    PyInt, PyList, PySet, PyString, PyTimeAccess, PyTuple, PyTzInfo,
+};
+
+use crate::encode::{compact, encode, tagged};
+use crate::tags::{self, SurrealTag, TAG_DATETIME_COMPACT, TAG_DECIMAL, TAG_DURATION_COMPACT, TAG_RECORDID, TAG_UUID};
+use crate::{envelope_records, surrealql, tag_kind, CborInput};
+
+/// Convert a plain Python value (None, bool, int, float, str, list, tuple, dict)
+/// into a CBOR value.
+pub(crate) fn py_to_value(obj: &Bound<'_, PyAny>) -> PyResult<Value> {
+    if obj.is_none() {
+        return Ok(Value::Null);
+    }
+    // bool must be checked before int, since it is a subclass.
+    if let Ok(b) = obj.downcast::<PyBool>() {
+        return Ok(Value::Bool(b.is_true()));
+    }
+    if obj.is_instance_of::<PyInt>() {
+        return Ok(Value::Integer(obj.extract::<i128>()?));
+    }
+    if let Ok(f) = obj.downcast::<PyFloat>() {
+        return Ok(Value::Float(f.value()));
+    }
+    if let Ok(s) = obj.downcast::<PyString>() {
+        return Ok(Value::Text(s.to_str()?.to_string()));
+    }
+    if let Ok(list) = obj.downcast::<PyList>() {
+        return list.iter().map(|item| py_to_value(&item)).collect::<PyResult<_>>().map(Value::Array);
+    }
+    if let Ok(tuple) = obj.downcast::<PyTuple>() {
+        return tuple.iter().map(|item| py_to_value(&item)).collect::<PyResult<_>>().map(Value::Array);
+    }
+    if let Ok(dict) = obj.downcast::<PyDict>() {
+        let mut entries = Vec::with_capacity(dict.len());
+        for (k, v) in dict.iter() {
+            let key = k.downcast::<PyString>().map_err(|_| {
+                PyTypeError::new_err(format!("Object keys must be str, got {}", k.get_type().name().map(|n| n.to_string()).unwrap_or_default()))
+            })?;
+            entries.push((Value::Text(key.to_str()?.to_string()), py_to_value(&v)?));
+        }
+        return Ok(Value::Map(entries));
+    }
+    Err(PyTypeError::new_err(format!(
+        "Cannot convert value of type {} to SurrealQL",
+        obj.get_type().name().map(|n| n.to_string()).unwrap_or_default()
+    )))
+}
+
+/// Convert the records of a statement of a CBOR response to a list of dicts.
+///
+/// SurrealDB values become their Python counterparts: record ids `table:key`
+/// strings, datetimes timezone-aware `datetime.datetime`s (to the microsecond),
+/// UUIDs `uuid.UUID`s and decimals `decimal.Decimal`s; durations stay SurrealQL
+/// duration strings. A lone object or scalar result is a one-item list and an
+/// empty one an empty list. With `envelope=False`, `data` is a bare array of
+/// records instead of an RPC response. For when Arrow is more than is needed.
+#[pyfunction]
+#[pyo3(signature = (data, statement=0, envelope=true))]
+pub(crate) fn cbor_to_dicts(py: Python, data: CborInput, statement: isize, envelope: bool) -> PyResult<PyObject> {
+    let root = data.decode(py)?;
+    let records = envelope_records(&root, statement, envelope)?;
+    let natives = Natives::import(py)?;
+    let records = records
+        .unwrap_or_default()
+        .iter()
+        .map(|record| to_py(py, record, Some(&natives)))
+        .collect::<PyResult<Vec<_>>>()?;
+    Ok(PyList::new(py, records)?.into_any().unbind())
+}
+
+/// The Python types of SurrealDB values for `cbor_to_dicts` and `encode_params`,
+/// imported once per call.
+struct Natives<'py> {
+    uuid: Bound<'py, PyAny>,
+    decimal: Bound<'py, PyAny>,
+    utc: Bound<'py, PyTzInfo>,
+}
+
+impl<'py> Natives<'py> {
+    fn import(py: Python<'py>) -> PyResult<Self> {
+        Ok(Natives {
+            uuid: py.import("uuid")?.getattr("UUID")?,
+            decimal: py.import("decimal")?.getattr("Decimal")?,
+            utc: pyo3::types::timezone_utc(py),
+        })
+    }
+
+    /// The Python value of a SurrealDB datetime, UUID or decimal, or `None` for other values.
+    fn convert(&self, tag: u64, payload: &Value) -> PyResult<Option<PyObject>> {
+        let py = self.utc.py();
+        let value = match SurrealTag::of(tag) {
+            SurrealTag::Datetime => match tags::datetime_to_nanos(tag, payload) {
+                Some(nanos) => {
+                    let at = chrono::DateTime::from_timestamp_nanos(nanos);
+                    let (month, day, hour) = (at.month() as u8, at.day() as u8, at.hour() as u8);
+                    let (minute, second, micros) = (at.minute() as u8, at.second() as u8, at.nanosecond() / 1000);
+                    PyDateTime::new(py, at.year(), month, day, hour, minute, second, micros, Some(&self.utc))?.into_any()
+                }
+                None => return Ok(None),
+            },
+            SurrealTag::Uuid => match tags::uuid_bytes(tag, payload) {
+                Some(bytes) => {
+                    let kwargs = PyDict::new(py);
+                    kwargs.set_item("bytes", pyo3::types::PyBytes::new(py, &bytes))?;
+                    self.uuid.call((), Some(&kwargs))?
+                }
+                None => return Ok(None),
+            },
+            SurrealTag::Decimal => match payload {
+                Value::Text(s) => self.decimal.call1((s,))?,
+                _ => return Ok(None),
+            },
+            SurrealTag::DecimalFraction => match tags::decimal_fraction_string(payload) {
+                Some(text) => self.decimal.call1((text,))?,
+                None => return Ok(None),
+            },
+            SurrealTag::Duration => match tags::duration_to_nanos(tag, payload) {
+                Some(nanos) => tags::format_duration(nanos).into_pyobject(py)?.into_any(),
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(value.unbind()))
+    }
+}
+
+/// Convert a CBOR value into the equivalent plain Python value. Tagged
+/// SurrealDB values that have no plain Python counterpart become strings.
+pub(crate) fn value_to_py(py: Python<'_>, value: &Value) -> PyResult<PyObject> {
+    to_py(py, value, None)
+}
+
+/// Convert a CBOR value into a Python value as `cbor_to_dicts` converts the
+/// values of records.
+pub(crate) fn value_to_native(py: Python<'_>, value: &Value) -> PyResult<PyObject> {
+    to_py(py, value, Some(&Natives::import(py)?))
+}
+
+/// `value_to_py`, converting the tags `natives` has types for to those.
+fn to_py(py: Python<'_>, value: &Value, natives: Option<&Natives>) -> PyResult<PyObject> {
+    Ok(match value {
+        Value::Null => py.None(),
+        Value::Bool(b) => b.into_pyobject(py)?.to_owned().into_any().unbind(),
+        Value::Integer(i) => i.into_pyobject(py)?.into_any().unbind(),
+        Value::Float(f) => f.into_pyobject(py)?.into_any().unbind(),
+        Value::Text(s) => s.into_pyobject(py)?.into_any().unbind(),
+        Value::Bytes(b) => pyo3::types::PyBytes::new(py, b).into_any().unbind(),
+        Value::Array(items) => {
+            let items = items.iter().map(|item| to_py(py, item, natives)).collect::<PyResult<Vec<_>>>()?;
+            PyList::new(py, items)?.into_any().unbind()
+        }
+        Value::Map(entries) => {
+            let dict = PyDict::new(py);
+            for (k, v) in entries {
+                dict.set_item(to_py(py, k, natives)?, to_py(py, v, natives)?)?;
+            }
+            dict.into_any().unbind()
+        }
+        Value::Tag(tag, inner) => {
+            if let Some(natives) = natives {
+                if let Some(converted) = natives.convert(*tag, inner)? {
+                    return Ok(converted);
+                }
+            }
+            match tag_kind(value) {
+                Some(SurrealTag::None) => py.None(),
+                Some(SurrealTag::RecordId) => match tags::record_id_string(inner) {
+                    Some(s) => s.into_pyobject(py)?.into_any().unbind(),
+                    None => to_py(py, inner, natives)?,
+                },
+                Some(SurrealTag::DecimalFraction) => match tags::decimal_fraction_string(inner) {
+                    Some(text) => text.into_pyobject(py)?.into_any().unbind(),
+                    None => to_py(py, inner, natives)?,
+                },
+                Some(SurrealTag::BigNum) => match tags::bignum_string(*tag, inner) {
+                    Some(digits) => py.get_type::<PyInt>().call1((digits,))?.unbind(),
+                    None => to_py(py, inner, natives)?,
+                },
+                _ => to_py(py, inner, natives)?,
+            }
+        }
+        _ => py.None(),
+    })
+}
+
+/// Encode Python values as SurrealDB CBOR, for the parameters of a query: the
+/// mirror of `cbor_to_dicts`.
+///
+/// `datetime.datetime`s become datetimes (naive ones taken as UTC) and
+/// `datetime.date`s datetimes at midnight UTC, `datetime.timedelta`s durations,
+/// `uuid.UUID`s UUIDs and `decimal.Decimal`s decimals, each with its SurrealDB
+/// tag. Strings that parse as record ids (`person:tobie`) become record ids
+/// unless `record_ids=False`. Dicts, lists, tuples and sets are encoded item by
+/// item; bytes stay bytes.
+#[pyfunction]
+#[pyo3(signature = (params, record_ids=true))]
+pub(crate) fn encode_params(py: Python, params: &Bound<'_, PyAny>, record_ids: bool) -> PyResult<PyObject> {
+    let natives = Natives::import(py)?;
+    let value = natives.to_value(params, record_ids)?;
+    let bytes = py.allow_threads(|| encode(&value));
+    Ok(PyBytes::new(py, &bytes).into_any().unbind())
+}
+
+/// The SurrealDB value of a Python value, as `encode_params` encodes it.
+pub(crate) fn surreal_value(obj: &Bound<'_, PyAny>, record_ids: bool) -> PyResult<Value> {
+    Natives::import(obj.py())?.to_value(obj, record_ids)
+}
+
+impl Natives<'_> {
+    /// The SurrealDB value of a Python value, as `encode_params` encodes it.
+    fn to_value(&self, obj: &Bound<'_, PyAny>, record_ids: bool) -> PyResult<Value> {
+        if let Ok(s) = obj.downcast::<PyString>() {
+            let s = s.to_str()?;
+            return Ok(match record_ids.then(|| surrealql::parse_record_id(s).ok()).flatten() {
+                Some((table, key)) => tagged(TAG_RECORDID, Value::Array(vec![Value::Text(table), key])),
+                None => Value::Text(s.to_string()),
+            });
+        }
+        if let Ok(list) = obj.downcast::<PyList>() {
+            return list.iter().map(|item| self.to_value(&item, record_ids)).collect::<PyResult<_>>().map(Value::Array);
+        }
+        if let Ok(tuple) = obj.downcast::<PyTuple>() {
+            return tuple.iter().map(|item| self.to_value(&item, record_ids)).collect::<PyResult<_>>().map(Value::Array);
+        }
+        if obj.is_instance_of::<PySet>() || obj.is_instance_of::<PyFrozenSet>() {
+            return obj.try_iter()?.map(|item| self.to_value(&item?, record_ids)).collect::<PyResult<_>>().map(Value::Array);
+        }
+        if let Ok(dict) = obj.downcast::<PyDict>() {
+            let mut entries = Vec::with_capacity(dict.len());
+            for (k, v) in dict.iter() {
+                let key = k.downcast::<PyString>().map_err(|_| {
+                    PyTypeError::new_err(format!("Object keys must be str, got {}", k.get_type().name().map(|n| n.to_string()).unwrap_or_default()))
+                })?;
+                entries.push((Value::Text(key.to_str()?.to_string()), self.to_value(&v, record_ids)?));
+            }
+            return Ok(Value::Map(entries));
+        }
+        // datetime must be checked before date, since it is a subclass.
+        if let Ok(at) = obj.downcast::<PyDateTime>() {
+            let micros = chrono::NaiveDate::from_ymd_opt(at.get_year(), at.get_month().into(), at.get_day().into())
+                .and_then(|date| {
+                    date.and_hms_micro_opt(at.get_hour().into(), at.get_minute().into(), at.get_second().into(), at.get_microsecond())
+                })
+                .map(|at| at.and_utc().timestamp_micros())
+                .ok_or_else(|| PyValueError::new_err("datetime out of range"))?;
+            let offset = match at.call_method0("utcoffset")? {
+                offset if offset.is_none() => 0,
+                offset => delta_micros(offset.downcast::<PyDelta>()?),
+            };
+            return Ok(compact(TAG_DATETIME_COMPACT, micros - offset, 1_000_000));
+        }
+        if let Ok(date) = obj.downcast::<PyDate>() {
+            let days = chrono::NaiveDate::from_ymd_opt(date.get_year(), date.get_month().into(), date.get_day().into())
+                .map(|date| date.and_time(chrono::NaiveTime::MIN).and_utc().timestamp())
+                .ok_or_else(|| PyValueError::new_err("date out of range"))?;
+            return Ok(compact(TAG_DATETIME_COMPACT, days, 1));
+        }
+        if let Ok(delta) = obj.downcast::<PyDelta>() {
+            let micros = delta_micros(delta);
+            if micros < 0 {
+                return Err(PyValueError::new_err("SurrealDB durations cannot be negative"));
+            }
+            return Ok(compact(TAG_DURATION_COMPACT, micros, 1_000_000));
+        }
+        if obj.is_instance(&self.uuid)? {
+            return Ok(tagged(TAG_UUID, Value::Bytes(obj.getattr("bytes")?.extract()?)));
+        }
+        if obj.is_instance(&self.decimal)? {
+            return Ok(tagged(TAG_DECIMAL, Value::Text(obj.str()?.to_str()?.to_string())));
+        }
+        if obj.is_instance_of::<PyBytes>() || obj.is_instance_of::<PyByteArray>() {
+            return Ok(Value::Bytes(obj.extract()?));
+        }
+        py_to_value(obj)
+    }
+}
+
+fn delta_micros(delta: &Bound<'_, PyDelta>) -> i64 {
+    (i64::from(delta.get_days()) * 86_400 + i64::from(delta.get_seconds())) * 1_000_000 + i64::from(delta.get_microseconds())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    #[test]
+    fn python_values_encode_with_tags() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let modules = PyDict::new(py);
+            for name in ["datetime", "uuid", "decimal"] {
+                modules.set_item(name, py.import(name).unwrap()).unwrap();
+            }
+            let params = py
+                .eval(
+                    c"{'id': 'person:tobie', 'at': datetime.datetime(1970, 1, 1, 1, 0, 1, 5, \
+                       datetime.timezone(datetime.timedelta(hours=1))), 'day': datetime.date(1970, 1, 2), \
+                       'took': datetime.timedelta(seconds=90), 'uuid': uuid.UUID(int=1), \
+                       'price': decimal.Decimal('1.50'), 'nested': [('x', None)], 'raw': b'\\x01'}",
+                    None,
+                    Some(&modules),
+                )
+                .unwrap();
+            let encode = |record_ids| {
+                let bytes = encode_params(py, &params, record_ids).unwrap();
+                crate::pull::decode(bytes.bind(py).downcast::<PyBytes>().unwrap().as_bytes()).unwrap()
+            };
+            let compact = |tag, secs: i128, nanos: i128| {
+                tagged(tag, Value::Array(vec![Value::Integer(secs), Value::Integer(nanos)]))
+            };
+            let mut uuid = vec![0; 16];
+            uuid[15] = 1;
+            let record = |id| {
+                Value::Map(vec![
+                    (text("id"), id),
+                    (text("at"), compact(TAG_DATETIME_COMPACT, 1, 5_000)),
+                    (text("day"), compact(TAG_DATETIME_COMPACT, 86_400, 0)),
+                    (text("took"), compact(crate::tags::TAG_DURATION_COMPACT, 90, 0)),
+                    (text("uuid"), tagged(crate::tags::TAG_UUID, Value::Bytes(uuid.clone()))),
+                    (text("price"), tagged(crate::tags::TAG_DECIMAL, text("1.50"))),
+                    (text("nested"), Value::Array(vec![Value::Array(vec![text("x"), Value::Null])])),
+                    (text("raw"), Value::Bytes(vec![1])),
+                ])
+            };
+            let tobie = tagged(TAG_RECORDID, Value::Array(vec![text("person"), text("tobie")]));
+            assert_eq!(encode(true), record(tobie));
+            assert_eq!(encode(false), record(text("person:tobie")));
+
+            let err = encode_params(py, &py.eval(c"{1: 2}", None, None).unwrap(), true).unwrap_err();
+            assert!(err.is_instance_of::<PyTypeError>(py), "{}", err);
+            let err = encode_params(py, &py.eval(c"object()", None, None).unwrap(), true).unwrap_err();
+            assert!(err.is_instance_of::<PyTypeError>(py), "{}", err);
+        });
+    }
+}
@@ -0,0 +1,169 @@
+//! SQL over converted results, with the `datafusion` crate.
+//!
+//! Built with the `datafusion` feature. The batches of a statement are scanned
+//! as a table of one partition, converted as the query takes them, and the
+//! query runs in the accelerator, without pyarrow or the Python `datafusion`
+//! package.
+
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use arrow::array::{RecordBatch, RecordBatchReader};
+use arrow::datatypes::SchemaRef;
+use datafusion::catalog::streaming::StreamingTable;
+use datafusion::error::DataFusionError;
+use datafusion::execution::TaskContext;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::streaming::PartitionStream;
+use datafusion::physical_plan::SendableRecordBatchStream;
+use datafusion::prelude::SessionContext;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::export::{batch_error, statement_reader};
+use crate::frames::frame_options;
+use crate::pull::Payload;
+use crate::{output, CborInput, OutputMode};
+
+/// Convert a statement of a CBOR response and run the SQL query `sql` over it
+/// with DataFusion, returning the result as a `pyarrow.Table`.
+///
+/// The converted batches are registered as the table `table`, e.g.
+/// `query_arrow(data, "SELECT region, count(*) FROM t GROUP BY region")`. A
+/// statement without records cannot be queried unless `empty_schema` names its
+/// columns. Accepts the keyword options of `cbor_to_arrow` other than `output`.
+#[pyfunction]
+#[pyo3(signature = (data, sql, statement=0, table="t", **options))]
+pub(crate) fn query_arrow(
+    py: Python,
+    data: CborInput,
+    sql: &str,
+    statement: isize,
+    table: &str,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<PyObject> {
+    let opts = frame_options("query_arrow", options, OutputMode::Table)?;
+    opts.check_bytes(&data)?;
+    let payload = Arc::new(Payload::load(py, data, true, &opts)?);
+    let reader = statement_reader(py, &[payload], statement, &opts, false)?;
+    let (batches, schema) = py.allow_threads(|| run_query(reader, table, sql))?;
+    output::emit_batches(py, batches, schema, &opts)
+}
+
+/// Run `sql` over the batches of `reader`, registered as the table `table`.
+fn run_query(
+    reader: Box<dyn RecordBatchReader + Send>,
+    table: &str,
+    sql: &str,
+) -> PyResult<(Vec<RecordBatch>, SchemaRef)> {
+    let source = Arc::new(ReaderPartition::new(reader));
+    let provider = StreamingTable::try_new(source.schema.clone(), vec![source.clone()]).map_err(query_error)?;
+    let session = SessionContext::new();
+    session.register_table(table, Arc::new(provider)).map_err(query_error)?;
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let result = runtime.block_on(async {
+        let frame = session.sql(sql).await?;
+        let schema = frame.schema().inner().clone();
+        Ok::<_, DataFusionError>((frame.collect().await?, schema))
+    });
+    source.take_error()?;
+    result.map_err(query_error)
+}
+
+/// The batches of a reader as the one partition of a table, scanned once.
+pub(crate) struct ReaderPartition {
+    pub(crate) schema: SchemaRef,
+    reader: Mutex<Option<Box<dyn RecordBatchReader + Send>>>,
+    /// The error the first batch that failed to convert raised.
+    error: Arc<Mutex<Option<PyErr>>>,
+}
+
+impl ReaderPartition {
+    pub(crate) fn new(reader: Box<dyn RecordBatchReader + Send>) -> Self {
+        ReaderPartition { schema: reader.schema(), reader: Mutex::new(Some(reader)), error: Arc::default() }
+    }
+
+    /// Raise the error of the batch that failed to convert, which fails the
+    /// scan with a `DataFusionError` of its message only.
+    pub(crate) fn take_error(&self) -> PyResult<()> {
+        match self.error.lock().unwrap_or_else(PoisonError::into_inner).take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for ReaderPartition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReaderPartition").field("schema", &self.schema).finish_non_exhaustive()
+    }
+}
+
+impl PartitionStream for ReaderPartition {
+    fn schema(&self) -> &SchemaRef {
+        &self.schema
+    }
+
+    fn execute(&self, _ctx: Arc<TaskContext>) -> SendableRecordBatchStream {
+        let reader = self.reader.lock().unwrap_or_else(PoisonError::into_inner).take();
+        let error = self.error.clone();
+        let batches = reader.into_iter().flatten().map(move |batch| {
+            batch.map_err(|e| {
+                let message = e.to_string();
+                error.lock().unwrap_or_else(PoisonError::into_inner).get_or_insert(batch_error(e));
+                DataFusionError::Execution(message)
+            })
+        });
+        Box::pin(RecordBatchStreamAdapter::new(self.schema.clone(), futures::stream::iter(batches)))
+    }
+}
+
+fn query_error(error: DataFusionError) -> PyErr {
+    PyValueError::new_err(format!("DataFusion query error: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{AsArray, Int64Array, RecordBatchIterator, StringArray};
+    use arrow::datatypes::Int64Type;
+    use arrow::error::ArrowError;
+
+    use super::*;
+
+    fn regions() -> RecordBatch {
+        let regions = Arc::new(StringArray::from(vec!["eu", "us", "eu", "eu"])) as _;
+        let sizes = Arc::new(Int64Array::from(vec![1, 2, 3, 4])) as _;
+        RecordBatch::try_from_iter([("region", regions), ("size", sizes)]).unwrap()
+    }
+
+    fn query(batches: Vec<Result<RecordBatch, ArrowError>>, sql: &str) -> PyResult<Vec<RecordBatch>> {
+        let reader = RecordBatchIterator::new(batches, regions().schema());
+        Ok(run_query(Box::new(reader), "t", sql)?.0)
+    }
+
+    #[test]
+    fn queries_run_over_every_batch() {
+        pyo3::prepare_freethreaded_python();
+        let sql = "SELECT region, count(*) AS n, sum(size) AS total FROM t GROUP BY region ORDER BY region";
+        let batches = query(vec![Ok(regions()), Ok(regions())], sql).unwrap();
+        let result = arrow::compute::concat_batches(&batches[0].schema(), &batches).unwrap();
+        let region: Vec<_> = result.column(0).as_string::<i32>().iter().flatten().collect();
+        assert_eq!(region, ["eu", "us"]);
+        assert_eq!(result.column(1).as_primitive::<Int64Type>().values(), &[6, 2]);
+        assert_eq!(result.column(2).as_primitive::<Int64Type>().values(), &[16, 4]);
+    }
+
+    #[test]
+    fn failed_batches_and_queries_raise() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let failing = ArrowError::ExternalError(Box::new(pyo3::exceptions::PyKeyError::new_err("row 2")));
+            let err = query(vec![Ok(regions()), Err(failing)], "SELECT count(*) FROM t").unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyKeyError>(py));
+            let err = query(vec![Ok(regions())], "SELECT missing FROM t").unwrap_err();
+            assert!(err.is_instance_of::<PyValueError>(py));
+            assert!(err.to_string().contains("DataFusion query error"));
+        });
+    }
+}
@@ -0,0 +1,55 @@
+//! Lazy conversion behind `output="reader"`.
+
+use std::sync::Arc;
+
+use arrow::array::{RecordBatch, RecordBatchReader};
+use arrow::datatypes::SchemaRef;
+use arrow::error::ArrowError;
+use pyo3::prelude::*;
+
+use crate::pull::Payload;
+use crate::{records_at, BatchPlan, ConvertOptions, RecordsAt};
+
+/// Rows converted per batch by the reader unless `max_rows_per_batch` is set.
+const READER_BATCH_ROWS: usize = 65_536;
+
+/// Converts the records of one result a batch at a time, on demand.
+///
+/// Holds the scanned response so the records are never copied; each call to
+/// `next` decodes and converts the following slice with the schema inferred up front.
+pub(crate) struct LazyBatches {
+    payload: Arc<Payload>,
+    at: RecordsAt,
+    plan: BatchPlan,
+    opts: ConvertOptions,
+    pos: usize,
+}
+
+impl LazyBatches {
+    pub(crate) fn new(payload: Arc<Payload>, at: RecordsAt, plan: BatchPlan, opts: ConvertOptions) -> Self {
+        LazyBatches { payload, at, plan, opts, pos: 0 }
+    }
+}
+
+impl Iterator for LazyBatches {
+    type Item = Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let records = records_at(&self.payload, self.at);
+        if self.pos >= records.len() {
+            return None;
+        }
+        let end = (self.pos + self.opts.max_rows_per_batch.unwrap_or(READER_BATCH_ROWS)).min(records.len());
+        let batch = self.plan.build_rows(records.slice(self.pos, end), self.pos, &self.opts)
+            .and_then(|batch| Python::with_gil(|py| self.opts.warn_losses(py).and(self.opts.report_rows(py))).map(|_| batch))
+            .map_err(|e| ArrowError::ExternalError(Box::new(e)));
+        self.pos = end;
+        Some(batch)
+    }
+}
+
+impl RecordBatchReader for LazyBatches {
+    fn schema(&self) -> SchemaRef {
+        self.plan.schema.clone()
+    }
+}
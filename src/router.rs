@@ -0,0 +1,502 @@
+//! `FrameRouter`, handing the responses of requests in flight over one
+//! WebSocket to the requests they answer.
+//!
+//! The server answers requests in the order it finishes them, so a client with
+//! several requests in flight reads their responses back interleaved. The
+//! router keeps a waiter per pending request id; each frame read from the
+//! socket is fed to it, decoded and handed to the waiter of its id. Frames of no
+//! pending request, such as live query notifications, are left to the caller.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+
+use cbor4ii::core::Value;
+use pyo3::exceptions::{PyConnectionAbortedError, PyConnectionError, PyTimeoutError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::pull::Payload;
+use crate::pyvalue::value_to_native;
+use crate::{convert_statement, map_get, response_error, seconds, CborInput, ConvertOptions};
+
+/// Match the response frames of requests sent over one WebSocket to the
+/// requests, by id.
+///
+/// ```python
+/// router = FrameRouter()
+/// pending = router.expect("q1")
+/// await ws.send(build_query("q1", "SELECT * FROM person"))
+/// async for frame in ws:
+///     if not router.feed(frame):
+///         handle_notification(frame)
+/// table = pending.result()
+/// ```
+///
+/// `expect` returns a `PendingResponse` to wait on from any thread, and
+/// `expect_async` an asyncio future of the running loop. `feed` hands a frame to
+/// the request it answers and returns `True`, or returns `False` for a frame
+/// without an id (a live query notification) or of no pending request.
+///
+/// Without `statement` or options, the result of a request is the `result` of
+/// its response, converted as `cbor_to_dicts` converts values. With either, the
+/// response is that of a query, converted as `cbor_to_arrow` converts it with
+/// `statement` (default 0) and the options. A response reporting an error
+/// raises `QueryStatusError`.
+#[pyclass(frozen)]
+pub(crate) struct FrameRouter {
+    state: Mutex<RouterState>,
+}
+
+#[derive(Default)]
+struct RouterState {
+    pending: HashMap<RequestId, Waiter>,
+    /// Why the router was closed, once it was.
+    closed: Option<String>,
+}
+
+/// A request id, a string or an integer.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum RequestId {
+    Text(String),
+    Integer(i128),
+}
+
+/// Who waits for a response, and how to convert it.
+struct Waiter {
+    target: Target,
+    /// The statement and options to convert a query response with, if given.
+    query: Option<(isize, Option<Py<PyDict>>)>,
+}
+
+enum Target {
+    Response(Py<PendingResponse>),
+    Future { event_loop: PyObject, future: PyObject },
+}
+
+#[pymethods]
+impl FrameRouter {
+    #[new]
+    fn new() -> Self {
+        FrameRouter { state: Mutex::new(RouterState::default()) }
+    }
+
+    /// Wait for the response to the request `id`, a string or int.
+    #[pyo3(signature = (id, statement=None, **options))]
+    fn expect(
+        &self,
+        py: Python,
+        id: &Bound<'_, PyAny>,
+        statement: Option<isize>,
+        options: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<Py<PendingResponse>> {
+        let pending = Py::new(
+            py,
+            PendingResponse { id: id.clone().unbind(), outcome: Mutex::new(None), settled: Condvar::new() },
+        )?;
+        self.add(id, statement, options, Target::Response(pending.clone_ref(py)))?;
+        Ok(pending)
+    }
+
+    /// `expect`, as an asyncio future of the running loop.
+    #[pyo3(signature = (id, statement=None, **options))]
+    fn expect_async<'py>(
+        &self,
+        id: &Bound<'py, PyAny>,
+        statement: Option<isize>,
+        options: Option<&Bound<'py, PyDict>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let event_loop = id.py().import("asyncio")?.call_method0("get_running_loop")?;
+        let future = event_loop.call_method0("create_future")?;
+        let target = Target::Future { event_loop: event_loop.unbind(), future: future.clone().unbind() };
+        self.add(id, statement, options, target)?;
+        Ok(future)
+    }
+
+    /// Hand the response frame `frame` to the request it answers. Returns
+    /// whether a pending request took it. Raises `CborDecodeError` for a frame
+    /// that is not CBOR, as it cannot tell whose it is.
+    fn feed(&self, py: Python, frame: CborInput) -> PyResult<bool> {
+        let root = frame.decode(py)?;
+        let id = match &root {
+            Value::Map(map) => map_get(map, "id").and_then(RequestId::of_value),
+            _ => None,
+        };
+        let Some(waiter) = id.and_then(|id| self.state.lock().unwrap().pending.remove(&id)) else {
+            return Ok(false);
+        };
+        let outcome = match response_error(&root) {
+            Some(err) => Err(err),
+            None => waiter.convert(py, &frame, &root),
+        };
+        waiter.target.settle(py, outcome);
+        Ok(true)
+    }
+
+    /// Stop waiting for the response to `id`: its `PendingResponse` raises
+    /// `ConnectionAbortedError` and its future is cancelled. Returns whether the
+    /// request was pending.
+    fn cancel(&self, py: Python, id: &Bound<'_, PyAny>) -> PyResult<bool> {
+        let id = RequestId::of_py(id)?;
+        let Some(waiter) = self.state.lock().unwrap().pending.remove(&id) else {
+            return Ok(false);
+        };
+        match &waiter.target {
+            Target::Future { event_loop, future } => {
+                let _ = event_loop.call_method1(py, "call_soon_threadsafe", (future.getattr(py, "cancel")?,));
+            }
+            Target::Response(_) => {
+                waiter.target.settle(py, Err(PyConnectionAbortedError::new_err("the request was cancelled")));
+            }
+        }
+        Ok(true)
+    }
+
+    /// Fail every pending request with `ConnectionError`, as when the
+    /// connection was lost, and any expected afterwards.
+    #[pyo3(signature = (reason=None))]
+    fn close(&self, py: Python, reason: Option<String>) {
+        let reason = reason.unwrap_or_else(|| "the connection is closed".to_string());
+        let pending = {
+            let mut state = self.state.lock().unwrap();
+            state.closed = Some(reason.clone());
+            std::mem::take(&mut state.pending)
+        };
+        for waiter in pending.into_values() {
+            waiter.target.settle(py, Err(PyConnectionError::new_err(reason.clone())));
+        }
+    }
+
+    /// The number of requests waiting for their response.
+    #[getter]
+    fn pending(&self) -> usize {
+        self.state.lock().unwrap().pending.len()
+    }
+
+    fn __repr__(&self) -> String {
+        let state = self.state.lock().unwrap();
+        match state.closed {
+            Some(_) => "FrameRouter(closed)".to_string(),
+            None => format!("FrameRouter(pending={})", state.pending.len()),
+        }
+    }
+}
+
+impl FrameRouter {
+    fn add(
+        &self,
+        id: &Bound<'_, PyAny>,
+        statement: Option<isize>,
+        options: Option<&Bound<'_, PyDict>>,
+        target: Target,
+    ) -> PyResult<()> {
+        let key = RequestId::of_py(id)?;
+        let query = match (statement, options) {
+            (None, None) => None,
+            (statement, options) => {
+                // Invalid options are reported now rather than with the response.
+                ConvertOptions::from_kwargs(options)?;
+                Some((statement.unwrap_or(0), options.map(|options| options.clone().unbind())))
+            }
+        };
+        let mut state = self.state.lock().unwrap();
+        if let Some(reason) = &state.closed {
+            return Err(PyConnectionError::new_err(reason.clone()));
+        }
+        if state.pending.contains_key(&key) {
+            return Err(PyValueError::new_err(format!("request {} is already pending", id.repr()?)));
+        }
+        state.pending.insert(key, Waiter { target, query });
+        Ok(())
+    }
+}
+
+impl RequestId {
+    fn of_py(id: &Bound<'_, PyAny>) -> PyResult<RequestId> {
+        if let Ok(id) = id.extract::<String>() {
+            return Ok(RequestId::Text(id));
+        }
+        match id.extract::<i128>() {
+            Ok(id) => Ok(RequestId::Integer(id)),
+            Err(_) => Err(PyTypeError::new_err("request ids are strings or ints")),
+        }
+    }
+
+    fn of_value(id: &Value) -> Option<RequestId> {
+        match id {
+            Value::Text(id) => Some(RequestId::Text(id.clone())),
+            Value::Integer(id) => Some(RequestId::Integer(*id)),
+            _ => None,
+        }
+    }
+}
+
+impl Waiter {
+    /// The result of the response `frame`, decoded as `root`.
+    fn convert(&self, py: Python, frame: &CborInput, root: &Value) -> PyResult<PyObject> {
+        let Some((statement, options)) = &self.query else {
+            let result = match root {
+                Value::Map(map) => map_get(map, "result"),
+                _ => None,
+            };
+            return value_to_native(py, result.unwrap_or(&Value::Null));
+        };
+        let opts = ConvertOptions::from_kwargs(options.as_ref().map(|options| options.bind(py)))?;
+        opts.check_len(frame.as_bytes().len())?;
+        let payload = Arc::new(Payload::load_owned(py, frame.as_bytes().to_vec(), true, &opts)?);
+        convert_statement(py, &payload, *statement, &opts)
+    }
+}
+
+impl Target {
+    /// Hand `outcome` to the waiter.
+    fn settle(&self, py: Python, outcome: PyResult<PyObject>) {
+        match self {
+            Target::Response(pending) => {
+                let pending = pending.get();
+                *pending.outcome.lock().unwrap() = Some(outcome);
+                pending.settled.notify_all();
+            }
+            Target::Future { event_loop, future } => {
+                let (value, error) = match outcome {
+                    Ok(value) => (value, py.None()),
+                    Err(err) => (py.None(), err.into_value(py).into_any()),
+                };
+                // Futures are only touched on their loop's thread. A closed loop
+                // has no one left waiting.
+                let _ = wrap_pyfunction!(settle_future, py).and_then(|settle| {
+                    event_loop.call_method1(py, "call_soon_threadsafe", (settle, future, value, error))
+                });
+            }
+        }
+    }
+}
+
+/// Set the result or exception of `future`, unless it was cancelled.
+#[pyfunction]
+fn settle_future(future: &Bound<'_, PyAny>, value: PyObject, error: PyObject) -> PyResult<()> {
+    if future.call_method0("done")?.is_truthy()? {
+        return Ok(());
+    }
+    match error.is_none(future.py()) {
+        true => future.call_method1("set_result", (value,))?,
+        false => future.call_method1("set_exception", (error,))?,
+    };
+    Ok(())
+}
+
+/// The response to a request of a `FrameRouter`, once it has come.
+#[pyclass(frozen)]
+pub(crate) struct PendingResponse {
+    id: PyObject,
+    outcome: Mutex<Option<PyResult<PyObject>>>,
+    settled: Condvar,
+}
+
+#[pymethods]
+impl PendingResponse {
+    /// The result of the request, waiting up to `timeout` seconds (without
+    /// limit if `None`) for its response. Raises what converting the response
+    /// raised, and `TimeoutError` if it has not come in time.
+    #[pyo3(signature = (timeout=None))]
+    fn result(&self, py: Python, timeout: Option<f64>) -> PyResult<PyObject> {
+        let timeout = timeout.map(|timeout| seconds("timeout", timeout)).transpose()?;
+        let settled = py.allow_threads(|| {
+            let outcome = self.outcome.lock().unwrap();
+            match timeout {
+                Some(timeout) => {
+                    let (outcome, _) = self.settled.wait_timeout_while(outcome, timeout, |o| o.is_none()).unwrap();
+                    outcome.is_some()
+                }
+                None => self.settled.wait_while(outcome, |o| o.is_none()).unwrap().is_some(),
+            }
+        });
+        if !settled {
+            return Err(PyTimeoutError::new_err(format!(
+                "no response to request {} within {} s",
+                self.id.bind(py).repr()?,
+                timeout.unwrap_or_default().as_secs_f64()
+            )));
+        }
+        match self.outcome.lock().unwrap().as_ref() {
+            Some(Ok(value)) => Ok(value.clone_ref(py)),
+            Some(Err(err)) => Err(err.clone_ref(py)),
+            None => unreachable!("the response was settled"),
+        }
+    }
+
+    /// Whether the response has come, or the request failed.
+    #[getter]
+    fn done(&self) -> bool {
+        self.outcome.lock().unwrap().is_some()
+    }
+
+    /// The id of the request.
+    #[getter]
+    fn id(&self, py: Python) -> PyObject {
+        self.id.clone_ref(py)
+    }
+
+    fn __repr__(&self, py: Python) -> PyResult<String> {
+        let state = if self.done() { "done" } else { "pending" };
+        Ok(format!("PendingResponse({}, {})", self.id.bind(py).repr()?, state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::AsArray;
+    use arrow::datatypes::Int64Type;
+    use pyo3::exceptions::PyConnectionAbortedError;
+    use pyo3::ffi::c_str;
+    use pyo3::types::PyBytes;
+    use pyo3::IntoPyObjectExt;
+    use pyo3_arrow::PyRecordBatch;
+
+    use super::*;
+    use crate::encode::encode;
+    use crate::errors::{CborDecodeError, QueryStatusError};
+
+    fn text(value: &str) -> Value {
+        Value::Text(value.to_string())
+    }
+
+    /// The response to the request `id`, with `value` under `key`.
+    fn response(id: Value, key: &str, value: Value) -> Vec<u8> {
+        encode(&Value::Map(vec![(text("id"), id), (text(key), value)]))
+    }
+
+    /// A query response of one statement with `status`, selecting `n` from 0 to 2.
+    fn query_response(id: &str, status: &str) -> Vec<u8> {
+        let records = (0..3).map(|n| Value::Map(vec![(text("n"), Value::Integer(n))])).collect();
+        let statement = vec![(text("status"), text(status)), (text("result"), Value::Array(records))];
+        response(text(id), "result", Value::Array(vec![Value::Map(statement)]))
+    }
+
+    fn feed(router: &FrameRouter, py: Python, frame: &[u8]) -> PyResult<bool> {
+        router.feed(py, PyBytes::new(py, frame).extract()?)
+    }
+
+    /// `id` as a Python object.
+    fn py_id<'py>(py: Python<'py>, id: impl IntoPyObject<'py>) -> Bound<'py, PyAny> {
+        id.into_bound_py_any(py).unwrap()
+    }
+
+    fn expect<'py>(router: &FrameRouter, py: Python<'py>, id: impl IntoPyObject<'py>) -> PyResult<Py<PendingResponse>> {
+        router.expect(py, &py_id(py, id), None, None)
+    }
+
+    #[test]
+    fn responses_go_to_the_request_of_their_id() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let router = FrameRouter::new();
+            let options = PyDict::new(py);
+            options.set_item("output", "batch").unwrap();
+            let query = router.expect(py, &py_id(py, "q1"), Some(0), Some(&options)).unwrap();
+            let answer = expect(&router, py, 2).unwrap();
+            assert_eq!(router.__repr__(), "FrameRouter(pending=2)");
+
+            // Answered in the reverse order of the requests.
+            let result = Value::Map(vec![(text("a"), Value::Integer(1))]);
+            assert!(feed(&router, py, &response(Value::Integer(2), "result", result)).unwrap());
+            assert!(answer.get().done() && !query.get().done());
+            let value = answer.get().result(py, None).unwrap();
+            assert_eq!(value.bind(py).get_item("a").unwrap().extract::<i64>().unwrap(), 1);
+            // Notifications, and responses of no pending request, are left to the caller.
+            let notification = encode(&Value::Map(vec![(text("result"), Value::Map(Vec::new()))]));
+            assert!(!feed(&router, py, &notification).unwrap());
+            assert!(!feed(&router, py, &response(text("q9"), "result", Value::Null)).unwrap());
+            assert!(feed(&router, py, &query_response("q1", "OK")).unwrap());
+            let batch = query.get().result(py, Some(0.0)).unwrap().extract::<PyRecordBatch>(py).unwrap().into_inner();
+            assert_eq!(batch.column_by_name("n").unwrap().as_primitive::<Int64Type>().values(), &[0, 1, 2]);
+            assert_eq!(router.pending(), 0);
+            // A response is only handed over once.
+            assert!(!feed(&router, py, &query_response("q1", "OK")).unwrap());
+
+            let failed = expect(&router, py, "q2").unwrap();
+            let error = vec![(text("code"), Value::Integer(-32000)), (text("message"), text("refused"))];
+            assert!(feed(&router, py, &response(text("q2"), "error", Value::Map(error))).unwrap());
+            let err = failed.get().result(py, None).unwrap_err();
+            assert!(err.is_instance_of::<QueryStatusError>(py));
+            assert!(err.to_string().ends_with("refused"), "{}", err);
+            let failed = router.expect(py, &py_id(py, "q3"), Some(0), None).unwrap();
+            assert!(feed(&router, py, &query_response("q3", "ERR")).unwrap());
+            assert!(failed.get().result(py, None).unwrap_err().is_instance_of::<QueryStatusError>(py));
+            assert!(feed(&router, py, b"\xff").unwrap_err().is_instance_of::<CborDecodeError>(py));
+        });
+    }
+
+    #[test]
+    fn requests_fail_when_late_cancelled_or_closed() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let router = Py::new(py, FrameRouter::new()).unwrap();
+            let router = router.get();
+            let late = expect(router, py, "a").unwrap();
+            let err = late.get().result(py, Some(0.05)).unwrap_err();
+            assert_eq!(err.to_string(), "TimeoutError: no response to request 'a' within 0.05 s");
+            let err = expect(router, py, "a").unwrap_err();
+            assert_eq!(err.to_string(), "ValueError: request 'a' is already pending");
+            let err = expect(router, py, 1.5).unwrap_err();
+            assert_eq!(err.to_string(), "TypeError: request ids are strings or ints");
+            let options = PyDict::new(py);
+            options.set_item("output", "nope").unwrap();
+            // Bad options are reported as the request is expected.
+            let err = router.expect(py, &py_id(py, "b"), None, Some(&options)).unwrap_err();
+            assert!(err.is_instance_of::<PyValueError>(py));
+
+            assert!(router.cancel(py, &py_id(py, "a")).unwrap());
+            assert!(!router.cancel(py, &py_id(py, "a")).unwrap());
+            let err = late.get().result(py, None).unwrap_err();
+            assert!(err.is_instance_of::<PyConnectionAbortedError>(py));
+            assert_eq!(err.to_string(), "ConnectionAbortedError: the request was cancelled");
+
+            // Waited for with the GIL released, while another thread feeds the response.
+            let waited = expect(router, py, "c").unwrap();
+            let fed = std::thread::scope(|scope| {
+                let feeding = scope.spawn(|| {
+                    std::thread::sleep(std::time::Duration::from_millis(50));
+                    Python::with_gil(|py| feed(router, py, &response(text("c"), "result", Value::Integer(3))).unwrap())
+                });
+                let value = waited.get().result(py, Some(5.0)).unwrap();
+                assert_eq!(value.extract::<i64>(py).unwrap(), 3);
+                py.allow_threads(|| feeding.join().unwrap())
+            });
+            assert!(fed);
+
+            let lost = expect(router, py, "d").unwrap();
+            router.close(py, Some("the socket was lost".to_string()));
+            assert_eq!(router.__repr__(), "FrameRouter(closed)");
+            let err = lost.get().result(py, None).unwrap_err();
+            assert_eq!(err.to_string(), "ConnectionError: the socket was lost");
+            let err = expect(router, py, "e").unwrap_err();
+            assert_eq!(err.to_string(), "ConnectionError: the socket was lost");
+        });
+    }
+
+    #[test]
+    fn futures_are_settled_on_their_loop() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let code = c_str!(
+                "import asyncio\n\
+                 async def route(router, frame):\n    \
+                     answered = router.expect_async(7)\n    \
+                     cancelled = router.expect_async(8)\n    \
+                     asyncio.get_running_loop().call_soon(router.feed, frame)\n    \
+                     router.cancel(8)\n    \
+                     try:\n        \
+                         await cancelled\n    \
+                     except asyncio.CancelledError:\n        \
+                         return await answered\n"
+            );
+            let module = PyModule::from_code(py, code, c_str!("router_test.py"), c_str!("router_test")).unwrap();
+            let router = Py::new(py, FrameRouter::new()).unwrap();
+            let frame = PyBytes::new(py, &response(Value::Integer(7), "result", text("seven")));
+            let coroutine = module.getattr("route").unwrap().call1((&router, frame)).unwrap();
+            let answer = py.import("asyncio").unwrap().call_method1("run", (coroutine,)).unwrap();
+            assert_eq!(answer.extract::<String>().unwrap(), "seven");
+            assert_eq!(router.get().pending(), 0);
+        });
+    }
+}
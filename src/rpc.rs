@@ -0,0 +1,581 @@
+//! Building of SurrealDB RPC requests: the frames of the RPC methods, and bulk
+//! writes from Arrow data.
+//!
+//! A request is a CBOR map `{id, method, params}`, the frame a websocket
+//! connection sends, so a client only moves bytes between the socket and these
+//! functions. Records are encoded as `arrow_to_cbor` encodes them, so bulk data
+//! goes from Arrow to bytes on the wire without Python objects in between.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use arrow::array::RecordBatch;
+use cbor4ii::core::Value;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyList};
+
+use crate::encode::{batch_records, encode, tagged, ArrowInput};
+use crate::pyvalue::{py_to_value, surreal_value};
+use crate::surrealql;
+use crate::tags::{TAG_NONE, TAG_RECORDID};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A new request id: `rs-1`, `rs-2`, ... Unique within the process, and apart
+/// from the numeric ids `RawSurrealConnection` counts.
+pub(crate) fn next_id() -> String {
+    format!("rs-{}", NEXT_ID.fetch_add(1, Ordering::Relaxed))
+}
+
+/// The CBOR frame of a request calling `method` with `params`, with a new id.
+pub(crate) fn request(method: &str, params: Vec<Value>) -> Vec<u8> {
+    request_with_id(Value::Text(next_id()), method, params)
+}
+
+/// The CBOR frame of the request `id` calling `method` with `params`.
+pub(crate) fn request_with_id(id: Value, method: &str, params: Vec<Value>) -> Vec<u8> {
+    encode(&Value::Map(vec![
+        (Value::Text("id".to_string()), id),
+        (Value::Text("method".to_string()), Value::Text(method.to_string())),
+        (Value::Text("params".to_string()), Value::Array(params)),
+    ]))
+}
+
+/// The frame of a request, as bytes. `id` is a string or int, or `None` for a
+/// new id from `next_id`.
+fn frame(py: Python, id: Option<&Bound<'_, PyAny>>, method: &str, params: Vec<Value>) -> PyResult<PyObject> {
+    let id = match id {
+        Some(id) if !id.is_none() => py_to_value(id)?,
+        _ => Value::Text(next_id()),
+    };
+    Ok(PyBytes::new(py, &request_with_id(id, method, params)).into_any().unbind())
+}
+
+fn text(s: &str) -> Value {
+    Value::Text(s.to_string())
+}
+
+/// Build a `query` request running the SurrealQL `sql` with the variables
+/// `params`, encoded as `encode_params` encodes them.
+///
+/// Like every `build_*` function, it returns the CBOR frame as `bytes`; `id` is
+/// the request id to match the response by, a string or int, or `None` for a
+/// new unique id.
+#[pyfunction]
+#[pyo3(signature = (id, sql, params=None, record_ids=true))]
+pub(crate) fn build_query(
+    py: Python,
+    id: Option<&Bound<'_, PyAny>>,
+    sql: &str,
+    params: Option<&Bound<'_, PyDict>>,
+    record_ids: bool,
+) -> PyResult<PyObject> {
+    let vars = match params {
+        Some(params) => surreal_value(params, record_ids)?,
+        None => Value::Map(Vec::new()),
+    };
+    frame(py, id, "query", vec![text(sql), vars])
+}
+
+/// Build a `use` request switching to the namespace `ns` and database `db`;
+/// `None` keeps the current one.
+#[pyfunction]
+#[pyo3(signature = (id, ns=None, db=None))]
+pub(crate) fn build_use(py: Python, id: Option<&Bound<'_, PyAny>>, ns: Option<&str>, db: Option<&str>) -> PyResult<PyObject> {
+    let param = |name: Option<&str>| name.map_or(tagged(TAG_NONE, Value::Null), text);
+    frame(py, id, "use", vec![param(ns), param(db)])
+}
+
+/// Build a `signin` request: as a root, namespace or database user with
+/// `username` and `password`, or through the record `access` method of
+/// `namespace` and `database` with the variables `vars`.
+#[pyfunction]
+#[pyo3(signature = (id, username=None, password=None, namespace=None, database=None, access=None, **vars))]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn build_signin(
+    py: Python,
+    id: Option<&Bound<'_, PyAny>>,
+    username: Option<&str>,
+    password: Option<&str>,
+    namespace: Option<&str>,
+    database: Option<&str>,
+    access: Option<&str>,
+    vars: Option<&Bound<'_, PyDict>>,
+) -> PyResult<PyObject> {
+    let params = credentials(
+        [("user", username), ("pass", password), ("NS", namespace), ("DB", database), ("AC", access)],
+        vars,
+    )?;
+    frame(py, id, "signin", vec![params])
+}
+
+/// Build a `signup` request creating a record user through the record `access`
+/// method of `namespace` and `database`, with the variables `vars`.
+#[pyfunction]
+#[pyo3(signature = (id, namespace, database, access, **vars))]
+pub(crate) fn build_signup(
+    py: Python,
+    id: Option<&Bound<'_, PyAny>>,
+    namespace: &str,
+    database: &str,
+    access: &str,
+    vars: Option<&Bound<'_, PyDict>>,
+) -> PyResult<PyObject> {
+    let params = credentials([("NS", Some(namespace)), ("DB", Some(database)), ("AC", Some(access))], vars)?;
+    frame(py, id, "signup", vec![params])
+}
+
+/// The map of the given credentials and `vars`.
+pub(crate) fn credentials<const N: usize>(given: [(&str, Option<&str>); N], vars: Option<&Bound<'_, PyDict>>) -> PyResult<Value> {
+    let mut entries: Vec<(Value, Value)> =
+        given.into_iter().filter_map(|(key, value)| Some((text(key), text(value?)))).collect();
+    if let Some(Value::Map(vars)) = vars.map(|vars| surreal_value(vars, false)).transpose()? {
+        entries.extend(vars);
+    }
+    Ok(Value::Map(entries))
+}
+
+/// Build an `authenticate` request signing in with the token of an earlier signin.
+#[pyfunction]
+#[pyo3(signature = (id, token))]
+pub(crate) fn build_authenticate(py: Python, id: Option<&Bound<'_, PyAny>>, token: &str) -> PyResult<PyObject> {
+    frame(py, id, "authenticate", vec![text(token)])
+}
+
+/// Build an `invalidate` request ending the session's authentication.
+#[pyfunction]
+#[pyo3(signature = (id))]
+pub(crate) fn build_invalidate(py: Python, id: Option<&Bound<'_, PyAny>>) -> PyResult<PyObject> {
+    frame(py, id, "invalidate", Vec::new())
+}
+
+/// Build a `let` request setting the session variable `name` (without `$`) to
+/// `value`, encoded as `encode_params` encodes it.
+#[pyfunction]
+#[pyo3(signature = (id, name, value, record_ids=true))]
+pub(crate) fn build_let(
+    py: Python,
+    id: Option<&Bound<'_, PyAny>>,
+    name: &str,
+    value: &Bound<'_, PyAny>,
+    record_ids: bool,
+) -> PyResult<PyObject> {
+    frame(py, id, "let", vec![text(name), surreal_value(value, record_ids)?])
+}
+
+/// Build an `unset` request removing the session variable `name`.
+#[pyfunction]
+#[pyo3(signature = (id, name))]
+pub(crate) fn build_unset(py: Python, id: Option<&Bound<'_, PyAny>>, name: &str) -> PyResult<PyObject> {
+    frame(py, id, "unset", vec![text(name)])
+}
+
+/// Build a `live` request starting a live query on `table`; with `diff=True`
+/// notifications carry JSON Patch changes instead of whole records.
+#[pyfunction]
+#[pyo3(signature = (id, table, diff=false))]
+pub(crate) fn build_live(py: Python, id: Option<&Bound<'_, PyAny>>, table: &str, diff: bool) -> PyResult<PyObject> {
+    frame(py, id, "live", vec![text(table), Value::Bool(diff)])
+}
+
+/// Build a `kill` request stopping the live query `live_id`, a `uuid.UUID` or
+/// its string.
+#[pyfunction]
+#[pyo3(signature = (id, live_id))]
+pub(crate) fn build_kill(py: Python, id: Option<&Bound<'_, PyAny>>, live_id: &Bound<'_, PyAny>) -> PyResult<PyObject> {
+    frame(py, id, "kill", vec![surreal_value(live_id, false)?])
+}
+
+/// Build a request calling `method` with `params`, for the methods without a
+/// builder of their own (`ping`, `version`, `info`, `select`, `delete`, ...).
+/// `params` are encoded as `encode_params` encodes them.
+#[pyfunction]
+#[pyo3(signature = (id, method, params=None, record_ids=true))]
+pub(crate) fn build_request(
+    py: Python,
+    id: Option<&Bound<'_, PyAny>>,
+    method: &str,
+    params: Option<&Bound<'_, PyList>>,
+    record_ids: bool,
+) -> PyResult<PyObject> {
+    let params = match params.map(|params| surreal_value(params, record_ids)).transpose()? {
+        Some(Value::Array(params)) => params,
+        _ => Vec::new(),
+    };
+    frame(py, id, method, params)
+}
+
+/// The rows of `batches` as records, in chunks of `chunk_rows`; only the last
+/// chunk may be shorter. Each chunk is converted as it is needed, so at most one
+/// chunk of records is held at a time.
+pub(crate) fn record_chunks(
+    batches: &[RecordBatch],
+    chunk_rows: usize,
+    record_ids: &[String],
+    mut chunk: impl FnMut(Vec<Value>) -> PyResult<()>,
+) -> PyResult<()> {
+    if chunk_rows == 0 {
+        return Err(PyValueError::new_err("chunk_rows must be positive"));
+    }
+    let mut pending = Vec::new();
+    for batch in batches {
+        let mut offset = 0;
+        while offset < batch.num_rows() {
+            let len = (chunk_rows - pending.len()).min(batch.num_rows() - offset);
+            pending.extend(batch_records(&batch.slice(offset, len), record_ids, false)?);
+            offset += len;
+            if pending.len() == chunk_rows {
+                chunk(std::mem::take(&mut pending))?;
+            }
+        }
+    }
+    match pending.is_empty() {
+        true => Ok(()),
+        false => chunk(pending),
+    }
+}
+
+/// Build the `insert` requests writing the rows of a pyarrow RecordBatch or
+/// Table to `table`, `chunk_rows` records to a request.
+///
+/// Returns a list of CBOR frames ready to send on a SurrealDB websocket, each
+/// with its own request id; empty data gives an empty list. Values are encoded
+/// as `arrow_to_cbor` encodes them, with the strings of the `record_ids`
+/// columns as record ids.
+#[pyfunction]
+#[pyo3(signature = (table, batch, chunk_rows=10_000, record_ids=vec!["id".to_string()]))]
+pub(crate) fn build_insert_payload<'py>(
+    py: Python<'py>,
+    table: &str,
+    batch: ArrowInput,
+    chunk_rows: usize,
+    record_ids: Vec<String>,
+) -> PyResult<Bound<'py, PyList>> {
+    let batches = batch.into_batches();
+    let frames = py.allow_threads(|| -> PyResult<Vec<Vec<u8>>> {
+        let mut frames = Vec::new();
+        record_chunks(&batches, chunk_rows, &record_ids, |records| {
+            frames.push(request("insert", vec![Value::Text(table.to_string()), Value::Array(records)]));
+            Ok(())
+        })?;
+        Ok(frames)
+    })?;
+    PyList::new(py, frames.iter().map(|frame| PyBytes::new(py, frame)))
+}
+
+/// Render the rows of a pyarrow RecordBatch or Table as SurrealQL
+/// `INSERT INTO table [...];` statements of `rows_per_statement` records each,
+/// for deployments reached only over HTTP (the `/sql` endpoint).
+///
+/// Values are those `build_insert_payload` sends, written as SurrealQL literals:
+/// `d'...'` datetimes, `...dec` decimals, `u'...'` UUIDs and escaped record ids
+/// for the strings of the `record_ids` columns. Returns a list of statements.
+#[pyfunction]
+#[pyo3(signature = (table, batch, rows_per_statement=1000, record_ids=vec!["id".to_string()]))]
+pub(crate) fn batch_to_insert_sql(
+    py: Python,
+    table: &str,
+    batch: ArrowInput,
+    rows_per_statement: usize,
+    record_ids: Vec<String>,
+) -> PyResult<Vec<String>> {
+    if rows_per_statement == 0 {
+        return Err(PyValueError::new_err("rows_per_statement must be positive"));
+    }
+    let batches = batch.into_batches();
+    py.allow_threads(|| {
+        let mut statements = Vec::new();
+        let prefix = format!("INSERT INTO {} ", surrealql::escape_rid(table));
+        record_chunks(&batches, rows_per_statement, &record_ids, |records| {
+            let mut sql = prefix.clone();
+            let records = Value::Array(records);
+            surrealql::write_value(&records, &mut sql)
+                .ok_or_else(|| PyValueError::new_err("a value has no SurrealQL literal"))?;
+            sql.push(';');
+            statements.push(sql);
+            Ok(())
+        })?;
+        Ok(statements)
+    })
+}
+
+/// Build the `insert_relation` requests creating an edge in `edge_table` for
+/// each row of a pyarrow RecordBatch or Table, `chunk_rows` edges to a request.
+///
+/// The `in` and `out` columns hold the record ids the edges link, as `table:key`
+/// strings or `{tb, id}` structs; the other columns are properties of the edges,
+/// with an `id` column of record ids naming them. Returns a list of CBOR frames,
+/// as `build_insert_payload` does.
+#[pyfunction]
+#[pyo3(signature = (edge_table, batch, chunk_rows=10_000))]
+pub(crate) fn build_relate_payload<'py>(
+    py: Python<'py>,
+    edge_table: &str,
+    batch: ArrowInput,
+    chunk_rows: usize,
+) -> PyResult<Bound<'py, PyList>> {
+    let batches = batch.into_batches();
+    let record_ids = ["in", "out", "id"].map(String::from);
+    let frames = py.allow_threads(|| -> PyResult<Vec<Vec<u8>>> {
+        let mut frames = Vec::new();
+        let mut row = 0;
+        record_chunks(&batches, chunk_rows, &record_ids, |records| {
+            for record in &records {
+                for end in ["in", "out"] {
+                    if !matches!(field(record, end), Some(Value::Tag(TAG_RECORDID, _))) {
+                        return Err(no_record_id(row, end));
+                    }
+                }
+                row += 1;
+            }
+            frames.push(request("insert_relation", vec![Value::Text(edge_table.to_string()), Value::Array(records)]));
+            Ok(())
+        })?;
+        Ok(frames)
+    })?;
+    PyList::new(py, frames.iter().map(|frame| PyBytes::new(py, frame)))
+}
+
+/// The value of the `name` field of a record built by `batch_records`.
+fn field<'a>(record: &'a Value, name: &str) -> Option<&'a Value> {
+    match record {
+        Value::Map(entries) => entries.iter().find(|(k, _)| matches!(k, Value::Text(k) if k == name)).map(|(_, v)| v),
+        _ => None,
+    }
+}
+
+/// Build a `merge` request for each row of a pyarrow RecordBatch or Table,
+/// patching the record named in `id_column` with the other columns of the row.
+///
+/// With `method="upsert"` the requests replace the records instead, creating
+/// those that do not exist. With `sparse=True`, the default, null columns are
+/// left out of the patch and keep their values; otherwise they are set to null.
+/// Returns a list of CBOR frames, one a record, as `build_insert_payload` does.
+#[pyfunction]
+#[pyo3(signature = (batch, id_column="id", method="merge", sparse=true))]
+pub(crate) fn build_merge_payload<'py>(
+    py: Python<'py>,
+    batch: ArrowInput,
+    id_column: &str,
+    method: &str,
+    sparse: bool,
+) -> PyResult<Bound<'py, PyList>> {
+    if !matches!(method, "merge" | "upsert") {
+        return Err(PyValueError::new_err(format!(
+            "unknown method {:?}; expected \"merge\" or \"upsert\"",
+            method
+        )));
+    }
+    let batches = batch.into_batches();
+    let record_ids = [id_column.to_string()];
+    let frames = py.allow_threads(|| -> PyResult<Vec<Vec<u8>>> {
+        let mut frames = Vec::new();
+        for batch in &batches {
+            for record in batch_records(batch, &record_ids, !sparse)? {
+                let Value::Map(mut entries) = record else { unreachable!() };
+                let id = entries.iter().position(|(k, _)| matches!(k, Value::Text(k) if k == id_column));
+                let id = match id.map(|i| entries.remove(i).1) {
+                    Some(id @ Value::Tag(TAG_RECORDID, _)) => id,
+                    _ => return Err(no_record_id(frames.len(), id_column)),
+                };
+                frames.push(request(method, vec![id, Value::Map(entries)]));
+            }
+        }
+        Ok(frames)
+    })?;
+    PyList::new(py, frames.iter().map(|frame| PyBytes::new(py, frame)))
+}
+
+fn no_record_id(row: usize, column: &str) -> PyErr {
+    PyValueError::new_err(format!("row {} has no record id in column {}", row, column))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, Int64Array, StringArray};
+    use pyo3_arrow::PyRecordBatch;
+
+    use super::*;
+
+    #[test]
+    fn insert_statements_keep_hostile_keys_quoted() {
+        pyo3::prepare_freethreaded_python();
+        let ids = StringArray::from(vec!["user:⟨x\\\\\\⟩; DELETE user; --⟩", "user:⟨a\\\\⟩"]);
+        let names = StringArray::from(vec!["x\\'); DELETE user; --", "b"]);
+        let batch = RecordBatch::try_from_iter([("id", Arc::new(ids) as _), ("name", Arc::new(names) as _)]).unwrap();
+        let statements = Python::with_gil(|py| {
+            let input = ArrowInput::Batch(PyRecordBatch::new(batch));
+            batch_to_insert_sql(py, "user⟩", input, 1, vec!["id".to_string()]).unwrap()
+        });
+        assert_eq!(
+            statements,
+            [
+                "INSERT INTO ⟨user\\⟩⟩ [{ id: user:⟨x\\\\\\⟩; DELETE user; --⟩, name: \"x\\\\'); DELETE user; --\" }];",
+                "INSERT INTO ⟨user\\⟩⟩ [{ id: user:⟨a\\\\⟩, name: 'b' }];",
+            ]
+        );
+        let (table, key) = surrealql::parse_record_id("user:⟨x\\\\\\⟩; DELETE user; --⟩").unwrap();
+        assert_eq!((table.as_str(), key), ("user", Value::Text("x\\⟩; DELETE user; --".to_string())));
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    /// The method and params of each request frame of `frames`.
+    fn requests(frames: &[Bound<PyAny>]) -> Vec<(String, Vec<Value>)> {
+        frames
+            .iter()
+            .map(|frame| {
+                let Value::Map(entries) = crate::pull::decode(frame.extract::<&[u8]>().unwrap()).unwrap() else {
+                    panic!("the frame is no map")
+                };
+                let get = |key: &str| entries.iter().find(|(k, _)| *k == text(key)).unwrap().1.clone();
+                assert!(matches!(get("id"), Value::Text(id) if id.starts_with("rs-")));
+                let (Value::Text(method), Value::Array(params)) = (get("method"), get("params")) else {
+                    panic!("{:?}", entries)
+                };
+                (method, params)
+            })
+            .collect()
+    }
+
+    fn numbers(n: std::ops::Range<i64>) -> RecordBatch {
+        let ids: StringArray = n.clone().map(|i| Some(format!("person:{}", i))).collect();
+        let numbers = Int64Array::from_iter_values(n);
+        RecordBatch::try_from_iter([("id", Arc::new(ids) as ArrayRef), ("n", Arc::new(numbers) as _)]).unwrap()
+    }
+
+    fn person(i: i128) -> Value {
+        Value::Map(vec![
+            (text("id"), tagged(TAG_RECORDID, Value::Array(vec![text("person"), Value::Integer(i)]))),
+            (text("n"), Value::Integer(i)),
+        ])
+    }
+
+    #[test]
+    fn inserts_are_chunked() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let batches = vec![numbers(0..3), numbers(3..5)];
+            let table = ArrowInput::Table(pyo3_arrow::PyTable::try_new(batches, numbers(0..0).schema()).unwrap());
+            let frames = build_insert_payload(py, "person", table, 2, vec!["id".to_string()]).unwrap();
+            let frames: Vec<Bound<PyAny>> = frames.extract().unwrap();
+            let chunk = |rows: std::ops::Range<i128>| {
+                ("insert".to_string(), vec![text("person"), Value::Array(rows.map(person).collect())])
+            };
+            // Chunks run across batches.
+            assert_eq!(requests(&frames), [chunk(0..2), chunk(2..4), chunk(4..5)]);
+
+            let input = || ArrowInput::Batch(PyRecordBatch::new(numbers(0..0)));
+            assert!(build_insert_payload(py, "person", input(), 2, Vec::new()).unwrap().is_empty());
+            let err = build_insert_payload(py, "person", input(), 0, Vec::new()).unwrap_err();
+            assert!(err.is_instance_of::<PyValueError>(py), "{}", err);
+        });
+    }
+
+    #[test]
+    fn relations_link_record_ids() {
+        pyo3::prepare_freethreaded_python();
+        let edges = |outs: Vec<&str>| {
+            let ins = StringArray::from(vec!["person:1", "person:2"]);
+            let since = Int64Array::from(vec![2020, 2021]);
+            let columns = [("in", ins), ("out", StringArray::from(outs))];
+            let mut columns: Vec<(&str, ArrayRef)> = columns.into_iter().map(|(k, v)| (k, Arc::new(v) as _)).collect();
+            columns.push(("since", Arc::new(since)));
+            ArrowInput::Batch(PyRecordBatch::new(RecordBatch::try_from_iter(columns).unwrap()))
+        };
+        let id = |table: &str, key: i128| tagged(TAG_RECORDID, Value::Array(vec![text(table), Value::Integer(key)]));
+        let edge = |from, to, since| {
+            Value::Map(vec![
+                (text("in"), id("person", from)),
+                (text("out"), id("post", to)),
+                (text("since"), Value::Integer(since)),
+            ])
+        };
+        Python::with_gil(|py| {
+            let frames: Vec<Bound<PyAny>> =
+                build_relate_payload(py, "wrote", edges(vec!["post:7", "post:8"]), 10).unwrap().extract().unwrap();
+            let expected = vec![text("wrote"), Value::Array(vec![edge(1, 7, 2020), edge(2, 8, 2021)])];
+            assert_eq!(requests(&frames), [("insert_relation".to_string(), expected)]);
+
+            let err = build_relate_payload(py, "wrote", edges(vec!["post:7", "nowhere"]), 10).unwrap_err();
+            assert_eq!(err.value(py).to_string(), "row 1 has no record id in column out");
+        });
+    }
+
+    #[test]
+    fn merges_patch_each_record() {
+        pyo3::prepare_freethreaded_python();
+        let batch = || {
+            let ids = StringArray::from(vec!["person:1", "person:2"]);
+            let names = StringArray::from(vec![Some("a"), None]);
+            let batch = RecordBatch::try_from_iter([("id", Arc::new(ids) as ArrayRef), ("name", Arc::new(names) as _)]);
+            ArrowInput::Batch(PyRecordBatch::new(batch.unwrap()))
+        };
+        let id = |key| tagged(TAG_RECORDID, Value::Array(vec![text("person"), Value::Integer(key)]));
+        Python::with_gil(|py| {
+            let merge = |method, sparse| -> Vec<(String, Vec<Value>)> {
+                requests(&build_merge_payload(py, batch(), "id", method, sparse).unwrap().extract::<Vec<_>>().unwrap())
+            };
+            let patch = |method: &str, key, fields: Vec<(Value, Value)>| {
+                (method.to_string(), vec![id(key), Value::Map(fields)])
+            };
+            let name = |value| vec![(text("name"), value)];
+            assert_eq!(merge("merge", true), [patch("merge", 1, name(text("a"))), patch("merge", 2, Vec::new())]);
+            // Without sparse, null columns are set to null.
+            let upserts = [patch("upsert", 1, name(text("a"))), patch("upsert", 2, name(Value::Null))];
+            assert_eq!(merge("upsert", false), upserts);
+            let err = build_merge_payload(py, batch(), "id", "update", true).unwrap_err();
+            assert!(err.is_instance_of::<PyValueError>(py), "{}", err);
+            let err = build_merge_payload(py, batch(), "name", "merge", true).unwrap_err();
+            assert_eq!(err.value(py).to_string(), "row 0 has no record id in column name");
+        });
+    }
+
+    #[test]
+    fn rpc_frames_are_built() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let id = 7i64.into_pyobject(py).unwrap().into_any();
+            let vars = PyDict::new(py);
+            vars.set_item("who", "person:1").unwrap();
+            let person = tagged(TAG_RECORDID, Value::Array(vec![text("person"), Value::Integer(1)]));
+            let none = tagged(TAG_NONE, Value::Null);
+            let frames = [
+                build_query(py, Some(&id), "SELECT * FROM $who", Some(&vars), true).unwrap(),
+                build_use(py, Some(&id), Some("ns"), None).unwrap(),
+                build_signin(py, Some(&id), Some("root"), Some("pw"), None, None, None, None).unwrap(),
+                build_let(py, Some(&id), "x", &"person:1".into_pyobject(py).unwrap(), false).unwrap(),
+                build_live(py, Some(&id), "person", true).unwrap(),
+                build_kill(py, Some(&id), &"abc".into_pyobject(py).unwrap()).unwrap(),
+                build_request(py, Some(&id), "ping", None, true).unwrap(),
+            ];
+            let expected = [
+                ("query", vec![text("SELECT * FROM $who"), Value::Map(vec![(text("who"), person)])]),
+                ("use", vec![text("ns"), none]),
+                ("signin", vec![Value::Map(vec![(text("user"), text("root")), (text("pass"), text("pw"))])]),
+                ("let", vec![text("x"), text("person:1")]),
+                ("live", vec![text("person"), Value::Bool(true)]),
+                ("kill", vec![text("abc")]),
+                ("ping", Vec::new()),
+            ];
+            for (frame, (method, params)) in frames.iter().zip(expected) {
+                let request = crate::pull::decode(frame.extract::<&[u8]>(py).unwrap()).unwrap();
+                let expected = Value::Map(vec![
+                    (text("id"), Value::Integer(7)),
+                    (text("method"), text(method)),
+                    (text("params"), Value::Array(params)),
+                ]);
+                assert_eq!(request, expected);
+            }
+            // Without an id, each frame gets a new one.
+            let frames: Vec<_> = (0..2).map(|_| build_invalidate(py, None).unwrap().into_bound(py)).collect();
+            let invalidate = ("invalidate".to_string(), Vec::new());
+            assert_eq!(requests(&frames), [invalidate.clone(), invalidate]);
+            assert!(!frames[0].eq(&frames[1]).unwrap());
+        });
+    }
+}
@@ -0,0 +1,379 @@
+//! Combining schemas inferred from separate chunks of one result.
+//!
+//! serde_arrow traces a schema from a slice held entirely in memory. Records that
+//! are decoded a chunk at a time are traced chunk by chunk (with null-only fields
+//! allowed) and the chunk schemas merged here, giving the schema a single trace
+//! over all records would have produced.
+//!
+//! A schema remembered from an earlier call is checked here against new records
+//! before it is used for them in place of a trace.
+
+use std::sync::Arc;
+
+use arrow::datatypes::{DataType, Field, FieldRef, Fields, TimeUnit, UnionFields, UnionMode, DECIMAL256_MAX_PRECISION};
+use cbor4ii::core::Value;
+
+use crate::builder::{decimal_value, resolve};
+use crate::tags::{self, tag_kind, SurrealTag};
+use crate::{is_null, key_string, BigInt, ConvertOptions, ObjectsAs, RecordIdMode, TypeConflicts, UnknownTagPolicy, UuidMode};
+
+/// Merge two sets of traced fields. Fields missing on one side become nullable.
+/// Types differing between the sides are combined as the tracing options would
+/// have combined them within one trace, or as `type_conflicts` says.
+pub(crate) fn merge_fields(a: &[FieldRef], b: &[FieldRef], opts: &ConvertOptions) -> Result<Vec<FieldRef>, String> {
+    let mut merged = Vec::with_capacity(a.len().max(b.len()));
+    for field in a {
+        merged.push(match b.iter().find(|other| other.name() == field.name()) {
+            Some(other) => merge_field(field, other, opts)?,
+            None => Arc::new(field.as_ref().clone().with_nullable(true)),
+        });
+    }
+    for field in b {
+        if !a.iter().any(|other| other.name() == field.name()) {
+            merged.push(Arc::new(field.as_ref().clone().with_nullable(true)));
+        }
+    }
+    // serde_arrow traces maps as structs with sorted fields.
+    merged.sort_by(|x, y| x.name().cmp(y.name()));
+    Ok(merged)
+}
+
+fn merge_field(a: &FieldRef, b: &FieldRef, opts: &ConvertOptions) -> Result<FieldRef, String> {
+    let tracing = &opts.tracing;
+    let nullable = a.is_nullable() || b.is_nullable();
+    let data_type = match (a.data_type(), b.data_type()) {
+        // A chunk that only saw nulls says nothing about the type.
+        (DataType::Null, _) => return Ok(Arc::new(b.as_ref().clone().with_nullable(nullable))),
+        (_, DataType::Null) => return Ok(Arc::new(a.as_ref().clone().with_nullable(nullable))),
+        _ if is_json(a) || is_json(b) => return Ok(json_field(a.name(), nullable)),
+        (DataType::Struct(x), DataType::Struct(y)) => DataType::Struct(Fields::from(merge_fields(x, y, opts)?)),
+        (DataType::List(x), DataType::List(y)) => DataType::List(merge_field(x, y, opts)?),
+        (DataType::LargeList(x), DataType::LargeList(y)) => DataType::LargeList(merge_field(x, y, opts)?),
+        (DataType::Map(x, sorted), DataType::Map(y, _)) => DataType::Map(merge_field(x, y, opts)?, *sorted),
+        (x, y) if x == y => x.clone(),
+        // Decimal fractions with more digits after the point in one chunk, or too
+        // wide for Decimal128.
+        (DataType::Decimal128(p, s), DataType::Decimal128(_, t)) => DataType::Decimal128(*p, *s.max(t)),
+        (DataType::Decimal128(_, s) | DataType::Decimal256(_, s), DataType::Decimal128(_, t) | DataType::Decimal256(_, t)) => {
+            DataType::Decimal256(DECIMAL256_MAX_PRECISION, *s.max(t))
+        }
+        // A chunk with big integers next to one without.
+        (x @ DataType::Decimal256(..), DataType::Int64 | DataType::UInt64)
+        | (DataType::Int64 | DataType::UInt64, x @ DataType::Decimal256(..))
+            if opts.big_int == BigInt::Decimal =>
+        {
+            x.clone()
+        }
+        (x @ (DataType::Utf8 | DataType::LargeUtf8), DataType::Int64 | DataType::UInt64)
+        | (DataType::Int64 | DataType::UInt64, x @ (DataType::Utf8 | DataType::LargeUtf8))
+            if opts.big_int == BigInt::String =>
+        {
+            x.clone()
+        }
+        (x, y) if (tracing.coerce_numbers || promotes_numbers(opts))
+            && is_number(x)
+            && is_number(y) =>
+        {
+            if x.is_floating() || y.is_floating() {
+                DataType::Float64
+            } else {
+                DataType::Int64
+            }
+        }
+        // Strings guessed as dates in one chunk but not in another stay strings.
+        (DataType::Timestamp(TimeUnit::Millisecond, _), x @ (DataType::Utf8 | DataType::LargeUtf8))
+        | (x @ (DataType::Utf8 | DataType::LargeUtf8), DataType::Timestamp(TimeUnit::Millisecond, _))
+            if tracing.guess_dates =>
+        {
+            x.clone()
+        }
+        (x @ (DataType::Utf8 | DataType::LargeUtf8), y) | (y, x @ (DataType::Utf8 | DataType::LargeUtf8))
+            if tracing.allow_to_string && (is_number(y) || *y == DataType::Boolean) =>
+        {
+            x.clone()
+        }
+        // Datetimes and durations are traced as Int64 and only refined when every
+        // value agrees, so a mix across chunks stays Int64.
+        (x, y) if is_nanos(x) && is_nanos(y) => DataType::Int64,
+        // Likewise UUIDs refined to FixedSizeBinary(16) next to other bytes.
+        (x @ (DataType::Binary | DataType::LargeBinary), DataType::FixedSizeBinary(16))
+        | (DataType::FixedSizeBinary(16), x @ (DataType::Binary | DataType::LargeBinary)) => x.clone(),
+        (x, y) if opts.type_conflicts == TypeConflicts::Error => {
+            return Err(format!("conflicting types for field {}: {} and {}", a.name(), x, y))
+        }
+        _ if opts.type_conflicts == TypeConflicts::Json => return Ok(json_field(a.name(), nullable)),
+        // A union's nulls are those of its variants, so it is always nullable.
+        (x, y) if opts.type_conflicts == TypeConflicts::Union => {
+            return Ok(Arc::new(Field::new(a.name(), union_of(x, y, opts)?, true)))
+        }
+        // Whatever else does not combine is kept as text.
+        _ => DataType::LargeUtf8,
+    };
+    // Keep only the metadata both sides agree on, e.g. drop the geoarrow
+    // extension type if one chunk held plain bytes.
+    let metadata = a
+        .metadata()
+        .iter()
+        .filter(|(k, v)| b.metadata().get(*k) == Some(*v))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    Ok(Arc::new(Field::new(a.name(), data_type, nullable).with_metadata(metadata)))
+}
+
+/// True for a column of JSON text, marked with the `arrow.json` extension type.
+pub(crate) fn is_json(field: &Field) -> bool {
+    field.metadata().get("ARROW:extension:name").is_some_and(|name| name == "arrow.json")
+}
+
+fn json_field(name: &str, nullable: bool) -> FieldRef {
+    let metadata = [("ARROW:extension:name".to_string(), "arrow.json".to_string())].into();
+    Arc::new(Field::new(name, DataType::LargeUtf8, nullable).with_metadata(metadata))
+}
+
+/// True if `type_conflicts` widens integers next to floats.
+fn promotes_numbers(opts: &ConvertOptions) -> bool {
+    matches!(opts.type_conflicts, TypeConflicts::Promote | TypeConflicts::Json | TypeConflicts::Union)
+}
+
+/// A union with the variants of `a` and `b`. Variants of the same container type
+/// are merged; other types are kept as separate variants.
+fn union_of(a: &DataType, b: &DataType, opts: &ConvertOptions) -> Result<DataType, String> {
+    let mut variants: Vec<FieldRef> = Vec::new();
+    for variant in union_variants(a).into_iter().chain(union_variants(b)) {
+        let same = variants.iter().position(|v| match (v.data_type(), variant.data_type()) {
+            (DataType::Struct(_), DataType::Struct(_))
+            | (DataType::List(_), DataType::List(_))
+            | (DataType::LargeList(_), DataType::LargeList(_))
+            | (DataType::Map(..), DataType::Map(..)) => true,
+            (x, y) => x == y,
+        });
+        match same {
+            Some(i) => variants[i] = merge_field(&variants[i], &variant, opts)?,
+            None => variants.push(variant),
+        }
+    }
+    let mut names: Vec<String> = Vec::with_capacity(variants.len());
+    for variant in &variants {
+        let base = variant_name(variant.data_type());
+        let (mut name, mut n) = (base.clone(), 1);
+        while names.contains(&name) {
+            n += 1;
+            name = format!("{}_{}", base, n);
+        }
+        names.push(name);
+    }
+    let fields = variants.iter().zip(names).map(|(v, name)| Field::clone(v).with_name(name).with_nullable(true));
+    let mode = if opts.sparse_unions { UnionMode::Sparse } else { UnionMode::Dense };
+    Ok(DataType::Union(UnionFields::new(0..variants.len() as i8, fields), mode))
+}
+
+fn union_variants(data_type: &DataType) -> Vec<FieldRef> {
+    match data_type {
+        DataType::Union(fields, _) => fields.iter().map(|(_, f)| f.clone()).collect(),
+        other => vec![Arc::new(Field::new(variant_name(other), other.clone(), true))],
+    }
+}
+
+/// The name of a union variant holding `data_type`.
+fn variant_name(data_type: &DataType) -> String {
+    match data_type {
+        DataType::Boolean => "bool",
+        DataType::Int64 => "int",
+        DataType::UInt64 => "uint",
+        DataType::Float64 => "float",
+        DataType::Utf8 | DataType::LargeUtf8 => "string",
+        DataType::Binary | DataType::LargeBinary => "bytes",
+        DataType::FixedSizeBinary(16) => "uuid",
+        DataType::Timestamp(..) => "datetime",
+        DataType::Duration(_) => "duration",
+        DataType::Struct(_) => "struct",
+        DataType::List(_) | DataType::LargeList(_) => "list",
+        DataType::Map(..) => "map",
+        other => return other.to_string().to_lowercase(),
+    }
+    .to_string()
+}
+
+fn is_number(data_type: &DataType) -> bool {
+    matches!(data_type, DataType::Int64 | DataType::UInt64 | DataType::Float64)
+}
+
+fn is_nanos(data_type: &DataType) -> bool {
+    matches!(data_type, DataType::Int64 | DataType::Timestamp(_, _) | DataType::Duration(_))
+}
+
+/// The first field (searching depth first) whose type is still `Null`.
+pub(crate) fn null_only_field(fields: &[FieldRef]) -> Option<&str> {
+    fields.iter().find_map(|field| match field.data_type() {
+        DataType::Null => Some(field.name().as_str()),
+        DataType::Struct(children) => null_only_field(children),
+        DataType::List(element) | DataType::LargeList(element) | DataType::Map(element, _) => {
+            null_only_field(std::slice::from_ref(element))
+        }
+        _ => None,
+    })
+}
+
+/// `fields` with every field, and the fields of structs and lists in them, nullable.
+pub(crate) fn all_nullable(fields: &[FieldRef]) -> Vec<FieldRef> {
+    fields.iter().map(nullable_field).collect()
+}
+
+fn nullable_field(field: &FieldRef) -> FieldRef {
+    let data_type = match field.data_type() {
+        DataType::Struct(children) => DataType::Struct(Fields::from(all_nullable(children))),
+        DataType::List(element) => DataType::List(nullable_field(element)),
+        DataType::LargeList(element) => DataType::LargeList(nullable_field(element)),
+        other => other.clone(),
+    };
+    Arc::new(field.as_ref().clone().with_data_type(data_type).with_nullable(true))
+}
+
+/// `fields` with the 64-bit offset string, binary and list types serde_arrow traces
+/// replaced, at any depth, by their 32-bit offset counterparts.
+pub(crate) fn small_types(fields: &[FieldRef]) -> Vec<FieldRef> {
+    fields.iter().map(small_field).collect()
+}
+
+fn small_field(field: &FieldRef) -> FieldRef {
+    let data_type = match field.data_type() {
+        DataType::LargeUtf8 => DataType::Utf8,
+        DataType::LargeBinary => DataType::Binary,
+        DataType::Struct(children) => DataType::Struct(Fields::from(small_types(children))),
+        DataType::List(element) | DataType::LargeList(element) => DataType::List(small_field(element)),
+        DataType::Map(entries, sorted) => DataType::Map(small_field(entries), *sorted),
+        DataType::Union(variants, mode) => {
+            let ids = variants.iter().map(|(id, _)| id);
+            DataType::Union(UnionFields::new(ids, variants.iter().map(|(_, f)| Field::clone(&small_field(f)))), *mode)
+        }
+        _ => return field.clone(),
+    };
+    Arc::new(field.as_ref().clone().with_data_type(data_type))
+}
+
+/// Turn the struct types in `field` into maps from string keys to the merged type
+/// of the struct's fields, as serde_arrow traces objects without `map_as_struct`.
+pub(crate) fn structs_to_maps(field: &FieldRef, opts: &ConvertOptions) -> Result<FieldRef, String> {
+    let data_type = match field.data_type() {
+        DataType::Struct(children) => {
+            let mut value: Option<FieldRef> = None;
+            for child in children {
+                let child = structs_to_maps(child, opts)?;
+                value = Some(match value {
+                    Some(value) => merge_field(&value, &child, opts)?,
+                    None => child,
+                });
+            }
+            let value = match value {
+                Some(value) => value.as_ref().clone().with_name("value").with_nullable(true),
+                None => Field::new("value", DataType::Null, true),
+            };
+            let entries = Field::new_struct("entries", vec![Field::new("key", DataType::LargeUtf8, false), value], false);
+            DataType::Map(Arc::new(entries), false)
+        }
+        DataType::List(element) => DataType::List(structs_to_maps(element, opts)?),
+        DataType::LargeList(element) => DataType::LargeList(structs_to_maps(element, opts)?),
+        _ => return Ok(field.clone()),
+    };
+    Ok(Arc::new(field.as_ref().clone().with_data_type(data_type)))
+}
+
+/// True if the record `map` converts with `fields` without losing anything: every
+/// member has a field of its type and every non-nullable field is present.
+/// Members named in `split_ids` must be record ids; their columns are the split ones.
+/// Members left out by `columns` are not checked.
+pub(crate) fn conforms(fields: &[FieldRef], map: &[(Value, Value)], split_ids: &[String], opts: &ConvertOptions) -> bool {
+    let members_fit = map.iter().all(|(k, v)| {
+        let name = key_string(k, opts.map_keys);
+        if !opts.selects(&name) {
+            return true;
+        }
+        if split_ids.iter().any(|id| *id == name) {
+            return is_null(v) || tag_kind(v) == Some(SurrealTag::RecordId);
+        }
+        member_fits(fields, &name, v, opts)
+    });
+    members_fit && required_present(fields, map, opts)
+}
+
+/// `conforms` for a nested object.
+fn struct_conforms(fields: &[FieldRef], map: &[(Value, Value)], opts: &ConvertOptions) -> bool {
+    map.iter().all(|(k, v)| member_fits(fields, &key_string(k, opts.map_keys), v, opts)) && required_present(fields, map, opts)
+}
+
+fn member_fits(fields: &[FieldRef], name: &str, value: &Value, opts: &ConvertOptions) -> bool {
+    fields.iter().find(|f| f.name() == name).is_some_and(|f| value_conforms(f, Some(value), opts))
+}
+
+fn required_present(fields: &[FieldRef], map: &[(Value, Value)], opts: &ConvertOptions) -> bool {
+    fields.iter().all(|f| f.is_nullable() || map.iter().any(|(k, _)| key_string(k, opts.map_keys) == *f.name()))
+}
+
+/// True if `value` converts into a column of `field` as traced.
+pub(crate) fn value_conforms(field: &Field, value: Option<&Value>, opts: &ConvertOptions) -> bool {
+    let Some(value) = resolve(value, opts) else {
+        return field.is_nullable();
+    };
+    if is_json(field) {
+        return true;
+    }
+    let data_type = match field.data_type() {
+        DataType::Dictionary(_, values) => values.as_ref(),
+        data_type => data_type,
+    };
+    match (data_type, value) {
+        (DataType::Struct(fields), Value::Map(map)) => struct_conforms(fields, map, opts),
+        (DataType::List(element) | DataType::LargeList(element), Value::Array(items)) => {
+            items.iter().all(|item| value_conforms(element, Some(item), opts))
+        }
+        (DataType::Map(entries, _), Value::Map(map)) => match entries.data_type() {
+            DataType::Struct(kv) => map.iter().all(|(_, v)| value_conforms(&kv[1], Some(v), opts)),
+            _ => false,
+        },
+        (DataType::Union(variants, _), value) => variants.iter().any(|(_, f)| value_conforms(f, Some(value), opts)),
+        (DataType::Decimal128(precision, scale) | DataType::Decimal256(precision, scale), value) => {
+            decimal_value(value, *precision, *scale, false).is_some()
+        }
+        (data_type, Value::Tag(tag, payload)) if SurrealTag::of(*tag) == SurrealTag::BigNum => {
+            match tags::bignum_i128(*tag, payload) {
+                Some(i) => value_conforms(field, Some(&Value::Integer(i)), opts),
+                None => matches!(data_type, DataType::Utf8 | DataType::LargeUtf8) && opts.big_int == BigInt::String,
+            }
+        }
+        (data_type, Value::Tag(tag, _)) => tag_conforms(data_type, SurrealTag::of(*tag), opts),
+        (DataType::Boolean, Value::Bool(_)) | (DataType::Float64, Value::Float(_)) => true,
+        (DataType::Float64, Value::Integer(_)) => opts.tracing.coerce_numbers || promotes_numbers(opts),
+        (DataType::Int64, Value::Integer(i)) => i64::try_from(*i).is_ok(),
+        (DataType::UInt64, Value::Integer(i)) => u64::try_from(*i).is_ok(),
+        (DataType::Utf8 | DataType::LargeUtf8, Value::Text(_)) => true,
+        (DataType::Utf8 | DataType::LargeUtf8, Value::Integer(_)) => opts.big_int == BigInt::String,
+        (DataType::Utf8 | DataType::LargeUtf8, Value::Map(_)) => opts.objects_as == ObjectsAs::Json,
+        (DataType::Binary | DataType::LargeBinary, Value::Bytes(_)) => true,
+        _ => false,
+    }
+}
+
+/// True if a SurrealDB tag of `kind` is traced as `data_type`. Tags serialized as
+/// structs are not checked further.
+fn tag_conforms(data_type: &DataType, kind: SurrealTag, opts: &ConvertOptions) -> bool {
+    let text = matches!(data_type, DataType::Utf8 | DataType::LargeUtf8);
+    let bytes = matches!(data_type, DataType::Binary | DataType::LargeBinary);
+    let structure = matches!(data_type, DataType::Struct(_));
+    match kind {
+        SurrealTag::Datetime => matches!(data_type, DataType::Timestamp(_, _) | DataType::Int64),
+        SurrealTag::Duration => matches!(data_type, DataType::Duration(_) | DataType::Int64),
+        SurrealTag::Uuid => match opts.uuid_mode {
+            UuidMode::String => text,
+            UuidMode::Binary => bytes || *data_type == DataType::FixedSizeBinary(16),
+        },
+        SurrealTag::RecordId => match opts.record_id_mode {
+            RecordIdMode::Struct => structure,
+            _ => text,
+        },
+        SurrealTag::Table | SurrealTag::Decimal | SurrealTag::DecimalFraction => text,
+        SurrealTag::Geometry => bytes,
+        SurrealTag::Range => structure,
+        SurrealTag::Unknown => opts.on_unknown_tag == UnknownTagPolicy::Raw && structure,
+        _ => false,
+    }
+}
@@ -0,0 +1,151 @@
+//! Pattern-based selection of record members with `include` and `exclude`.
+//!
+//! Patterns match the dotted path of a member, e.g. `meta.created`; the objects
+//! in an array share the array's path. Records left encoded in a response are
+//! filtered as they are decoded (see `pull`), so excluded subtrees are never
+//! materialized. Records decoded whole are filtered by copying what is kept.
+
+use std::borrow::Cow;
+
+use cbor4ii::core::Value;
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use regex::Regex;
+
+use crate::{key_string, MapKeys};
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Selection {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    /// How non-text keys are named in paths.
+    pub(crate) keys: MapKeys,
+}
+
+/// What to do with one member of an object.
+pub(crate) enum Member {
+    Drop,
+    /// Keep the member and everything in it.
+    Keep,
+    /// Keep what is selected inside the member; `included` if an include
+    /// pattern already matched it or an object it is in.
+    Descend { included: bool },
+}
+
+impl Selection {
+    /// The selection for the `include` and `exclude` options, or `None` if both are empty.
+    pub(crate) fn new(include: Vec<Regex>, exclude: Vec<Regex>, keys: MapKeys) -> Option<Self> {
+        (!include.is_empty() || !exclude.is_empty()).then_some(Selection { include, exclude, keys })
+    }
+
+    pub(crate) fn member(&self, path: &str, included: bool) -> Member {
+        if self.exclude.iter().any(|re| re.is_match(path)) {
+            return Member::Drop;
+        }
+        let included = included || self.include.is_empty() || self.include.iter().any(|re| re.is_match(path));
+        match included && self.exclude.is_empty() {
+            true => Member::Keep,
+            false => Member::Descend { included },
+        }
+    }
+
+    /// A copy of `record` with only the selected members. Records other than
+    /// objects are kept whole.
+    pub(crate) fn record(&self, record: &Value) -> Value {
+        match record {
+            Value::Map(_) => self.select(record, &mut String::new(), false).unwrap_or(Value::Map(Vec::new())),
+            _ => record.clone(),
+        }
+    }
+
+    /// The selected part of `value` at `path`, or `None` if nothing in it is selected.
+    fn select(&self, value: &Value, path: &mut String, included: bool) -> Option<Value> {
+        match value {
+            Value::Map(map) => {
+                let mut entries = Vec::new();
+                for (k, v) in map {
+                    let len = push_member(path, &key_string(k, self.keys));
+                    match self.member(path, included) {
+                        Member::Drop => {}
+                        Member::Keep => entries.push((k.clone(), v.clone())),
+                        Member::Descend { included } => {
+                            if let Some(v) = self.select(v, path, included) {
+                                entries.push((k.clone(), v));
+                            }
+                        }
+                    }
+                    path.truncate(len);
+                }
+                (included || !entries.is_empty()).then_some(Value::Map(entries))
+            }
+            Value::Array(items) => {
+                let items: Vec<Option<Value>> = items.iter().map(|item| self.select(item, path, included)).collect();
+                (included || items.iter().any(Option::is_some))
+                    .then(|| Value::Array(items.into_iter().map(|item| item.unwrap_or(Value::Null)).collect()))
+            }
+            _ => included.then(|| value.clone()),
+        }
+    }
+}
+
+/// `records` with only the selected members, borrowed unchanged without a selection.
+pub(crate) fn records<'a>(select: Option<&Selection>, records: &'a [Value]) -> Cow<'a, [Value]> {
+    match select {
+        Some(select) => Cow::Owned(records.iter().map(|record| select.record(record)).collect()),
+        None => Cow::Borrowed(records),
+    }
+}
+
+/// Append the member `name` to `path`, returning the length to truncate back to.
+pub(crate) fn push_member(path: &mut String, name: &str) -> usize {
+    let len = path.len();
+    if !path.is_empty() {
+        path.push('.');
+    }
+    path.push_str(name);
+    len
+}
+
+/// Compile the patterns of the `include` or `exclude` option: strings are globs
+/// where `*` matches within one path segment, `**` across segments and `?` one
+/// character; compiled `re` patterns must match the whole path.
+pub(crate) fn parse_patterns(key: &str, value: &Bound<'_, PyAny>) -> PyResult<Vec<Regex>> {
+    let mut patterns = Vec::new();
+    for item in value.try_iter()? {
+        let item = item?;
+        let source = match item.extract::<String>() {
+            Ok(glob) => glob_regex(&glob),
+            Err(_) => match item.getattr("pattern").and_then(|p| p.extract::<String>()) {
+                Ok(pattern) => format!("^(?:{})$", pattern),
+                Err(_) => {
+                    return Err(PyTypeError::new_err(format!(
+                        "{} patterns must be strings or compiled regular expressions",
+                        key
+                    )))
+                }
+            },
+        };
+        let re = Regex::new(&source)
+            .map_err(|e| PyValueError::new_err(format!("invalid {} pattern '{}': {}", key, item, e)))?;
+        patterns.push(re);
+    }
+    Ok(patterns)
+}
+
+fn glob_regex(glob: &str) -> String {
+    let mut source = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                source.push_str(".*");
+            }
+            '*' => source.push_str("[^.]*"),
+            '?' => source.push_str("[^.]"),
+            c => source.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    source.push('$');
+    source
+}
@@ -0,0 +1,169 @@
+//! Incremental conversion of responses that arrive as many frames.
+
+use cbor4ii::core::Value;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::pull::Rows;
+use crate::{output, result_records, root_responses, select, statement_records, BatchPlan, CborInput, ConvertOptions};
+
+/// Default number of rows per flushed batch.
+const DEFAULT_BATCH_SIZE: usize = 65_536;
+
+/// Buffers records from pushed CBOR frames and converts them in fixed-size batches.
+///
+/// Each pushed frame is a complete RPC response (or, with `envelope=False`, a
+/// bare record array), or a CBOR sequence of them. Records from every statement are buffered in order;
+/// `flush()` converts them `batch_size` rows at a time. The schema of the first
+/// flushed batch (or the given `schema`) is kept for every later batch, whose
+/// values are coerced to it. Remaining keyword options are those of `cbor_to_arrow`.
+#[pyclass]
+pub(crate) struct StreamingConverter {
+    opts: ConvertOptions,
+    batch_size: usize,
+    envelope: bool,
+    buffer: Vec<Value>,
+    /// The plan of the first flushed batch, once known.
+    plan: Option<BatchPlan>,
+}
+
+#[pymethods]
+impl StreamingConverter {
+    #[new]
+    #[pyo3(signature = (batch_size=DEFAULT_BATCH_SIZE, envelope=true, **options))]
+    fn new(batch_size: usize, envelope: bool, options: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        if batch_size == 0 {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>("batch_size must be positive"));
+        }
+        Ok(StreamingConverter { opts: ConvertOptions::from_kwargs(options)?, batch_size, envelope, buffer: Vec::new(), plan: None })
+    }
+
+    /// Decode one frame and buffer its records. Returns the number of buffered rows.
+    /// `max_rows` bounds the number of buffered rows.
+    fn push(&mut self, py: Python, data: CborInput) -> PyResult<usize> {
+        self.opts.check_bytes(&data)?;
+        let roots = data.decode_sequence(py)?;
+        let mut frame = Vec::new();
+        for root in &roots {
+            if self.envelope {
+                for (index, response) in root_responses(root)?.iter().enumerate() {
+                    if let Some(records_arr) = statement_records(response, index)? {
+                        frame.push(records_arr);
+                    }
+                }
+            } else if let Some(records_arr) = result_records(root) {
+                frame.push(records_arr);
+            }
+        }
+        self.opts.check_rows(self.buffer.len() + frame.iter().map(|records| records.len()).sum::<usize>())?;
+        for records_arr in frame {
+            self.buffer.extend_from_slice(&select::records(self.opts.select.as_ref(), records_arr));
+        }
+        Ok(self.buffer.len())
+    }
+
+    /// Convert every complete batch of `batch_size` buffered rows. With
+    /// `final=True` the remaining rows are converted as well.
+    ///
+    /// A batch that fails to convert stays buffered: the batches before it are
+    /// returned, and the next `flush()` raises its error.
+    #[pyo3(signature = (r#final=false))]
+    fn flush(&mut self, py: Python, r#final: bool) -> PyResult<Vec<PyObject>> {
+        let mut batches = Vec::new();
+        let mut start = 0;
+        while self.buffer.len() - start >= self.batch_size || (r#final && start < self.buffer.len()) {
+            let end = (start + self.batch_size).min(self.buffer.len());
+            match self.convert(py, start..end) {
+                Ok(batch) => batches.push(batch),
+                Err(e) if batches.is_empty() => return Err(e),
+                Err(_) => break,
+            }
+            start = end;
+        }
+        self.buffer.drain(..start);
+        Ok(batches)
+    }
+
+    /// Number of buffered rows not yet flushed.
+    fn __len__(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+impl StreamingConverter {
+    /// Convert the buffered rows `range`, planning the schema on the first call.
+    fn convert(&mut self, py: Python, range: std::ops::Range<usize>) -> PyResult<PyObject> {
+        let StreamingConverter { opts, buffer, plan, .. } = self;
+        let rows = &buffer[range];
+        let batch = py.allow_threads(|| {
+            opts.check_rows(rows.len())?;
+            let plan = match plan {
+                Some(plan) => plan,
+                None => plan.insert(BatchPlan::for_rows(Rows::Decoded(rows, None), opts)?.pinned(opts)),
+            };
+            opts.check_cells(rows.len(), plan.fields.len())?;
+            plan.build(rows, opts)
+        })?;
+        output::emit_batch(py, batch, opts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{Array, AsArray, RecordBatch};
+    use arrow::datatypes::{DataType, Int64Type};
+    use pyo3::types::PyBytes;
+    use pyo3_arrow::PyRecordBatch;
+
+    use super::*;
+    use crate::encode::encode;
+
+    /// A bare record array of an `{a: value}` object for each of `values`.
+    fn frame<'py>(py: Python<'py>, values: &[Value]) -> Bound<'py, PyAny> {
+        let records = values.iter().map(|v| Value::Map(vec![(Value::Text("a".to_string()), v.clone())])).collect();
+        PyBytes::new(py, &encode(&Value::Array(records))).into_any()
+    }
+
+    fn converter(py: Python, kwargs: &str) -> StreamingConverter {
+        let kwargs = py.eval(&std::ffi::CString::new(format!("dict(output='batch', {})", kwargs)).unwrap(), None, None);
+        StreamingConverter::new(2, false, Some(kwargs.unwrap().downcast().unwrap())).unwrap()
+    }
+
+    fn batches(py: Python, flushed: Vec<PyObject>) -> Vec<RecordBatch> {
+        flushed.into_iter().map(|b| b.extract::<PyRecordBatch>(py).unwrap().into_inner()).collect()
+    }
+
+    #[test]
+    fn later_batches_keep_the_first_schema() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let mut stream = converter(py, "");
+            stream.push(py, frame(py, &[Value::Integer(1), Value::Integer(2)]).extract().unwrap()).unwrap();
+            stream.push(py, frame(py, &[Value::Null, Value::Null, Value::Integer(5)]).extract().unwrap()).unwrap();
+            let flushed = batches(py, stream.flush(py, true).unwrap());
+            assert_eq!(flushed.len(), 3);
+            for batch in &flushed {
+                assert_eq!(batch.schema().field(0).data_type(), &DataType::Int64);
+            }
+            assert_eq!(flushed[1].column(0).null_count(), 2);
+            assert_eq!(flushed[2].column(0).as_primitive::<Int64Type>().value(0), 5);
+            assert_eq!(stream.__len__(), 0);
+        });
+    }
+
+    #[test]
+    fn failed_batches_stay_buffered() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let mut stream = converter(py, "");
+            let nested = Value::Map(vec![(Value::Text("b".to_string()), Value::Integer(1))]);
+            stream.push(py, frame(py, &[Value::Integer(1), Value::Integer(2)]).extract().unwrap()).unwrap();
+            stream.push(py, frame(py, &[nested.clone(), nested]).extract().unwrap()).unwrap();
+            let flushed = batches(py, stream.flush(py, false).unwrap());
+            assert_eq!(flushed.len(), 1);
+            assert_eq!(stream.__len__(), 2);
+            assert!(stream.flush(py, false).is_err());
+            assert_eq!(stream.__len__(), 2);
+        });
+    }
+}
@@ -0,0 +1,31 @@
+"""
+Arrow Flight endpoint serving SurrealDB query results.
+
+The server is the accelerator's ``SurrealFlightServer``, built with its
+``flight`` feature: a Rust gRPC server, needing no pyarrow. Flight clients
+(Spark, DuckDB, other services) fetch results with ``do_get``. A ticket is
+either SurrealQL, run on the server's ``Connection``, or the ticket returned by
+the ``push`` action for a CBOR response pushed to the server::
+
+    from surrealengine.flight import SurrealFlightServer
+    from surrealengine.surrealengine_accelerator import Connection
+
+    connection = Connection.connect("ws://localhost:8000")
+    connection.signin(username="root", password="root")
+    connection.use_ns_db("test", "test")
+    server = SurrealFlightServer("grpc://0.0.0.0:8815", connection)
+    server.serve()  # until server.shutdown() is called from another thread
+
+    # elsewhere
+    client = pyarrow.flight.connect("grpc://localhost:8815")
+    table = client.do_get(pyarrow.flight.Ticket(b"SELECT * FROM person")).read_all()
+"""
+
+from .raw_connection import accelerator
+
+SurrealFlightServer = getattr(accelerator, "SurrealFlightServer", None)
+
+if SurrealFlightServer is None:
+    raise ImportError("SurrealFlightServer needs the Rust accelerator built with its flight feature")
+
+__all__ = ["SurrealFlightServer"]
@@ -221,15 +221,14 @@ class RawSurrealConnection:
         else:
             raise ConnectionError("Websocket not connected")
 
-    async def query_arrow(self, sql: str, vars: dict = None):
+    async def query_cbor(self, sql: str, vars: dict = None) -> bytes:
         """
-        Execute the query and return an Arrow RecordBatch using the Rust accelerator.
+        Execute the query and return the raw CBOR response, undecoded.
         """
         if not self.ws:
             raise RuntimeError("Not connected")
 
         assert cbor2 is not None
-        assert accelerator is not None
 
         req_id = str(uuid.uuid4())
         req = {"id": req_id, "method": "query", "params": [sql, vars or {}]}
@@ -238,17 +237,23 @@ class RawSurrealConnection:
         await self.ws.send(cbor2.dumps(req))
 
         # Receive Raw Bytes
-        resp_bytes = await self.ws.recv()
+        return await self.ws.recv()
+
+    async def query_arrow(self, sql: str, vars: dict = None):
+        """
+        Execute the query and return an Arrow RecordBatch using the Rust accelerator.
+        """
+        assert accelerator is not None
+
+        resp_bytes = await self.query_cbor(sql, vars)
 
         # Level 3: Rust Zero-Copy Accelerator
         # We pass the raw bytes directly to Rust.
         # The Rust extension handles envelope parsing and Arrow conversion.
         try:
-            batch = accelerator.cbor_to_arrow(resp_bytes)
-            if batch:
-                import pyarrow as pa
-
-                return pa.Table.from_batches([batch])
+            table = accelerator.cbor_to_arrow(resp_bytes, output="table")
+            if table is not None:
+                return table
             else:
                 # Empty result
                 return None
@@ -0,0 +1,481 @@
+//! SurrealQL literal rendering, following SurrealDB's own escaping rules.
+//!
+//! Used wherever a decoded value has to be shown the way SurrealDB itself would
+//! print it, most importantly record ids with complex keys.
+
+use std::borrow::Cow;
+use std::fmt::Write;
+
+use cbor4ii::core::Value;
+
+use crate::tags::{self, SurrealTag};
+
+const BRACKET_L: char = '⟨';
+const BRACKET_R: char = '⟩';
+const BRACKET_ESC: &str = "\\⟩";
+
+/// Escape a record id part (table or string key) with `⟨⟩` when needed.
+///
+/// Plain identifiers (`[A-Za-z0-9_]`) are left as-is, except purely numeric
+/// strings which would otherwise read back as integers. Inside the brackets
+/// `\` and `⟩` are escaped with a backslash.
+pub(crate) fn escape_rid(s: &str) -> Cow<'_, str> {
+    let plain = s.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_');
+    if !s.is_empty() && plain && !s.bytes().all(|b| b.is_ascii_digit()) {
+        Cow::Borrowed(s)
+    } else {
+        Cow::Owned(format!("{}{}{}", BRACKET_L, s.replace('\\', "\\\\").replace(BRACKET_R, BRACKET_ESC), BRACKET_R))
+    }
+}
+
+/// Escape an identifier (table or field name) with backticks when it is not a
+/// plain identifier, or is purely numeric.
+pub(crate) fn escape_ident(s: &str) -> Cow<'_, str> {
+    let plain = s.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_');
+    if !s.is_empty() && plain && !s.bytes().all(|b| b.is_ascii_digit()) {
+        Cow::Borrowed(s)
+    } else {
+        Cow::Owned(format!("`{}`", s.replace('\\', "\\\\").replace('`', "\\`")))
+    }
+}
+
+/// Escape an object key with double quotes when it is not a plain identifier.
+pub(crate) fn escape_key(s: &str) -> Cow<'_, str> {
+    if !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+        Cow::Borrowed(s)
+    } else {
+        Cow::Owned(format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"")))
+    }
+}
+
+/// Quote a string literal. Single quotes are preferred; strings containing a
+/// single quote are wrapped in double quotes instead.
+pub(crate) fn quote_str(s: &str) -> String {
+    let (quote, escape_double) = if s.contains('\'') { ('"', true) } else { ('\'', false) };
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push(quote);
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' if escape_double => out.push_str("\\\""),
+            c => out.push(c),
+        }
+    }
+    out.push(quote);
+    out
+}
+
+/// Render a full record id as `table:key`.
+pub(crate) fn render_record_id(table: &str, key: &Value) -> Option<String> {
+    Some(format!("{}:{}", escape_rid(table), render_record_key(key)?))
+}
+
+/// Render the key part of a record id.
+pub(crate) fn render_record_key(key: &Value) -> Option<String> {
+    match key {
+        Value::Text(s) => Some(escape_rid(s).into_owned()),
+        Value::Tag(tag, payload) if SurrealTag::of(*tag) == SurrealTag::Range => {
+            let mut out = String::new();
+            write_range(payload, &mut out)?;
+            Some(out)
+        }
+        other => {
+            let mut out = String::new();
+            write_value(other, &mut out)?;
+            Some(out)
+        }
+    }
+}
+
+/// Append the SurrealQL literal for `value`. Returns `None` for values without
+/// a literal form (e.g. geometries or unknown tags).
+pub(crate) fn write_value(value: &Value, out: &mut String) -> Option<()> {
+    match value {
+        Value::Null => out.push_str("NULL"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Integer(i) => write!(out, "{}", i).ok()?,
+        Value::Float(f) if f.is_nan() => out.push_str("NaN"),
+        Value::Float(f) if f.is_infinite() => out.push_str(if *f > 0.0 { "Infinity" } else { "-Infinity" }),
+        Value::Float(f) => write!(out, "{}f", f).ok()?,
+        Value::Text(s) => out.push_str(&quote_str(s)),
+        Value::Bytes(b) => {
+            out.push_str("b\"");
+            for byte in b {
+                write!(out, "{:02X}", byte).ok()?;
+            }
+            out.push('"');
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_value(item, out)?;
+            }
+            out.push(']');
+        }
+        Value::Map(entries) if entries.is_empty() => out.push_str("{}"),
+        Value::Map(entries) => {
+            out.push_str("{ ");
+            for (i, (k, v)) in entries.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                match k {
+                    Value::Text(s) => out.push_str(&escape_key(s)),
+                    other => write_value(other, out)?,
+                }
+                out.push_str(": ");
+                write_value(v, out)?;
+            }
+            out.push_str(" }");
+        }
+        Value::Tag(tag, payload) => write_tagged(*tag, payload, out)?,
+        _ => return None,
+    }
+    Some(())
+}
+
+fn write_tagged(tag: u64, payload: &Value, out: &mut String) -> Option<()> {
+    match SurrealTag::of(tag) {
+        SurrealTag::None => out.push_str("NONE"),
+        SurrealTag::Table => match payload {
+            Value::Text(s) => out.push_str(&escape_rid(s)),
+            _ => return None,
+        },
+        SurrealTag::RecordId => out.push_str(&tags::record_id_string(payload)?),
+        SurrealTag::Uuid => write!(out, "u'{}'", tags::format_uuid(&tags::uuid_bytes(tag, payload)?)).ok()?,
+        SurrealTag::Decimal => match payload {
+            Value::Text(s) => write!(out, "{}dec", s).ok()?,
+            _ => return None,
+        },
+        SurrealTag::Datetime => write!(out, "d'{}'", tags::format_datetime(tags::datetime_to_nanos(tag, payload)?)).ok()?,
+        SurrealTag::Duration => out.push_str(&tags::format_duration(tags::duration_to_nanos(tag, payload)?)),
+        SurrealTag::Range => write_range(payload, out)?,
+        // Integers beyond i64 stay exact as decimals.
+        SurrealTag::BigNum => match tags::bignum_i128(tag, payload).and_then(|i| i64::try_from(i).ok()) {
+            Some(i) => write!(out, "{}", i).ok()?,
+            None => write!(out, "{}dec", tags::bignum_string(tag, payload)?).ok()?,
+        },
+        SurrealTag::DecimalFraction => write!(out, "{}dec", tags::decimal_fraction_string(payload)?).ok()?,
+        SurrealTag::Future | SurrealTag::Bound | SurrealTag::Geometry | SurrealTag::Unknown => return None,
+    }
+    Some(())
+}
+
+/// Ranges render as `start..end`, with `>` marking an exclusive start and `=`
+/// an inclusive end.
+fn write_range(payload: &Value, out: &mut String) -> Option<()> {
+    let Value::Array(bounds) = payload else {
+        return None;
+    };
+    if bounds.len() != 2 {
+        return None;
+    }
+    if let Value::Tag(tag, v) = &bounds[0] {
+        write_value(v, out)?;
+        if *tag == tags::TAG_BOUND_EXCLUDED {
+            out.push('>');
+        }
+    }
+    out.push_str("..");
+    if let Value::Tag(tag, v) = &bounds[1] {
+        if *tag == tags::TAG_BOUND_INCLUDED {
+            out.push('=');
+        }
+        write_value(v, out)?;
+    }
+    Some(())
+}
+
+/// Parse a SurrealQL record id such as `user:john`, `user:⟨a-b⟩`, `user:123`,
+/// `temp:['London', 2024]` or `person:{ name: 'x' }` into its table and key.
+pub(crate) fn parse_record_id(input: &str) -> Result<(String, Value), String> {
+    let mut p = Parser { src: input, pos: 0 };
+    let table = p.ident_part().ok_or_else(|| p.error("expected a table name"))?;
+    if !p.eat(':') {
+        return Err(p.error("expected ':' after the table name"));
+    }
+    let key = p.record_key()?;
+    p.skip_ws();
+    if p.pos != input.len() {
+        return Err(p.error("unexpected trailing input"));
+    }
+    Ok((table, key))
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.src[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_ws(&mut self) {
+        while let Some(c) = self.peek().filter(|c| c.is_whitespace()) {
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn error(&self, msg: &str) -> String {
+        format!("Invalid record id {:?}: {} at offset {}", self.src, msg, self.pos)
+    }
+
+    /// A bare identifier, a `⟨⟩`-escaped or a backtick-escaped name.
+    fn ident_part(&mut self) -> Option<String> {
+        if self.eat(BRACKET_L) {
+            return self.escaped(BRACKET_R);
+        }
+        if self.eat('`') {
+            return self.escaped('`');
+        }
+        let len = self
+            .rest()
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(self.rest().len());
+        if len == 0 {
+            return None;
+        }
+        let ident = self.rest()[..len].to_string();
+        self.pos += len;
+        Some(ident)
+    }
+
+    /// Read up to the unescaped `close` delimiter, resolving `\` escapes.
+    fn escaped(&mut self, close: char) -> Option<String> {
+        let mut out = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    let (_, next) = chars.next()?;
+                    out.push(next);
+                }
+                c if c == close => {
+                    self.pos += i + c.len_utf8();
+                    return Some(out);
+                }
+                c => out.push(c),
+            }
+        }
+        None
+    }
+
+    fn record_key(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some('[' | '{') => self.value(),
+            Some('u') if self.rest()[1..].starts_with(['\'', '"']) => self.value(),
+            Some('-') => self.number(),
+            Some(c) => {
+                let bare = c != BRACKET_L && c != '`';
+                let ident = self.ident_part().ok_or_else(|| self.error("expected a record key"))?;
+                // Purely numeric bare keys are integers; escaped ones stay strings.
+                if bare && ident.bytes().all(|b| b.is_ascii_digit()) {
+                    if let Ok(i) = ident.parse::<i64>() {
+                        return Ok(Value::Integer(i.into()));
+                    }
+                }
+                Ok(Value::Text(ident))
+            }
+            None => Err(self.error("expected a record key")),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_ws();
+        match self.peek() {
+            Some('[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_ws();
+                    if self.eat(']') {
+                        break;
+                    }
+                    items.push(self.value()?);
+                    self.skip_ws();
+                    if !self.eat(',') {
+                        self.skip_ws();
+                        if !self.eat(']') {
+                            return Err(self.error("expected ',' or ']'"));
+                        }
+                        break;
+                    }
+                }
+                Ok(Value::Array(items))
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut entries = Vec::new();
+                loop {
+                    self.skip_ws();
+                    if self.eat('}') {
+                        break;
+                    }
+                    let key = match self.peek() {
+                        Some(q @ ('\'' | '"')) => {
+                            self.pos += 1;
+                            self.escaped(q).ok_or_else(|| self.error("unterminated string"))?
+                        }
+                        _ => self.ident_part().ok_or_else(|| self.error("expected an object key"))?,
+                    };
+                    self.skip_ws();
+                    if !self.eat(':') {
+                        return Err(self.error("expected ':' after an object key"));
+                    }
+                    entries.push((Value::Text(key), self.value()?));
+                    self.skip_ws();
+                    if !self.eat(',') {
+                        self.skip_ws();
+                        if !self.eat('}') {
+                            return Err(self.error("expected ',' or '}'"));
+                        }
+                        break;
+                    }
+                }
+                Ok(Value::Map(entries))
+            }
+            Some(q @ ('\'' | '"')) => {
+                self.pos += 1;
+                let s = self.escaped(q).ok_or_else(|| self.error("unterminated string"))?;
+                Ok(Value::Text(s))
+            }
+            Some(prefix @ ('u' | 'd' | 's' | 'r')) if self.rest()[1..].starts_with(['\'', '"']) => {
+                self.pos += 1;
+                let q = self.peek().unwrap_or('\'');
+                self.pos += 1;
+                let s = self.escaped(q).ok_or_else(|| self.error("unterminated string"))?;
+                Ok(match prefix {
+                    'u' => Value::Tag(tags::TAG_UUID_STRING, Box::new(Value::Text(s))),
+                    'd' => Value::Tag(tags::TAG_DATETIME, Box::new(Value::Text(s))),
+                    'r' => {
+                        let (table, key) = parse_record_id(&s)?;
+                        Value::Tag(tags::TAG_RECORDID, Box::new(Value::Array(vec![Value::Text(table), key])))
+                    }
+                    _ => Value::Text(s),
+                })
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(_) => {
+                let ident = self.ident_part().ok_or_else(|| self.error("expected a value"))?;
+                if self.peek() == Some(':') {
+                    // Nested record id, e.g. `[user:john, 1]`
+                    self.pos += 1;
+                    let key = self.record_key()?;
+                    return Ok(Value::Tag(tags::TAG_RECORDID, Box::new(Value::Array(vec![Value::Text(ident), key]))));
+                }
+                match ident.to_ascii_lowercase().as_str() {
+                    "true" => Ok(Value::Bool(true)),
+                    "false" => Ok(Value::Bool(false)),
+                    "null" => Ok(Value::Null),
+                    "none" => Ok(Value::Tag(tags::TAG_NONE, Box::new(Value::Null))),
+                    _ => Err(self.error("unexpected identifier")),
+                }
+            }
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let len = self
+            .rest()
+            .char_indices()
+            .find(|(i, c)| !(c.is_ascii_digit() || *c == '.' || *c == 'e' || *c == 'E' || ((*c == '-' || *c == '+') && (*i == 0 || self.rest()[..*i].ends_with(['e', 'E'])))))
+            .map(|(i, _)| i)
+            .unwrap_or(self.rest().len());
+        let text = self.rest()[..len].to_string();
+        let value = if let Ok(i) = text.parse::<i64>() {
+            Value::Integer(i.into())
+        } else if let Ok(f) = text.parse::<f64>() {
+            Value::Float(f)
+        } else {
+            return Err(self.error("invalid number"));
+        };
+        self.pos += len;
+        // Optional SurrealQL number suffixes
+        if self.rest().starts_with("dec") {
+            self.pos += 3;
+            return Ok(Value::Tag(tags::TAG_DECIMAL, Box::new(Value::Text(text.to_string()))));
+        }
+        if self.eat('f') {
+            return Ok(Value::Float(text.parse::<f64>().map_err(|_| self.error("invalid float"))?));
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    #[test]
+    fn record_ids_render_in_surrealql_form() {
+        let id = |table: &str, key: Value| render_record_id(table, &key).unwrap();
+        assert_eq!(id("user", text("john")), "user:john");
+        assert_eq!(id("user", text("john-doe")), "user:⟨john-doe⟩");
+        assert_eq!(id("user", text("123")), "user:⟨123⟩");
+        assert_eq!(id("user", Value::Integer(123)), "user:123");
+        assert_eq!(id("my table", text("a")), "⟨my table⟩:a");
+        assert_eq!(id("t", Value::Array(vec![text("London"), Value::Integer(2024)])), "t:['London', 2024]");
+        assert_eq!(id("t", Value::Map(vec![(text("name"), text("x"))])), "t:{ name: 'x' }");
+        let range = Value::Array(vec![
+            Value::Tag(tags::TAG_BOUND_INCLUDED, Box::new(Value::Integer(1))),
+            Value::Tag(tags::TAG_BOUND_EXCLUDED, Box::new(Value::Integer(5))),
+        ]);
+        assert_eq!(id("t", Value::Tag(tags::TAG_RANGE, Box::new(range))), "t:1..5");
+    }
+
+    #[test]
+    fn escaped_keys_close_where_they_end() {
+        assert_eq!(render_record_id("t", &text("a\\")).unwrap(), "t:⟨a\\\\⟩");
+        assert_eq!(render_record_id("t", &text("a⟩b")).unwrap(), "t:⟨a\\⟩b⟩");
+        assert_eq!(render_record_id("t\\", &text("a")).unwrap(), "⟨t\\\\⟩:a");
+        assert_eq!(escape_rid(""), "⟨⟩");
+    }
+
+    #[test]
+    fn formatted_record_ids_parse_back() {
+        let keys = [
+            text("john"),
+            text("a\\"),
+            text("a⟩b"),
+            text("a\\⟩; DELETE user; --"),
+            text("⟨x⟩"),
+            text("\\\\"),
+            text("123"),
+            text(""),
+            text("a\0b"),
+            Value::Integer(-7),
+            Value::Array(vec![text("it's"), text("\"\\"), Value::Integer(1)]),
+            Value::Map(vec![(text("a b"), text("⟩")), (text("k"), Value::Array(vec![]))]),
+        ];
+        for key in keys {
+            for table in ["t", "my-table", "a⟩\\"] {
+                let rendered = render_record_id(table, &key).unwrap();
+                let (parsed_table, parsed_key) = parse_record_id(&rendered).unwrap();
+                assert_eq!((parsed_table.as_str(), &parsed_key), (table, &key), "{}", rendered);
+            }
+        }
+    }
+}
@@ -0,0 +1,664 @@
+//! SurrealDB CBOR tag dispatch.
+//!
+//! Every tag documented by the SurrealDB CBOR protocol is classified here and
+//! serialized into the primitive serde_arrow sees. The schema refinement pass in
+//! `lib.rs` uses [`tag_kind`] to restore the semantic Arrow type afterwards.
+
+use arrow::datatypes::{i256, Decimal256Type, DecimalType, DECIMAL256_MAX_PRECISION};
+use cbor4ii::core::Value;
+use serde::ser::{Error, SerializeMap};
+use serde::{Serialize, Serializer};
+
+use crate::surrealql;
+use crate::{BigInt, ConvertOptions, Loss, RecordIdMode, SurrealValueRef, UnknownTagPolicy, UuidMode};
+
+/// SurrealDB datetime as an RFC 3339 string.
+pub(crate) const TAG_DATETIME: u64 = 0;
+/// RFC 8949 unsigned bignum: big-endian bytes of the integer.
+pub(crate) const TAG_BIGNUM: u64 = 2;
+/// RFC 8949 negative bignum: big-endian bytes of `n` for the integer `-1 - n`.
+pub(crate) const TAG_NEGATIVE_BIGNUM: u64 = 3;
+/// RFC 8949 decimal fraction `[exponent, mantissa]`, worth `mantissa * 10^exponent`.
+pub(crate) const TAG_DECIMAL_FRACTION: u64 = 4;
+/// RFC 8949 self-described CBOR marker, wrapped around a whole payload.
+pub(crate) const TAG_SELF_DESCRIBED: u64 = 55799;
+/// SurrealDB `NONE`, distinct from CBOR null. The payload is ignored.
+pub(crate) const TAG_NONE: u64 = 6;
+/// SurrealDB table name (a bare table reference).
+pub(crate) const TAG_TABLE: u64 = 7;
+/// SurrealDB RecordID as `[table, id]`.
+pub(crate) const TAG_RECORDID: u64 = 8;
+/// SurrealDB UUID as a hyphenated string.
+pub(crate) const TAG_UUID_STRING: u64 = 9;
+/// SurrealDB decimal as a string, kept verbatim to stay lossless.
+pub(crate) const TAG_DECIMAL: u64 = 10;
+/// SurrealDB datetime in compact form `[seconds, nanoseconds]`.
+pub(crate) const TAG_DATETIME_COMPACT: u64 = 12;
+/// SurrealDB duration as a SurrealQL duration string, e.g. `1h30m`.
+pub(crate) const TAG_DURATION: u64 = 13;
+/// SurrealDB duration in compact form `[seconds, nanoseconds]`.
+pub(crate) const TAG_DURATION_COMPACT: u64 = 14;
+/// SurrealDB future: an unevaluated SurrealQL block, not data.
+pub(crate) const TAG_FUTURE: u64 = 15;
+/// SurrealDB UUID as 16 raw bytes (RFC 9562 binary form).
+pub(crate) const TAG_UUID: u64 = 37;
+/// SurrealDB range `[start_bound, end_bound]`; a null bound is unbounded.
+pub(crate) const TAG_RANGE: u64 = 49;
+/// Inclusive range bound.
+pub(crate) const TAG_BOUND_INCLUDED: u64 = 50;
+/// Exclusive range bound.
+pub(crate) const TAG_BOUND_EXCLUDED: u64 = 51;
+/// SurrealDB geometry tags: Point, Line, Polygon, MultiPoint, MultiLine,
+/// MultiPolygon and Collection.
+pub(crate) const TAG_GEOMETRY_POINT: u64 = 88;
+pub(crate) const TAG_GEOMETRY_LINE: u64 = 89;
+pub(crate) const TAG_GEOMETRY_POLYGON: u64 = 90;
+pub(crate) const TAG_GEOMETRY_MULTIPOINT: u64 = 91;
+pub(crate) const TAG_GEOMETRY_MULTILINE: u64 = 92;
+pub(crate) const TAG_GEOMETRY_MULTIPOLYGON: u64 = 93;
+pub(crate) const TAG_GEOMETRY_COLLECTION: u64 = 94;
+
+/// The SurrealDB meaning of a CBOR tag number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SurrealTag {
+    None,
+    Table,
+    RecordId,
+    Uuid,
+    Decimal,
+    Datetime,
+    Duration,
+    Future,
+    Range,
+    Bound,
+    Geometry,
+    /// An RFC 8949 bignum; not a SurrealDB tag, but intermediaries emit them.
+    BigNum,
+    /// An RFC 8949 decimal fraction, likewise.
+    DecimalFraction,
+    Unknown,
+}
+
+impl SurrealTag {
+    pub(crate) fn of(tag: u64) -> Self {
+        match tag {
+            TAG_NONE => SurrealTag::None,
+            TAG_TABLE => SurrealTag::Table,
+            TAG_RECORDID => SurrealTag::RecordId,
+            TAG_UUID | TAG_UUID_STRING => SurrealTag::Uuid,
+            TAG_DECIMAL => SurrealTag::Decimal,
+            TAG_DATETIME | TAG_DATETIME_COMPACT => SurrealTag::Datetime,
+            TAG_DURATION | TAG_DURATION_COMPACT => SurrealTag::Duration,
+            TAG_FUTURE => SurrealTag::Future,
+            TAG_RANGE => SurrealTag::Range,
+            TAG_BOUND_INCLUDED | TAG_BOUND_EXCLUDED => SurrealTag::Bound,
+            TAG_GEOMETRY_POINT..=TAG_GEOMETRY_COLLECTION => SurrealTag::Geometry,
+            TAG_BIGNUM | TAG_NEGATIVE_BIGNUM => SurrealTag::BigNum,
+            TAG_DECIMAL_FRACTION => SurrealTag::DecimalFraction,
+            _ => SurrealTag::Unknown,
+        }
+    }
+}
+
+/// The SurrealDB kind of a tagged value, or `None` for untagged values.
+pub(crate) fn tag_kind(value: &Value) -> Option<SurrealTag> {
+    match value {
+        Value::Tag(tag, _) => Some(SurrealTag::of(*tag)),
+        _ => None,
+    }
+}
+
+/// Serialize a tagged value according to its SurrealDB semantics.
+///
+/// With `strict_tags` any conversion that would lose information (unknown tags,
+/// futures, undecodable record ids, stray range bounds) is an error instead of
+/// falling back to the untagged payload.
+pub(crate) fn serialize_tag<S: Serializer>(
+    tag: u64,
+    value: &Value,
+    opts: &ConvertOptions,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let kind = SurrealTag::of(tag);
+    match kind {
+        SurrealTag::None => serializer.serialize_none(),
+        SurrealTag::Table => match value {
+            Value::Text(name) => serializer.serialize_str(name),
+            other => Err(S::Error::custom(format!("Invalid SurrealDB table name: {:?}", other))),
+        },
+        SurrealTag::RecordId => match opts.record_id_mode {
+            RecordIdMode::String | RecordIdMode::Split => match record_id_string(value) {
+                Some(id) => serializer.serialize_str(&id),
+                None if opts.strict_tags => Err(S::Error::custom(format!("Unsupported SurrealDB record id: {:?}", value))),
+                None => {
+                    opts.lose(Loss::Tag, 1);
+                    SurrealValueRef(value, opts).serialize(serializer)
+                }
+            },
+            RecordIdMode::Struct => match record_id_parts(value) {
+                Some((tb, id)) => {
+                    let mut m = serializer.serialize_map(Some(2))?;
+                    m.serialize_entry("tb", &tb)?;
+                    m.serialize_entry("id", &id)?;
+                    m.end()
+                }
+                None if opts.strict_tags => Err(S::Error::custom(format!("Unsupported SurrealDB record id: {:?}", value))),
+                None => {
+                    opts.lose(Loss::Tag, 1);
+                    SurrealValueRef(value, opts).serialize(serializer)
+                }
+            },
+        },
+        SurrealTag::Uuid => match uuid_bytes(tag, value) {
+            Some(bytes) => match opts.uuid_mode {
+                UuidMode::String => serializer.serialize_str(&format_uuid(&bytes)),
+                UuidMode::Binary => serializer.serialize_bytes(&bytes),
+            },
+            None => Err(S::Error::custom(format!("Invalid SurrealDB UUID: {:?}", value))),
+        },
+        SurrealTag::Decimal => match value {
+            Value::Text(s) => serializer.serialize_str(s),
+            other => Err(S::Error::custom(format!("Invalid SurrealDB decimal: {:?}", other))),
+        },
+        // Datetimes and durations are emitted as i64 nanoseconds; the refinement pass
+        // turns the traced Int64 into the temporal Arrow type.
+        SurrealTag::Datetime => match datetime_to_nanos(tag, value) {
+            Some(nanos) => serializer.serialize_i64(nanos),
+            None => Err(S::Error::custom(format!("Invalid or out of range SurrealDB datetime: {:?}", value))),
+        },
+        SurrealTag::Duration => match duration_to_nanos(tag, value) {
+            Some(nanos) => serializer.serialize_i64(nanos),
+            None => Err(S::Error::custom(format!("Invalid or out of range SurrealDB duration: {:?}", value))),
+        },
+        SurrealTag::Range => serialize_range(value, opts, serializer),
+        // Bignums that fit are plain integers; larger ones follow `big_int`.
+        SurrealTag::BigNum => match (bignum_i128(tag, value), opts.big_int) {
+            (Some(i), _) => SurrealValueRef(&Value::Integer(i), opts).serialize(serializer),
+            (None, BigInt::Null) => {
+                opts.lose(Loss::BigInt, 1);
+                serializer.serialize_none()
+            }
+            (None, BigInt::Decimal) if opts.tracing_records && bignum_decimal(tag, value).is_none() => Err(S::Error::custom(
+                format!("integer {} does not fit in Decimal256({}, 0)", bignum_string(tag, value).unwrap_or_default(), DECIMAL256_MAX_PRECISION),
+            )),
+            (None, BigInt::Decimal | BigInt::String) if opts.tracing_records => serializer.serialize_i64(i64::MIN),
+            (None, mode) => match bignum_string(tag, value) {
+                Some(digits) if mode != BigInt::Error => serializer.serialize_str(&digits),
+                Some(digits) => Err(S::Error::custom(format!("integer {} does not fit in 128 bits", digits))),
+                None => Err(S::Error::custom(format!("Invalid CBOR bignum: {:?}", value))),
+            },
+        },
+        // Serialized as decimal text; `refine_field` picks the Decimal column type.
+        SurrealTag::DecimalFraction => match decimal_fraction_string(value) {
+            Some(text) => serializer.serialize_str(&text),
+            None => Err(S::Error::custom(format!("Invalid CBOR decimal fraction: {:?}", value))),
+        },
+        SurrealTag::Geometry => match geometry_to_wkb(tag, value) {
+            Some(wkb) => serializer.serialize_bytes(&wkb),
+            None => Err(S::Error::custom(format!("Invalid SurrealDB geometry: {:?}", value))),
+        },
+        SurrealTag::Unknown => match opts.on_unknown_tag {
+            UnknownTagPolicy::Ignore => {
+                opts.lose(Loss::Tag, 1);
+                SurrealValueRef(value, opts).serialize(serializer)
+            }
+            UnknownTagPolicy::Error => Err(S::Error::custom(format!("Unsupported CBOR tag {}", tag))),
+            UnknownTagPolicy::Raw => {
+                let mut m = serializer.serialize_map(Some(2))?;
+                m.serialize_entry("tag", &tag)?;
+                m.serialize_entry("value", &SurrealValueRef(value, opts))?;
+                m.end()
+            }
+        },
+        SurrealTag::Future | SurrealTag::Bound => {
+            if opts.strict_tags {
+                return Err(S::Error::custom(match kind {
+                    SurrealTag::Future => "SurrealDB futures cannot be converted".to_string(),
+                    _ => format!("Range bound tag {} outside of a range", tag),
+                }));
+            }
+            // Lossy fallback: ignore the tag, serialize the payload
+            opts.lose(Loss::Tag, 1);
+            SurrealValueRef(value, opts).serialize(serializer)
+        }
+    }
+}
+
+/// Canonical SurrealQL `table:key` rendering of a RecordID payload.
+pub(crate) fn record_id_string(value: &Value) -> Option<String> {
+    match value {
+        // Usually value is Array(2) [table, id]
+        Value::Array(arr) if arr.len() == 2 => match &arr[0] {
+            Value::Text(table) => surrealql::render_record_id(table, &arr[1]),
+            _ => None,
+        },
+        Value::Text(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+/// Split a RecordID payload into its table and key. String keys are returned
+/// unescaped; integer, array, object, UUID and range keys in SurrealQL form.
+pub(crate) fn record_id_parts(value: &Value) -> Option<(String, String)> {
+    match value {
+        Value::Array(arr) if arr.len() == 2 => {
+            let table = match &arr[0] {
+                Value::Text(s) => s.clone(),
+                _ => return None,
+            };
+            match &arr[1] {
+                Value::Text(id) => Some((table, id.clone())),
+                key => Some((table, surrealql::render_record_key(key)?)),
+            }
+        }
+        Value::Text(s) => s.split_once(':').map(|(tb, id)| (tb.to_string(), id.to_string())),
+        _ => None,
+    }
+}
+
+/// Ranges become a `{start, end, start_inclusive, end_inclusive}` struct.
+fn serialize_range<S: Serializer>(value: &Value, opts: &ConvertOptions, serializer: S) -> Result<S::Ok, S::Error> {
+    let bounds = match value {
+        Value::Array(bounds) if bounds.len() == 2 => bounds,
+        other => return Err(S::Error::custom(format!("Invalid SurrealDB range: {:?}", other))),
+    };
+    let mut parsed = Vec::with_capacity(2);
+    for bound in bounds {
+        parsed.push(match bound {
+            Value::Tag(TAG_BOUND_INCLUDED, v) => (Some(v.as_ref()), Some(true)),
+            Value::Tag(TAG_BOUND_EXCLUDED, v) => (Some(v.as_ref()), Some(false)),
+            b if crate::is_null(b) => (None, None),
+            other => return Err(S::Error::custom(format!("Invalid SurrealDB range bound: {:?}", other))),
+        });
+    }
+    let mut m = serializer.serialize_map(Some(4))?;
+    m.serialize_entry("start", &parsed[0].0.map(|v| SurrealValueRef(v, opts)))?;
+    m.serialize_entry("end", &parsed[1].0.map(|v| SurrealValueRef(v, opts)))?;
+    m.serialize_entry("start_inclusive", &parsed[0].1)?;
+    m.serialize_entry("end_inclusive", &parsed[1].1)?;
+    m.end()
+}
+
+/// Decode a SurrealDB datetime tag payload into nanoseconds since the Unix epoch.
+///
+/// Returns `None` if the payload is malformed or does not fit into an `i64`
+/// nanosecond timestamp (roughly years 1677 - 2262).
+pub(crate) fn datetime_to_nanos(tag: u64, value: &Value) -> Option<i64> {
+    match (tag, value) {
+        (TAG_DATETIME, Value::Text(s)) => {
+            chrono::DateTime::parse_from_rfc3339(s).ok()?.timestamp_nanos_opt()
+        }
+        (TAG_DATETIME_COMPACT, Value::Array(parts)) => {
+            let secs = match parts.first() {
+                Some(Value::Integer(i)) => i64::try_from(*i).ok()?,
+                _ => return None,
+            };
+            let nanos = match parts.get(1) {
+                Some(Value::Integer(i)) => i64::try_from(*i).ok()?,
+                None => 0,
+                _ => return None,
+            };
+            secs.checked_mul(1_000_000_000)?.checked_add(nanos)
+        }
+        _ => None,
+    }
+}
+
+/// Format nanoseconds since the Unix epoch as an RFC 3339 UTC timestamp.
+pub(crate) fn format_datetime(nanos: i64) -> String {
+    chrono::DateTime::from_timestamp_nanos(nanos).to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)
+}
+
+/// Format nanoseconds as a SurrealQL duration literal, e.g. `1h30m`.
+pub(crate) fn format_duration(nanos: i64) -> String {
+    const UNITS: [(&str, u64); 9] = [
+        ("y", 365 * 86_400 * 1_000_000_000),
+        ("w", 7 * 86_400 * 1_000_000_000),
+        ("d", 86_400 * 1_000_000_000),
+        ("h", 3_600 * 1_000_000_000),
+        ("m", 60 * 1_000_000_000),
+        ("s", 1_000_000_000),
+        ("ms", 1_000_000),
+        ("µs", 1_000),
+        ("ns", 1),
+    ];
+    if nanos == 0 {
+        return "0ns".to_string();
+    }
+    let mut out = String::new();
+    if nanos < 0 {
+        out.push('-');
+    }
+    let mut rest = nanos.unsigned_abs();
+    for (unit, size) in UNITS {
+        if rest >= size {
+            out.push_str(&format!("{}{}", rest / size, unit));
+            rest %= size;
+        }
+    }
+    out
+}
+
+/// Decode a SurrealDB duration tag payload into nanoseconds.
+pub(crate) fn duration_to_nanos(tag: u64, value: &Value) -> Option<i64> {
+    match (tag, value) {
+        (TAG_DURATION, Value::Text(s)) => parse_duration_nanos(s),
+        (TAG_DURATION_COMPACT, Value::Array(parts)) => {
+            let secs = match parts.first() {
+                Some(Value::Integer(i)) => i64::try_from(*i).ok()?,
+                None => 0,
+                _ => return None,
+            };
+            let nanos = match parts.get(1) {
+                Some(Value::Integer(i)) => i64::try_from(*i).ok()?,
+                None => 0,
+                _ => return None,
+            };
+            secs.checked_mul(1_000_000_000)?.checked_add(nanos)
+        }
+        _ => None,
+    }
+}
+
+/// Parse a SurrealQL duration literal such as `1y2w3d4h5m6s7ms8us9ns` into nanoseconds.
+fn parse_duration_nanos(s: &str) -> Option<i64> {
+    const NS_PER_SEC: i64 = 1_000_000_000;
+    let mut total: i64 = 0;
+    let mut rest = s;
+    if rest.is_empty() {
+        return None;
+    }
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        if digits == 0 {
+            return None;
+        }
+        let amount: i64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+        let unit_len = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+        let factor = match &rest[..unit_len] {
+            "ns" => 1,
+            "us" | "µs" => 1_000,
+            "ms" => 1_000_000,
+            "s" => NS_PER_SEC,
+            "m" => 60 * NS_PER_SEC,
+            "h" => 3_600 * NS_PER_SEC,
+            "d" => 86_400 * NS_PER_SEC,
+            "w" => 7 * 86_400 * NS_PER_SEC,
+            "y" => 365 * 86_400 * NS_PER_SEC,
+            _ => return None,
+        };
+        rest = &rest[unit_len..];
+        total = total.checked_add(amount.checked_mul(factor)?)?;
+    }
+    Some(total)
+}
+
+/// Decode a SurrealDB UUID tag payload (string or binary form) into its 16 bytes.
+pub(crate) fn uuid_bytes(tag: u64, value: &Value) -> Option<[u8; 16]> {
+    match (tag, value) {
+        (TAG_UUID, Value::Bytes(b)) => b.as_slice().try_into().ok(),
+        (TAG_UUID_STRING, Value::Text(s)) => {
+            let hex: Vec<u8> = s.bytes().filter(|b| *b != b'-').collect();
+            if hex.len() != 32 {
+                return None;
+            }
+            let mut out = [0u8; 16];
+            for (i, pair) in hex.chunks(2).enumerate() {
+                let pair = std::str::from_utf8(pair).ok()?;
+                out[i] = u8::from_str_radix(pair, 16).ok()?;
+            }
+            Some(out)
+        }
+        _ => None,
+    }
+}
+
+/// The big-endian magnitude bytes of a bignum without leading zeros.
+fn bignum_bytes(value: &Value) -> Option<&[u8]> {
+    let Value::Bytes(bytes) = value else {
+        return None;
+    };
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    Some(&bytes[start..])
+}
+
+/// The integer of a bignum tag, if it fits `i128`.
+pub(crate) fn bignum_i128(tag: u64, value: &Value) -> Option<i128> {
+    let bytes = bignum_bytes(value)?;
+    if bytes.len() > 16 {
+        return None;
+    }
+    let mut buf = [0u8; 16];
+    buf[16 - bytes.len()..].copy_from_slice(bytes);
+    let n = i128::try_from(u128::from_be_bytes(buf)).ok()?;
+    Some(if tag == TAG_NEGATIVE_BIGNUM { -1 - n } else { n })
+}
+
+/// The integer of a bignum tag, if it fits `i256`.
+pub(crate) fn bignum_i256(tag: u64, value: &Value) -> Option<i256> {
+    let bytes = bignum_bytes(value)?;
+    if bytes.len() > 32 || bytes.len() == 32 && bytes[0] & 0x80 != 0 {
+        return None;
+    }
+    let mut buf = [0u8; 32];
+    buf[32 - bytes.len()..].copy_from_slice(bytes);
+    let n = i256::from_be_bytes(buf);
+    Some(if tag == TAG_NEGATIVE_BIGNUM { i256::MINUS_ONE - n } else { n })
+}
+
+/// The integer of a bignum tag, if it fits a `Decimal256` of the largest precision.
+pub(crate) fn bignum_decimal(tag: u64, value: &Value) -> Option<i256> {
+    bignum_i256(tag, value).filter(|n| Decimal256Type::is_valid_decimal_precision(*n, DECIMAL256_MAX_PRECISION))
+}
+
+/// The decimal digits of a bignum tag of any size.
+pub(crate) fn bignum_string(tag: u64, value: &Value) -> Option<String> {
+    if let Some(n) = bignum_i256(tag, value) {
+        return Some(n.to_string());
+    }
+    let mut magnitude = bignum_bytes(value)?.to_vec();
+    if tag == TAG_NEGATIVE_BIGNUM {
+        // -1 - n has the magnitude n + 1.
+        let carry = magnitude.iter_mut().rev().all(|b| {
+            let (sum, overflow) = b.overflowing_add(1);
+            *b = sum;
+            overflow
+        });
+        if carry {
+            magnitude.insert(0, 1);
+        }
+    }
+    // Long division by 10^9, collecting nine digits at a time from the lowest.
+    let mut chunks = Vec::new();
+    while magnitude.iter().any(|b| *b != 0) {
+        let mut rem = 0u64;
+        for b in magnitude.iter_mut() {
+            let cur = (rem << 8) | u64::from(*b);
+            *b = (cur / 1_000_000_000) as u8;
+            rem = cur % 1_000_000_000;
+        }
+        chunks.push(rem);
+    }
+    let mut digits = String::from(if tag == TAG_NEGATIVE_BIGNUM { "-" } else { "" });
+    for (i, chunk) in chunks.iter().rev().enumerate() {
+        match i {
+            0 => digits.push_str(&chunk.to_string()),
+            _ => digits.push_str(&format!("{:09}", chunk)),
+        }
+    }
+    Some(digits)
+}
+
+/// The exponent and mantissa of a decimal fraction; the mantissa is an integer
+/// or a bignum.
+pub(crate) fn decimal_fraction(value: &Value) -> Option<(i64, i256)> {
+    let Value::Array(pair) = value else {
+        return None;
+    };
+    let [Value::Integer(exponent), mantissa] = pair.as_slice() else {
+        return None;
+    };
+    let mantissa = match mantissa {
+        Value::Integer(i) => i256::from_i128(*i),
+        Value::Tag(tag, payload) if SurrealTag::of(*tag) == SurrealTag::BigNum => bignum_i256(*tag, payload)?,
+        _ => return None,
+    };
+    Some((i64::try_from(*exponent).ok()?, mantissa))
+}
+
+/// The unscaled value of `mantissa * 10^exponent` with `scale` digits after the
+/// point, if it is exact.
+pub(crate) fn rescale(mantissa: i256, exponent: i64, scale: i8) -> Option<i256> {
+    let shift = exponent.checked_add(i64::from(scale))?;
+    if mantissa == i256::ZERO {
+        return Some(mantissa);
+    }
+    let factor = i256::from_i128(10).checked_pow(u32::try_from(shift.unsigned_abs()).ok()?)?;
+    match shift >= 0 {
+        true => mantissa.checked_mul(factor),
+        false => (mantissa.checked_rem(factor)? == i256::ZERO).then(|| mantissa.wrapping_div(factor)),
+    }
+}
+
+/// The fewest digits after the point a decimal fraction needs.
+pub(crate) fn decimal_fraction_scale(value: &Value) -> Option<i64> {
+    decimal_fraction(value).map(|(exponent, _)| exponent.saturating_neg().max(0))
+}
+
+/// A decimal fraction as plain decimal text, e.g. `[-2, 12345]` as `123.45`.
+pub(crate) fn decimal_fraction_string(value: &Value) -> Option<String> {
+    let (exponent, mantissa) = decimal_fraction(value)?;
+    let digits = mantissa.wrapping_abs().to_string();
+    let sign = if mantissa < i256::ZERO { "-" } else { "" };
+    // Digits after the point, written out up to the widest Decimal256.
+    let point = exponent.saturating_neg();
+    Some(match point {
+        0 => format!("{}{}", sign, digits),
+        _ if mantissa == i256::ZERO => "0".to_string(),
+        1..=76 if (point as usize) < digits.len() => {
+            let (whole, fraction) = digits.split_at(digits.len() - point as usize);
+            format!("{}{}.{}", sign, whole, fraction)
+        }
+        1..=76 => format!("{}0.{}{}", sign, "0".repeat(point as usize - digits.len()), digits),
+        _ => format!("{}{}e{}", sign, digits, exponent),
+    })
+}
+
+/// Format 16 UUID bytes in the canonical `8-4-4-4-12` lowercase form.
+pub(crate) fn format_uuid(bytes: &[u8; 16]) -> String {
+    let mut out = String::with_capacity(36);
+    for (i, b) in bytes.iter().enumerate() {
+        if matches!(i, 4 | 6 | 8 | 10) {
+            out.push('-');
+        }
+        out.push_str(&format!("{:02x}", b));
+    }
+    out
+}
+
+/// Encode a SurrealDB geometry tag as little-endian ISO WKB.
+///
+/// Nested members may be tagged (as SurrealDB sends them) or bare coordinate arrays.
+pub(crate) fn geometry_to_wkb(tag: u64, value: &Value) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    write_wkb(tag, value, &mut out)?;
+    Some(out)
+}
+
+fn write_wkb(tag: u64, value: &Value, out: &mut Vec<u8>) -> Option<()> {
+    fn untag(expected: u64, value: &Value) -> Option<&Value> {
+        match value {
+            Value::Tag(t, inner) if *t == expected => Some(inner),
+            Value::Tag(_, _) => None,
+            other => Some(other),
+        }
+    }
+    fn coord(value: &Value) -> Option<f64> {
+        match value {
+            Value::Float(f) => Some(*f),
+            Value::Integer(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+    fn point(value: &Value, out: &mut Vec<u8>) -> Option<()> {
+        match untag(TAG_GEOMETRY_POINT, value)? {
+            Value::Array(xy) if xy.len() == 2 => {
+                out.extend_from_slice(&coord(&xy[0])?.to_le_bytes());
+                out.extend_from_slice(&coord(&xy[1])?.to_le_bytes());
+                Some(())
+            }
+            _ => None,
+        }
+    }
+    fn members(value: &Value) -> Option<&Vec<Value>> {
+        match value {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+    fn ring(expected: u64, value: &Value, out: &mut Vec<u8>) -> Option<()> {
+        let points = members(untag(expected, value)?)?;
+        out.extend_from_slice(&(points.len() as u32).to_le_bytes());
+        points.iter().try_for_each(|p| point(p, out))
+    }
+
+    fn multi(member_tag: u64, value: &Value, out: &mut Vec<u8>) -> Option<()> {
+        let items = members(value)?;
+        out.extend_from_slice(&(items.len() as u32).to_le_bytes());
+        items
+            .iter()
+            .try_for_each(|item| write_wkb(member_tag, untag(member_tag, item)?, out))
+    }
+
+    // Byte order marker (1 = little endian) followed by the geometry type code.
+    out.push(1);
+    let code: u32 = match tag {
+        TAG_GEOMETRY_POINT => 1,
+        TAG_GEOMETRY_LINE => 2,
+        TAG_GEOMETRY_POLYGON => 3,
+        TAG_GEOMETRY_MULTIPOINT => 4,
+        TAG_GEOMETRY_MULTILINE => 5,
+        TAG_GEOMETRY_MULTIPOLYGON => 6,
+        TAG_GEOMETRY_COLLECTION => 7,
+        _ => return None,
+    };
+    out.extend_from_slice(&code.to_le_bytes());
+    match tag {
+        TAG_GEOMETRY_POINT => point(value, out),
+        TAG_GEOMETRY_LINE => ring(TAG_GEOMETRY_LINE, value, out),
+        TAG_GEOMETRY_POLYGON => {
+            let rings = members(value)?;
+            out.extend_from_slice(&(rings.len() as u32).to_le_bytes());
+            rings.iter().try_for_each(|r| ring(TAG_GEOMETRY_LINE, r, out))
+        }
+        TAG_GEOMETRY_MULTIPOINT => multi(TAG_GEOMETRY_POINT, value, out),
+        TAG_GEOMETRY_MULTILINE => multi(TAG_GEOMETRY_LINE, value, out),
+        TAG_GEOMETRY_MULTIPOLYGON => multi(TAG_GEOMETRY_POLYGON, value, out),
+        _ => {
+            // Collections hold arbitrary tagged geometries.
+            let items = members(value)?;
+            out.extend_from_slice(&(items.len() as u32).to_le_bytes());
+            items.iter().try_for_each(|item| match item {
+                Value::Tag(t, inner) if SurrealTag::of(*t) == SurrealTag::Geometry => write_wkb(*t, inner, out),
+                _ => None,
+            })
+        }
+    }
+}
+
+/// The bound value behind the `start` / `end` member of a serialized range.
+pub(crate) fn range_bound<'a>(range: &'a Value, member: &str) -> Option<&'a Value> {
+    let index = match member {
+        "start" => 0,
+        "end" => 1,
+        _ => return None,
+    };
+    match range {
+        Value::Array(bounds) => match bounds.get(index)? {
+            Value::Tag(TAG_BOUND_INCLUDED | TAG_BOUND_EXCLUDED, v) => Some(v),
+            _ => None,
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,177 @@
+//! TLS for the embedded client, with rustls.
+//!
+//! `wss://` and `https://` connections are encrypted by tungstenite and
+//! reqwest with the rustls settings built here, trusting the CAs in `ca_file` or
+//! the system's, and presenting the client certificate in `cert_file` if given.
+
+use std::sync::Arc;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ClientConfig, RootCertStore};
+
+/// The TLS settings of a connection: trusting the CAs in `ca_file` (or the
+/// system's) and presenting the client certificate in `cert_file`, whose key is
+/// in `key_file` or with it.
+pub(crate) fn config(ca_file: Option<&str>, cert_file: Option<&str>, key_file: Option<&str>) -> PyResult<Arc<ClientConfig>> {
+    if key_file.is_some() && cert_file.is_none() {
+        return Err(PyValueError::new_err("key_file needs cert_file"));
+    }
+    let mut roots = RootCertStore::empty();
+    match ca_file {
+        Some(ca_file) => {
+            for cert in certificates("ca_file", ca_file)? {
+                roots.add(cert).map_err(|e| load_error("ca_file", ca_file, e))?;
+            }
+        }
+        None => {
+            let native = rustls_native_certs::load_native_certs();
+            if native.certs.is_empty() {
+                if let Some(e) = native.errors.first() {
+                    return Err(PyValueError::new_err(format!("cannot load the system's CA certificates: {}", e)));
+                }
+            }
+            roots.add_parsable_certificates(native.certs);
+        }
+    }
+    let builder = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| PyValueError::new_err(format!("TLS error: {}", e)))?
+        .with_root_certificates(roots);
+    let config = match cert_file {
+        Some(cert_file) => {
+            let chain = certificates("cert_file", cert_file)?;
+            let (name, path) = key_file.map_or(("cert_file", cert_file), |key_file| ("key_file", key_file));
+            let key = PrivateKeyDer::from_pem_file(path).map_err(|e| load_error(name, path, e))?;
+            builder.with_client_auth_cert(chain, key).map_err(|e| load_error(name, path, e))?
+        }
+        None => builder.with_no_client_auth(),
+    };
+    Ok(Arc::new(config))
+}
+
+/// The settings of the TLS options `ca_file`, `cert_file` and `key_file` of a
+/// connection, or `None` if none is given.
+pub(crate) fn options(
+    ca_file: Option<&str>,
+    cert_file: Option<&str>,
+    key_file: Option<&str>,
+) -> PyResult<Option<Arc<ClientConfig>>> {
+    if ca_file.is_none() && cert_file.is_none() && key_file.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(config(ca_file, cert_file, key_file)?))
+}
+
+/// The certificates of the PEM file `path`, given as argument `name`.
+fn certificates(name: &str, path: &str) -> PyResult<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| load_error(name, path, e))?;
+    match certs.is_empty() {
+        true => Err(load_error(name, path, "no certificate in the file")),
+        false => Ok(certs),
+    }
+}
+
+fn load_error(name: &str, path: &str, error: impl std::fmt::Display) -> PyErr {
+    PyValueError::new_err(format!("cannot load {} {:?}: {}", name, path, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+
+    use futures::{SinkExt, StreamExt};
+    use rustls::{ServerConfig, ServerConnection, StreamOwned};
+    use tokio_tungstenite::tungstenite::{self, Message};
+
+    use super::*;
+    use crate::websocket::{self, Endpoint};
+
+    /// A self-signed certificate for `localhost`, in the PEM file returned, and
+    /// a server configured with it.
+    fn server() -> (PathBuf, Arc<ServerConfig>) {
+        let signed = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("surrealengine-{}-tls", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ca_file = dir.join("ca.pem");
+        std::fs::write(&ca_file, signed.cert.pem()).unwrap();
+        let key = PrivateKeyDer::try_from(signed.key_pair.serialize_der()).unwrap();
+        let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![signed.cert.der().clone()], key)
+            .unwrap();
+        (ca_file, Arc::new(config))
+    }
+
+    /// Serve one WebSocket, answering the message read with it in capitals.
+    fn serve(config: Arc<ServerConfig>) -> (u16, std::thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let served = std::thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let tls = StreamOwned::new(ServerConnection::new(config).unwrap(), tcp);
+            let Ok(mut socket) = tungstenite::accept_hdr(tls, websocket::accept_cbor) else { return };
+            if let Ok(Message::Binary(request)) = socket.read() {
+                socket.send(Message::binary(request.to_ascii_uppercase())).unwrap();
+            }
+        });
+        (port, served)
+    }
+
+    fn open(port: u16, config: Arc<ClientConfig>) -> std::io::Result<Vec<u8>> {
+        let endpoint = Endpoint::parse(&format!("wss://localhost:{}", port)).unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            let mut socket = websocket::open(&endpoint, "cbor", Some(&config)).await?;
+            socket.send(Message::binary(b"ping".to_vec())).await.map_err(websocket::websocket_error)?;
+            match socket.next().await {
+                Some(Ok(message)) => Ok(message.into_data().to_vec()),
+                _ => Err(std::io::Error::other("no answer")),
+            }
+        })
+    }
+
+    #[test]
+    fn websockets_are_encrypted() {
+        let (ca_file, server_config) = server();
+        let (port, served) = serve(server_config);
+        let config = config(ca_file.to_str(), None, None).unwrap();
+        assert_eq!(open(port, config).unwrap(), b"PING");
+        served.join().unwrap();
+    }
+
+    #[test]
+    fn untrusted_servers_are_refused() {
+        let (_, server_config) = server();
+        let (port, served) = serve(server_config);
+        let untrusted = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(RootCertStore::empty())
+            .with_no_client_auth();
+        let err = open(port, Arc::new(untrusted)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("TLS error: invalid peer certificate"), "{}", err);
+        served.join().unwrap();
+    }
+
+    #[test]
+    fn bad_settings_raise() {
+        pyo3::prepare_freethreaded_python();
+        let message = |result: PyResult<Arc<ClientConfig>>| result.err().unwrap().to_string();
+        assert_eq!(message(config(None, None, Some("key.pem"))), "ValueError: key_file needs cert_file");
+        let missing = message(config(Some("/nonexistent/ca.pem"), None, None));
+        assert!(missing.starts_with("ValueError: cannot load ca_file \"/nonexistent/ca.pem\""), "{}", missing);
+        let (ca_file, _) = server();
+        // The file holds a certificate but no key.
+        let keyless = message(config(None, ca_file.to_str(), None));
+        assert!(keyless.starts_with(&format!("ValueError: cannot load cert_file {:?}", ca_file)), "{}", keyless);
+    }
+}
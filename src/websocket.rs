@@ -0,0 +1,201 @@
+//! WebSocket connections of the embedded client, with tokio-tungstenite.
+//!
+//! `Endpoint` is the URL a client connects to, parsed; `open` opens a WebSocket
+//! to it asking for a subprotocol, over TLS for `wss://`. tungstenite answers
+//! pings and joins fragmented messages.
+
+use std::io;
+use std::sync::Arc;
+
+use rustls::ClientConfig;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Error;
+use tokio_tungstenite::{Connector, MaybeTlsStream, WebSocketStream};
+
+/// The largest frame read, and the largest message of fragments joined; a
+/// peer sending more fails the connection rather than exhausting memory.
+const MAX_FRAME_BYTES: u64 = 1 << 32;
+const MAX_MESSAGE_BYTES: u64 = 1 << 36;
+
+pub(crate) type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Where a client connects: the parts of a `ws://`, `wss://`, `http://` or
+/// `https://` URL.
+#[derive(Debug, Clone)]
+pub(crate) struct Endpoint {
+    pub(crate) scheme: Scheme,
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) path: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Scheme {
+    Ws,
+    Wss,
+    Http,
+    Https,
+}
+
+impl Scheme {
+    pub(crate) fn is_secure(self) -> bool {
+        matches!(self, Scheme::Wss | Scheme::Https)
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Scheme::Ws => "ws",
+            Scheme::Wss => "wss",
+            Scheme::Http => "http",
+            Scheme::Https => "https",
+        }
+    }
+}
+
+impl Endpoint {
+    /// Parse `scheme://host[:port][/path]`. The port defaults to the scheme's
+    /// and the path to `/rpc`, SurrealDB's RPC endpoint.
+    pub(crate) fn parse(url: &str) -> Result<Endpoint, String> {
+        let (scheme, rest) = url.split_once("://").ok_or_else(|| format!("invalid URL {:?}: no scheme", url))?;
+        let scheme = match scheme.to_ascii_lowercase().as_str() {
+            "ws" => Scheme::Ws,
+            "wss" => Scheme::Wss,
+            "http" => Scheme::Http,
+            "https" => Scheme::Https,
+            other => return Err(format!("unsupported URL scheme {:?}; expected ws, wss, http or https", other)),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, ""),
+        };
+        let (host, port) = match authority.strip_prefix('[') {
+            // An IPv6 address, `[::1]:8000`
+            Some(v6) => {
+                let (host, after) = v6.split_once(']').ok_or_else(|| format!("invalid URL {:?}", url))?;
+                (host, after.strip_prefix(':'))
+            }
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(format!("invalid URL {:?}: no host", url));
+        }
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| format!("invalid port in URL {:?}", url))?,
+            None if scheme.is_secure() => 443,
+            None => 80,
+        };
+        let path = match path.trim_end_matches('/') {
+            "" => "/rpc".to_string(),
+            path => path.to_string(),
+        };
+        Ok(Endpoint { scheme, host: host.to_string(), port, path })
+    }
+
+    /// The `Host` header of requests to the endpoint.
+    pub(crate) fn host_header(&self) -> String {
+        let host = match self.host.contains(':') {
+            true => format!("[{}]", self.host),
+            false => self.host.clone(),
+        };
+        match (self.scheme.is_secure(), self.port) {
+            (false, 80) | (true, 443) => host,
+            (_, port) => format!("{}:{}", host, port),
+        }
+    }
+
+    /// The URL of the endpoint, with the default path filled in.
+    pub(crate) fn url(&self) -> String {
+        format!("{}://{}{}", self.scheme.as_str(), self.host_header(), self.path)
+    }
+}
+
+/// Open a WebSocket to `endpoint` asking for `protocol`, with TLS set up by
+/// `tls` for `wss://`.
+pub(crate) async fn open(
+    endpoint: &Endpoint,
+    protocol: &str,
+    tls: Option<&Arc<ClientConfig>>,
+) -> io::Result<WebSocket> {
+    let mut request = endpoint.url().into_client_request().map_err(websocket_error)?;
+    let header = HeaderValue::from_str(protocol).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    request.headers_mut().insert("Sec-WebSocket-Protocol", header);
+    let mut config = WebSocketConfig::default();
+    config.max_frame_size = Some(MAX_FRAME_BYTES as usize);
+    config.max_message_size = Some(MAX_MESSAGE_BYTES as usize);
+    let connector = tls.map(|tls| Connector::Rustls(tls.clone()));
+    // tungstenite refuses a server answering without the protocol.
+    let (socket, _) = tokio_tungstenite::connect_async_tls_with_config(request, Some(config), true, connector)
+        .await
+        .map_err(websocket_error)?;
+    Ok(socket)
+}
+
+/// A WebSocket error as an I/O error, of the I/O error it comes from if any.
+pub(crate) fn websocket_error(error: Error) -> io::Error {
+    match error {
+        Error::Io(e) if e.get_ref().is_some_and(|inner| inner.is::<rustls::Error>()) => {
+            io::Error::new(io::ErrorKind::InvalidData, format!("TLS error: {}", e))
+        }
+        Error::Io(e) => e,
+        Error::Tls(e) => io::Error::new(io::ErrorKind::InvalidData, format!("TLS error: {}", e)),
+        Error::Http(response) => {
+            io::Error::other(format!("WebSocket handshake refused with HTTP status {}", response.status().as_u16()))
+        }
+        Error::Capacity(e) => io::Error::new(io::ErrorKind::InvalidData, format!("WebSocket {}", e)),
+        e => io::Error::other(e.to_string()),
+    }
+}
+
+/// Accept a WebSocket speaking the `cbor` protocol, for the servers of tests.
+#[cfg(test)]
+#[allow(clippy::result_large_err)]
+pub(crate) fn accept_cbor(
+    _: &tokio_tungstenite::tungstenite::handshake::server::Request,
+    mut response: tokio_tungstenite::tungstenite::handshake::server::Response,
+) -> Result<
+    tokio_tungstenite::tungstenite::handshake::server::Response,
+    tokio_tungstenite::tungstenite::handshake::server::ErrorResponse,
+> {
+    response.headers_mut().insert("Sec-WebSocket-Protocol", HeaderValue::from_static("cbor"));
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoints_parse() {
+        let endpoint = |url: &str| {
+            let e = Endpoint::parse(url).unwrap();
+            (e.scheme, e.host, e.port, e.path)
+        };
+        let host = |host: &str| host.to_string();
+        assert_eq!(endpoint("ws://localhost:8000"), (Scheme::Ws, host("localhost"), 8000, "/rpc".to_string()));
+        assert_eq!(endpoint("WSS://db.example.com/"), (Scheme::Wss, host("db.example.com"), 443, "/rpc".to_string()));
+        assert_eq!(endpoint("http://db"), (Scheme::Http, host("db"), 80, "/rpc".to_string()));
+        assert_eq!(endpoint("https://[::1]:8000/sub/rpc/"), (Scheme::Https, host("::1"), 8000, "/sub/rpc".to_string()));
+
+        assert_eq!(Endpoint::parse("ws://[::1]:8000").unwrap().host_header(), "[::1]:8000");
+        assert_eq!(Endpoint::parse("wss://db:443").unwrap().host_header(), "db");
+        assert_eq!(Endpoint::parse("ws://db:443").unwrap().host_header(), "db:443");
+
+        let failures = [
+            ("localhost:8000", "invalid URL \"localhost:8000\": no scheme"),
+            ("ftp://db", "unsupported URL scheme \"ftp\"; expected ws, wss, http or https"),
+            ("ws://:8000", "invalid URL \"ws://:8000\": no host"),
+            ("ws://db:port", "invalid port in URL \"ws://db:port\""),
+            ("ws://db:65536", "invalid port in URL \"ws://db:65536\""),
+            ("ws://[::1", "invalid URL \"ws://[::1\""),
+        ];
+        for (url, message) in failures {
+            assert_eq!(Endpoint::parse(url).unwrap_err(), message);
+        }
+    }
+}
@@ -18,7 +18,7 @@ print(f"CBOR Bytes: {len(cbor_bytes)} bytes")
 
 # Test cbor_to_arrow
 try:
-    batch = accelerator.cbor_to_arrow(cbor_bytes)
+    batch = accelerator.cbor_to_arrow(cbor_bytes, output="pyarrow")
     print("Success! Result type:", type(batch))
     print("RecordBatch:", batch)
     